@@ -0,0 +1,158 @@
+//! The benchmarks performance changes are measured against: inserts
+//! through SQL text and through `insert_rows`, point lookups, full scans,
+//! and flushing a database then opening it again.
+//!
+//! Run with `cargo perf`, which builds in release mode; name benchmarks to
+//! run only those, as in `cargo perf -- scan`. Rows come from `Gen` with
+//! fixed seeds, so every run works on the same data.
+//!
+//! Each benchmark runs on 1,000 rows, about as many as a table holds, and
+//! on 100,000 spread over a hundred databases of 1,000. Databases live in
+//! memory, or for `flush_reopen` in a `MemoryVfs`, so the disk is not
+//! measured.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use VoidDB::row::{Row, RowLayout};
+use VoidDB::testing::Gen;
+use VoidDB::vfs::MemoryVfs;
+use VoidDB::{Database, OnConflict, Options};
+
+const ROWS_SEED: u64 = 0x766f_6964;
+const LOOKUP_SEED: u64 = 0x6c6f_6f6b;
+const ROWS_PER_DATABASE: usize = 1_000;
+const DATASETS: [(&str, usize); 2] = [("1k", 1_000), ("100k", 100_000)];
+
+/// How long each benchmark keeps taking samples, and how many it takes at
+/// least and at most.
+const SAMPLE_TIME: Duration = Duration::from_secs(1);
+const MIN_SAMPLES: usize = 5;
+const MAX_SAMPLES: usize = 100;
+
+/// The benchmarks named on the command line; all of them if none are.
+struct Filter(Vec<String>);
+
+impl Filter {
+    fn from_args() -> Self {
+        // `cargo bench` passes `--bench`.
+        Filter(std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect())
+    }
+
+    /// Times `routine` on what `setup` makes for it, which is not timed,
+    /// and prints the median run with the rate at which it got through
+    /// `rows` rows.
+    fn bench<T>(&self, name: &str, rows: usize, mut setup: impl FnMut() -> T, mut routine: impl FnMut(T)) {
+        if !self.0.is_empty() && !self.0.iter().any(|wanted| name.contains(wanted.as_str())) {
+            return;
+        }
+        routine(setup());
+
+        let mut samples = Vec::new();
+        let started = Instant::now();
+        while samples.len() < MIN_SAMPLES || (samples.len() < MAX_SAMPLES && started.elapsed() < SAMPLE_TIME) {
+            let input = setup();
+            let start = Instant::now();
+            routine(input);
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        let median = samples[samples.len() / 2];
+        println!(
+            "{:<20} {:>9.3} ms  (min {:>9.3} ms, {:>3} runs)  {:>12.0} rows/s",
+            name,
+            median.as_secs_f64() * 1e3,
+            samples[0].as_secs_f64() * 1e3,
+            samples.len(),
+            rows as f64 / median.as_secs_f64()
+        );
+    }
+}
+
+/// `rows` generated rows, a database's worth at a time.
+fn dataset(rows: usize) -> Vec<Vec<Row>> {
+    let mut gen = Gen::new(ROWS_SEED);
+    let rows: Vec<Row> = (0..rows).map(|_| gen.row(RowLayout::DEFAULT)).collect();
+    rows.chunks(ROWS_PER_DATABASE).map(<[Row]>::to_vec).collect()
+}
+
+fn insert_sql(row: &Row) -> String {
+    format!("insert {} {} {} {}", row.id, row.username(), row.email(), row.age)
+}
+
+/// A database in memory for each part of `data`, holding its rows.
+fn loaded(data: &[Vec<Row>]) -> Vec<Database> {
+    data.iter().map(|rows| {
+        let mut db = Database::open_in_memory();
+        insert_all(&mut db, rows.clone());
+        db
+    }).collect()
+}
+
+fn insert_all(db: &mut Database, rows: Vec<Row>) {
+    let count = rows.len();
+    let summary = db.insert_rows(rows, OnConflict::Fail).unwrap();
+    assert_eq!(summary.inserted, count, "{:?}", summary.errors.first());
+}
+
+fn fresh(count: usize) -> Vec<Database> {
+    (0..count).map(|_| Database::open_in_memory()).collect()
+}
+
+fn main() {
+    let filter = Filter::from_args();
+    for (size, rows) in DATASETS {
+        let data = dataset(rows);
+        let name = |bench: &str| format!("{}/{}", bench, size);
+
+        let statements: Vec<Vec<String>> = data.iter().map(|rows| rows.iter().map(insert_sql).collect()).collect();
+        filter.bench(&name("insert_sql"), rows, || fresh(data.len()), |mut dbs| {
+            for (db, statements) in dbs.iter_mut().zip(&statements) {
+                for sql in statements {
+                    db.execute(sql).unwrap();
+                }
+            }
+        });
+
+        filter.bench(&name("insert_rows"), rows, || (fresh(data.len()), data.clone()), |(mut dbs, data)| {
+            for (db, rows) in dbs.iter_mut().zip(data) {
+                insert_all(db, rows);
+            }
+        });
+
+        let dbs = loaded(&data);
+        let mut gen = Gen::new(LOOKUP_SEED);
+        let lookups: Vec<Vec<String>> = data.iter().map(|rows| {
+            (0..rows.len()).map(|_| format!("select where id = {}", rows[gen.below(rows.len() as u64) as usize].id)).collect()
+        }).collect();
+        filter.bench(&name("point_lookup"), rows, || (), |()| {
+            for (db, lookups) in dbs.iter().zip(&lookups) {
+                for sql in lookups {
+                    assert_eq!(db.select(sql).unwrap().count(), 1);
+                }
+            }
+        });
+
+        filter.bench(&name("full_scan"), rows, || (), |()| {
+            for (db, rows) in dbs.iter().zip(&data) {
+                assert_eq!(db.select("select").unwrap().count(), rows.len());
+            }
+        });
+
+        let path = Path::new("bench.db");
+        filter.bench(&name("flush_reopen"), rows, || {
+            data.iter().map(|rows| {
+                let options = Options::new().vfs(Arc::new(MemoryVfs::new()));
+                let mut db = options.open(path).unwrap();
+                insert_all(&mut db, rows.clone());
+                (db, options)
+            }).collect::<Vec<_>>()
+        }, |dbs| {
+            for ((db, options), rows) in dbs.into_iter().zip(&data) {
+                db.close().unwrap();
+                assert_eq!(options.open(path).unwrap().stats().tables[0].1, rows.len());
+            }
+        });
+    }
+}
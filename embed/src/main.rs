@@ -0,0 +1,38 @@
+//! Stores the arguments as rows of an in-memory database and prints them
+//! back, using only the database engine.
+
+use VoidDB::{Database, DbError, QueryResult};
+
+fn run(names: &[String]) -> Result<Vec<String>, DbError> {
+    let mut db = Database::open_in_memory();
+    for (id, name) in names.iter().enumerate() {
+        db.execute(&format!("insert {} {} {}@example.com", id + 1, name, name))?;
+    }
+    match db.execute("select")? {
+        QueryResult::Rows(rows) => Ok(rows.iter().map(|row| format!("{} {} {}", row.id, row.username(), row.email())).collect()),
+        _ => unreachable!("a select returns rows"),
+    }
+}
+
+fn main() {
+    let names: Vec<String> = std::env::args().skip(1).collect();
+    match run(&names) {
+        Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let names = ["alice".to_string(), "bob".to_string()];
+        assert_eq!(run(&names).unwrap(), ["1 alice alice@example.com", "2 bob bob@example.com"]);
+        assert_eq!(run(&["a".repeat(40)]).unwrap_err().code(), "VALUE_TOO_LONG");
+    }
+}
@@ -0,0 +1,46 @@
+//! Times loading 100,000 rows through SQL text against `insert_rows`.
+//!
+//! Run with `cargo run --release --example bulk_insert`. A table holds
+//! little more than a thousand rows, so the rows go into a fresh database
+//! every `ROWS_PER_DATABASE`.
+
+use std::time::{Duration, Instant};
+
+use VoidDB::row::Row;
+use VoidDB::{Database, OnConflict};
+
+const ROWS: u64 = 100_000;
+const ROWS_PER_DATABASE: u64 = 1_000;
+
+fn row(id: u64) -> Row {
+    Row::try_new(id, &format!("user{}", id), &format!("user{}@example.com", id)).unwrap()
+}
+
+/// Loads `ROWS` rows with `load`, which fills one database with the ids
+/// in the range it is given.
+fn time(mut load: impl FnMut(&mut Database, std::ops::Range<u64>)) -> Duration {
+    let start = Instant::now();
+    for first in (0..ROWS).step_by(ROWS_PER_DATABASE as usize) {
+        let mut db = Database::open_in_memory();
+        load(&mut db, first..first + ROWS_PER_DATABASE);
+        assert_eq!(db.select("select").unwrap().count() as u64, ROWS_PER_DATABASE);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let sql = time(|db, ids| {
+        for id in ids {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+    });
+    let bulk = time(|db, ids| {
+        let summary = db.insert_rows(ids.map(row), OnConflict::Fail).unwrap();
+        assert!(summary.errors.is_empty());
+    });
+
+    let rate = |elapsed: Duration| ROWS as f64 / elapsed.as_secs_f64();
+    println!("sql text:    {:>8.1} ms  {:>10.0} rows/s", sql.as_secs_f64() * 1e3, rate(sql));
+    println!("insert_rows: {:>8.1} ms  {:>10.0} rows/s", bulk.as_secs_f64() * 1e3, rate(bulk));
+    println!("speedup:     {:>8.1}x", sql.as_secs_f64() / bulk.as_secs_f64());
+}
@@ -0,0 +1,49 @@
+//! Times reading a piped script of 500,000 inserts a line at a time, with
+//! `InputBuffer`, which reads every line into the same buffer, against
+//! `BufRead::read_line` into a fresh `String` per line, trimmed into
+//! another.
+//!
+//! Run with `cargo run --release --example piped_script`. Only reading is
+//! timed: a table holds little more than a thousand rows, so the inserts
+//! themselves could not all run.
+
+use std::io::{BufRead, Cursor};
+use std::time::{Duration, Instant};
+
+use VoidDB::input::InputBuffer;
+
+const LINES: u64 = 500_000;
+
+/// Reads every line of `script` with `read`, which gives the length of the
+/// trimmed line or `None` at the end, and checks it saw them all.
+fn time(mut read: impl FnMut() -> Option<usize>) -> Duration {
+    let start = Instant::now();
+    let (mut lines, mut bytes) = (0, 0);
+    while let Some(len) = read() {
+        lines += 1;
+        bytes += len;
+    }
+    assert_eq!(lines, LINES);
+    assert!(bytes > 0);
+    start.elapsed()
+}
+
+fn main() {
+    let script: String = (1..=LINES).map(|id| format!("insert {} user{} user{}@example.com\n", id, id, id)).collect();
+
+    let mut reader = Cursor::new(script.as_bytes());
+    let fresh = time(|| {
+        let mut line = String::new();
+        match reader.read_line(&mut line).unwrap() {
+            0 => None,
+            _ => Some(line.trim().to_string().len()),
+        }
+    });
+    let mut input = InputBuffer::from_reader(Cursor::new(script.clone().into_bytes()));
+    let reused = time(|| input.read_input().unwrap().map(str::len));
+
+    let rate = |elapsed: Duration| LINES as f64 / elapsed.as_secs_f64();
+    println!("fresh string: {:>8.1} ms  {:>10.0} lines/s", fresh.as_secs_f64() * 1e3, rate(fresh));
+    println!("InputBuffer:  {:>8.1} ms  {:>10.0} lines/s", reused.as_secs_f64() * 1e3, rate(reused));
+    println!("speedup:      {:>8.1}x", fresh.as_secs_f64() / reused.as_secs_f64());
+}
@@ -0,0 +1,45 @@
+//! Times printing 100,000 rows to stdout: the shell running `select`,
+//! which writes its results a block at a time, against `println!` for
+//! each row.
+//!
+//! Run with `cargo run --release --example select_output > /dev/null`;
+//! the times go to stderr. A table holds little more than a thousand rows,
+//! so the same `ROWS_PER_SELECT` are selected again and again.
+
+use std::time::{Duration, Instant};
+
+use VoidDB::output::Console;
+use VoidDB::repl::Repl;
+use VoidDB::row::Row;
+use VoidDB::{Database, OnConflict};
+
+const ROWS: u64 = 100_000;
+const ROWS_PER_SELECT: u64 = 1_000;
+
+fn time(mut select: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROWS / ROWS_PER_SELECT {
+        select();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut db = Database::open_in_memory();
+    let rows = (1..=ROWS_PER_SELECT).map(|id| Row::try_new(id, &format!("user{}", id), &format!("user{}@example.com", id)).unwrap());
+    db.insert_rows(rows, OnConflict::Fail).unwrap();
+
+    let mut repl = Repl::new(db);
+    let mut console = Console::new();
+    let shell = time(|| repl.run_line("select", &mut console).unwrap());
+    let println = time(|| {
+        for row in repl.database().select("select").unwrap() {
+            row.unwrap().print();
+        }
+    });
+
+    let rate = |elapsed: Duration| ROWS as f64 / elapsed.as_secs_f64();
+    eprintln!("println!: {:>8.1} ms  {:>10.0} rows/s", println.as_secs_f64() * 1e3, rate(println));
+    eprintln!("shell:    {:>8.1} ms  {:>10.0} rows/s", shell.as_secs_f64() * 1e3, rate(shell));
+    eprintln!("speedup:  {:>8.1}x", println.as_secs_f64() / shell.as_secs_f64());
+}
@@ -0,0 +1,67 @@
+/* The C interface of VoidDB. Build the library with
+ * `cargo rustc --release --lib --features ffi --crate-type cdylib`
+ * and link against libVoidDB.
+ *
+ * Strings passed in must be NUL-terminated UTF-8; strings passed out are
+ * owned by the library. */
+
+#ifndef VOIDDB_H
+#define VOIDDB_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct voiddb voiddb;
+
+/* What a call returned. The numbers never change. */
+typedef enum voiddb_code {
+    VOIDDB_OK = 0,
+    VOIDDB_ERROR = 1,
+    VOIDDB_UNRECOGNIZED_STATEMENT = 2,
+    VOIDDB_SYNTAX_ERROR = 3,
+    VOIDDB_TABLE_FULL = 4,
+    VOIDDB_DUPLICATE_KEY = 5,
+    VOIDDB_IO_ERROR = 6,
+    VOIDDB_CORRUPT = 7,
+    VOIDDB_INTERRUPTED = 8,
+    VOIDDB_ATTACH_ERROR = 9,
+    VOIDDB_NO_SUCH_DATABASE = 10,
+    VOIDDB_TABLE_EXISTS = 11,
+    VOIDDB_NO_SUCH_TABLE = 12,
+    VOIDDB_READ_ONLY = 13,
+    VOIDDB_VALUE_TOO_LONG = 14,
+    VOIDDB_BAD_LAYOUT = 15,
+    VOIDDB_CHECKSUM_MISMATCH = 16,
+    VOIDDB_MISUSE = 17,
+    VOIDDB_ABORT = 18,
+    VOIDDB_INTERNAL = 19,
+    VOIDDB_LOCKED = 20,
+    VOIDDB_NO_SUCH_COLUMN = 21,
+    VOIDDB_TYPE_MISMATCH = 22,
+    VOIDDB_CANNOT_OPEN = 23,
+} voiddb_code;
+
+/* Called once per row of a select. values[i] is NULL for a null value and
+ * is only valid until the callback returns. Return non-zero to stop the
+ * select; voiddb_exec then returns VOIDDB_ABORT. */
+typedef int (*voiddb_callback)(void *user_data, int columns, const char *const *values, const char *const *names);
+
+/* Opens the database file at path, or an in-memory database for ":memory:".
+ * *db is set even when the open fails and must be closed either way. */
+voiddb_code voiddb_open(const char *path, voiddb **db);
+
+/* Runs one statement, calling callback (if not NULL) for each row. */
+voiddb_code voiddb_exec(voiddb *db, const char *sql, voiddb_callback callback, void *user_data);
+
+/* The message of the last call on db; valid until the next call on db. */
+const char *voiddb_errmsg(const voiddb *db);
+
+/* Writes out everything buffered and frees db. NULL is ignored. */
+voiddb_code voiddb_close(voiddb *db);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
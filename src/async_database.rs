@@ -0,0 +1,326 @@
+//! Running statements from async code without blocking the executor.
+//!
+//! Pages are still read with blocking file I/O. Each call hands that work to
+//! a thread of its own and hands back a future, or a stream of rows, that
+//! the executor polls like any other.
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::compiler::QueryResult;
+use crate::connection::Connection;
+use crate::database::Database;
+use crate::error::DbError;
+use crate::row::Row;
+
+/// How many rows a stream reads ahead of its consumer.
+const STREAM_BUFFER: usize = 64;
+
+/// A database shared between tasks. Clones are cheap and reach the same
+/// database; statements are ordered by the locks of `Connection`.
+///
+/// Dropping a future before it completes does not cancel the statement: it
+/// runs to the end on its thread and its result is thrown away, so a write
+/// is either made in full or not at all. Dropping a `RowStream` stops the
+/// select at the next row.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    connection: Connection,
+}
+
+impl AsyncDatabase {
+    /// Opens the database file at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        Ok(Self::from(Connection::open(path)?))
+    }
+
+    pub fn open_in_memory() -> Self {
+        Self::from(Connection::open_in_memory())
+    }
+
+    /// The connection underneath, for blocking calls.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Runs one statement; see `Connection::execute`.
+    pub async fn execute(&self, sql: &str) -> Result<QueryResult, DbError> {
+        let (connection, sql) = (self.connection.clone(), sql.to_string());
+        spawn_blocking(move || connection.execute(&sql)).await
+    }
+
+    /// Writes out everything buffered.
+    pub async fn flush(&self) -> Result<(), DbError> {
+        let connection = self.connection.clone();
+        spawn_blocking(move || connection.flush()).await
+    }
+
+    /// The rows of a select, read as the stream is polled. An error, such
+    /// as a statement that is not a select, comes out as the only item.
+    pub fn select(&self, sql: &str) -> RowStream {
+        let (connection, sql) = (self.connection.clone(), sql.to_string());
+        let (sender, receiver) = mpsc::sync_channel(STREAM_BUFFER);
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+        let shared = waker.clone();
+        thread::spawn(move || {
+            let wake = || {
+                if let Some(waker) = shared.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                    waker.wake();
+                }
+            };
+            let db = connection.read();
+            let rows: Box<dyn Iterator<Item = Result<Row, DbError>>> = match db.select(&sql) {
+                Ok(rows) => Box::new(rows),
+                Err(err) => Box::new(std::iter::once(Err(err))),
+            };
+            for row in rows {
+                // A full buffer blocks here until the consumer catches up;
+                // a dropped stream makes the send fail.
+                if sender.send(row).is_err() {
+                    return;
+                }
+                wake();
+            }
+            drop(sender);
+            wake();
+        });
+        RowStream { receiver, waker }
+    }
+}
+
+impl From<Connection> for AsyncDatabase {
+    fn from(connection: Connection) -> Self {
+        AsyncDatabase { connection }
+    }
+}
+
+impl From<Database> for AsyncDatabase {
+    fn from(db: Database) -> Self {
+        Self::from(Connection::new(db))
+    }
+}
+
+/// The rows of a select, from `AsyncDatabase::select`.
+///
+/// `poll_next` has the signature of `futures::Stream::poll_next`, so the
+/// stream can be wrapped for stream combinators.
+pub struct RowStream {
+    receiver: Receiver<Result<Row, DbError>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl RowStream {
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Row, DbError>>> {
+        let this = self.get_mut();
+        match this.receiver.try_recv() {
+            Ok(row) => return Poll::Ready(Some(row)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+        *this.waker.lock().unwrap_or_else(PoisonError::into_inner) = Some(cx.waker().clone());
+        // A row sent before the waker was stored would not wake us.
+        match this.receiver.try_recv() {
+            Ok(row) => Poll::Ready(Some(row)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// The next row, or `None` after the last.
+    pub async fn next(&mut self) -> Option<Result<Row, DbError>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// All the remaining rows, stopping at the first error.
+    pub async fn collect(mut self) -> Result<Vec<Row>, DbError> {
+        let mut rows = Vec::new();
+        while let Some(row) = self.next().await {
+            rows.push(row?);
+        }
+        Ok(rows)
+    }
+}
+
+/// The result of `work`, run on a thread of its own.
+fn spawn_blocking<T, F>(work: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let state = Arc::new(Mutex::new(State { result: None, waker: None }));
+    let shared = state.clone();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(work));
+        let mut state = shared.lock().unwrap_or_else(PoisonError::into_inner);
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    Blocking { state }
+}
+
+struct State<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+struct Blocking<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            // A panic on the thread surfaces in the task that awaited it.
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+
+    /// Wakes the test thread.
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    type Task<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+    /// Polls all of `futures` on this thread until every one is done.
+    fn join_all<T>(futures: Vec<Task<'_, T>>) -> Vec<T> {
+        let waker = Arc::new(Unpark(thread::current()));
+        let waker = Waker::from(waker);
+        let mut cx = Context::from_waker(&waker);
+        let mut futures: Vec<_> = futures.into_iter().map(Some).collect();
+        let mut results: Vec<Option<T>> = futures.iter().map(|_| None).collect();
+        while results.iter().any(Option::is_none) {
+            for (future, result) in futures.iter_mut().zip(&mut results) {
+                if let Some(pending) = future {
+                    if let Poll::Ready(value) = pending.as_mut().poll(&mut cx) {
+                        *result = Some(value);
+                        *future = None;
+                    }
+                }
+            }
+            if results.iter().any(Option::is_none) {
+                thread::park();
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        join_all(vec![Box::pin(future)]).pop().unwrap()
+    }
+
+    fn insert(id: u64) -> String {
+        format!("insert {} user{} user{}@example.com", id, id, id)
+    }
+
+    #[test]
+    fn test_concurrent_queries() {
+        let db = AsyncDatabase::open_in_memory();
+        let writes: Vec<Task<Result<QueryResult, DbError>>> = (1..=50)
+            .map(|id| {
+                let db = &db;
+                Box::pin(async move { db.execute(&insert(id)).await }) as Task<_>
+            })
+            .collect();
+        for result in join_all(writes) {
+            assert_eq!(result.unwrap(), QueryResult::RowsAffected(1));
+        }
+
+        let reads: Vec<Task<(usize, QueryResult)>> = (1..=8)
+            .map(|reader| {
+                let db = db.clone();
+                Box::pin(async move {
+                    let all = db.select("select").collect().await.unwrap();
+                    let one = db.execute(&format!("select where id = {}", reader)).await.unwrap();
+                    (all.len(), one)
+                }) as Task<_>
+            })
+            .collect();
+        for (reader, (len, one)) in join_all(reads).into_iter().enumerate() {
+            let id = reader as u64 + 1;
+            assert_eq!(len, 50);
+            assert_eq!(one, QueryResult::Rows(vec![Row::new(id, &format!("user{}", id), &format!("user{}@example.com", id))]));
+        }
+    }
+
+    #[test]
+    fn test_stream_reads_past_its_buffer() {
+        let db = AsyncDatabase::open_in_memory();
+        for id in 1..=(STREAM_BUFFER as u64 * 3) {
+            db.connection().execute(&insert(id)).unwrap();
+        }
+        let rows = block_on(async {
+            let mut stream = db.select("select");
+            let mut ids = Vec::new();
+            while let Some(row) = stream.next().await {
+                ids.push(row.unwrap().id);
+            }
+            ids
+        });
+        assert_eq!(rows, (1..=STREAM_BUFFER as u64 * 3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_errors() {
+        let db = AsyncDatabase::open_in_memory();
+        assert_eq!(block_on(db.execute("bogus")).unwrap_err().code(), "UNRECOGNIZED_STATEMENT");
+        assert_eq!(block_on(db.select("insert 1 a a@x").collect()).unwrap_err().code(), "READ_ONLY");
+        assert_eq!(block_on(db.select("select from other.users").collect()).unwrap_err().code(), "NO_SUCH_DATABASE");
+    }
+
+    #[test]
+    fn test_dropped_stream_lets_writers_in() {
+        let db = AsyncDatabase::open_in_memory();
+        for id in 1..=(STREAM_BUFFER as u64 * 2) {
+            db.connection().execute(&insert(id)).unwrap();
+        }
+        let mut stream = db.select("select");
+        assert_eq!(block_on(stream.next()).unwrap().unwrap().id, 1);
+        // The reader is blocked on a full buffer, holding the read lock,
+        // until the stream goes away.
+        drop(stream);
+        assert_eq!(block_on(db.execute(&insert(1000))).unwrap(), QueryResult::RowsAffected(1));
+    }
+
+    #[test]
+    fn test_dropped_execute_still_runs() {
+        let db = AsyncDatabase::open_in_memory();
+        let sql = insert(1);
+        let mut write = Box::pin(db.execute(&sql));
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let _ = write.as_mut().poll(&mut Context::from_waker(&waker));
+        drop(write);
+        // The write was handed to its thread on the first poll and is made
+        // in full; the next statement waits for it.
+        while block_on(db.select("select").collect()).unwrap().is_empty() {
+            thread::yield_now();
+        }
+        assert_eq!(block_on(db.execute(&insert(1))).unwrap_err().code(), "DUPLICATE_KEY");
+    }
+}
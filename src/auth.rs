@@ -0,0 +1,76 @@
+//! The shared secret a server can ask its clients for, read from the file
+//! given to `--auth-token-file`.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The environment variable `VoidDB --connect` reads a token from when it
+/// is given no `--token-file`.
+pub const TOKEN_VAR: &str = "VOIDDB_TOKEN";
+
+/// A secret that clients must present before their statements are run.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token(String);
+
+impl Token {
+    /// The token in `text`, without the whitespace around it, or `None`
+    /// if that leaves nothing.
+    pub fn new(text: &str) -> Option<Token> {
+        let token = text.trim();
+        (!token.is_empty()).then(|| Token(token.to_string()))
+    }
+
+    /// Reads a token from the file at `path`. A file holding nothing but
+    /// whitespace is an `InvalidData` error.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Token> {
+        let text = std::fs::read_to_string(path)?;
+        Token::new(&text).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the token file is empty"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `given` is this token, taking as long to say so whichever
+    /// byte they first differ at, so that the time taken gives nothing
+    /// away but the length of `given`.
+    pub fn matches(&self, given: &str) -> bool {
+        let (expected, given) = (self.0.as_bytes(), given.as_bytes());
+        let mut difference = (expected.len() != given.len()) as u8;
+        for (i, &byte) in given.iter().enumerate() {
+            difference |= byte ^ expected.get(i).copied().unwrap_or(!byte);
+        }
+        difference == 0
+    }
+}
+
+/// Never shows the secret.
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Token(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn test_tokens() {
+        let token = Token::new("  s3cret\n").unwrap();
+        assert!(token.matches("s3cret"));
+        for wrong in ["", "s3cre", "s3crets", "S3cret", "s3cret\n", "x3cret"] {
+            assert!(!token.matches(wrong), "{:?}", wrong);
+        }
+        assert_eq!(Token::new(" \n"), None);
+        assert_eq!(format!("{:?}", token), "Token(..)");
+
+        let path = TempPath::new("token");
+        std::fs::write(&path, "\n").unwrap();
+        assert_eq!(Token::read(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        std::fs::write(&path, "s3cret\n").unwrap();
+        assert_eq!(Token::read(&path).unwrap(), token);
+    }
+}
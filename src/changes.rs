@@ -0,0 +1,129 @@
+//! Telling a listener which rows a database changed; see
+//! `Database::set_update_hook`.
+
+/// How a row changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// A row with a new id was added.
+    Insert,
+    /// The row with the id was overwritten, by an import that replaces
+    /// rows whose id is taken.
+    Update,
+}
+
+/// Called once per changed row with the kind of change, the table named as
+/// in `Database::tables`, and the row's id.
+pub type UpdateHook = Box<dyn FnMut(ChangeKind, &str, u64) + Send + Sync>;
+
+/// The update hook of a database, and the changes of the call in progress
+/// that it has not heard about yet.
+#[derive(Default)]
+pub(crate) struct Changes {
+    hook: Option<UpdateHook>,
+    pending: Vec<(ChangeKind, String, u64)>,
+}
+
+impl Changes {
+    pub(crate) fn set_hook(&mut self, hook: Option<UpdateHook>) {
+        self.hook = hook;
+        self.pending.clear();
+    }
+
+    /// Whether anyone is listening, so callers can skip working out what
+    /// changed.
+    pub(crate) fn wanted(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    /// Holds a change until `notify`.
+    pub(crate) fn record(&mut self, kind: ChangeKind, table: &str, id: u64) {
+        if self.hook.is_some() {
+            self.pending.push((kind, table.to_string(), id));
+        }
+    }
+
+    /// Forgets the changes held, because they were undone.
+    pub(crate) fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Hands the changes held to the hook, in the order they were made.
+    pub(crate) fn notify(&mut self) {
+        let Some(hook) = &mut self.hook else {
+            return;
+        };
+        for (kind, table, id) in self.pending.drain(..) {
+            hook(kind, &table, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::import::OnConflict;
+    use crate::row::Row;
+    use crate::testing::TempPath;
+    use std::sync::{Arc, Mutex};
+
+    type Seen = Arc<Mutex<Vec<(ChangeKind, String, u64)>>>;
+
+    fn listen(db: &mut Database) -> Seen {
+        let seen = Seen::default();
+        let sink = seen.clone();
+        db.set_update_hook(Box::new(move |kind, table, id| sink.lock().unwrap().push((kind, table.to_string(), id))));
+        seen
+    }
+
+    fn take(seen: &Seen) -> Vec<(ChangeKind, String, u64)> {
+        std::mem::take(&mut *seen.lock().unwrap())
+    }
+
+    fn change(kind: ChangeKind, table: &str, id: u64) -> (ChangeKind, String, u64) {
+        (kind, table.to_string(), id)
+    }
+
+    #[test]
+    fn test_changes_are_reported_per_row() {
+        use ChangeKind::*;
+        let path = TempPath::new("changes.db");
+        let mut db = Database::open_in_memory();
+        db.attach(&path, "archive").unwrap();
+        let seen = listen(&mut db);
+
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        db.execute("insert 1 again again@example.com").unwrap_err();
+        db.execute("select").unwrap();
+        db.query("insert 2 bob bob@example.com").unwrap();
+        assert_eq!(take(&seen), [change(Insert, "users", 1), change(Insert, "users", 2)]);
+
+        db.execute("insert into archive.users select from users").unwrap();
+        assert_eq!(take(&seen), [change(Insert, "archive.users", 1), change(Insert, "archive.users", 2)]);
+
+        let rows = [Row::new(2, "robert", "bob@example.com"), Row::new(3, "carol", "carol@example.com")];
+        db.insert_rows(rows, OnConflict::Replace).unwrap();
+        assert_eq!(take(&seen), [change(Update, "users", 2), change(Insert, "users", 3)]);
+
+        db.clear_update_hook();
+        db.execute("insert 4 dave dave@example.com").unwrap();
+        assert_eq!(take(&seen), []);
+    }
+
+    #[test]
+    fn test_undone_changes_are_not_reported() {
+        let mut db = Database::open_in_memory();
+        db.execute("insert 3 carol carol@example.com").unwrap();
+        let seen = listen(&mut db);
+
+        // The third row's id is taken, so the two before it are taken out.
+        let rows = [Row::new(1, "alice", "a@x"), Row::new(2, "bob", "b@x"), Row::new(3, "carol", "c@x")];
+        let summary = db.insert_rows(rows, OnConflict::Abort).unwrap();
+        assert!(summary.aborted);
+        db.execute("insert 2 bob b@x").unwrap();
+        db.execute("insert into users select from users").unwrap_err();
+
+        assert_eq!(take(&seen), [change(ChangeKind::Insert, "users", 2)]);
+        assert_eq!(db.select("select").unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,266 @@
+//! The other end of `VoidDB serve`: sends statements to a server and reads
+//! back what they did, for `VoidDB --connect`.
+//!
+//! Over the line protocol the client asks for JSON results, so rows come
+//! back with their types and are printed here in whatever mode the shell
+//! is in. Over the binary protocol they come back as serialized rows.
+
+use std::fmt;
+use std::io::{self, BufReader, Write};
+
+use crate::auth::Token;
+use crate::codec::{self, Decoder, Message};
+use crate::compiler::QueryResult;
+use crate::error::ErrorCode;
+use crate::import;
+use crate::json::{self, Json};
+use crate::net::{Address, Stream};
+use crate::protocol::{self, Response};
+
+/// A connection to a server.
+pub struct Client {
+    address: String,
+    reader: BufReader<Stream>,
+    writer: Stream,
+    /// What has arrived of the next frame, over the binary protocol; `None`
+    /// over the line protocol.
+    decoder: Option<Decoder>,
+}
+
+/// Why a statement sent to a server did not run.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server ran the statement and it failed.
+    Server { code: String, message: String },
+    /// The connection failed or was closed, or the server sent back
+    /// something that is not a response.
+    Connection(io::Error),
+}
+
+impl ClientError {
+    /// The code of the error as `DbError::code` has it: the server's, if
+    /// it is one this version knows, `SERVER_ERROR` if not, and `IO_ERROR`
+    /// for a lost connection.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClientError::Server { code, .. } => ErrorCode::parse(code).map_or("SERVER_ERROR", ErrorCode::as_str),
+            ClientError::Connection(_) => ErrorCode::IoError.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Server { message, .. } => f.write_str(message),
+            ClientError::Connection(err) => write!(f, "Lost the connection to the server: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Connection(err)
+    }
+}
+
+impl Client {
+    /// Connects to the server at `address`, as `host:port` or
+    /// `unix:PATH`, presenting `token` if given. A server that turns the
+    /// client away makes this a `PermissionDenied` error.
+    pub fn connect(address: &str, token: Option<&Token>) -> io::Result<Client> {
+        let mut client = Client::open(address, None)?;
+        let token = token.map(|token| format!("AUTH {}", token.as_str()));
+        for request in token.iter().map(String::as_str).chain([".mode json"]) {
+            check(client.request(request)?.error)?;
+        }
+        Ok(client)
+    }
+
+    /// As `connect`, speaking the binary protocol in `codec`. A server that
+    /// does not answer in kind makes this an `InvalidData` error.
+    pub fn connect_binary(address: &str, token: Option<&Token>) -> io::Result<Client> {
+        let mut client = Client::open(address, Some(Decoder::default()))?;
+        client.writer.write_all(&codec::MAGIC)?;
+        client.writer.flush()?;
+        codec::read_magic(&mut client.reader, &mut Vec::new())?;
+        if let Some(token) = token {
+            codec::write_message(&mut client.writer, &Message::Auth(token.as_str().to_string()))?;
+            client.writer.flush()?;
+            let error = match client.read_response()? {
+                (_, Some(ClientError::Server { code, message })) => Some((code, message)),
+                _ => None,
+            };
+            check(error)?;
+        }
+        Ok(client)
+    }
+
+    fn open(address: &str, decoder: Option<Decoder>) -> io::Result<Client> {
+        let writer = Stream::connect(&Address::parse(address))?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client { address: address.to_string(), reader, writer, decoder })
+    }
+
+    /// The address connected to, as given to `connect`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Runs one statement on the server.
+    pub fn execute(&mut self, sql: &str) -> Result<QueryResult, ClientError> {
+        if self.decoder.is_some() {
+            codec::write_message(&mut self.writer, &Message::Query(sql.to_string()))?;
+            self.writer.flush()?;
+            let (result, error) = self.read_response()?;
+            return error.map_or(Ok(result), Err);
+        }
+        let response = self.request(&protocol::encode_request(sql))?;
+        if let Some((code, message)) = response.error {
+            return Err(ClientError::Server { code, message });
+        }
+        if response.results.trim().is_empty() {
+            return Ok(QueryResult::Done);
+        }
+        let bad = |what: String| ClientError::Connection(io::Error::new(io::ErrorKind::InvalidData, format!("bad response from the server: {}", what)));
+        match json::parse(&response.results).map_err(bad)? {
+            Json::Array(rows) => Ok(QueryResult::Rows(rows.into_iter().map(import::json_row).collect::<Result<_, _>>().map_err(bad)?)),
+            Json::Object(fields) => match &fields[..] {
+                [(name, Json::Number(count))] if name == "rows_affected" => count.parse().map(QueryResult::RowsAffected).map_err(|_| bad(count.clone())),
+                _ => Err(bad(response.results)),
+            },
+            _ => Err(bad(response.results)),
+        }
+    }
+
+    /// Reads a binary response up to its `Done`: what the statements did,
+    /// with the rows of a select gathered from all their frames, and the
+    /// error the request failed with, if it did.
+    fn read_response(&mut self) -> io::Result<(QueryResult, Option<ClientError>)> {
+        let decoder = self.decoder.as_mut().expect("reading a binary response over the line protocol");
+        let (mut result, mut error) = (QueryResult::Done, None);
+        loop {
+            match codec::read_message(&mut self.reader, decoder)? {
+                Some(Message::Rows(rows)) => match &mut result {
+                    QueryResult::Rows(all) => all.extend(rows),
+                    _ => result = QueryResult::Rows(rows),
+                },
+                Some(Message::RowsAffected(count)) => result = QueryResult::RowsAffected(count as usize),
+                Some(Message::Error { code, message }) => error = Some(ClientError::Server { code, message }),
+                Some(Message::Done) => return Ok((result, error)),
+                Some(message) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad response from the server: {:?}", message))),
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection")),
+            }
+        }
+    }
+
+    fn request(&mut self, line: &str) -> io::Result<Response> {
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        protocol::read_response(&mut self.reader)
+    }
+}
+
+/// The error a server answered one of `connect`'s own requests with, if
+/// any.
+fn check(error: Option<(String, String)>) -> io::Result<()> {
+    match error {
+        Some((code, message)) if code == "AUTH_FAILED" => Err(io::Error::new(io::ErrorKind::PermissionDenied, message)),
+        Some((_, message)) => Err(io::Error::other(message)),
+        None => Ok(()),
+    }
+}
+
+impl Drop for Client {
+    /// Says goodbye, so the server ends the session at once.
+    fn drop(&mut self) {
+        let _ = match self.decoder {
+            Some(_) => codec::write_message(&mut self.writer, &Message::Quit),
+            None => writeln!(self.writer, ".quit"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::connection::Connection;
+    use crate::interrupt::Interrupt;
+    use crate::row::Row;
+    use crate::server::Session;
+
+    /// A client connected to a session on `connection`, in a thread of its
+    /// own, that hangs up after `requests` requests besides the client's
+    /// own `.mode`.
+    fn client(connection: &Connection, requests: usize) -> Client {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut session = Session::new(connection.clone());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            for _ in 0..=requests {
+                let mut line = String::new();
+                io::BufRead::read_line(&mut reader, &mut line).unwrap();
+                session.run(&mut line.as_bytes(), &mut stream, &Interrupt::default()).unwrap();
+            }
+        });
+        Client::connect(&address, None).unwrap()
+    }
+
+    #[test]
+    fn test_statements_come_back_as_results() {
+        let connection = Connection::open_in_memory();
+        let mut client = client(&connection, 4);
+        assert_eq!(client.execute("insert 1 'two\nlines' a@x 7").unwrap(), QueryResult::RowsAffected(1));
+        assert_eq!(client.execute("select").unwrap(), QueryResult::Rows(vec![Row::new(1, "two\nlines", "a@x").with_age(7)]));
+        let err = client.execute("insert 1 a a@x").unwrap_err();
+        assert_eq!((err.code(), err.to_string().as_str()), ("DUPLICATE_KEY", "Error: Duplicate key."));
+        assert_eq!(client.execute("create table users (id integer, username varchar(32), email varchar(255), age integer)").unwrap(), QueryResult::Done);
+
+        // The session has had all it will read, and hangs up.
+        let err = client.execute("select").unwrap_err();
+        assert_eq!(err.code(), "IO_ERROR");
+        assert!(err.to_string().starts_with("Lost the connection to the server: "), "{}", err);
+    }
+
+    #[test]
+    fn test_binary_protocol() {
+        let connection = Connection::open_in_memory();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut session = Session::new(connection.clone()).require_token(Token::new("s3cret").unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            session.run_binary(&mut reader, &mut &stream, &Interrupt::default())
+        });
+        let mut client = Client::connect_binary(&address, Some(&Token::new("s3cret").unwrap())).unwrap();
+        assert_eq!(client.execute("insert 1 'two\nlines' a@x 7").unwrap(), QueryResult::RowsAffected(1));
+        assert_eq!(client.execute("select").unwrap(), QueryResult::Rows(vec![Row::new(1, "two\nlines", "a@x").with_age(7)]));
+        assert_eq!(client.execute("select where id = 2").unwrap(), QueryResult::Rows(Vec::new()));
+        let err = client.execute("insert 1 a a@x").unwrap_err();
+        assert_eq!((err.code(), err.to_string().as_str()), ("DUPLICATE_KEY", "Error: Duplicate key."));
+        drop(client);
+        server.join().unwrap().unwrap();
+
+        // A server speaking only lines does not answer in kind.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"!UNRECOGNIZED_STATEMENT Unrecognized keyword.\n.\n");
+        });
+        assert_eq!(Client::connect_binary(&address, None).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_unknown_server_codes() {
+        let err = ClientError::Server { code: "OVERLOADED".to_string(), message: "Busy.".to_string() };
+        assert_eq!(err.code(), "SERVER_ERROR");
+    }
+}
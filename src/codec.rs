@@ -0,0 +1,603 @@
+//! The binary protocol `VoidDB serve` speaks besides the line protocol in
+//! `protocol`, for clients that would rather not parse text.
+//!
+//! A binary client opens the connection with `MAGIC`, whose first byte, a
+//! NUL, no line protocol request starts with; the server answers with
+//! `MAGIC` in turn. After that each message is a frame: its length as a
+//! little-endian `u32`, then an opcode byte and the payload, with the
+//! length counting both. A frame longer than the reader's limit is
+//! rejected from its length alone, before any of its payload is read.
+//!
+//! The client sends `Query` with statements as UTF-8, `Auth` with a token,
+//! or `Quit`. Every request but `Quit` gets one response: for each
+//! statement run, `Rows` or `RowsAffected` or nothing, then `Error` if one
+//! failed, then `Done`. The rows of a select come in one or more `Rows`
+//! frames, each holding the widths of its columns and then the rows
+//! serialized as a table stores them, so a client reads rows of the
+//! default widths with `Row::deserialize`.
+//!
+//! A leader replicating to a follower is a client too, which sends
+//! `Replicate` and then `Snapshot` and `Frame`s; see `replication`.
+//!
+//! ```text
+//! > 00 56 44 42 01                       MAGIC
+//! < 00 56 44 42 01
+//! > 07 00 00 00  01  73 65 6c 65 63 74   Query "select"
+//! < 09 00 00 00  10  20 00 ff 00 00 00 00 00
+//! <                                      Rows, default widths, none
+//! < 01 00 00 00  13                      Done
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::checksum::crc32;
+use crate::replication::Change;
+use crate::row::{Row, RowLayout, RowRef};
+
+/// What a binary client sends first, and the server sends back: a NUL,
+/// `VDB` and the protocol version.
+pub const MAGIC: [u8; 5] = *b"\0VDB\x01";
+
+/// The longest frame, opcode and payload, that is read unless a decoder is
+/// given a limit of its own.
+pub const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// How many rows a server puts in one `Rows` frame. Rows are at most a page
+/// long, so a full frame stays well under `MAX_FRAME_SIZE`.
+pub const ROWS_PER_FRAME: usize = 1024;
+
+/// Bytes in the length that starts a frame.
+const LENGTH_SIZE: usize = 4;
+
+// Opcodes. A client's are below 0x10, a server's from there on.
+const QUERY: u8 = 0x01;
+const AUTH: u8 = 0x02;
+const QUIT: u8 = 0x03;
+const REPLICATE: u8 = 0x04;
+const FRAME: u8 = 0x05;
+const SNAPSHOT: u8 = 0x06;
+const ROWS: u8 = 0x10;
+const ROWS_AFFECTED: u8 = 0x11;
+const ERROR: u8 = 0x12;
+const DONE: u8 = 0x13;
+const POSITION: u8 = 0x14;
+
+// The kinds of `Change` in a `Frame`.
+const WRITE: u8 = 0;
+const TRUNCATE: u8 = 1;
+
+/// One frame's worth of a conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Statements to run, separated by `;` if there are several.
+    Query(String),
+    /// The token a server that asks for one wants first.
+    Auth(String),
+    /// The client is done.
+    Quit,
+    /// A leader, which started at `epoch`, asks where the follower is.
+    Replicate { epoch: u64 },
+    /// The changes the leader made to its file in its `seq`th sync.
+    Frame { seq: u64, changes: Vec<Change> },
+    /// The leader's whole file, as of its `seq`th sync.
+    Snapshot { seq: u64, data: Vec<u8> },
+    /// Some of the rows a select read.
+    Rows(Vec<Row>),
+    /// How many rows a statement wrote.
+    RowsAffected(u64),
+    /// The request failed, with a code as listed on `DbError::code` or one
+    /// of the server's own.
+    Error { code: String, message: String },
+    /// The response is over.
+    Done,
+    /// The follower has applied everything up to frame `applied` of the
+    /// leader that started at `epoch`.
+    Position { epoch: u64, applied: u64 },
+}
+
+/// Why a frame could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// The frame's length is over the reader's limit.
+    FrameTooLarge { size: usize, max: usize },
+    /// A frame with no opcode.
+    EmptyFrame,
+    UnknownOpcode(u8),
+    /// The payload does not hold what the opcode says it does.
+    BadPayload { opcode: u8, reason: String },
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::FrameTooLarge { size, max } => write!(f, "a frame of {} bytes is over the limit of {}", size, max),
+            CodecError::EmptyFrame => f.write_str("a frame with no opcode"),
+            CodecError::UnknownOpcode(opcode) => write!(f, "unknown opcode 0x{:02x}", opcode),
+            CodecError::BadPayload { opcode, reason } => write!(f, "bad payload for opcode 0x{:02x}: {}", opcode, reason),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<CodecError> for io::Error {
+    fn from(err: CodecError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl Message {
+    /// The whole frame: length, opcode and payload.
+    ///
+    /// # Panics
+    ///
+    /// If a row in `Rows` is too long for any layout a table can have,
+    /// which no row read from a table is.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = vec![0; LENGTH_SIZE];
+        match self {
+            Message::Query(sql) => {
+                frame.push(QUERY);
+                frame.extend_from_slice(sql.as_bytes());
+            }
+            Message::Auth(token) => {
+                frame.push(AUTH);
+                frame.extend_from_slice(token.as_bytes());
+            }
+            Message::Quit => frame.push(QUIT),
+            Message::Replicate { epoch } => {
+                frame.push(REPLICATE);
+                frame.extend_from_slice(&epoch.to_le_bytes());
+            }
+            Message::Frame { seq, changes } => {
+                frame.push(FRAME);
+                let start = frame.len();
+                frame.extend_from_slice(&seq.to_le_bytes());
+                frame.extend_from_slice(&(changes.len() as u32).to_le_bytes());
+                for change in changes {
+                    match change {
+                        Change::Write { offset, data } => {
+                            frame.push(WRITE);
+                            frame.extend_from_slice(&offset.to_le_bytes());
+                            frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                            frame.extend_from_slice(data);
+                        }
+                        Change::Truncate(len) => {
+                            frame.push(TRUNCATE);
+                            frame.extend_from_slice(&len.to_le_bytes());
+                        }
+                    }
+                }
+                let checksum = crc32(&frame[start..]);
+                frame.extend_from_slice(&checksum.to_le_bytes());
+            }
+            Message::Snapshot { seq, data } => {
+                frame.push(SNAPSHOT);
+                let start = frame.len();
+                frame.extend_from_slice(&seq.to_le_bytes());
+                frame.extend_from_slice(data);
+                let checksum = crc32(&frame[start..]);
+                frame.extend_from_slice(&checksum.to_le_bytes());
+            }
+            Message::Rows(rows) => {
+                let layout = layout_for(rows);
+                frame.push(ROWS);
+                frame.extend_from_slice(&(layout.username_size() as u16).to_le_bytes());
+                frame.extend_from_slice(&(layout.email_size() as u16).to_le_bytes());
+                frame.extend_from_slice(&(rows.len() as u32).to_le_bytes());
+                for row in rows {
+                    let start = frame.len();
+                    frame.resize(start + layout.row_size(), 0);
+                    layout.serialize_into(row, &mut frame[start..]);
+                }
+            }
+            Message::RowsAffected(count) => {
+                frame.push(ROWS_AFFECTED);
+                frame.extend_from_slice(&count.to_le_bytes());
+            }
+            Message::Error { code, message } => {
+                // The code is short and has no spaces, so a length byte
+                // is all it needs; the message is the rest.
+                let code = &code.as_bytes()[..code.len().min(u8::MAX as usize)];
+                frame.push(ERROR);
+                frame.push(code.len() as u8);
+                frame.extend_from_slice(code);
+                frame.extend_from_slice(message.as_bytes());
+            }
+            Message::Done => frame.push(DONE),
+            Message::Position { epoch, applied } => {
+                frame.push(POSITION);
+                frame.extend_from_slice(&epoch.to_le_bytes());
+                frame.extend_from_slice(&applied.to_le_bytes());
+            }
+        }
+        let length = (frame.len() - LENGTH_SIZE) as u32;
+        frame[..LENGTH_SIZE].copy_from_slice(&length.to_le_bytes());
+        frame
+    }
+
+    /// The message in a frame's opcode and payload.
+    fn decode(opcode: u8, payload: &[u8]) -> Result<Message, CodecError> {
+        let bad = |reason: &str| CodecError::BadPayload { opcode, reason: reason.to_string() };
+        let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| bad("not UTF-8"));
+        match opcode {
+            QUERY => Ok(Message::Query(text(payload)?)),
+            AUTH => Ok(Message::Auth(text(payload)?)),
+            QUIT | DONE if !payload.is_empty() => Err(bad("expected no payload")),
+            QUIT => Ok(Message::Quit),
+            DONE => Ok(Message::Done),
+            ROWS => {
+                let Some((header, data)) = payload.split_first_chunk::<8>() else {
+                    return Err(bad("too short"));
+                };
+                let username_size = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let email_size = u16::from_le_bytes([header[2], header[3]]) as usize;
+                let count = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+                let layout = RowLayout::new(username_size, email_size).map_err(|err| bad(&err.to_string()))?;
+                if data.len() != count * layout.row_size() {
+                    return Err(bad(&format!("{} bytes for {} rows of {}", data.len(), count, layout.row_size())));
+                }
+                let rows = data.chunks_exact(layout.row_size()).map(|row| RowRef::with_layout(row, layout).and_then(|row| row.to_row()));
+                Ok(Message::Rows(rows.collect::<Result<_, _>>().map_err(|err| bad(&err.to_string()))?))
+            }
+            ROWS_AFFECTED => Ok(Message::RowsAffected(Fields(payload).u64().filter(|_| payload.len() == 8).ok_or_else(|| bad("expected 8 bytes"))?)),
+            REPLICATE => Ok(Message::Replicate { epoch: Fields(payload).u64().filter(|_| payload.len() == 8).ok_or_else(|| bad("expected 8 bytes"))? }),
+            POSITION => {
+                let mut fields = Fields(payload);
+                match (fields.u64(), fields.u64(), fields.0.is_empty()) {
+                    (Some(epoch), Some(applied), true) => Ok(Message::Position { epoch, applied }),
+                    _ => Err(bad("expected 16 bytes")),
+                }
+            }
+            FRAME | SNAPSHOT => {
+                // Both end with a checksum of the rest of the payload.
+                let Some((payload, checksum)) = payload.split_last_chunk::<4>() else {
+                    return Err(bad("too short"));
+                };
+                if crc32(payload) != u32::from_le_bytes(*checksum) {
+                    return Err(bad("checksum mismatch"));
+                }
+                let mut fields = Fields(payload);
+                let seq = fields.u64().ok_or_else(|| bad("too short"))?;
+                if opcode == SNAPSHOT {
+                    return Ok(Message::Snapshot { seq, data: fields.0.to_vec() });
+                }
+                let count = fields.u32().ok_or_else(|| bad("too short"))?;
+                let mut changes = Vec::new();
+                for _ in 0..count {
+                    let change = match fields.u8() {
+                        Some(WRITE) => fields.u64().zip(fields.u32().and_then(|len| fields.bytes(len as usize))).map(|(offset, data)| Change::Write { offset, data: data.to_vec() }),
+                        Some(TRUNCATE) => fields.u64().map(Change::Truncate),
+                        Some(_) => return Err(bad("unknown kind of change")),
+                        None => None,
+                    };
+                    changes.push(change.ok_or_else(|| bad("too short"))?);
+                }
+                if !fields.0.is_empty() {
+                    return Err(bad("bytes after the last change"));
+                }
+                Ok(Message::Frame { seq, changes })
+            }
+            ERROR => {
+                let Some((&length, rest)) = payload.split_first() else {
+                    return Err(bad("too short"));
+                };
+                if rest.len() < length as usize {
+                    return Err(bad("too short"));
+                }
+                let (code, message) = rest.split_at(length as usize);
+                Ok(Message::Error { code: text(code)?, message: text(message)? })
+            }
+            opcode => Err(CodecError::UnknownOpcode(opcode)),
+        }
+    }
+}
+
+/// Reads little-endian fields off the front of a payload.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|bytes| u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8).map(|bytes| u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+}
+
+/// The narrowest layout, no narrower than the default, that every row in
+/// `rows` fits.
+fn layout_for(rows: &[Row]) -> RowLayout {
+    let username_size = rows.iter().map(|row| row.username().len()).fold(RowLayout::DEFAULT.username_size(), usize::max);
+    let email_size = rows.iter().map(|row| row.email().len()).fold(RowLayout::DEFAULT.email_size(), usize::max);
+    RowLayout::new(username_size, email_size).expect("rows read from a table fit a table's layout")
+}
+
+/// Takes bytes as they arrive, however they are split up, and gives back
+/// whole messages.
+#[derive(Debug)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new(MAX_FRAME_SIZE)
+    }
+}
+
+impl Decoder {
+    /// A decoder that rejects frames longer than `max_frame_size`.
+    pub fn new(max_frame_size: usize) -> Self {
+        Decoder { buf: Vec::new(), max_frame_size }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Whether bytes of a frame not yet whole are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The next whole message, if one has arrived. A frame that is too
+    /// long is an error as soon as its length has.
+    pub fn decode(&mut self) -> Result<Option<Message>, CodecError> {
+        let Some(length) = self.buf.first_chunk::<LENGTH_SIZE>() else {
+            return Ok(None);
+        };
+        let length = u32::from_le_bytes(*length) as usize;
+        if length > self.max_frame_size {
+            return Err(CodecError::FrameTooLarge { size: length, max: self.max_frame_size });
+        }
+        if length == 0 {
+            return Err(CodecError::EmptyFrame);
+        }
+        if self.buf.len() < LENGTH_SIZE + length {
+            return Ok(None);
+        }
+        let message = Message::decode(self.buf[LENGTH_SIZE], &self.buf[LENGTH_SIZE + 1..LENGTH_SIZE + length]);
+        self.buf.drain(..LENGTH_SIZE + length);
+        message.map(Some)
+    }
+}
+
+/// Reads from `reader` until `decoder` has a whole message. `None` means
+/// the other end closed the connection between messages, while closing it
+/// partway through one is an `UnexpectedEof` error.
+///
+/// A read that fails, as one that times out does, leaves what had arrived
+/// in `decoder`, so calling this again carries on where it left off.
+pub fn read_message(reader: &mut dyn Read, decoder: &mut Decoder) -> io::Result<Option<Message>> {
+    let mut buf = [0; 8192];
+    loop {
+        if let Some(message) = decoder.decode()? {
+            return Ok(Some(message));
+        }
+        match reader.read(&mut buf)? {
+            0 if decoder.is_empty() => return Ok(None),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed partway through a frame")),
+            n => decoder.push(&buf[..n]),
+        }
+    }
+}
+
+/// Writes `message` as a frame.
+pub fn write_message(out: &mut dyn Write, message: &Message) -> io::Result<()> {
+    out.write_all(&message.encode())
+}
+
+/// Reads `MAGIC` into `greeting`, which holds whatever of it earlier calls
+/// read, and checks it. As with `read_message`, a read that fails can be
+/// carried on from.
+pub fn read_magic(reader: &mut dyn Read, greeting: &mut Vec<u8>) -> io::Result<()> {
+    while greeting.len() < MAGIC.len() {
+        let mut buf = [0; MAGIC.len()];
+        match reader.read(&mut buf[..MAGIC.len() - greeting.len()])? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the connection closed before the binary protocol was agreed")),
+            n => greeting.extend_from_slice(&buf[..n]),
+        }
+    }
+    if greeting[..] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the other end does not speak this version of the binary protocol"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_messages() -> Vec<Message> {
+        vec![
+            Message::Query("insert 1 'a b' a@x; select".to_string()),
+            Message::Query(String::new()),
+            Message::Auth("s3cret".to_string()),
+            Message::Quit,
+            Message::Rows(vec![Row::new(1, "alice", "alice@example.com").with_age(30), Row::new(2, "", "")]),
+            Message::Rows(Vec::new()),
+            Message::Rows(vec![Row::new(3, &"u".repeat(40), &"e".repeat(300))]),
+            Message::RowsAffected(u64::MAX),
+            Message::Error { code: "DUPLICATE_KEY".to_string(), message: "Error: Duplicate key.".to_string() },
+            Message::Error { code: String::new(), message: String::new() },
+            Message::Done,
+            Message::Replicate { epoch: 42 },
+            Message::Frame { seq: 7, changes: vec![Change::Write { offset: 4096, data: vec![1, 2, 3] }, Change::Truncate(8192), Change::Write { offset: 0, data: Vec::new() }] },
+            Message::Frame { seq: 8, changes: Vec::new() },
+            Message::Snapshot { seq: 9, data: vec![0; 4096] },
+            Message::Position { epoch: 42, applied: 9 },
+        ]
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for message in all_messages() {
+            let mut decoder = Decoder::default();
+            decoder.push(&message.encode());
+            assert_eq!(decoder.decode(), Ok(Some(message.clone())));
+            assert_eq!(decoder.decode(), Ok(None));
+            assert!(decoder.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_rows_are_serialized_rows() {
+        let row = Row::new(7, "bob", "bob@example.com").with_age(41);
+        let frame = Message::Rows(vec![row.clone()]).encode();
+        let header = 4 + 1 + 8;
+        assert_eq!(frame.len(), header + row.serialize().len());
+        assert_eq!(Row::deserialize(&frame[header..]).unwrap(), row);
+    }
+
+    #[test]
+    fn test_partial_reads() {
+        let messages = all_messages();
+        let stream: Vec<u8> = messages.iter().flat_map(Message::encode).collect();
+        for chunk in [1, 2, 3, 5, 7, 64, stream.len()] {
+            let mut decoder = Decoder::default();
+            let mut decoded = Vec::new();
+            for bytes in stream.chunks(chunk) {
+                decoder.push(bytes);
+                while let Some(message) = decoder.decode().unwrap() {
+                    decoded.push(message);
+                }
+            }
+            assert_eq!(decoded, messages, "in chunks of {}", chunk);
+            assert!(decoder.is_empty());
+        }
+
+        // A reader that hands over a byte at a time, and times out between.
+        struct Trickle<'a>(&'a [u8], bool);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 && !self.0.is_empty() {
+                    return Err(io::Error::from(io::ErrorKind::WouldBlock));
+                }
+                let n = self.0.len().min(buf.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let mut greeted = MAGIC.to_vec();
+        greeted.extend_from_slice(&stream);
+        let mut reader = Trickle(&greeted, false);
+        let mut greeting = Vec::new();
+        while let Err(err) = read_magic(&mut reader, &mut greeting) {
+            assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        }
+        let mut decoder = Decoder::default();
+        let mut decoded = Vec::new();
+        loop {
+            match read_message(&mut reader, &mut decoder) {
+                Ok(Some(message)) => decoded.push(message),
+                Ok(None) => break,
+                Err(err) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+            }
+        }
+        assert_eq!(decoded, messages);
+
+        // Hanging up partway through a frame is not a clean end.
+        let frame = Message::Done.encode();
+        let err = read_message(&mut &frame[..3], &mut Decoder::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_magic(&mut &MAGIC[..2], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = read_magic(&mut &b"\0VDB\x02"[..], &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_oversized_frames() {
+        let frame = Message::Query("x".repeat(99)).encode();
+        let mut decoder = Decoder::new(100);
+        decoder.push(&frame);
+        assert_eq!(decoder.decode().unwrap(), Some(Message::Query("x".repeat(99))));
+
+        // Rejected from the length alone, before the payload arrives.
+        let frame = Message::Query("x".repeat(100)).encode();
+        let mut decoder = Decoder::new(100);
+        decoder.push(&frame[..LENGTH_SIZE]);
+        assert_eq!(decoder.decode(), Err(CodecError::FrameTooLarge { size: 101, max: 100 }));
+        let mut decoder = Decoder::default();
+        decoder.push(&u32::MAX.to_le_bytes());
+        assert_eq!(decoder.decode(), Err(CodecError::FrameTooLarge { size: u32::MAX as usize, max: MAX_FRAME_SIZE }));
+        let err = read_message(&mut &u32::MAX.to_le_bytes()[..], &mut Decoder::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), format!("a frame of {} bytes is over the limit of {}", u32::MAX, MAX_FRAME_SIZE));
+    }
+
+    #[test]
+    fn test_bad_frames() {
+        let decode = |frame: &[u8]| {
+            let mut decoder = Decoder::default();
+            decoder.push(frame);
+            decoder.decode()
+        };
+        let bad = |opcode: u8| move |result: Result<Option<Message>, CodecError>| matches!(result, Err(CodecError::BadPayload { opcode: o, .. }) if o == opcode);
+        assert_eq!(decode(&[0, 0, 0, 0]), Err(CodecError::EmptyFrame));
+        assert_eq!(decode(&[1, 0, 0, 0, 0x7f]), Err(CodecError::UnknownOpcode(0x7f)));
+        assert!(bad(QUERY)(decode(&[2, 0, 0, 0, QUERY, 0xff])));
+        assert!(bad(DONE)(decode(&[2, 0, 0, 0, DONE, 0])));
+        assert!(bad(ROWS_AFFECTED)(decode(&[2, 0, 0, 0, ROWS_AFFECTED, 1])));
+        assert!(bad(ERROR)(decode(&[3, 0, 0, 0, ERROR, 5, b'X'])));
+        assert!(bad(ROWS)(decode(&[5, 0, 0, 0, ROWS, 32, 0, 255, 0])));
+        // One row promised, none sent; and widths no table can have.
+        assert!(bad(ROWS)(decode(&[9, 0, 0, 0, ROWS, 32, 0, 255, 0, 1, 0, 0, 0])));
+        assert!(bad(ROWS)(decode(&[9, 0, 0, 0, ROWS, 0, 0, 255, 0, 0, 0, 0, 0])));
+        // A row whose username claims more bytes than its column holds.
+        let mut frame = Message::Rows(vec![Row::new(1, "a", "a@x")]).encode();
+        frame[4 + 1 + 8 + 8] = 200;
+        assert!(bad(ROWS)(decode(&frame)));
+
+        // A frame of changes that was damaged on the way.
+        let mut frame = Message::Frame { seq: 1, changes: vec![Change::Write { offset: 0, data: vec![7; 16] }] }.encode();
+        frame[20] ^= 1;
+        assert_eq!(decode(&frame), Err(CodecError::BadPayload { opcode: FRAME, reason: "checksum mismatch".to_string() }));
+        let mut frame = Message::Snapshot { seq: 1, data: vec![7; 16] }.encode();
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+        assert!(bad(SNAPSHOT)(decode(&frame)));
+        assert!(bad(POSITION)(decode(&[9, 0, 0, 0, POSITION, 0, 0, 0, 0, 0, 0, 0, 0])));
+        assert!(bad(REPLICATE)(decode(&[1, 0, 0, 0, REPLICATE])));
+    }
+
+    #[test]
+    fn test_interleaved_errors() {
+        // A request of three statements whose second fails: what the first
+        // did, the error, and the end, and the next response after it.
+        let responses = [
+            Message::RowsAffected(1),
+            Message::Error { code: "DUPLICATE_KEY".to_string(), message: "Error: Duplicate key.".to_string() },
+            Message::Done,
+            Message::Rows(vec![Row::new(1, "a", "a@x")]),
+            Message::Rows(vec![Row::new(2, "b", "b@x")]),
+            Message::Error { code: "INTERRUPTED".to_string(), message: "Interrupted.".to_string() },
+            Message::Done,
+        ];
+        let mut stream: Vec<u8> = responses.iter().flat_map(Message::encode).collect();
+        // A stray byte after the last frame is the start of one more.
+        stream.push(9);
+        let mut reader = &stream[..];
+        let mut decoder = Decoder::default();
+        for expected in &responses {
+            assert_eq!(read_message(&mut reader, &mut decoder).unwrap().as_ref(), Some(expected));
+        }
+        assert_eq!(read_message(&mut reader, &mut decoder).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
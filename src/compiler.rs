@@ -1,221 +1,952 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::checksum::crc32;
+use crate::cursor::{Cursor, Rows};
+#[cfg(feature = "cli")]
+use crate::database::Database;
+use crate::database::Options;
+use crate::error::DbError;
+use crate::format::{Header, CHECKSUM_SIZE, FLAG_ROW_CHECKSUMS};
+#[cfg(feature = "cli")]
 use crate::input::InputBuffer;
+use crate::interrupt::Interrupt;
+use crate::pager::{Pager, MAX_PAGES, PAGE_SIZE};
+pub use crate::row::Row;
+use crate::row::{RowLayout, RowRef};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MetaCommandResult {
     Success,
     UnrecognizedCommand,
+    /// `.exit` ran: the caller should write out what it has buffered and
+    /// exit with this status.
+    Exit(i32),
 }
 
-pub enum PrepareResult {
-    Success,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrepareError {
     UnrecognizedStatement,
     SyntaxError,
 }
 
-pub enum ExecuteResult {
-    Success,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
     TableFull,
+    DuplicateKey,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatementType {
     Insert,
     Select,
+    /// `insert into .. select`: copies every row of one table into another.
+    InsertSelect,
+    /// `create table`: sets the column sizes of an empty table.
+    CreateTable,
 }
 
-const COLUMN_USERNAME_SIZE: usize = 32;
-const COLUMN_EMAIL_SIZE: usize = 255;
+/// The name of the one table each database holds.
+pub const TABLE_NAME: &str = "users";
 
-pub struct Row {
-    pub id: u32,
-    pub username: [u8; COLUMN_USERNAME_SIZE],
-    pub email: [u8; COLUMN_EMAIL_SIZE],
+pub struct Table {
+    pub(crate) num_rows: usize,
+    layout: RowLayout,
+    checksums: bool,
+    cell_size: usize,
+    // Holds a cell that straddles two pages while it is split apart.
+    scratch: Vec<u8>,
+    // Row number of each id, built by the first lookup so inserts can check
+    // for duplicates without scanning the table each time. A reader may
+    // build it, so it is set through `&self`.
+    ids: OnceLock<HashMap<u64, usize>>,
+    pager: Pager,
+    /// Whether rows have changed since the last flush.
+    dirty: bool,
+    /// Checked by scans before each row.
+    pub(crate) interrupt: Interrupt,
+    pub(crate) rows: RowCounters,
+}
+
+/// Rows a table has taken in, dropped and handed to readers since it was
+/// opened or the counters were last reset.
+#[derive(Debug, Default)]
+pub(crate) struct RowCounters {
+    pub(crate) inserted: AtomicU64,
+    pub(crate) deleted: AtomicU64,
+    pub(crate) read: AtomicU64,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Row {
-    fn new(id: u32, username: &str, email: &str) -> Self {
-        let usernamelen = if username.len() > COLUMN_USERNAME_SIZE { COLUMN_USERNAME_SIZE } else { username.len() };
-        let mut username_array = [0u8; COLUMN_USERNAME_SIZE];
-        for (i, byte) in username[0..usernamelen].as_bytes().iter().enumerate() {
-            username_array[i] = *byte;
+impl Table {
+    pub fn new() -> Self {
+        Self::with_layout(Pager::memory(), RowLayout::DEFAULT, false)
+    }
+
+    fn with_layout(pager: Pager, layout: RowLayout, checksums: bool) -> Self {
+        let cell_size = layout.row_size() + if checksums { CHECKSUM_SIZE } else { 0 };
+        Table {
+            num_rows: 0,
+            layout,
+            checksums,
+            cell_size,
+            scratch: vec![0; cell_size],
+            ids: OnceLock::new(),
+            pager,
+            dirty: false,
+            interrupt: Interrupt::default(),
+            rows: RowCounters::default(),
         }
+    }
+
+    /// An in-memory table with the given column widths.
+    pub fn memory(layout: RowLayout, checksums: bool) -> Self {
+        Self::with_layout(Pager::memory(), layout, checksums)
+    }
+
+    /// Opens the table stored at `path`. `layout` and `checksums` are only
+    /// used when the file is new; an existing file keeps the settings it was
+    /// created with.
+    pub fn open<P: AsRef<Path>>(path: P, layout: RowLayout, checksums: bool) -> Result<Self, DbError> {
+        Self::with_pager(Pager::open(path)?, layout, checksums)
+    }
 
-        let emaillen = if email.len() > COLUMN_EMAIL_SIZE { COLUMN_EMAIL_SIZE } else { email.len() };
-        let mut email_array = [0u8; COLUMN_EMAIL_SIZE];
-        for (i, byte) in email[0..emaillen].as_bytes().iter().enumerate() {
-            email_array[i] = *byte;
+    /// The table stored in the file `pager` has open, as for `open`.
+    /// A file whose first flush stopped before it wrote the header, which
+    /// comes last, has a zeroed page 0 and no rows yet.
+    pub(crate) fn with_pager(pager: Pager, layout: RowLayout, checksums: bool) -> Result<Self, DbError> {
+        if pager.num_pages() == 0 || pager.get_page(0)?.iter().all(|&byte| byte == 0) {
+            return Ok(Self::with_layout(pager, layout, checksums));
         }
 
-        Row {
-            id,
-            username: username_array,
-            email: email_array,
+        let header = Header::decode(&pager.get_page(0)?)?;
+        let layout = RowLayout::new(header.username_size as usize, header.email_size as usize)
+            .map_err(|err| DbError::Corrupt(format!("bad header: {}", err)))?;
+
+        let mut table = Self::with_layout(pager, layout, header.flags & FLAG_ROW_CHECKSUMS != 0);
+        table.num_rows = header.num_rows as usize;
+        Ok(table)
+    }
+
+    pub fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    /// A copy of the table as it is now, in memory, sharing its pages with
+    /// this one until either changes them.
+    pub(crate) fn snapshot(&self) -> Result<Table, DbError> {
+        let mut snapshot = Table::with_layout(self.pager.snapshot(self.used_pages())?, self.layout, self.checksums);
+        snapshot.num_rows = self.num_rows;
+        snapshot.interrupt = self.interrupt.clone();
+        Ok(snapshot)
+    }
+
+    pub(crate) fn pager_mut(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    /// Number of pages holding the header or rows, going by the row count.
+    /// The rest were left behind by rows that were dropped.
+    pub(crate) fn used_pages(&self) -> usize {
+        let pages = 1 + (self.num_rows * self.cell_size).div_ceil(PAGE_SIZE);
+        pages.min(self.pager.num_pages())
+    }
+
+    pub fn layout(&self) -> RowLayout {
+        self.layout
+    }
+
+    /// Whether each row is stored with a CRC-32 that is checked on read.
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+
+    pub(crate) fn max_rows(&self) -> usize {
+        // Page 0 holds the file header, rows are packed end to end from
+        // page 1 and may straddle a page boundary.
+        (MAX_PAGES - 1) * PAGE_SIZE / self.cell_size
+    }
+
+    /// The row number holding `id`, if any. The first call reads every row
+    /// to build the id index; later calls are lookups.
+    pub(crate) fn find_row(&self, id: u64) -> Result<Option<usize>, DbError> {
+        if self.ids.get().is_none() {
+            // Sized for a full table up front, so inserts never grow it.
+            let mut ids = HashMap::with_capacity(self.max_rows());
+            let mut cursor = Cursor::table_start(self);
+            let mut row_num = 0;
+            while !cursor.end_of_table() {
+                ids.entry(cursor.row_ref()?.id()).or_insert(row_num);
+                cursor.advance();
+                row_num += 1;
+            }
+            // A reader on another thread may have built it first, from the
+            // same rows.
+            let _ = self.ids.set(ids);
         }
+        Ok(self.ids.get().and_then(|ids| ids.get(&id).copied()))
     }
 
-    fn serialize(&self) -> Vec<u8> {
-        let mut result = vec![];
-        result.extend_from_slice(&self.id.to_le_bytes());
-        result.extend_from_slice(&self.username);
-        result.extend_from_slice(&self.email);
-        result
+    /// Records that a row with `id` was written just past the last row.
+    fn push_row(&mut self, id: u64) {
+        let num_rows = self.num_rows;
+        if let Some(ids) = self.ids.get_mut() {
+            ids.insert(id, num_rows);
+        }
+        self.num_rows += 1;
+        self.rows.inserted.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        let id = u32::from_le_bytes(data[0..4].try_into().unwrap());
-        let mut username = [0u8; COLUMN_USERNAME_SIZE];
-        username.copy_from_slice(&data[4..36]);
-        let mut email = [0u8; COLUMN_EMAIL_SIZE];
-        email.copy_from_slice(&data[36..291]);
+    /// Counts a row handed to a reader.
+    pub(crate) fn count_read(&self) {
+        self.rows.read.fetch_add(1, Ordering::Relaxed);
+    }
 
-        Row { id, username, email }
+    /// Forgets the rows from `num_rows` on. Their bytes stay in the pages
+    /// until new rows overwrite them.
+    pub(crate) fn truncate(&mut self, num_rows: usize) {
+        if let Some(ids) = self.ids.get_mut() {
+            ids.retain(|_, row_num| *row_num < num_rows);
+        }
+        if num_rows < self.num_rows {
+            self.rows.deleted.fetch_add((self.num_rows - num_rows) as u64, Ordering::Relaxed);
+            self.num_rows = num_rows;
+            self.dirty = true;
+        }
     }
 
-    fn print(&self) {
-        let username_str = String::from_utf8_lossy(&self.username);
-        let email_str = String::from_utf8_lossy(&self.email);
-        println!("({}, {}, {})", self.id, username_str, email_str);
+    /// The page and offset where the cell for `row_num` starts.
+    fn cell_position(&self, row_num: usize) -> (usize, usize) {
+        let byte = row_num * self.cell_size;
+        (1 + byte / PAGE_SIZE, byte % PAGE_SIZE)
     }
-}
 
-const PAGE_SIZE: usize = 4096;
-const TABLE_MAX_PAGES: usize = 100;
-const ROW_SIZE: usize = 291;
-const ROWS_PER_PAGE: usize = PAGE_SIZE / ROW_SIZE;
-const TABLE_MAX_ROWS: usize = ROWS_PER_PAGE * TABLE_MAX_PAGES;
+    pub(crate) fn page_for_row(&self, row_num: usize) -> usize {
+        self.cell_position(row_num).0
+    }
 
-pub struct Table {
-    num_rows: usize,
-    pages: [Option<Vec<u8>>; TABLE_MAX_PAGES],
-}
+    /// The bytes of the cell for `row_num`. A cell within one page is
+    /// borrowed in place; one that straddles two pages is copied together
+    /// into `scratch` first.
+    pub(crate) fn read_cell<'a>(&'a self, row_num: usize, scratch: &'a mut Vec<u8>) -> Result<&'a [u8], DbError> {
+        let (page_num, offset) = self.cell_position(row_num);
+        let cell_size = self.cell_size;
 
-impl Table {
-    pub fn new() -> Self {
-        Table {
-            num_rows: 0,
-            pages: {
-                const NONE: Option<Vec<u8>> = None;
-                let mut pages = [NONE; TABLE_MAX_PAGES];
-                for page in &mut pages {
-                    *page = None;
-                }
-                pages
-            },
+        if offset + cell_size <= PAGE_SIZE {
+            let page = self.pager.get_page(page_num)?.bytes();
+            return Ok(&page[offset..offset + cell_size]);
         }
+
+        let head = PAGE_SIZE - offset;
+        scratch.resize(cell_size, 0);
+        scratch[..head].copy_from_slice(&self.pager.get_page(page_num)?[offset..]);
+        scratch[head..].copy_from_slice(&self.pager.get_page(page_num + 1)?[..cell_size - head]);
+        Ok(scratch)
     }
 
-    fn row_slot(&mut self, row_num: usize) -> &mut [u8] {
-        let page_num = row_num / ROWS_PER_PAGE;
-        let page_offset = row_num % ROWS_PER_PAGE * ROW_SIZE;
+    /// Serializes `row` into the cell for `row_num`, followed by its
+    /// checksum when the table keeps them.
+    pub(crate) fn write_row(&mut self, row_num: usize, row: &Row) -> Result<(), DbError> {
+        let layout = self.layout;
+        let checksums = self.checksums;
+        layout.check(row)?;
 
-        if self.pages[page_num].is_none() {
-            self.pages[page_num] = Some(vec![0; PAGE_SIZE]);
+        self.write_cell(row_num, |cell| fill_cell(layout, checksums, row, cell))
+    }
+
+    /// Writes `rows` after the last row, taking their ids. The caller has
+    /// checked they fit the layout, that their ids are new and that the
+    /// table has room. Rows sharing a page are written to it together, so
+    /// it is fetched and marked once per run instead of once per row.
+    ///
+    /// If a page cannot be written, none of `rows` is kept.
+    pub(crate) fn append_rows(&mut self, rows: &[Row]) -> Result<(), DbError> {
+        let start = self.num_rows;
+        let written = self.append_runs(rows);
+        if written.is_err() {
+            self.truncate(start);
+        }
+        written
+    }
+
+    fn append_runs(&mut self, mut rows: &[Row]) -> Result<(), DbError> {
+        let (layout, checksums, cell_size) = (self.layout, self.checksums, self.cell_size);
+        while let Some(first) = rows.first() {
+            let (page_num, offset) = self.cell_position(self.num_rows);
+            let fits = (PAGE_SIZE - offset) / cell_size;
+            if fits == 0 {
+                self.write_row(self.num_rows, first)?;
+                self.push_row(first.id);
+                rows = &rows[1..];
+                continue;
+            }
+
+            let (run, rest) = rows.split_at(fits.min(rows.len()));
+            self.grow_to(page_num + 1)?;
+            self.dirty = true;
+            let mut page = self.pager.get_page_mut(page_num)?;
+            for (row, cell) in run.iter().zip(page[offset..].chunks_exact_mut(cell_size)) {
+                layout.check(row)?;
+                fill_cell(layout, checksums, row, cell);
+            }
+            for row in run {
+                self.push_row(row.id);
+            }
+            rows = rest;
+        }
+        Ok(())
+    }
+
+    /// Lets `fill` write the cell for `row_num`, splitting it across two
+    /// pages when it straddles a boundary.
+    pub(crate) fn write_cell(&mut self, row_num: usize, fill: impl FnOnce(&mut [u8])) -> Result<(), DbError> {
+        let (page_num, offset) = self.cell_position(row_num);
+        let cell_size = self.cell_size;
+        let straddles = offset + cell_size > PAGE_SIZE;
+        self.grow_to(page_num + 1 + straddles as usize)?;
+        self.dirty = true;
+
+        if !straddles {
+            fill(&mut self.pager.get_page_mut(page_num)?[offset..offset + cell_size]);
+            return Ok(());
         }
 
-        self.pages[page_num].as_mut().unwrap().get_mut(page_offset..page_offset + ROW_SIZE).unwrap()
+        fill(&mut self.scratch);
+        let head = PAGE_SIZE - offset;
+        self.pager.get_page_mut(page_num)?[offset..].copy_from_slice(&self.scratch[..head]);
+        self.pager.get_page_mut(page_num + 1)?[..cell_size - head].copy_from_slice(&self.scratch[head..]);
+        Ok(())
+    }
+
+    /// Allocates pages until the file holds `pages`. The table never frees
+    /// a page, so each new one comes at the end.
+    fn grow_to(&mut self, pages: usize) -> Result<(), DbError> {
+        while self.pager.num_pages() < pages {
+            self.pager.allocate_page()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the header and every cached page back to disk.
+    pub fn flush(&mut self) -> Result<(), DbError> {
+        let header = Header {
+            num_rows: self.num_rows as u32,
+            username_size: self.layout.username_size() as u16,
+            email_size: self.layout.email_size() as u16,
+            flags: if self.checksums { FLAG_ROW_CHECKSUMS } else { 0 },
+        };
+        self.grow_to(1)?;
+        header.encode(&mut self.pager.get_page_mut(0)?);
+
+        self.pager.flush()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Whether rows have changed since the table was last flushed.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
     }
 }
 
+/// Serializes `row` into `cell`, followed by its checksum when `checksums`
+/// is set.
+fn fill_cell(layout: RowLayout, checksums: bool, row: &Row, cell: &mut [u8]) {
+    let (payload, checksum) = cell.split_at_mut(layout.row_size());
+    layout.serialize_into(row, payload);
+    if checksums {
+        checksum.copy_from_slice(&crc32(payload).to_le_bytes());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Statement {
     pub typ: StatementType,
     pub row_to_insert: Option<Row>,
+    /// The attached database an insert writes to, if not the main one.
+    pub into: Option<String>,
+    /// The attached database a select reads from, if not the main one.
+    pub from: Option<String>,
+    /// The columns of a `create table`, which goes to `into`.
+    pub options: Option<Options>,
+    /// The one id a select keeps, from `where id = N`.
+    pub where_id: Option<u64>,
+}
+
+/// What a statement run by `Database::execute` gave back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    /// The rows a select read.
+    Rows(Vec<Row>),
+    /// How many rows an insert wrote.
+    RowsAffected(usize),
+    /// A statement that neither reads nor writes rows, such as `create
+    /// table`, succeeded.
+    Done,
 }
 
-pub fn do_meta_command(input_buffer: &mut InputBuffer) -> MetaCommandResult {
-    if input_buffer.buffer == ".exit" {
-        input_buffer.close();
-        std::process::exit(0);
-    } else {
-        MetaCommandResult::UnrecognizedCommand
+/// Handles `.exit` and `.exit CODE`. Leaves the exiting to the caller, which
+/// should flush the database first.
+#[cfg(feature = "cli")]
+pub fn do_meta_command(input_buffer: &mut InputBuffer, _db: &mut Database) -> MetaCommandResult {
+    let mut words = input_buffer.buffer.split_whitespace();
+    let code = match (words.next(), words.next(), words.next()) {
+        (Some(".exit"), None, None) => 0,
+        (Some(".exit"), Some(code), None) => match code.parse() {
+            Ok(code) => code,
+            Err(_) => return MetaCommandResult::UnrecognizedCommand,
+        },
+        _ => return MetaCommandResult::UnrecognizedCommand,
+    };
+    input_buffer.close();
+    MetaCommandResult::Exit(code)
+}
+
+#[cfg(feature = "cli")]
+pub fn prepare_statement(input_buffer: &InputBuffer) -> Result<Statement, PrepareError> {
+    prepare(&input_buffer.buffer)
+}
+
+/// Parses one statement. A trailing `;` is allowed. Insert values may be
+/// single quoted to hold spaces; see `quote`.
+///
+/// The table may be named, as `users` or `SCHEMA.users` for a database
+/// attached as SCHEMA: `insert into archive.users 1 a a@x`, `select from
+/// archive.users`, and `insert into users select from archive.users` to copy
+/// rows between databases. A select keeps only the row with one id given
+/// `where id = N`: `select from users where id = 3`.
+pub fn prepare(sql: &str) -> Result<Statement, PrepareError> {
+    let sql = sql.strip_suffix(';').unwrap_or(sql).trim_end();
+    let mut args = Tokens { rest: sql };
+    match args.next().transpose()?.as_deref() {
+        Some("insert") => {}
+        Some("create") => return prepare_create_table(args),
+        Some("select") => {
+            let (from, where_id) = prepare_from(&mut args)?;
+            return Ok(Statement { typ: StatementType::Select, row_to_insert: None, into: None, from, options: None, where_id });
+        }
+        _ => return Err(PrepareError::UnrecognizedStatement),
+    }
+
+    let mut into = None;
+    let mut next = args.next().transpose()?;
+    if next.as_deref() == Some("into") {
+        into = prepare_table(args.next().transpose()?.as_deref())?;
+        next = args.next().transpose()?;
+        if next.as_deref() == Some("select") {
+            let (from, where_id) = prepare_from(&mut args)?;
+            return Ok(Statement { typ: StatementType::InsertSelect, row_to_insert: None, into, from, options: None, where_id });
+        }
     }
+
+    let id = match next.and_then(|s| s.parse().ok()) {
+        Some(id) => id,
+        None => return Err(PrepareError::SyntaxError),
+    };
+
+    let username = match args.next().transpose()? {
+        Some(username) => username,
+        None => return Err(PrepareError::SyntaxError),
+    };
+
+    let email = match args.next().transpose()? {
+        Some(email) => email,
+        None => return Err(PrepareError::SyntaxError),
+    };
+
+    let age = match args.next().transpose()? {
+        Some(age) => match age.parse() {
+            Ok(age) => age,
+            Err(_) => return Err(PrepareError::SyntaxError),
+        },
+        None => 0,
+    };
+
+    if args.next().is_some() {
+        return Err(PrepareError::SyntaxError);
+    }
+
+    let row = Row::new(id, &username, &email).with_age(age);
+
+    Ok(Statement { typ: StatementType::Insert , row_to_insert: Some(row), into, from: None, options: None, where_id: None })
 }
 
-pub fn prepare_statement(input_buffer: &InputBuffer) -> Result<Statement, PrepareResult> {
-    if input_buffer.buffer.starts_with("insert") {
-        let mut args = input_buffer.buffer.split_whitespace();
-        args.next(); // skip insert
-        
-        let id = match args.next().and_then(|s| s.parse().ok()) {
-            Some(id) => id,
-            None => return Err(PrepareResult::SyntaxError),
-        };
-        
-        let username = match args.next() {
-            Some(username) => username,
-            None => return Err(PrepareResult::SyntaxError),
-        };
-        
-        let email = match args.next() {
-            Some(email) => email,
-            None => return Err(PrepareResult::SyntaxError),
-        };
+/// The rest of `create table TABLE (id integer primary key, username
+/// varchar(N), email varchar(N), age integer) [with checksums]`, as written
+/// by `create_table_sql`. The columns are always these four; only the
+/// sizes of the text columns, and whether rows have checksums, vary.
+fn prepare_create_table(mut args: Tokens<'_>) -> Result<Statement, PrepareError> {
+    if args.next().transpose()?.as_deref() != Some("table") {
+        return Err(PrepareError::SyntaxError);
+    }
+    let into = prepare_table(args.next().transpose()?.as_deref())?;
+    let rest = args.rest.trim();
+    let (columns, rest) = rest.strip_prefix('(').and_then(|rest| rest.rsplit_once(')')).ok_or(PrepareError::SyntaxError)?;
+    let row_checksums = match rest.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => false,
+        ["with", "checksums"] => true,
+        _ => return Err(PrepareError::SyntaxError),
+    };
+    let varchar = |ty: &str| ty.strip_prefix("varchar(")?.strip_suffix(')')?.parse().ok();
+    let columns: Vec<Vec<&str>> = columns.split(',').map(|column| column.split_whitespace().collect()).collect();
+    let (username_size, email_size) = match &columns[..] {
+        [id, username, email, age]
+            if matches!(id[..], ["id", "integer"] | ["id", "integer", "primary", "key"]) && age[..] == ["age", "integer"] =>
+        {
+            match (&username[..], &email[..]) {
+                (["username", username], ["email", email]) => (varchar(username), varchar(email)),
+                _ => return Err(PrepareError::SyntaxError),
+            }
+        }
+        _ => return Err(PrepareError::SyntaxError),
+    };
+    let (Some(username_size), Some(email_size)) = (username_size, email_size) else {
+        return Err(PrepareError::SyntaxError);
+    };
+    let options = Options { username_size, email_size, row_checksums, ..Options::default() };
+    Ok(Statement { typ: StatementType::CreateTable, row_to_insert: None, into, from: None, options: Some(options), where_id: None })
+}
+
+/// The table of the database attached as `schema`, or of the main one,
+/// named as statements name it.
+pub(crate) fn table_name(schema: Option<&str>) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", schema, TABLE_NAME),
+        None => TABLE_NAME.to_string(),
+    }
+}
 
-        if args.next().is_some() {
-            return Err(PrepareResult::SyntaxError);
+/// The `create table` statement that gives a table the column sizes in
+/// `options`; `schema` names the database it is attached as, if any.
+pub fn create_table_sql(schema: Option<&str>, options: &Options) -> String {
+    format!(
+        "create table {} (id integer primary key, username varchar({}), email varchar({}), age integer){};",
+        table_name(schema),
+        options.username_size,
+        options.email_size,
+        if options.row_checksums { " with checksums" } else { "" }
+    )
+}
+
+/// The rest of a select: `from` and a table, then `where id = N`, each
+/// optional.
+fn prepare_from(args: &mut Tokens<'_>) -> Result<(Option<String>, Option<u64>), PrepareError> {
+    let mut next = args.next().transpose()?;
+    let mut from = None;
+    if next.as_deref() == Some("from") {
+        from = prepare_table(args.next().transpose()?.as_deref())?;
+        next = args.next().transpose()?;
+    }
+    let where_id = match next.as_deref() {
+        None => None,
+        Some("where") => {
+            let words: Vec<_> = args.by_ref().take(3).collect::<Result<_, _>>()?;
+            match &words[..] {
+                [column, eq, id] if column == "id" && eq == "=" => Some(id.parse().map_err(|_| PrepareError::SyntaxError)?),
+                _ => return Err(PrepareError::SyntaxError),
+            }
         }
+        Some(_) => return Err(PrepareError::SyntaxError),
+    };
+    match args.next() {
+        None => Ok((from, where_id)),
+        Some(_) => Err(PrepareError::SyntaxError),
+    }
+}
 
-        let row = Row::new(id, username, email);
- 
-        Ok(Statement { typ: StatementType::Insert , row_to_insert: Some(row)})
-    } else if input_buffer.buffer == "select" {
-        Ok(Statement { typ: StatementType::Select, row_to_insert: None })
-    } else {
-        Err(PrepareResult::UnrecognizedStatement)
+/// The database named by a table, `users` or `SCHEMA.users`. `main` is
+/// the database the statement runs on, as is a table with no schema.
+pub(crate) fn prepare_table(table: Option<&str>) -> Result<Option<String>, PrepareError> {
+    let (schema, name) = match table.map(|table| table.split_once('.')) {
+        Some(Some((schema, name))) => (Some(schema), name),
+        Some(None) => (None, table.unwrap_or_default()),
+        None => return Err(PrepareError::SyntaxError),
+    };
+    if name != TABLE_NAME || schema == Some("") {
+        return Err(PrepareError::SyntaxError);
+    }
+    Ok(schema.filter(|&schema| schema != "main").map(str::to_string))
+}
+
+/// Quotes `value` so that `prepare` reads it back unchanged, whatever it
+/// contains: `'` is doubled, and backslash, newline, carriage return, tab
+/// and NUL are written as `\\`, `\n`, `\r`, `\t` and `\0`, so a
+/// statement always stays on one line.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        match c {
+            '\'' => quoted.push_str("''"),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            c => quoted.push(c),
+        }
     }
+    quoted.push('\'');
+    quoted
+}
+
+/// Splits `sql` at each `;` outside a quoted value, for running several
+/// statements given on one line. A `--` starting a word outside a quoted
+/// value comments out the rest of its line, ending the statement before
+/// it. Blank statements are dropped and the rest trimmed.
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    split(sql).0
 }
 
-fn execute_insert(statement: &Statement, table: &mut Table) -> ExecuteResult {
-    if table.num_rows >= TABLE_MAX_ROWS {
-        return ExecuteResult::TableFull;
+/// Whether `sql` closes every quoted value it opens. A statement typed
+/// over several lines is not complete until the line closing its value.
+pub fn is_complete(sql: &str) -> bool {
+    !split(sql).1
+}
+
+/// The statements of `sql`, as `split_statements` gives them, and whether
+/// it ends inside a quoted value.
+fn split(sql: &str) -> (Vec<&str>, bool) {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    // Quotes only open a value at the start of a word, as in `Tokens`.
+    let mut word_start = true;
+    let mut chars = sql.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if quoted {
+            match c {
+                '\'' | '\\' if chars.peek().map(|&(_, next)| next) == Some(c) => {
+                    chars.next();
+                }
+                '\'' => quoted = false,
+                _ => {}
+            }
+            word_start = false;
+            continue;
+        }
+        match c {
+            '\'' if word_start => quoted = true,
+            '-' if word_start && chars.peek().map(|&(_, next)| next) == Some('-') => {
+                statements.push(&sql[start..i]);
+                let end = sql[i..].find('\n').map_or(sql.len(), |end| i + end);
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+                start = end;
+            }
+            ';' => {
+                statements.push(&sql[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        word_start = c.is_whitespace() || c == ';';
+    }
+    statements.push(&sql[start..]);
+    (statements.into_iter().map(str::trim).filter(|statement| !statement.is_empty()).collect(), quoted)
+}
+
+/// The whitespace separated words of a statement. A word starting with `'`
+/// runs to the matching quote and is unescaped as described on `quote`;
+/// an unknown escape is kept as written.
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Cow<'a, str>, PrepareError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_start();
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let Some(quoted) = self.rest.strip_prefix('\'') else {
+            let end = self.rest.find(char::is_whitespace).unwrap_or(self.rest.len());
+            let (word, rest) = self.rest.split_at(end);
+            self.rest = rest;
+            return Some(Ok(Cow::Borrowed(word)));
+        };
+
+        let mut word = String::new();
+        let mut chars = quoted.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\'' if chars.peek().map(|&(_, c)| c) == Some('\'') => {
+                    chars.next();
+                    word.push('\'');
+                }
+                '\'' => {
+                    self.rest = &quoted[i + 1..];
+                    // The closing quote must end the word.
+                    if self.rest.starts_with(|c: char| !c.is_whitespace()) {
+                        return Some(Err(PrepareError::SyntaxError));
+                    }
+                    return Some(Ok(Cow::Owned(word)));
+                }
+                '\\' => match chars.peek().map(|&(_, c)| c) {
+                    Some(escape @ ('\\' | 'n' | 'r' | 't' | '0')) => {
+                        chars.next();
+                        word.push(match escape {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            '0' => '\0',
+                            _ => '\\',
+                        });
+                    }
+                    _ => word.push('\\'),
+                },
+                c => word.push(c),
+            }
+        }
+        self.rest = "";
+        Some(Err(PrepareError::SyntaxError))
     }
+}
 
+fn execute_insert(statement: &Statement, table: &mut Table) -> Result<QueryResult, DbError> {
     match &statement.row_to_insert {
-        Some(row) => {
-            let slot = table.row_slot(table.num_rows);
-            slot.copy_from_slice(&row.serialize());
-            table.num_rows += 1;
-            ExecuteResult::Success
+        Some(row) => insert_row(row, table).map(|()| QueryResult::RowsAffected(1)),
+        None => Err(DbError::Execute(ExecuteError::TableFull))
+    }
+}
+
+/// Appends `row`, failing if its id is taken, a value does not fit or the
+/// table is full. Bulk loads call this directly to skip building a
+/// statement for every row.
+pub(crate) fn insert_row(row: &Row, table: &mut Table) -> Result<(), DbError> {
+    if table.num_rows >= table.max_rows() {
+        return Err(DbError::Execute(ExecuteError::TableFull));
+    }
+    if table.find_row(row.id)?.is_some() {
+        return Err(DbError::Execute(ExecuteError::DuplicateKey));
+    }
+    table.write_row(table.num_rows, row)?;
+    table.push_row(row.id);
+    Ok(())
+}
+
+/// Inserts `row`, or overwrites the row with the same id if there is one.
+/// Returns whether a row was replaced.
+pub(crate) fn upsert_row(row: &Row, table: &mut Table) -> Result<bool, DbError> {
+    let existing = table.find_row(row.id)?;
+    if existing.is_none() && table.num_rows >= table.max_rows() {
+        return Err(DbError::Execute(ExecuteError::TableFull));
+    }
+
+    table.write_row(existing.unwrap_or(table.num_rows), row)?;
+    if existing.is_none() {
+        table.push_row(row.id);
+    }
+    Ok(existing.is_some())
+}
+
+fn execute_select(statement: &Statement, table: &Table) -> Result<QueryResult, DbError> {
+    Ok(QueryResult::Rows(read_rows(table, statement.where_id)?))
+}
+
+/// The rows of `table` a select reads: every one in order, or only the row
+/// with `where_id`.
+pub(crate) fn read_rows(table: &Table, where_id: Option<u64>) -> Result<Vec<Row>, DbError> {
+    select_rows(table, where_id)?.collect()
+}
+
+fn select_rows(table: &Table, where_id: Option<u64>) -> Result<Rows<'_>, DbError> {
+    match where_id {
+        Some(id) => Ok(Rows::single(Cursor::table_find(table, id)?)),
+        None => Ok(Rows::new(Cursor::table_start(table))),
+    }
+}
+
+/// The rows of a select, read lazily through a shared reference to the
+/// table. Fails with `DbError::ReadOnly` for any other statement.
+pub fn select_statement<'a>(statement: &Statement, table: &'a Table) -> Result<Rows<'a>, DbError> {
+    match statement.typ {
+        StatementType::Select => select_rows(table, statement.where_id),
+        _ => Err(DbError::ReadOnly),
+    }
+}
+
+/// Like `execute_statement`, but a select hands back its rows lazily instead
+/// of collecting them. Other statements run to completion and yield no rows.
+pub fn query_statement<'a>(statement: &Statement, table: &'a mut Table) -> Result<Rows<'a>, DbError> {
+    match statement.typ {
+        StatementType::Select => select_rows(table, statement.where_id),
+        _ => match execute_statement(statement, table)? {
+            QueryResult::RowsAffected(count) => Ok(Rows::affected(statement.typ, count)),
+            QueryResult::Rows(_) | QueryResult::Done => Ok(Rows::affected(statement.typ, 0)),
         },
-        None => ExecuteResult::TableFull
     }
 }
 
-fn execute_select(_statement: &Statement, table: &mut Table) -> ExecuteResult {
-    for i in 0..table.num_rows {
-        let slot = table.row_slot(i);
-        let row = Row::deserialize(slot);
-        row.print();
+/// Walks the rows of a select, handing each one to `f` until the table ends
+/// or `f` breaks. The value `f` breaks with is returned, so a callback can
+/// stop the scan with an error of its own. Fails with `DbError::Interrupted`
+/// if the table's interrupt is set part way.
+pub fn scan_statement<B>(
+    statement: &Statement,
+    table: &mut Table,
+    mut f: impl FnMut(RowRef<'_>) -> ControlFlow<B>,
+) -> Result<Option<B>, DbError> {
+    if !matches!(statement.typ, StatementType::Select) {
+        return execute_statement(statement, table).map(|_| None);
+    }
+
+    if let Some(id) = statement.where_id {
+        let mut cursor = Cursor::table_find(table, id)?;
+        if cursor.end_of_table() {
+            return Ok(None);
+        }
+        let row = cursor.row_ref()?;
+        table.count_read();
+        return Ok(f(row).break_value());
+    }
+
+    let mut cursor = Cursor::table_start(table);
+    while !cursor.end_of_table() {
+        if cursor.interrupted() {
+            return Err(DbError::Interrupted);
+        }
+        let row = cursor.row_ref()?;
+        table.count_read();
+        if let ControlFlow::Break(value) = f(row) {
+            return Ok(Some(value));
+        }
+        cursor.advance();
     }
-    ExecuteResult::Success
+    Ok(None)
 }
 
-pub fn execute_statement(statement: &Statement, table: &mut Table) -> ExecuteResult {
+/// Runs `statement` on `table`, whatever database it names; a copy made by
+/// `insert into .. select` reads and writes the same table.
+pub fn execute_statement(statement: &Statement, table: &mut Table) -> Result<QueryResult, DbError> {
     match statement.typ {
         StatementType::Insert => execute_insert(statement, table),
         StatementType::Select => execute_select(statement, table),
+        StatementType::InsertSelect => {
+            let rows = read_rows(table, statement.where_id)?;
+            insert_all(&rows, table).map(QueryResult::RowsAffected)
+        }
+        StatementType::CreateTable => execute_create_table(statement, table),
     }
 }
 
+/// Gives an empty table the columns of a `create table`. A table with rows
+/// keeps its columns, so then the statement only succeeds if they match.
+fn execute_create_table(statement: &Statement, table: &mut Table) -> Result<QueryResult, DbError> {
+    let Some(options) = &statement.options else {
+        return Err(DbError::Prepare(PrepareError::SyntaxError));
+    };
+    let layout = RowLayout::new(options.username_size, options.email_size)?;
+    if layout == table.layout && options.row_checksums == table.checksums {
+        return Ok(QueryResult::Done);
+    }
+    if table.num_rows > 0 {
+        return Err(DbError::TableExists(TABLE_NAME.to_string()));
+    }
+
+    let pager = std::mem::replace(&mut table.pager, Pager::memory());
+    let interrupt = table.interrupt.clone();
+    let rows = std::mem::take(&mut table.rows);
+    *table = Table::with_layout(pager, layout, options.row_checksums);
+    table.interrupt = interrupt;
+    table.rows = rows;
+    table.dirty = true;
+    Ok(QueryResult::Done)
+}
+
+/// Inserts every row of `rows`, or none of them: if one cannot be stored,
+/// the rows inserted before it are taken out again.
+pub(crate) fn insert_all(rows: &[Row], table: &mut Table) -> Result<usize, DbError> {
+    let num_rows = table.num_rows;
+    for row in rows {
+        if let Err(err) = insert_row(row, table) {
+            table.truncate(num_rows);
+            return Err(err);
+        }
+    }
+    Ok(rows.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Selects read through shared references all the way down to the
+    /// page cache, so this only compiles while that stays so.
+    #[test]
+    fn test_selects_need_only_shared_references() {
+        let mut table = Table::new();
+        let statement = prepare("insert 1 alice alice@example.com").unwrap();
+        execute_statement(&statement, &mut table).unwrap();
+
+        let table: &Table = &table;
+        let select = prepare("select").unwrap();
+        assert!(matches!(execute_select(&select, table), Ok(QueryResult::Rows(rows)) if rows.len() == 1));
+        assert_eq!(select_statement(&select, table).unwrap().count(), 1);
+        let mut cursor = Cursor::table_find(table, 1).unwrap();
+        assert_eq!(cursor.row_ref().unwrap().id(), 1);
+        assert!(table.pager().get_page(1).unwrap().iter().any(|&byte| byte != 0));
+        // A page that was never allocated is an error, not a new page.
+        assert!(matches!(table.pager().get_page(2), Err(DbError::Corrupt(_))));
+    }
+
     #[test]
+    #[cfg(feature = "cli")]
+    fn test_do_meta_command_exit() {
+        let mut db = Database::open_in_memory();
+        let mut input_buffer = InputBuffer::from_stdin();
+        for (line, expected) in [
+            (".exit", MetaCommandResult::Exit(0)),
+            (".exit 2", MetaCommandResult::Exit(2)),
+            (".exit two", MetaCommandResult::UnrecognizedCommand),
+            (".quit", MetaCommandResult::UnrecognizedCommand),
+        ] {
+            input_buffer.buffer = line.to_string();
+            assert_eq!(do_meta_command(&mut input_buffer, &mut db), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
     fn test_prepare_select() {
-        let mut input_buffer = InputBuffer::new();
+        let mut input_buffer = InputBuffer::from_stdin();
         input_buffer.buffer = "select".to_string();
         
         let exec_status = prepare_statement(&input_buffer);
 
-        assert!(matches!(exec_status, Ok(Statement { typ: StatementType::Select, row_to_insert: None })));
+        assert_eq!(exec_status, Ok(Statement { typ: StatementType::Select, row_to_insert: None, into: None, from: None, options: None, where_id: None }));
     }
 
     #[test]
+    #[cfg(feature = "cli")]
     fn test_prepare_insert() {
-        let mut input_buffer = InputBuffer::new();
+        let mut input_buffer = InputBuffer::from_stdin();
         input_buffer.buffer = "insert 1 username email@email.com".to_string();
         
         let exec_status = prepare_statement(&input_buffer);
 
-        assert!(matches!(exec_status, Ok(Statement { typ: StatementType::Insert, row_to_insert: Some(_) })));
+        assert_eq!(
+            exec_status,
+            Ok(Statement {
+                typ: StatementType::Insert,
+                row_to_insert: Some(Row::new(1, "username", "email@email.com")),
+                into: None,
+                from: None,
+                options: None,
+                where_id: None,
+            })
+        );
     }
 
 
@@ -224,9 +955,263 @@ mod tests {
         let mut table = Table::new();
         let row = Row::new(1, "username", "email@email.com");
  
-        let statement = Statement { typ: StatementType::Insert , row_to_insert: Some(row)};
+        let statement = Statement { typ: StatementType::Insert , row_to_insert: Some(row), into: None, from: None, options: None, where_id: None };
         let exec_status = execute_statement(&statement, &mut table);
 
-        assert!(matches!(exec_status, ExecuteResult::Success));
+        assert_eq!(exec_status.unwrap(), QueryResult::RowsAffected(1));
     } 
+
+    #[test]
+    fn test_insert_does_not_allocate() {
+        let mut table = Table::new();
+        let first = prepare("insert 1 username email@email.com").unwrap();
+        execute_statement(&first, &mut table).unwrap();
+
+        let statements: Vec<Statement> = (2..10)
+            .map(|i| prepare(&format!("insert {} username email@email.com", i)).unwrap())
+            .collect();
+
+        let (_, allocations) = crate::alloc_counter::allocations_during(|| {
+            for statement in &statements {
+                execute_statement(statement, &mut table).unwrap();
+            }
+        });
+        assert_eq!(allocations, 0);
+    }
+
+    #[test]
+    fn test_prepare_insert_with_age() {
+        let statement = prepare("insert 1 alice alice@x.com 34").unwrap();
+        assert_eq!(statement.row_to_insert.unwrap().age, 34);
+
+        let statement = prepare("insert 1 alice alice@x.com").unwrap();
+        assert_eq!(statement.row_to_insert.unwrap().age, 0);
+
+        assert_eq!(prepare("insert 1 alice alice@x.com 65536"), Err(PrepareError::SyntaxError));
+        assert_eq!(prepare("insert 1 alice alice@x.com -1"), Err(PrepareError::SyntaxError));
+        assert_eq!(prepare("insert 1 alice alice@x.com 34 5"), Err(PrepareError::SyntaxError));
+    }
+
+    #[test]
+    fn test_rows_pack_across_pages() {
+        for (username_size, email_size) in [(32, 255), (1, 1), (100, 300), (255, 3800)] {
+            let layout = RowLayout::new(username_size, email_size).unwrap();
+            let mut table = Table::memory(layout, false);
+            let row_size = layout.row_size();
+
+            // Everything but the header page holds rows; at most the tail
+            // of the last page, less than one row, is unused.
+            let data_bytes = (MAX_PAGES - 1) * PAGE_SIZE;
+            assert!(data_bytes - table.max_rows() * row_size < row_size);
+
+            let rows: Vec<Row> = (0..table.max_rows() as u64)
+                .map(|i| Row::new(i, &"u".repeat(username_size), &"e".repeat(email_size)))
+                .collect();
+            for row in &rows {
+                let statement = Statement { typ: StatementType::Insert, row_to_insert: Some(row.clone()), into: None, from: None, options: None, where_id: None };
+                execute_statement(&statement, &mut table).unwrap();
+            }
+            let full = Statement { typ: StatementType::Insert, row_to_insert: Some(rows[0].clone()), into: None, from: None, options: None, where_id: None };
+            assert!(matches!(
+                execute_statement(&full, &mut table),
+                Err(DbError::Execute(ExecuteError::TableFull))
+            ));
+
+            let select = prepare("select").unwrap();
+            assert_eq!(execute_statement(&select, &mut table).unwrap(), QueryResult::Rows(rows));
+        }
+    }
+
+    #[test]
+    fn test_prepare_errors() {
+        assert_eq!(prepare("insert 1 username"), Err(PrepareError::SyntaxError));
+        assert_eq!(prepare("insert x username email"), Err(PrepareError::SyntaxError));
+        assert_eq!(prepare("delete 1"), Err(PrepareError::UnrecognizedStatement));
+    }
+
+    #[test]
+    fn test_prepare_named_tables() {
+        let tables = |sql| prepare(sql).map(|statement| (statement.typ, statement.into, statement.from));
+        let archive = Some("archive".to_string());
+
+        assert_eq!(tables("select from users"), Ok((StatementType::Select, None, None)));
+        assert_eq!(tables("select from main.users;"), Ok((StatementType::Select, None, None)));
+        assert_eq!(tables("select from archive.users"), Ok((StatementType::Select, None, archive.clone())));
+        assert_eq!(tables("insert into users 1 a a@x"), Ok((StatementType::Insert, None, None)));
+        assert_eq!(tables("insert into archive.users 1 a a@x 3"), Ok((StatementType::Insert, archive.clone(), None)));
+        assert_eq!(tables("insert into archive.users select"), Ok((StatementType::InsertSelect, archive.clone(), None)));
+        assert_eq!(tables("insert into users select from archive.users"), Ok((StatementType::InsertSelect, None, archive)));
+
+        for sql in ["select from", "select from orders", "select from .users", "select users", "select from users x", "insert into", "insert into users"] {
+            assert_eq!(prepare(sql), Err(PrepareError::SyntaxError), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_prepare_where() {
+        let selected = |sql| prepare(sql).map(|statement| (statement.typ, statement.from, statement.where_id));
+        assert_eq!(selected("select where id = 7"), Ok((StatementType::Select, None, Some(7))));
+        assert_eq!(selected("select from archive.users where id = 7;"), Ok((StatementType::Select, Some("archive".to_string()), Some(7))));
+        assert_eq!(selected("insert into users select from archive.users where id = 1"), Ok((StatementType::InsertSelect, Some("archive".to_string()), Some(1))));
+        assert_eq!(selected("select"), Ok((StatementType::Select, None, None)));
+
+        for sql in ["select where", "select where id", "select where id = x", "select where id == 1", "select where age = 1", "select where id = 1 x", "select where id = -1"] {
+            assert_eq!(prepare(sql), Err(PrepareError::SyntaxError), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_select_where_reads_one_row() {
+        let mut table = Table::new();
+        for id in [5, 3, 9] {
+            execute_statement(&prepare(&format!("insert {} u{} u{}@x", id, id, id)).unwrap(), &mut table).unwrap();
+        }
+        let ids = |sql: &str, table: &mut Table| -> Vec<u64> {
+            query_statement(&prepare(sql).unwrap(), table).unwrap().map(|row| row.unwrap().id).collect()
+        };
+        assert_eq!(ids("select where id = 3", &mut table), [3]);
+        assert_eq!(ids("select where id = 4", &mut table), Vec::<u64>::new());
+        assert_eq!(execute_statement(&prepare("select where id = 9").unwrap(), &mut table).unwrap(), QueryResult::Rows(vec![Row::new(9, "u9", "u9@x")]));
+
+        let mut seen = Vec::new();
+        let scanned = scan_statement(&prepare("select where id = 5").unwrap(), &mut table, |row| {
+            seen.push(row.id());
+            ControlFlow::<()>::Continue(())
+        });
+        assert_eq!((scanned.unwrap(), seen), (None, vec![5]));
+    }
+
+    #[test]
+    fn test_prepare_create_table() {
+        let options = Options { username_size: 8, email_size: 40, row_checksums: true, ..Options::default() };
+        for (schema, options) in [(None, Options::default()), (Some("archive"), options)] {
+            let statement = prepare(&create_table_sql(schema, &options)).unwrap();
+            assert_eq!(statement.typ, StatementType::CreateTable);
+            assert_eq!(statement.into.as_deref(), schema);
+            assert_eq!(statement.options, Some(options));
+        }
+        let plain = prepare("create table main.users (id integer, username varchar(1), email varchar(2), age integer)").unwrap();
+        assert_eq!((plain.into, plain.options), (None, Some(Options { username_size: 1, email_size: 2, ..Options::default() })));
+
+        for sql in [
+            "create users",
+            "create table orders (id integer, username varchar(1), email varchar(2), age integer)",
+            "create table users",
+            "create table users (id integer, username varchar(1), email varchar(2))",
+            "create table users (id integer, email varchar(2), username varchar(1), age integer)",
+            "create table users (id integer, username varchar(x), email varchar(2), age integer)",
+            "create table users (id integer, username text, email varchar(2), age integer)",
+            "create table users (id integer, username varchar(1), email varchar(2), age integer) with",
+        ] {
+            assert_eq!(prepare(sql), Err(PrepareError::SyntaxError), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_create_table_sets_columns_of_an_empty_table() {
+        let mut table = Table::new();
+        let narrow = create_table_sql(None, &Options { username_size: 4, email_size: 8, row_checksums: true, ..Options::default() });
+        execute_statement(&prepare(&narrow).unwrap(), &mut table).unwrap();
+        assert_eq!((table.layout().username_size(), table.layout().email_size(), table.checksums()), (4, 8, true));
+        assert!(matches!(execute_statement(&prepare("insert 1 alice a@x").unwrap(), &mut table), Err(DbError::Row(_))));
+
+        execute_statement(&prepare("insert 1 bob b@x").unwrap(), &mut table).unwrap();
+        execute_statement(&prepare(&narrow).unwrap(), &mut table).unwrap();
+        let wide = create_table_sql(None, &Options::default());
+        assert!(matches!(execute_statement(&prepare(&wide).unwrap(), &mut table), Err(DbError::TableExists(_))));
+        let bad = "create table users (id integer, username varchar(0), email varchar(8), age integer)";
+        assert!(matches!(execute_statement(&prepare(bad).unwrap(), &mut table), Err(DbError::Row(_))));
+    }
+
+    #[test]
+    fn test_insert_select_into_the_same_table() {
+        let mut table = Table::new();
+        let copy = prepare("insert into users select from users").unwrap();
+        assert_eq!(execute_statement(&copy, &mut table).unwrap(), QueryResult::RowsAffected(0));
+        execute_statement(&prepare("insert 1 alice a@x").unwrap(), &mut table).unwrap();
+        assert!(matches!(execute_statement(&copy, &mut table), Err(DbError::Execute(ExecuteError::DuplicateKey))));
+        assert_eq!(table.num_rows, 1);
+    }
+
+    #[test]
+    fn test_prepare_quoted_values() {
+        let row = |sql| prepare(sql).unwrap().row_to_insert.unwrap();
+
+        assert_eq!(row("insert 1 'alice smith' 'a@x' 3;"), Row::new(1, "alice smith", "a@x").with_age(3));
+        assert_eq!(row("insert 1 'it''s' ''"), Row::new(1, "it's", ""));
+        assert_eq!(row(r"insert 1 'a\nb\tc\r\0\\' 'C:\dir'"), Row::new(1, "a\nb\tc\r\0\\", "C:\\dir"));
+        assert_eq!(row(r"insert 1 a\nb e"), Row::new(1, r"a\nb", "e"));
+        assert_eq!(prepare("select;").unwrap().typ, StatementType::Select);
+
+        assert_eq!(prepare("insert 1 'open e"), Err(PrepareError::SyntaxError));
+        assert_eq!(prepare("insert 1 'a'b e"), Err(PrepareError::SyntaxError));
+    }
+
+    #[test]
+    fn test_quote_round_trips() {
+        let mut gen = crate::testing::Gen::new(135);
+        for _ in 0..500 {
+            let username = gen.text(32);
+            let email = gen.text(255);
+            let sql = format!("insert 7 {} {} 1;", quote(&username), quote(&email));
+            assert!(!sql.contains(['\n', '\r']));
+            assert_eq!(prepare(&sql).unwrap().row_to_insert.unwrap(), Row::new(7, &username, &email).with_age(1));
+        }
+    }
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(split_statements("insert 1 a a@x; select;"), ["insert 1 a a@x", "select"]);
+        assert_eq!(split_statements(" ; select ;; "), ["select"]);
+        assert_eq!(split_statements("insert 1 'a;b' 'it''s;' 2;select"), ["insert 1 'a;b' 'it''s;' 2", "select"]);
+        assert_eq!(split_statements(r"insert 1 'a\\' b;select"), [r"insert 1 'a\\' b", "select"]);
+        // A quote inside a word does not start a value.
+        assert_eq!(split_statements("insert 1 it's x;select"), ["insert 1 it's x", "select"]);
+        // An unterminated value runs to the end, for `prepare` to reject.
+        assert_eq!(split_statements("insert 1 'a;select"), ["insert 1 'a;select"]);
+        assert!(!is_complete("insert 1 'a;select"));
+        assert!(is_complete("insert 1 'a\nb' it's; select"));
+        assert!(!is_complete("insert 1 'it''s"));
+        // Comments run to the end of the line, but not inside values or words.
+        assert_eq!(split_statements("-- users"), Vec::<&str>::new());
+        assert_eq!(split_statements("select -- all; rows\nselect"), ["select", "select"]);
+        assert_eq!(split_statements("insert 1 '-- a' b--c; select --"), ["insert 1 '-- a' b--c", "select"]);
+
+        // Every quoted value splits back out as it was written.
+        let mut gen = crate::testing::Gen::new(152);
+        for _ in 0..200 {
+            let insert = format!("insert 7 {} {}", quote(&gen.text(32)), quote(&gen.text(64)));
+            assert_eq!(split_statements(&format!("{};select", insert)), [insert.trim(), "select"]);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let mut table = Table::new();
+        execute_statement(&prepare("insert 1 alice a@x").unwrap(), &mut table).unwrap();
+        execute_statement(&prepare("insert 2 bob b@x").unwrap(), &mut table).unwrap();
+
+        let duplicate = execute_statement(&prepare("insert 2 carol c@x").unwrap(), &mut table);
+        assert!(matches!(duplicate, Err(DbError::Execute(ExecuteError::DuplicateKey))));
+        assert_eq!(table.num_rows, 2);
+
+        assert!(upsert_row(&Row::new(2, "carol", "c@x"), &mut table).unwrap());
+        assert!(!upsert_row(&Row::new(3, "dave", "d@x"), &mut table).unwrap());
+        let rows = execute_statement(&prepare("select").unwrap(), &mut table).unwrap();
+        assert_eq!(
+            rows,
+            QueryResult::Rows(vec![Row::new(1, "alice", "a@x"), Row::new(2, "carol", "c@x"), Row::new(3, "dave", "d@x")])
+        );
+    }
+
+    #[test]
+    fn test_select_returns_inserted_rows() {
+        let mut table = Table::new();
+        let row = Row::new(1, "username", "email@email.com");
+        let insert = Statement { typ: StatementType::Insert, row_to_insert: Some(row.clone()), into: None, from: None, options: None, where_id: None };
+        execute_statement(&insert, &mut table).unwrap();
+
+        let select = prepare("select").unwrap();
+        assert_eq!(execute_statement(&select, &mut table).unwrap(), QueryResult::Rows(vec![row]));
+    }
 }
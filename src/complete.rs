@@ -0,0 +1,111 @@
+//! Tab completion for the shell: statement keywords, meta commands and the
+//! arguments each meta command takes. Works on plain text, so it does not
+//! need a terminal; the line editor calls it when Tab is pressed.
+
+/// Statement keywords, offered at the start of a line.
+const KEYWORDS: &[&str] = &["create", "insert", "select"];
+
+use crate::repl::commands::COMMANDS;
+
+const ON_OFF: &[&str] = &["on", "off"];
+const IMPORT_FLAGS: &[&str] = &["--on-conflict", "--skip-header", "--strict"];
+
+/// What can replace the word being typed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Completion {
+    /// Byte offset in the line where the word being completed starts.
+    pub start: usize,
+    /// Words that could replace it, in the order they should be listed.
+    pub candidates: Vec<&'static str>,
+}
+
+impl Completion {
+    /// The longest text every candidate starts with.
+    pub fn common_prefix(&self) -> &'static str {
+        let Some((first, rest)) = self.candidates.split_first() else {
+            return "";
+        };
+        rest.iter().fold(*first, |prefix, candidate| {
+            let len = prefix.chars().zip(candidate.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+            &prefix[..len]
+        })
+    }
+}
+
+/// Completes the word that ends at byte offset `cursor` of `line`, going by
+/// the words before it: after `.mode ` only modes are offered, and so on.
+pub fn complete(line: &str, cursor: usize) -> Completion {
+    let before = &line[..cursor];
+    let start = before.len() - before.chars().rev().take_while(|c| !c.is_whitespace()).map(char::len_utf8).sum::<usize>();
+    let word = &before[start..];
+    let previous: Vec<&str> = before[..start].split_whitespace().collect();
+
+    // Meta commands, at the start of a line that begins with `.`, and after
+    // `.help`.
+    if matches!(previous.as_slice(), [] | [".help"]) && word.starts_with('.') {
+        let candidates = COMMANDS.iter().map(|command| command.name).filter(|name| name.starts_with(word)).collect();
+        return Completion { start, candidates };
+    }
+    let options: &[&str] = match previous.as_slice() {
+        [] => KEYWORDS,
+        [".mode"] => &["list", "csv", "json", "column"],
+        [".errors"] => &["text", "json"],
+        [".color"] => &["on", "off", "auto"],
+        [".bail" | ".echo" | ".headers" | ".history" | ".pager" | ".quiet" | ".timer"] => ON_OFF,
+        [".output"] => &["stdout"],
+        [".export"] => &["json"],
+        [".stats"] => &["reset"],
+        [".import", .., "--on-conflict"] => &["fail", "abort", "skip", "replace"],
+        [".import", ..] if word.starts_with('-') => IMPORT_FLAGS,
+        [".import"] => &["csv", "jsonl"],
+        [".seed", ..] if word.starts_with('-') => &["--random", "--rng"],
+        _ => &[],
+    };
+    let candidates = options.iter().copied().filter(|option| option.starts_with(word)).collect();
+    Completion { start, candidates }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(line: &str) -> Vec<&'static str> {
+        complete(line, line.len()).candidates
+    }
+
+    #[test]
+    fn test_complete_first_word() {
+        assert_eq!(complete("ins", 3), Completion { start: 0, candidates: vec!["insert"] });
+        assert_eq!(candidates(".ex"), [".exit", ".export"]);
+        assert_eq!(candidates("  .mo"), [".mode"]);
+        assert_eq!(candidates(""), ["create", "insert", "select"]);
+        assert_eq!(candidates("drop"), Vec::<&str>::new());
+        // Only the text before the cursor counts.
+        assert_eq!(complete(".hea on", 4).candidates, [".headers"]);
+    }
+
+    #[test]
+    fn test_complete_arguments_in_context() {
+        assert_eq!(complete(".mode ", 6), Completion { start: 6, candidates: vec!["list", "csv", "json", "column"] });
+        assert_eq!(candidates(".mode c"), ["csv", "column"]);
+        assert_eq!(candidates(".mode csv "), Vec::<&str>::new());
+        assert_eq!(candidates(".headers o"), ["on", "off"]);
+        assert_eq!(candidates(".help .he"), [".headers", ".help"]);
+        assert_eq!(candidates(".color a"), ["auto"]);
+        assert_eq!(candidates(".import "), ["csv", "jsonl"]);
+        assert_eq!(candidates(".import csv data.csv --s"), ["--skip-header", "--strict"]);
+        assert_eq!(candidates(".import csv data.csv --on-conflict r"), ["replace"]);
+        assert_eq!(candidates(".seed 100 --r"), ["--random", "--rng"]);
+        // Statements take no keywords after the first.
+        assert_eq!(candidates("select s"), Vec::<&str>::new());
+        assert_eq!(candidates("insert 1 ."), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(complete(".ex", 3).common_prefix(), ".ex");
+        assert_eq!(complete(".mode c", 7).common_prefix(), "c");
+        assert_eq!(complete(".i", 2).common_prefix(), ".import");
+        assert_eq!(complete("x", 1).common_prefix(), "");
+    }
+}
@@ -0,0 +1,442 @@
+//! Sharing one open database between threads.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::{Duration, Instant};
+
+use crate::compiler::{prepare, QueryResult, StatementType};
+use crate::database::{Database, Options};
+use crate::error::DbError;
+use crate::row::Row;
+use crate::snapshot::Snapshot;
+use crate::vfs::Vfs;
+
+/// A handle to a database shared between threads. Clones are cheap and
+/// reach the same database.
+///
+/// The whole database, attached ones included, sits behind one `RwLock`.
+/// A select takes the read lock for as long as it reads, so any number run
+/// at once and each sees the table as it was when it started. Every other
+/// statement takes the write lock and runs alone, waiting for the selects
+/// before it to finish. Under the read lock, pages are loaded into the
+/// cache once and never changed, so readers need no lock of their own.
+///
+/// ```
+/// use VoidDB::{Connection, QueryResult};
+///
+/// let connection = Connection::open_in_memory();
+/// connection.execute("insert 1 alice alice@example.com").unwrap();
+///
+/// let reader = connection.clone();
+/// let rows = std::thread::spawn(move || reader.select("select").unwrap()).join().unwrap();
+/// assert_eq!(rows[0].username(), "alice");
+/// assert_eq!(connection.execute("select where id = 2").unwrap(), QueryResult::Rows(vec![]));
+/// ```
+///
+/// Dropping the last handle to a database writes it out; `close` does the
+/// same and reports whether it worked.
+#[derive(Clone)]
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+/// The database behind every handle of a `Connection`.
+struct Shared {
+    db: RwLock<Database>,
+}
+
+/// The files opened by `Connection::open` that some handle still has open.
+static OPEN: Mutex<Vec<OpenFile>> = Mutex::new(Vec::new());
+
+/// Signalled each time a dropped database takes its entry out of `OPEN`.
+static CLOSED: Condvar = Condvar::new();
+
+/// The least time `Connection::open` gives the last handle to a file to
+/// let go of it, however short the busy timeout.
+const CLOSE_WAIT: Duration = Duration::from_secs(1);
+
+/// An entry in `OPEN`: a file by canonical path and the VFS it is on.
+struct OpenFile {
+    path: PathBuf,
+    vfs: Arc<dyn Vfs>,
+    shared: Weak<Shared>,
+}
+
+impl Connection {
+    pub fn new(db: Database) -> Self {
+        Connection { shared: Arc::new(Shared { db: RwLock::new(db) }) }
+    }
+
+    /// Opens the database file at `path`, creating it if it does not exist.
+    ///
+    /// If another `Connection` in this process opened the same file and is
+    /// still open, the new handle shares its database, page cache and locks
+    /// instead of failing with `DbError::Locked`: writes through either are
+    /// seen by reads through both, and the file is written out and let go
+    /// of when the last handle is dropped. Databases opened any other way,
+    /// in this process or another, still hold the file for themselves.
+    ///
+    /// ```
+    /// use VoidDB::Connection;
+    ///
+    /// let path = std::env::temp_dir().join("voiddb-doctest-shared.db");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let orders = Connection::open(&path).unwrap();
+    /// let reports = Connection::open(&path).unwrap();
+    /// orders.execute("insert 1 alice alice@example.com").unwrap();
+    /// assert_eq!(reports.select("select").unwrap().len(), 1);
+    /// # drop((orders, reports));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        Self::open_with(path, Options::new())
+    }
+
+    /// Opens the database file at `path` with `options`, sharing it as
+    /// `open` does with another `Connection` that has the same file open on
+    /// the same VFS. A shared database keeps the options it was opened with.
+    ///
+    /// If the last handle to the file is still being dropped, waits for it
+    /// to let go of the file for up to the busy timeout, but no less than a
+    /// second, then fails with `DbError::Locked`.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use VoidDB::{vfs::MemoryVfs, Connection, Options};
+    ///
+    /// let options = Options::new().vfs(Arc::new(MemoryVfs::new()));
+    /// let connection = Connection::open_with("orders.db", options.clone()).unwrap();
+    /// connection.execute("insert 1 alice alice@example.com").unwrap();
+    /// let shared = Connection::open_with("orders.db", options).unwrap();
+    /// assert_eq!(shared.select("select").unwrap().len(), 1);
+    /// ```
+    pub fn open_with<P: AsRef<Path>>(path: P, options: Options) -> Result<Self, DbError> {
+        let deadline = Instant::now() + options.busy_timeout.max(CLOSE_WAIT);
+        // Held while the file is opened, so two threads opening it at once
+        // end up sharing it.
+        let mut open = OPEN.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Ok(canonical) = options.vfs.canonicalize(path.as_ref()) {
+            while let Some(file) = open.iter().find(|file| file.path == canonical && Arc::ptr_eq(&file.vfs, &options.vfs)) {
+                if let Some(shared) = file.shared.upgrade() {
+                    return Ok(Connection { shared });
+                }
+                // The last handle is gone but its drop has not let go of the
+                // file yet; it takes the entry out once it has, even if it
+                // panics.
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(DbError::Locked);
+                }
+                open = CLOSED.wait_timeout(open, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+            }
+        }
+        let db = options.open(&path)?;
+        let path = options.vfs.canonicalize(path.as_ref())?;
+        let connection = Self::new(db);
+        open.push(OpenFile { path, vfs: options.vfs, shared: Arc::downgrade(&connection.shared) });
+        Ok(connection)
+    }
+
+    pub fn open_in_memory() -> Self {
+        Self::new(Database::open_in_memory())
+    }
+
+    /// Runs one statement: a select under the read lock, anything else
+    /// under the write lock.
+    pub fn execute(&self, sql: &str) -> Result<QueryResult, DbError> {
+        let statement = prepare(sql.trim()).map_err(DbError::Prepare)?;
+        match statement.typ {
+            StatementType::Select => self.select(sql).map(QueryResult::Rows),
+            _ => self.write().execute(sql),
+        }
+    }
+
+    /// The rows of a select, read under the read lock. Fails with
+    /// `DbError::ReadOnly` for other statements.
+    pub fn select(&self, sql: &str) -> Result<Vec<Row>, DbError> {
+        self.read().select(sql)?.collect()
+    }
+
+    /// A view of the database as it is now, taken under the read lock.
+    /// Reading it takes no lock, so writers carry on while it is read.
+    pub fn snapshot(&self) -> Result<Snapshot, DbError> {
+        self.read().snapshot()
+    }
+
+    /// Writes out everything buffered, under the write lock.
+    pub fn flush(&self) -> Result<(), DbError> {
+        self.write().flush()
+    }
+
+    /// Writes out everything buffered and drops this handle. Other handles
+    /// to the same database carry on.
+    pub fn close(self) -> Result<(), DbError> {
+        self.flush()
+    }
+
+    /// The database under the read lock, for reading through `&Database`
+    /// with `Database::select`. Writers wait until the guard is dropped.
+    pub fn read(&self) -> RwLockReadGuard<'_, Database> {
+        // A panic while holding the lock cannot leave a half written row
+        // behind: inserts check a row before writing it.
+        self.shared.db.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The database under the write lock, for anything that needs
+    /// `&mut Database`.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Database> {
+        self.shared.db.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Shared {
+    /// Writes out the database and closes its file, then takes its entry
+    /// out of `OPEN`. Until then `Connection::open` waits rather than find
+    /// the file still held. There is no one left to report a failed write
+    /// to.
+    fn drop(&mut self) {
+        // Takes the entry out on the way out of a panic too, so no one
+        // waits on a file whose handle is gone for good.
+        let _forget = Forget(self as *const Shared);
+        let db = self.db.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if db.has_unsaved_changes() {
+            let _ = db.flush();
+        }
+        drop(std::mem::replace(db, Database::open_in_memory()));
+    }
+}
+
+/// Takes a dropped database's entry out of `OPEN` when dropped itself.
+struct Forget(*const Shared);
+
+impl Drop for Forget {
+    fn drop(&mut self) {
+        let mut open = OPEN.lock().unwrap_or_else(PoisonError::into_inner);
+        // Only this entry: another handle may be dropping too, and its file
+        // is held until it takes its own out.
+        open.retain(|file| !std::ptr::eq(file.shared.as_ptr(), self.0));
+        CLOSED.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use crate::vfs::MemoryVfs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_connection_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Connection>();
+        assert_send_sync::<Database>();
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_a_writer() {
+        const READERS: usize = 8;
+        const ROWS: u64 = 300;
+
+        let connection = Connection::open_in_memory();
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let (connection, done) = (connection.clone(), done.clone());
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    let mut last_len = 0;
+                    while !done.load(Ordering::SeqCst) || reads == 0 {
+                        let rows = connection.select("select").unwrap();
+                        // Rows only ever get added, in id order, each whole.
+                        assert!(rows.len() >= last_len);
+                        for (i, row) in rows.iter().enumerate() {
+                            assert_eq!(row.id, i as u64 + 1);
+                            assert_eq!(row.username(), format!("user{}", row.id));
+                            assert_eq!(row.email(), format!("user{}@example.com", row.id));
+                        }
+                        if let Some(last) = rows.last() {
+                            let found = connection.select(&format!("select where id = {}", last.id)).unwrap();
+                            assert_eq!(found.as_slice(), std::slice::from_ref(last));
+                        }
+                        last_len = rows.len();
+                        reads += 1;
+                        if reader % 2 == 0 {
+                            thread::yield_now();
+                        }
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        for id in 1..=ROWS {
+            connection.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(connection.select("select").unwrap().len(), ROWS as usize);
+    }
+
+    #[test]
+    fn test_readers_load_pages_from_the_file_together() {
+        let path = TempPath::new("connection.db");
+        let mut db = Database::open(&path).unwrap();
+        for id in 1..=500 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        let expected: Vec<Row> = db.select("select").unwrap().collect::<Result<_, _>>().unwrap();
+        db.close().unwrap();
+
+        // Every reader starts on a cold cache, so they race to load pages.
+        for _ in 0..20 {
+            let connection = Connection::open(&path).unwrap();
+            thread::scope(|scope| {
+                for _ in 0..8 {
+                    scope.spawn(|| assert_eq!(connection.select("select").unwrap(), expected));
+                }
+            });
+            let stats = connection.read().stats();
+            assert_eq!(stats.cached_pages, stats.pages);
+        }
+    }
+
+    #[test]
+    fn test_writes_need_the_write_lock() {
+        let connection = Connection::open_in_memory();
+        assert_eq!(connection.select("insert 1 a a@x").unwrap_err().code(), "READ_ONLY");
+        assert_eq!(connection.execute("insert 1 a a@x").unwrap(), QueryResult::RowsAffected(1));
+        assert_eq!(connection.execute("select").unwrap(), QueryResult::Rows(vec![Row::new(1, "a", "a@x")]));
+        assert_eq!(connection.execute("bogus").unwrap_err().code(), "UNRECOGNIZED_STATEMENT");
+    }
+
+    #[test]
+    fn test_opening_an_open_file_shares_it() {
+        let path = TempPath::new("shared.db");
+        let first = Connection::open(&path).unwrap();
+        let second = Connection::open(&path).unwrap();
+        assert!(matches!(Database::open(&path), Err(DbError::Locked)));
+
+        first.execute("insert 1 alice alice@example.com").unwrap();
+        assert_eq!(second.select("select").unwrap(), [Row::new(1, "alice", "alice@example.com")]);
+        thread::scope(|scope| {
+            for (connection, start) in [(&first, 100), (&second, 200)] {
+                scope.spawn(move || {
+                    for id in start..start + 50 {
+                        connection.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(first.select("select").unwrap().len(), 101);
+
+        // The file stays open until the last handle goes.
+        first.close().unwrap();
+        second.execute("insert 2 bob bob@example.com").unwrap();
+        let third = Connection::open(&path).unwrap();
+        assert_eq!(third.select("select where id = 2").unwrap().len(), 1);
+        drop((second, third));
+
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.select("select").unwrap().count(), 102);
+        db.execute("insert 3 carol carol@example.com").unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_opening_while_the_last_handle_drops() {
+        let path = TempPath::new("shared-race.db");
+        for id in 0..50u64 {
+            let last = Connection::open(&path).unwrap();
+            last.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+            let dying = Arc::downgrade(&last.shared);
+            let reopened = thread::scope(|scope| {
+                // Holding OPEN stops the drop after the last handle has gone
+                // but before its entry is taken out; the open then races it
+                // for the lock.
+                let open = OPEN.lock().unwrap_or_else(PoisonError::into_inner);
+                scope.spawn(move || drop(last));
+                while dying.strong_count() > 0 {
+                    thread::yield_now();
+                }
+                drop(open);
+                Connection::open(&path)
+            });
+            assert_eq!(reopened.unwrap().select("select").unwrap().len() as u64, id + 1);
+        }
+    }
+
+    #[test]
+    fn test_open_with_options() {
+        let memory = Arc::new(MemoryVfs::new());
+        let options = Options::new().vfs(memory.clone()).busy_timeout(Duration::from_secs(2));
+        let first = Connection::open_with("a.db", options.clone()).unwrap();
+        first.execute("insert 1 alice alice@example.com").unwrap();
+        assert_eq!(first.read().options(), options);
+        assert!(!Path::new("a.db").exists());
+
+        // Only a handle on the same VFS shares the file.
+        let second = Connection::open_with("a.db", Options::new().vfs(memory.clone())).unwrap();
+        assert_eq!(second.select("select").unwrap().len(), 1);
+        assert_eq!(second.read().options(), options);
+        let other = Connection::open_with("a.db", Options::new().vfs(Arc::new(MemoryVfs::new()))).unwrap();
+        assert!(other.select("select").unwrap().is_empty());
+        drop((first, second));
+        assert!(memory.contents(Path::new("a.db")).is_some());
+    }
+
+    #[test]
+    fn test_opening_gives_up_on_a_handle_that_never_lets_go() {
+        let path = TempPath::new("shared-stuck.db");
+        Database::open(&path).unwrap().close().unwrap();
+        let options = Options::new().busy_timeout(Duration::from_millis(1500));
+        // An entry whose handle is gone but never taken out, as if its drop
+        // were stuck writing out.
+        let stuck = OpenFile { path: options.vfs.canonicalize(&path).unwrap(), vfs: options.vfs.clone(), shared: Weak::new() };
+        OPEN.lock().unwrap_or_else(PoisonError::into_inner).push(stuck);
+        let start = Instant::now();
+        let result = Connection::open_with(&path, options);
+        let waited = start.elapsed();
+        let canonical = path.canonicalize().unwrap();
+        let take_out = || OPEN.lock().unwrap_or_else(PoisonError::into_inner).retain(|file| file.path != canonical);
+        take_out();
+        assert!(matches!(result, Err(DbError::Locked)));
+        assert!(waited >= Duration::from_millis(1500), "{:?}", waited);
+
+        // The wait ends as soon as the entry is taken out.
+        let stuck = OpenFile { path: canonical.clone(), vfs: crate::vfs::default(), shared: Weak::new() };
+        OPEN.lock().unwrap_or_else(PoisonError::into_inner).push(stuck);
+        let opened = thread::scope(|scope| {
+            let opener = scope.spawn(|| Connection::open(&path));
+            thread::sleep(Duration::from_millis(50));
+            take_out();
+            CLOSED.notify_all();
+            opener.join().unwrap()
+        });
+        assert!(opened.unwrap().select("select").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_handles_dropped_together_write_out_once() {
+        let path = TempPath::new("shared-drop.db");
+        for round in 0..10u64 {
+            let handles: Vec<_> = (0..4).map(|_| Connection::open(&path).unwrap()).collect();
+            for (i, connection) in handles.iter().enumerate() {
+                let id = round * 10 + i as u64;
+                connection.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+            }
+            thread::scope(|scope| {
+                for connection in handles {
+                    scope.spawn(move || drop(connection));
+                }
+            });
+            let db = Database::open(&path).unwrap();
+            let ids: Vec<u64> = db.select("select").unwrap().map(|row| row.unwrap().id).collect();
+            let expected: Vec<u64> = (0..=round).flat_map(|round| (0..4).map(move |i| round * 10 + i)).collect();
+            assert_eq!(ids, expected);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! CSV in the RFC 4180 style, shared by CSV output and `.import`: records
+//! end in `\n` (`\r\n` is accepted when reading), fields are split on a
+//! configurable separator, and a field is quoted only when it has to be.
+
+use std::io::{self, BufRead, Write};
+
+/// The separator used unless `.separator` says otherwise.
+pub const DEFAULT_SEPARATOR: char = ',';
+
+/// Writes one record. A field is quoted if it contains the separator, a
+/// double quote, CR or LF; quotes inside it are doubled.
+pub fn write_record<W, I, F>(out: &mut W, separator: char, fields: I) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    I: IntoIterator<Item = F>,
+    F: AsRef<str>,
+{
+    let mut buf = [0; 4];
+    let separator = separator.encode_utf8(&mut buf);
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(separator.as_bytes())?;
+        }
+        write_field(out, separator, field.as_ref())?;
+    }
+    out.write_all(b"\n")
+}
+
+fn write_field<W: Write + ?Sized>(out: &mut W, separator: &str, field: &str) -> io::Result<()> {
+    if !field.contains(['"', '\r', '\n']) && !field.contains(separator) {
+        return out.write_all(field.as_bytes());
+    }
+
+    out.write_all(b"\"")?;
+    out.write_all(field.replace('"', "\"\"").as_bytes())?;
+    out.write_all(b"\"")
+}
+
+/// One record read back, with the line it started on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Reads records one at a time, so a file of any size is never held in
+/// memory at once. A UTF-8 byte order mark at the start is skipped.
+pub struct Reader<R> {
+    input: R,
+    separator: char,
+    /// Lines read so far.
+    line: usize,
+    buf: String,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R, separator: char) -> Self {
+        Reader { input, separator, line: 0, buf: String::new() }
+    }
+
+    /// The next record, or `None` at the end of the input. A quoted field
+    /// may span lines; one that is never closed is an `InvalidData` error.
+    pub fn read_record(&mut self) -> io::Result<Option<Record>> {
+        if !self.next_line()? {
+            return Ok(None);
+        }
+        let start = self.line;
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            let mut chars = self.buf.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (quoted, c) {
+                    // A line ending outside quotes ends the record.
+                    (false, '\n') => break,
+                    (false, '\r') if chars.peek() == Some(&'\n') => break,
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    (true, '"') => quoted = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => quoted = true,
+                    (false, c) if c == self.separator => fields.push(std::mem::take(&mut field)),
+                    (false, c) => field.push(c),
+                }
+            }
+
+            if !quoted {
+                fields.push(field);
+                return Ok(Some(Record { line: start, fields }));
+            }
+            if !self.next_line()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("quoted field starting on line {} is never closed", start),
+                ));
+            }
+        }
+    }
+
+    /// Reads the next line into `buf`, returning false at the end of input.
+    fn next_line(&mut self) -> io::Result<bool> {
+        self.buf.clear();
+        if self.input.read_line(&mut self.buf)? == 0 {
+            return Ok(false);
+        }
+        if self.line == 0 {
+            if let Some(rest) = self.buf.strip_prefix('\u{feff}') {
+                self.buf = rest.to_string();
+            }
+        }
+        self.line += 1;
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Parses a `.separator` argument, which may be quoted and may use `\t`,
+/// `\n` or `\\`. The separator must be a single character other than a
+/// double quote.
+pub fn parse_separator(arg: &str) -> Option<char> {
+    let unquoted = ['\'', '"']
+        .iter()
+        .find_map(|&q| arg.strip_prefix(q).and_then(|rest| rest.strip_suffix(q)))
+        .unwrap_or(arg);
+    let separator = match unquoted {
+        "\\t" => '\t',
+        "\\n" => '\n',
+        "\\\\" => '\\',
+        other => {
+            let mut chars = other.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return None,
+            }
+        }
+    };
+    (separator != '"').then_some(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(separator: char, fields: &[&str]) -> String {
+        let mut out = Vec::new();
+        write_record(&mut out, separator, fields).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn read(input: &str, separator: char) -> Vec<Vec<String>> {
+        Reader::new(input.as_bytes(), separator).map(|record| record.unwrap().fields).collect()
+    }
+
+    #[test]
+    fn test_plain_fields_are_not_quoted() {
+        assert_eq!(record(',', &["1", "alice", "a@b.c"]), "1,alice,a@b.c\n");
+        assert_eq!(record(',', &[""]), "\n");
+    }
+
+    #[test]
+    fn test_special_fields_are_quoted() {
+        assert_eq!(record(',', &["a,b"]), "\"a,b\"\n");
+        assert_eq!(record(',', &["say \"hi\""]), "\"say \"\"hi\"\"\"\n");
+        assert_eq!(record(',', &["two\nlines", "cr\r"]), "\"two\nlines\",\"cr\r\"\n");
+    }
+
+    #[test]
+    fn test_separator() {
+        assert_eq!(record('\t', &["a,b", "c\td"]), "a,b\t\"c\td\"\n");
+        assert_eq!(read("a,b\t\"c\td\"\n", '\t'), vec![vec!["a,b", "c\td"]]);
+    }
+
+    #[test]
+    fn test_read() {
+        let input = "\u{feff}id,name\r\n1,\"a,\"\"b\"\"\"\n2,\"two\nlines\"\n3,\n";
+        let records: Vec<Record> = Reader::new(input.as_bytes(), ',').map(Result::unwrap).collect();
+
+        assert_eq!(records[0], Record { line: 1, fields: vec!["id".into(), "name".into()] });
+        assert_eq!(records[1], Record { line: 2, fields: vec!["1".into(), "a,\"b\"".into()] });
+        assert_eq!(records[2], Record { line: 3, fields: vec!["2".into(), "two\nlines".into()] });
+        assert_eq!(records[3], Record { line: 5, fields: vec!["3".into(), "".into()] });
+        assert_eq!(records.len(), 4);
+    }
+
+    #[test]
+    fn test_unclosed_quote() {
+        let err = Reader::new("1,\"open\n2,b\n".as_bytes(), ',').read_record().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "quoted field starting on line 1 is never closed");
+    }
+
+    #[test]
+    fn test_parse_separator() {
+        assert_eq!(parse_separator(","), Some(','));
+        assert_eq!(parse_separator("'\\t'"), Some('\t'));
+        assert_eq!(parse_separator("\\t"), Some('\t'));
+        assert_eq!(parse_separator("'|'"), Some('|'));
+        assert_eq!(parse_separator("\"\"\""), None);
+        assert_eq!(parse_separator("ab"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::checksum::crc32;
+use crate::compiler::{StatementType, Table};
+use crate::row::{Row, RowError, RowRef};
+use crate::error::DbError;
+
+/// A position in the table, used both to walk rows for a select and to find
+/// the slot an insert should write to.
+pub struct Cursor<'a> {
+    table: &'a Table,
+    row_num: usize,
+    end_of_table: bool,
+    // Holds a cell that straddles two pages while it is stitched together.
+    scratch: Vec<u8>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn table_start(table: &'a Table) -> Self {
+        let end_of_table = table.num_rows == 0;
+        Cursor { table, row_num: 0, end_of_table, scratch: Vec::new() }
+    }
+
+    pub fn table_end(table: &'a Table) -> Self {
+        let row_num = table.num_rows;
+        Cursor { table, row_num, end_of_table: true, scratch: Vec::new() }
+    }
+
+    /// The row with `id`, or the end of the table if there is none, which
+    /// is where a row with that id would be inserted.
+    pub fn table_find(table: &'a Table, id: u64) -> Result<Self, DbError> {
+        match table.find_row(id)? {
+            Some(row_num) => Ok(Cursor { table, row_num, end_of_table: false, scratch: Vec::new() }),
+            None => Ok(Cursor::table_end(table)),
+        }
+    }
+
+    pub fn end_of_table(&self) -> bool {
+        self.end_of_table
+    }
+
+    /// Whether the scan has been asked to stop, which uses up the request.
+    pub(crate) fn interrupted(&self) -> bool {
+        self.table.interrupt.take()
+    }
+
+    /// A view of the row under the cursor. The borrow ends before the cursor
+    /// can move, so the view never outlives the slot it points into.
+    pub fn row_ref(&mut self) -> Result<RowRef<'_>, DbError> {
+        let location = self.location();
+        let layout = self.table.layout();
+        let checksums = self.table.checksums();
+        let cell = self.table.read_cell(self.row_num, &mut self.scratch)?;
+        let (payload, stored_checksum) = cell.split_at(layout.row_size());
+
+        let row = RowRef::with_layout(payload, layout).map_err(|err| location.corrupt(err))?;
+        if checksums && crc32(payload).to_le_bytes() != stored_checksum {
+            return Err(location.corrupt(RowError::ChecksumMismatch { id: row.id() }));
+        }
+        Ok(row)
+    }
+
+    /// The row under the cursor, copied out of the page.
+    pub fn row(&mut self) -> Result<Row, DbError> {
+        let location = self.location();
+        self.row_ref()?.to_row().map_err(|err| location.corrupt(err))
+    }
+
+    fn location(&self) -> RowLocation {
+        RowLocation { row_num: self.row_num, page_num: self.table.page_for_row(self.row_num) }
+    }
+
+    pub fn advance(&mut self) {
+        self.row_num += 1;
+        if self.row_num >= self.table.num_rows {
+            self.end_of_table = true;
+        }
+    }
+}
+
+struct RowLocation {
+    row_num: usize,
+    page_num: usize,
+}
+
+impl RowLocation {
+    fn corrupt(&self, err: RowError) -> DbError {
+        DbError::CorruptRow { row_num: self.row_num, page_num: self.page_num, err }
+    }
+}
+
+/// Rows produced by a select, read from the table one at a time as the
+/// caller iterates.
+pub struct Rows<'a> {
+    cursor: Option<Cursor<'a>>,
+    /// Stop after the row under the cursor, for `where id = N`.
+    single: bool,
+    statement_type: StatementType,
+    rows_affected: Option<usize>,
+}
+
+impl<'a> Rows<'a> {
+    pub(crate) fn new(cursor: Cursor<'a>) -> Self {
+        Rows { cursor: Some(cursor), single: false, statement_type: StatementType::Select, rows_affected: None }
+    }
+
+    /// Only the row under `cursor`, if it is not at the end of the table.
+    pub(crate) fn single(cursor: Cursor<'a>) -> Self {
+        Rows { single: true, ..Rows::new(cursor) }
+    }
+
+    /// No rows, for a statement that changed `count` rows instead.
+    pub(crate) fn affected(statement_type: StatementType, count: usize) -> Self {
+        Rows { cursor: None, single: false, statement_type, rows_affected: Some(count) }
+    }
+
+    /// The kind of statement that produced these rows.
+    pub fn statement_type(&self) -> StatementType {
+        self.statement_type
+    }
+
+    /// How many rows the statement changed, or `None` if it is a select.
+    pub fn rows_affected(&self) -> Option<usize> {
+        self.rows_affected
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row, DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.cursor.as_mut()?;
+        if cursor.end_of_table() {
+            self.cursor = None;
+            return None;
+        }
+        if cursor.interrupted() {
+            self.cursor = None;
+            return Some(Err(DbError::Interrupted));
+        }
+
+        let row = cursor.row();
+        if row.is_ok() {
+            cursor.table.count_read();
+        }
+        if self.single {
+            self.cursor = None;
+            return Some(row);
+        }
+        match row {
+            Ok(row) => {
+                cursor.advance();
+                Some(Ok(row))
+            }
+            Err(err) => {
+                // A bad row does not stop the scan; the rows after it may
+                // still be readable.
+                cursor.advance();
+                Some(Err(err))
+            }
+        }
+    }
+}
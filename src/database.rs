@@ -0,0 +1,1823 @@
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::compiler::{
+    create_table_sql, execute_statement, insert_all, insert_row, prepare, prepare_table, read_rows, scan_statement, select_statement,
+    table_name, upsert_row, QueryResult, Table, TABLE_NAME,
+};
+use crate::cursor::Rows;
+use crate::error::DbError;
+use crate::import::{import_rows, ImportSummary, OnConflict};
+use crate::interrupt::Interrupt;
+use crate::changes::{ChangeKind, Changes, UpdateHook};
+use crate::compiler::{Statement, StatementType};
+use crate::pager::{Pager, PagerCounters, PagerOptions, SyncMode, PAGE_SIZE, MAX_PAGES};
+use crate::progress::{Progress, Tracker};
+use crate::row::Row;
+use crate::snapshot::Snapshot;
+use crate::trace::{StatementHook, StatementTrace, Tracer};
+use crate::typed::FromRow;
+use crate::vfs::{self, OpenMode, Vfs};
+use crate::row::{RowLayout, RowRef, DEFAULT_EMAIL_SIZE, DEFAULT_USERNAME_SIZE};
+
+/// How to open a database. Build them with `Options::new()` and a method
+/// per setting, then `open` the file:
+///
+/// ```
+/// use VoidDB::{Options, SyncMode};
+///
+/// let path = std::env::temp_dir().join("voiddb-doctest-options.db");
+/// # let _ = std::fs::remove_file(&path);
+/// let mut db = Options::new().username_size(16).sync(SyncMode::Normal).open(&path).unwrap();
+/// db.execute("insert 1 alice alice@example.com").unwrap();
+/// db.close().unwrap();
+///
+/// let db = Options::new().read_only(true).cache_pages(16).open(&path).unwrap();
+/// assert_eq!(db.select("select").unwrap().count(), 1);
+/// # drop(db);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+///
+/// The column sizes and `row_checksums` are used when the file is created;
+/// opening an existing file uses the ones stored in it instead. The others
+/// are not stored and apply only to the handle being opened.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub username_size: usize,
+    pub email_size: usize,
+    /// Store a CRC-32 with every row and verify it whenever the row is read.
+    pub row_checksums: bool,
+    /// Open the file for reading only: statements other than selects fail
+    /// with `DbError::ReadOnly`, and other read-only handles may have the
+    /// file open at the same time.
+    pub read_only: bool,
+    /// Create the file if it does not exist. On by default. A read-only
+    /// database cannot create its file, so opening a missing one read-only
+    /// fails either way.
+    pub create_if_missing: bool,
+    /// How many pages of the file stay cached after a flush. The rest are
+    /// read in again when next needed.
+    pub cache_pages: usize,
+    /// How a flush makes sure what it wrote reaches the disk.
+    pub sync: SyncMode,
+    /// How long opening the file, or attaching another, waits for a handle
+    /// that has it open to let go before failing with `DbError::Locked`.
+    pub busy_timeout: Duration,
+    /// Where the file is: the real file system unless set otherwise.
+    pub vfs: Arc<dyn Vfs>,
+}
+
+/// Options are equal if they say the same and name the same VFS.
+impl PartialEq for Options {
+    fn eq(&self, other: &Options) -> bool {
+        (self.username_size, self.email_size, self.row_checksums) == (other.username_size, other.email_size, other.row_checksums)
+            && (self.read_only, self.create_if_missing, self.cache_pages) == (other.read_only, other.create_if_missing, other.cache_pages)
+            && (self.sync, self.busy_timeout) == (other.sync, other.busy_timeout)
+            && Arc::ptr_eq(&self.vfs, &other.vfs)
+    }
+}
+
+impl Eq for Options {}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            username_size: DEFAULT_USERNAME_SIZE,
+            email_size: DEFAULT_EMAIL_SIZE,
+            row_checksums: false,
+            read_only: false,
+            create_if_missing: true,
+            cache_pages: MAX_PAGES,
+            sync: SyncMode::Full,
+            busy_timeout: Duration::ZERO,
+            vfs: vfs::default(),
+        }
+    }
+}
+
+impl Options {
+    /// The defaults, which `Database::open` uses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the database file at `path` with these options. Fails with
+    /// `DbError::CannotOpen` if the file is missing and may not be created,
+    /// or if `cache_pages` is 0.
+    ///
+    /// ```
+    /// use VoidDB::Options;
+    ///
+    /// let path = std::env::temp_dir().join("voiddb-doctest-options-missing.db");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let err = Options::new().read_only(true).open(&path).err().unwrap();
+    /// assert_eq!(err.code(), "CANNOT_OPEN");
+    /// ```
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Database, DbError> {
+        Database::open_with(path, self.clone())
+    }
+
+    /// A database in memory with these options.
+    pub fn open_in_memory(&self) -> Result<Database, DbError> {
+        Database::open_in_memory_with(self.clone())
+    }
+
+    pub fn username_size(mut self, size: usize) -> Self {
+        self.username_size = size;
+        self
+    }
+
+    pub fn email_size(mut self, size: usize) -> Self {
+        self.email_size = size;
+        self
+    }
+
+    pub fn row_checksums(mut self, on: bool) -> Self {
+        self.row_checksums = on;
+        self
+    }
+
+    pub fn read_only(mut self, on: bool) -> Self {
+        self.read_only = on;
+        self
+    }
+
+    pub fn create_if_missing(mut self, on: bool) -> Self {
+        self.create_if_missing = on;
+        self
+    }
+
+    pub fn cache_pages(mut self, pages: usize) -> Self {
+        self.cache_pages = pages;
+        self
+    }
+
+    pub fn sync(mut self, mode: SyncMode) -> Self {
+        self.sync = mode;
+        self
+    }
+
+    /// ```
+    /// use std::time::Duration;
+    /// use VoidDB::{Database, Options};
+    ///
+    /// let path = std::env::temp_dir().join("voiddb-doctest-busy-timeout.db");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let db = Database::open(&path).unwrap();
+    /// let second = Options::new().busy_timeout(Duration::from_millis(10)).open(&path);
+    /// assert_eq!(second.err().unwrap().code(), "LOCKED");
+    /// # drop(db);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// Where to find the file; see `vfs::MemoryVfs` for one in memory.
+    pub fn vfs(mut self, vfs: Arc<dyn Vfs>) -> Self {
+        self.vfs = vfs;
+        self
+    }
+
+    fn layout(&self) -> Result<RowLayout, DbError> {
+        Ok(RowLayout::new(self.username_size, self.email_size)?)
+    }
+
+    fn pager_options(&self) -> PagerOptions {
+        PagerOptions {
+            vfs: self.vfs.clone(),
+            read_only: self.read_only,
+            create: self.create_if_missing,
+            busy_timeout: self.busy_timeout,
+            cache_pages: self.cache_pages,
+            sync: self.sync,
+        }
+    }
+}
+
+/// An open VoidDB database.
+///
+/// `execute` runs one statement and returns what it did; every failure is a
+/// `DbError`. Nothing on this path prints, reads stdin or exits, and the
+/// shell is built on the same calls.
+///
+/// ```
+/// use VoidDB::{Database, DbError, QueryResult};
+///
+/// # fn main() -> Result<(), DbError> {
+/// let mut db = Database::open_in_memory();
+/// assert_eq!(db.execute("insert 1 alice alice@example.com 34")?, QueryResult::RowsAffected(1));
+/// db.execute("insert 2 bob bob@example.com")?;
+///
+/// match db.execute("select")? {
+///     QueryResult::Rows(rows) => assert_eq!(rows.iter().map(|row| row.id).collect::<Vec<_>>(), [1, 2]),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// match db.execute("select where id = 2")? {
+///     QueryResult::Rows(rows) => assert_eq!(rows[0].username(), "bob"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// assert_eq!(db.execute("select where id = 3")?, QueryResult::Rows(vec![]));
+/// assert_eq!(db.execute("create table users (id integer, username varchar(32), email varchar(255), age integer)")?, QueryResult::Done);
+///
+/// let err = db.execute("insert 1 carol carol@example.com").unwrap_err();
+/// assert_eq!(err.code(), "DUPLICATE_KEY");
+/// # Ok(())
+/// # }
+/// ```
+pub struct Database {
+    table: Table,
+    /// Where the database file is, or `None` in memory.
+    path: Option<PathBuf>,
+    /// Databases opened with `attach`, in the order they were attached.
+    attached: Vec<Attached>,
+    /// Statements run since the database was opened or `reset_stats`.
+    statements: AtomicU64,
+    on_statement: Option<StatementHook>,
+    changes: Changes,
+    /// The options the database was opened with, which `attach` opens
+    /// other files with too.
+    settings: Options,
+}
+
+/// A database opened alongside another by `Database::attach`, with its own
+/// file, pager and table.
+struct Attached {
+    name: String,
+    path: PathBuf,
+    table: Table,
+}
+
+/// Sizes and counters from `Database::stats`. The file, page and row
+/// numbers are of the main database; the cache and I/O counters add up
+/// every attached database too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The database file, or `None` in memory.
+    pub path: Option<PathBuf>,
+    /// Size of the file as of the last flush.
+    pub file_size: u64,
+    pub pages: usize,
+    /// Pages holding the header or rows.
+    pub used_pages: usize,
+    /// Pages past the last row, left behind by rows that were dropped.
+    pub free_pages: usize,
+    /// Each table, named as in `Database::tables`, with its row count.
+    pub tables: Vec<(String, usize)>,
+    /// Pages held in memory.
+    pub cached_pages: usize,
+    /// Counters since the database was opened or `reset_stats`.
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub statements: u64,
+}
+
+/// Counters from `Database::metrics`, over the database and every attached
+/// one, since they were opened or `reset_metrics`. Subtracting an earlier
+/// snapshot from a later one gives the work done in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub rows_inserted: u64,
+    /// Rows dropped, including inserts undone when a batch failed.
+    pub rows_deleted: u64,
+    /// Rows handed out by selects.
+    pub rows_read: u64,
+    /// Pages added past the end of a database.
+    pub pages_allocated: u64,
+    /// Pages loaded from a file.
+    pub pages_read: u64,
+    /// Pages written to a file by a flush.
+    pub pages_written: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub bytes_flushed: u64,
+}
+
+impl std::ops::Sub for Metrics {
+    type Output = Metrics;
+
+    /// The work done between two snapshots, the earlier one being `other`.
+    fn sub(self, other: Metrics) -> Metrics {
+        Metrics {
+            rows_inserted: self.rows_inserted.saturating_sub(other.rows_inserted),
+            rows_deleted: self.rows_deleted.saturating_sub(other.rows_deleted),
+            rows_read: self.rows_read.saturating_sub(other.rows_read),
+            pages_allocated: self.pages_allocated.saturating_sub(other.pages_allocated),
+            pages_read: self.pages_read.saturating_sub(other.pages_read),
+            pages_written: self.pages_written.saturating_sub(other.pages_written),
+            cache_hits: self.cache_hits.saturating_sub(other.cache_hits),
+            cache_misses: self.cache_misses.saturating_sub(other.cache_misses),
+            bytes_flushed: self.bytes_flushed.saturating_sub(other.bytes_flushed),
+        }
+    }
+}
+
+/// What `Database::clone_to` wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloneSummary {
+    pub rows: usize,
+    pub pages: usize,
+}
+
+impl Database {
+    /// Opens the database file at `path`, creating it if it does not exist.
+    /// Short for `Options::new().open(path)`.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let path = std::env::temp_dir().join("voiddb-doctest-open.db");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut db = Database::open(&path).unwrap();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.close().unwrap();
+    ///
+    /// let mut db = Database::open(&path).unwrap();
+    /// assert!(matches!(db.execute("select"), Ok(VoidDB::QueryResult::Rows(rows)) if rows.len() == 1));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, DbError> {
+        Self::open_with(path, Options::default())
+    }
+
+    /// Opens the database file at `path` with `options`; see
+    /// `Options::open`.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: Options) -> Result<Database, DbError> {
+        let pager = Pager::open_with(&path, &options.pager_options(), &Interrupt::default())?;
+        let table = Table::with_pager(pager, options.layout()?, options.row_checksums)?;
+        let path = options.vfs.canonicalize(path.as_ref()).ok();
+        Ok(Database { table, path, attached: Vec::new(), statements: AtomicU64::new(0), on_statement: None, changes: Changes::default(), settings: options })
+    }
+
+    /// Opens a database that lives only in memory and is lost on close.
+    pub fn open_in_memory() -> Database {
+        Database { table: Table::new(), path: None, attached: Vec::new(), statements: AtomicU64::new(0), on_statement: None, changes: Changes::default(), settings: Options::default() }
+    }
+
+    pub fn open_in_memory_with(options: Options) -> Result<Database, DbError> {
+        Ok(Database { table: Table::memory(options.layout()?, options.row_checksums), path: None, attached: Vec::new(), statements: AtomicU64::new(0), on_statement: None, changes: Changes::default(), settings: options })
+    }
+
+    /// Inserts `row` directly, without going through the statement parser.
+    pub(crate) fn insert_row(&mut self, row: &Row) -> Result<(), DbError> {
+        insert_row(row, self.writable(None)?)?;
+        self.changes.record(ChangeKind::Insert, TABLE_NAME, row.id);
+        Ok(())
+    }
+
+    /// Whether `row` can be added by `append_rows` after the rows of
+    /// `pending`: it fits the layout, the table has room and its id is
+    /// taken by neither. Fails with `DbError::ReadOnly` like an insert.
+    pub(crate) fn can_append(&mut self, row: &Row, pending: &[Row]) -> Result<bool, DbError> {
+        let table = self.writable(None)?;
+        Ok(table.layout().check(row).is_ok()
+            && table.num_rows + pending.len() < table.max_rows()
+            && !pending.iter().any(|other| other.id == row.id)
+            && table.find_row(row.id)?.is_none())
+    }
+
+    /// Inserts the rows of `rows`, which `can_append` has passed one after
+    /// another, a page at a time, and empties it. If a page cannot be
+    /// written none of them is kept.
+    pub(crate) fn append_rows(&mut self, rows: &mut Vec<Row>) -> Result<(), DbError> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let appended = self.writable(None).and_then(|table| table.append_rows(rows));
+        if appended.is_ok() {
+            for row in rows.iter() {
+                self.changes.record(ChangeKind::Insert, TABLE_NAME, row.id);
+            }
+        }
+        rows.clear();
+        appended
+    }
+
+    /// Inserts `row`, or overwrites the row with the same id. Returns
+    /// whether a row was replaced.
+    pub(crate) fn upsert_row(&mut self, row: &Row) -> Result<bool, DbError> {
+        let replaced = upsert_row(row, self.writable(None)?)?;
+        self.changes.record(if replaced { ChangeKind::Update } else { ChangeKind::Insert }, TABLE_NAME, row.id);
+        Ok(replaced)
+    }
+
+    /// Inserts every row of `rows`, resolving rows whose id is already taken
+    /// as `on_conflict` says. Rows that cannot be stored are reported in the
+    /// summary by their 1-based position instead of failing the call.
+    ///
+    /// ```
+    /// use VoidDB::{Database, OnConflict};
+    /// use VoidDB::row::Row;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    ///
+    /// let rows = [Row::try_new(1, "al", "al@example.com").unwrap(), Row::try_new(2, "bob", "bob@example.com").unwrap()];
+    /// let summary = db.insert_rows(rows, OnConflict::Skip).unwrap();
+    /// assert_eq!((summary.inserted, summary.skipped), (1, 1));
+    /// ```
+    pub fn insert_rows<I>(&mut self, rows: I, on_conflict: OnConflict) -> Result<ImportSummary, DbError>
+    where
+        I: IntoIterator<Item = Row>,
+    {
+        let source = rows.into_iter().enumerate().map(|(index, row)| Ok((index + 1, Ok(row))));
+        import_rows(self, source, false, on_conflict, &mut Tracker::new(None))
+    }
+
+    /// Like `insert_rows`, calling `progress` every `PROGRESS_ROWS` rows or
+    /// `PROGRESS_INTERVAL`, whichever comes first. If it returns
+    /// `ControlFlow::Break` the insert stops there with `cancelled` set in
+    /// the summary. There are no transactions: rows inserted before the
+    /// cancel stay in the table.
+    ///
+    /// ```
+    /// use std::ops::ControlFlow;
+    /// use VoidDB::{Database, OnConflict};
+    /// use VoidDB::row::Row;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// let rows = (0..1200).map(|id| Row::try_new(id, "user", "user@example.com").unwrap());
+    /// let summary = db.insert_rows_with_progress(rows, OnConflict::Fail, |_| ControlFlow::Break(())).unwrap();
+    /// assert!(summary.cancelled);
+    /// assert!(summary.inserted <= 1000);
+    /// ```
+    pub fn insert_rows_with_progress<I>(
+        &mut self,
+        rows: I,
+        on_conflict: OnConflict,
+        mut progress: impl FnMut(Progress) -> ControlFlow<()>,
+    ) -> Result<ImportSummary, DbError>
+    where
+        I: IntoIterator<Item = Row>,
+    {
+        let source = rows.into_iter().enumerate().map(|(index, row)| Ok((index + 1, Ok(row))));
+        import_rows(self, source, false, on_conflict, &mut Tracker::new(Some(&mut progress)))
+    }
+
+    /// A handle that stops the statement running on this database, from
+    /// another thread or a signal handler.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        self.table.interrupt.clone()
+    }
+
+    /// Makes `interrupt` the handle that stops statements on this
+    /// database, so that one handed out before it was opened still does.
+    #[cfg(feature = "cli")]
+    pub(crate) fn use_interrupt(&mut self, interrupt: Interrupt) {
+        self.table.interrupt = interrupt;
+    }
+
+    /// Whether an interrupt is pending, using it up.
+    pub(crate) fn interrupted(&self) -> bool {
+        self.table.interrupt.take()
+    }
+
+    pub(crate) fn row_count(&self) -> usize {
+        self.table.num_rows
+    }
+
+    /// Drops the rows after the first `num_rows`, undoing inserts that
+    /// have not been followed by anything else.
+    pub(crate) fn truncate(&mut self, num_rows: usize) {
+        self.table.truncate(num_rows);
+        self.changes.discard();
+    }
+
+    /// Tells the update hook about the rows changed since it last heard.
+    pub(crate) fn notify_changes(&mut self) {
+        self.changes.notify();
+    }
+
+    /// The options this database was opened with, with the column sizes
+    /// and checksum setting its file was created with.
+    pub fn options(&self) -> Options {
+        let table = table_options(&self.table);
+        Options { username_size: table.username_size, email_size: table.email_size, row_checksums: table.row_checksums, ..self.settings.clone() }
+    }
+
+    /// Sets how long `attach` waits for a file another handle has open;
+    /// see `Options::busy_timeout`.
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.settings.busy_timeout = timeout;
+    }
+
+    pub fn execute(&mut self, sql: &str) -> Result<QueryResult, DbError> {
+        let (statement, tracer) = self.begin_exclusive(sql)?;
+        let result = self.execute_prepared(&statement);
+        self.finish(tracer, sql, &statement, result.as_ref().map(|result| match result {
+            QueryResult::Rows(rows) => Some(rows.len()),
+            QueryResult::RowsAffected(count) => Some(*count),
+            QueryResult::Done => Some(0),
+        }));
+        result
+    }
+
+    fn execute_prepared(&mut self, statement: &Statement) -> Result<QueryResult, DbError> {
+        if statement.typ == StatementType::InsertSelect {
+            return self.insert_select(statement).map(QueryResult::RowsAffected);
+        }
+        let result = execute_statement(statement, self.table_for(statement)?)?;
+        self.inserted(statement);
+        Ok(result)
+    }
+
+    /// Reports the row a successful insert added to the update hook.
+    fn inserted(&mut self, statement: &Statement) {
+        if let (StatementType::Insert, Some(row)) = (statement.typ, &statement.row_to_insert) {
+            self.changes.record(ChangeKind::Insert, &table_name(statement.into.as_deref()), row.id);
+            self.changes.notify();
+        }
+    }
+
+    /// Runs `sql` and returns its rows as an iterator that reads pages only as
+    /// the caller advances it. Statements other than select run immediately
+    /// and yield no rows.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.execute("insert 2 bob bob@example.com").unwrap();
+    ///
+    /// let first = db.query("select").unwrap().next().unwrap().unwrap();
+    /// assert_eq!(first.id, 1);
+    /// ```
+    pub fn query(&mut self, sql: &str) -> Result<Rows<'_>, DbError> {
+        let (statement, tracer) = self.begin_exclusive(sql)?;
+        if statement.typ == StatementType::Select {
+            let db = &*self;
+            let rows = db.select_prepared(&statement);
+            db.finish(tracer, sql, &statement, rows.as_ref().map(|_| None));
+            return rows;
+        }
+        let result = self.execute_prepared(&statement).map(|result| match result {
+            QueryResult::RowsAffected(count) => count,
+            QueryResult::Rows(_) | QueryResult::Done => 0,
+        });
+        self.finish(tracer, sql, &statement, result.as_ref().map(|count| Some(*count)));
+        result.map(|count| Rows::affected(statement.typ, count))
+    }
+
+    /// Runs `sql`, calling `f` with a borrowed view of each selected row.
+    /// Returning `ControlFlow::Break` stops the scan: with `Ok` its value is
+    /// returned, with `Err` the statement fails with that error.
+    ///
+    /// ```
+    /// use std::ops::ControlFlow;
+    /// use VoidDB::{Database, DbError};
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.execute("insert 2 bob bob@example.com").unwrap();
+    ///
+    /// let found = db.execute_with("select", |row| {
+    ///     if row.id() == 2 { ControlFlow::Break(Ok(row.id())) } else { ControlFlow::Continue(()) }
+    /// });
+    /// assert_eq!(found.unwrap(), Some(2));
+    ///
+    /// let failed = db.execute_with::<()>("select", |_| ControlFlow::Break(Err(DbError::Corrupt("bad row".to_string()))));
+    /// assert!(matches!(failed, Err(DbError::Corrupt(_))));
+    /// ```
+    pub fn execute_with<B>(
+        &mut self,
+        sql: &str,
+        mut f: impl FnMut(RowRef<'_>) -> ControlFlow<Result<B, DbError>>,
+    ) -> Result<Option<B>, DbError> {
+        let (statement, tracer) = self.begin_exclusive(sql)?;
+        let mut rows = 0;
+        let result = match statement.typ {
+            StatementType::InsertSelect => self.insert_select(&statement).map(|count| {
+                rows = count;
+                None
+            }),
+            _ => self.table_for(&statement).and_then(|table| {
+                scan_statement(&statement, table, |row| {
+                    rows += 1;
+                    f(row)
+                })
+                .and_then(Option::transpose)
+            }),
+        };
+        if statement.typ == StatementType::Insert && result.is_ok() {
+            rows = 1;
+            self.inserted(&statement);
+        }
+        self.finish(tracer, sql, &statement, result.as_ref().map(|_| Some(rows)));
+        result
+    }
+
+    /// Runs `sql` and reads each selected row into a `T`, which picks its
+    /// columns by name; see `typed::FromRow`. Fails on the first row that
+    /// lacks a column `T` asks for or holds a value that does not fit it.
+    pub fn query_as<T: for<'a> FromRow<'a>>(&mut self, sql: &str) -> Result<Vec<T>, DbError> {
+        let mut values = Vec::new();
+        self.execute_with::<()>(sql, |row| match T::from_row(row) {
+            Ok(value) => {
+                values.push(value);
+                ControlFlow::Continue(())
+            }
+            Err(err) => ControlFlow::Break(Err(err.into())),
+        })?;
+        Ok(values)
+    }
+
+    /// Runs a select through a shared reference, returning its rows as
+    /// `query` does. Any number of threads may select at once, for instance
+    /// through a `Connection`; other statements fail with
+    /// `DbError::ReadOnly`.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    ///
+    /// let db = &db;
+    /// std::thread::scope(|scope| {
+    ///     let reader = scope.spawn(|| db.select("select").unwrap().count());
+    ///     assert_eq!(db.select("select where id = 1").unwrap().count(), 1);
+    ///     assert_eq!(reader.join().unwrap(), 1);
+    /// });
+    /// assert_eq!(db.select("insert 2 bob bob@example.com").err().unwrap().code(), "READ_ONLY");
+    /// ```
+    pub fn select(&self, sql: &str) -> Result<Rows<'_>, DbError> {
+        let (statement, tracer) = self.begin(sql)?;
+        let rows = self.select_prepared(&statement);
+        self.finish(tracer, sql, &statement, rows.as_ref().map(|_| None));
+        rows
+    }
+
+    fn select_prepared(&self, statement: &Statement) -> Result<Rows<'_>, DbError> {
+        let table = match statement.from.as_deref() {
+            None => &self.table,
+            Some(schema) => match self.attached.iter().find(|attached| attached.name == schema) {
+                Some(attached) => &attached.table,
+                None => return Err(DbError::NoSuchDatabase(schema.to_string())),
+            },
+        };
+        select_statement(statement, table)
+    }
+
+    /// Calls `hook` after each statement run through `execute`, `query`,
+    /// `execute_with` or `select`, failed ones included, with how long it
+    /// took, how many rows it touched and the pager activity it caused.
+    /// Replaces any hook set before. Without a hook statements are not
+    /// timed at all.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = log.clone();
+    /// db.on_statement(move |trace| sink.lock().unwrap().push((trace.redacted_sql(), trace.rows)));
+    ///
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.execute("select where id = 1").unwrap();
+    /// assert_eq!(*log.lock().unwrap(), [("insert into users ?".to_string(), Some(1)), ("select from users where id = ?".to_string(), Some(1))]);
+    /// ```
+    pub fn on_statement(&mut self, hook: impl Fn(&StatementTrace<'_>) + Send + Sync + 'static) {
+        self.on_statement = Some(Arc::new(hook));
+    }
+
+    /// Removes the hook set by `on_statement`.
+    pub fn clear_statement_hook(&mut self) {
+        self.on_statement = None;
+    }
+
+    /// Calls `hook` once for each row that a statement, `insert_rows` or an
+    /// import adds or overwrites, after the change succeeded. Rows of an
+    /// `insert .. select` or import are reported when it finishes, so rows
+    /// it took back after a failure are never reported. Replaces any hook
+    /// set before.
+    ///
+    /// The hook runs while the database is borrowed, so it cannot run
+    /// statements on it; one that reaches the database through a
+    /// `Connection` would wait on itself forever.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use VoidDB::Database;
+    /// use VoidDB::changes::ChangeKind;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// let changed = Arc::new(Mutex::new(Vec::new()));
+    /// let sink = changed.clone();
+    /// db.set_update_hook(Box::new(move |kind, table, id| sink.lock().unwrap().push((kind, table.to_string(), id))));
+    ///
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// assert_eq!(*changed.lock().unwrap(), [(ChangeKind::Insert, "users".to_string(), 1)]);
+    /// ```
+    pub fn set_update_hook(&mut self, hook: UpdateHook) {
+        self.changes.set_hook(Some(hook));
+    }
+
+    /// Removes the hook set by `set_update_hook`.
+    pub fn clear_update_hook(&mut self) {
+        self.changes.set_hook(None);
+    }
+
+    /// `begin` for a statement that has the database to itself, dropping
+    /// any interrupt left over from before. Through `&self` another
+    /// statement may be running, and the interrupt may be meant for it.
+    fn begin_exclusive(&mut self, sql: &str) -> Result<(Statement, Option<Tracer>), DbError> {
+        self.table.interrupt.clear();
+        self.begin(sql)
+    }
+
+    /// Prepares `sql` and counts it. With a hook set, also starts timing
+    /// it, or reports it right away if it does not parse.
+    fn begin(&self, sql: &str) -> Result<(Statement, Option<Tracer>), DbError> {
+        let Some(hook) = &self.on_statement else {
+            let statement = prepare(sql.trim())?;
+            self.statements.fetch_add(1, Ordering::Relaxed);
+            return Ok((statement, None));
+        };
+        let prepare_start = Instant::now();
+        match prepare(sql.trim()) {
+            Ok(statement) => {
+                self.statements.fetch_add(1, Ordering::Relaxed);
+                Ok((statement, Some(Tracer::new(hook.clone(), prepare_start, self.io_counters()))))
+            }
+            Err(err) => {
+                let err = DbError::Prepare(err);
+                let io = self.io_counters();
+                Tracer::new(hook.clone(), prepare_start, io).finish(sql, None, Err(&err), io);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reports a statement started by `begin` to the hook, if there is one.
+    fn finish(&self, tracer: Option<Tracer>, sql: &str, statement: &Statement, outcome: Result<Option<usize>, &DbError>) {
+        if let Some(tracer) = tracer {
+            tracer.finish(sql, Some(statement), outcome, self.io_counters());
+        }
+    }
+
+    /// The pager counters of this database and every attached one.
+    fn io_counters(&self) -> PagerCounters {
+        let mut counters = PagerCounters::default();
+        for table in self.tables_iter() {
+            counters += table.pager().counters();
+        }
+        counters
+    }
+
+    /// Runs `insert into .. select`, which may read one database and write
+    /// another. Returns how many rows were copied.
+    fn insert_select(&mut self, statement: &Statement) -> Result<usize, DbError> {
+        let rows = read_rows(self.table(statement.from.as_deref())?, statement.where_id)?;
+        let count = insert_all(&rows, self.writable(statement.into.as_deref())?)?;
+        if self.changes.wanted() {
+            let table = table_name(statement.into.as_deref());
+            for row in &rows {
+                self.changes.record(ChangeKind::Insert, &table, row.id);
+            }
+            self.changes.notify();
+        }
+        Ok(count)
+    }
+
+    /// The table `statement` reads or writes.
+    fn table_for(&mut self, statement: &Statement) -> Result<&mut Table, DbError> {
+        match statement.typ {
+            StatementType::Select => self.table(statement.from.as_deref()),
+            _ => self.writable(statement.into.as_deref()),
+        }
+    }
+
+    /// The table `schema` names, as for `table`, to write to. Fails with
+    /// `DbError::ReadOnly` if the database was opened read-only.
+    fn writable(&mut self, schema: Option<&str>) -> Result<&mut Table, DbError> {
+        if self.settings.read_only {
+            return Err(DbError::ReadOnly);
+        }
+        self.table(schema)
+    }
+
+    /// This database's table for `None`, or that of the database attached
+    /// as `schema`.
+    fn table(&mut self, schema: Option<&str>) -> Result<&mut Table, DbError> {
+        let Some(schema) = schema else {
+            return Ok(&mut self.table);
+        };
+        match self.attached.iter_mut().find(|attached| attached.name == schema) {
+            Some(attached) => Ok(&mut attached.table),
+            None => Err(DbError::NoSuchDatabase(schema.to_string())),
+        }
+    }
+
+    /// Opens the database file at `path` alongside this one, creating it
+    /// with this database's options if it does not exist. Statements reach
+    /// its table as `name.users`, and `insert into .. select` copies rows
+    /// between the two.
+    ///
+    /// Fails if `name` is `main`, already attached or not made of letters,
+    /// digits and `_`, or if `path` is this database's file or one already
+    /// attached.
+    ///
+    /// ```
+    /// use VoidDB::{Database, QueryResult};
+    ///
+    /// let path = std::env::temp_dir().join("voiddb-doctest-attach.db");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut db = Database::open_in_memory();
+    /// db.attach(&path, "archive").unwrap();
+    /// db.execute("insert into archive.users 1 alice alice@example.com").unwrap();
+    /// db.execute("insert into users select from archive.users").unwrap();
+    /// db.detach("archive").unwrap();
+    ///
+    /// assert!(matches!(db.execute("select"), Ok(QueryResult::Rows(rows)) if rows.len() == 1));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn attach<P: AsRef<Path>>(&mut self, path: P, name: &str) -> Result<(), DbError> {
+        let path = path.as_ref();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(DbError::Attach(format!("'{}' is not a valid database name.", name)));
+        }
+        if name == "main" || self.attached.iter().any(|attached| attached.name == name) {
+            return Err(DbError::Attach(format!("Database '{}' is already attached.", name)));
+        }
+        // A file that does not exist yet cannot be open already.
+        let vfs = self.settings.vfs.clone();
+        if let Ok(canonical) = vfs.canonicalize(path) {
+            let main = self.path.iter().map(|path| ("main", path));
+            let attached = self.attached.iter().map(|attached| (attached.name.as_str(), &attached.path));
+            if let Some((other, _)) = main.chain(attached).find(|&(_, path)| *path == canonical) {
+                return Err(DbError::Attach(format!("'{}' is already attached as '{}'.", path.display(), other)));
+            }
+        }
+
+        let options = self.options();
+        let interrupt = &self.table.interrupt;
+        interrupt.clear();
+        let pager = Pager::open_with(path, &options.pager_options(), interrupt)?;
+        let mut table = Table::with_pager(pager, options.layout()?, options.row_checksums)?;
+        // Ctrl-C stops a scan of any attached database too.
+        table.interrupt = interrupt.clone();
+        let path = vfs.canonicalize(path)?;
+        self.attached.push(Attached { name: name.to_string(), path, table });
+        Ok(())
+    }
+
+    /// Writes out and closes the database attached as `name`. A statement
+    /// still reading it holds a borrow of this database, so it has always
+    /// finished by the time this can be called.
+    pub fn detach(&mut self, name: &str) -> Result<(), DbError> {
+        if name == "main" {
+            return Err(DbError::Attach("The main database cannot be detached.".to_string()));
+        }
+        let Some(index) = self.attached.iter().position(|attached| attached.name == name) else {
+            return Err(DbError::NoSuchDatabase(name.to_string()));
+        };
+        self.attached[index].table.flush()?;
+        self.attached.remove(index);
+        Ok(())
+    }
+
+    /// The file this database lives in, or `None` in memory.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The name and file of this database, `main`, then of each attached
+    /// one. The file is `None` for a database in memory.
+    pub fn databases(&self) -> Vec<(&str, Option<&Path>)> {
+        let main = ("main", self.path.as_deref());
+        let attached = self.attached.iter().map(|attached| (attached.name.as_str(), Some(attached.path.as_path())));
+        std::iter::once(main).chain(attached).collect()
+    }
+
+    /// Executes a script with one statement per line, skipping blank lines.
+    /// Stops at the first statement that fails.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// let results = db.execute_batch("insert 1 a a@x.com\ninsert 2 b b@x.com\nselect").unwrap();
+    /// assert_eq!(results.len(), 3);
+    /// ```
+    pub fn execute_batch(&mut self, script: &str) -> Result<Vec<QueryResult>, DbError> {
+        script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| self.execute(line))
+            .collect()
+    }
+
+    /// Writes a copy of this database to a new file at `path`, with the same
+    /// options. The copy is rebuilt row by row rather than copied byte for
+    /// byte, so unused space in this file is left behind. Fails without
+    /// touching anything if `path` already exists; a copy that fails part
+    /// way is removed.
+    pub fn clone_to<P: AsRef<Path>>(&mut self, path: P) -> Result<CloneSummary, DbError> {
+        let path = path.as_ref();
+        // Claims the path, so an existing file is never overwritten.
+        self.settings.vfs.open(path, OpenMode::CreateNew)?;
+
+        let result = self.copy_rows(path);
+        if result.is_err() {
+            let _ = self.settings.vfs.remove(path);
+        }
+        result
+    }
+
+    fn copy_rows(&mut self, path: &Path) -> Result<CloneSummary, DbError> {
+        let mut copy = Database::open_with(path, Options { read_only: false, ..self.options() })?;
+        let mut rows = 0;
+        for row in self.query("select")? {
+            copy.insert_row(&row?)?;
+            rows += 1;
+        }
+        copy.flush()?;
+
+        let pages = (copy.table.pager().file_length() / PAGE_SIZE as u64) as usize;
+        Ok(CloneSummary { rows, pages })
+    }
+
+    /// A read-only view of this database and those attached to it as they
+    /// are now, which later writes do not change. Pages not cached yet are
+    /// read in first.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// let snapshot = db.snapshot().unwrap();
+    /// db.execute("insert 2 bob bob@example.com").unwrap();
+    ///
+    /// assert_eq!(snapshot.select("select").unwrap().count(), 1);
+    /// assert_eq!(db.select("select").unwrap().count(), 2);
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot, DbError> {
+        let mut attached = Vec::with_capacity(self.attached.len());
+        for Attached { name, path, table } in &self.attached {
+            attached.push(Attached { name: name.clone(), path: path.clone(), table: table.snapshot()? });
+        }
+        Ok(Snapshot::new(Database {
+            table: self.table.snapshot()?,
+            path: self.path.clone(),
+            attached,
+            statements: AtomicU64::new(0),
+            on_statement: None,
+            changes: Changes::default(),
+            settings: self.settings.clone(),
+        }))
+    }
+
+    /// The tables statements can name, as they would name them: each
+    /// database holds one, `users` here and `NAME.users` in the database
+    /// attached as NAME.
+    pub fn tables(&self) -> Vec<String> {
+        let attached = self.attached.iter().map(|attached| format!("{}.{}", attached.name, TABLE_NAME));
+        std::iter::once(TABLE_NAME.to_string()).chain(attached).collect()
+    }
+
+    /// The `create table` statements that give a new database the tables of
+    /// this one, with their column sizes: every table's, or only that of
+    /// `table`, named as in `tables`.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let db = Database::open_in_memory();
+    /// let schema = db.schema(Some("users")).unwrap();
+    /// assert_eq!(schema, ["create table users (id integer primary key, username varchar(32), email varchar(255), age integer);"]);
+    ///
+    /// let mut copy = Database::open_in_memory();
+    /// copy.execute(&schema[0]).unwrap();
+    /// ```
+    pub fn schema(&self, table: Option<&str>) -> Result<Vec<String>, DbError> {
+        let main = std::iter::once((None, &self.table));
+        let attached = self.attached.iter().map(|attached| (Some(attached.name.as_str()), &attached.table));
+        let mut tables = main.chain(attached).map(|(schema, table)| (schema, create_table_sql(schema, &table_options(table))));
+        let Some(name) = table else {
+            return Ok(tables.map(|(_, sql)| sql).collect());
+        };
+        let schema = prepare_table(Some(name)).map_err(|_| DbError::NoSuchTable(name.to_string()))?;
+        match tables.find(|(other, _)| *other == schema.as_deref()) {
+            Some((_, sql)) => Ok(vec![sql]),
+            None => Err(DbError::NoSuchTable(name.to_string())),
+        }
+    }
+
+    /// The size of this database and what it has done this session, from
+    /// counters kept as it runs.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.execute("select").unwrap();
+    ///
+    /// let stats = db.stats();
+    /// assert_eq!(stats.tables, [("users".to_string(), 1)]);
+    /// assert_eq!(stats.statements, 2);
+    /// ```
+    pub fn stats(&self) -> Stats {
+        let pager = self.table.pager();
+        let mut stats = Stats {
+            path: self.path.clone(),
+            file_size: pager.file_length(),
+            pages: pager.num_pages(),
+            used_pages: self.table.used_pages(),
+            free_pages: pager.num_pages() - self.table.used_pages(),
+            tables: self.tables().into_iter().zip(self.tables_iter().map(|table| table.num_rows)).collect(),
+            statements: self.statements.load(Ordering::Relaxed),
+            ..Stats::default()
+        };
+        for table in self.tables_iter() {
+            stats.cached_pages += table.pager().cached_pages();
+        }
+        let counters = self.io_counters();
+        stats.cache_hits = counters.cache_hits;
+        stats.cache_misses = counters.cache_misses;
+        stats.bytes_read = counters.bytes_read;
+        stats.bytes_written = counters.bytes_written;
+        stats
+    }
+
+    /// Zeroes the counters `stats` reports for this session: cache hits and
+    /// misses, bytes read and written, and statements run.
+    pub fn reset_stats(&mut self) {
+        self.statements = AtomicU64::new(0);
+        self.table.pager_mut().reset_counters();
+        for attached in &mut self.attached {
+            attached.table.pager_mut().reset_counters();
+        }
+    }
+
+    /// A snapshot of the row and page counters. They are bumped as the
+    /// work happens, so taking one costs a few loads.
+    ///
+    /// ```
+    /// use VoidDB::Database;
+    ///
+    /// let mut db = Database::open_in_memory();
+    /// let before = db.metrics();
+    /// db.execute("insert 1 alice alice@example.com").unwrap();
+    /// db.execute("select").unwrap();
+    ///
+    /// let work = db.metrics() - before;
+    /// assert_eq!((work.rows_inserted, work.rows_read), (1, 1));
+    /// ```
+    pub fn metrics(&self) -> Metrics {
+        let io = self.io_counters();
+        let mut metrics = Metrics {
+            pages_allocated: io.pages_allocated,
+            pages_read: io.pages_read,
+            pages_written: io.pages_written,
+            cache_hits: io.cache_hits,
+            cache_misses: io.cache_misses,
+            bytes_flushed: io.bytes_written,
+            ..Metrics::default()
+        };
+        for table in self.tables_iter() {
+            metrics.rows_inserted += table.rows.inserted.load(Ordering::Relaxed);
+            metrics.rows_deleted += table.rows.deleted.load(Ordering::Relaxed);
+            metrics.rows_read += table.rows.read.load(Ordering::Relaxed);
+        }
+        metrics
+    }
+
+    /// Zeroes the counters `metrics` reports. The cache and byte counters
+    /// are shared with `stats`, so this zeroes those there too.
+    pub fn reset_metrics(&mut self) {
+        for table in std::iter::once(&mut self.table).chain(self.attached.iter_mut().map(|attached| &mut attached.table)) {
+            table.pager_mut().reset_counters();
+            table.rows = Default::default();
+        }
+    }
+
+    /// The main table, then those of the attached databases.
+    fn tables_iter(&self) -> impl Iterator<Item = &Table> {
+        std::iter::once(&self.table).chain(self.attached.iter().map(|attached| &attached.table))
+    }
+
+    /// Whether this database or an attached one has changes that `flush`
+    /// has not written out yet.
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.table.is_dirty() || self.attached.iter().any(|attached| attached.table.is_dirty())
+    }
+
+    /// Writes out this database and every attached one.
+    pub fn flush(&mut self) -> Result<(), DbError> {
+        self.table.flush()?;
+        for attached in &mut self.attached {
+            attached.table.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn close(mut self) -> Result<(), DbError> {
+        self.flush()
+    }
+}
+
+/// The column sizes and checksum setting of `table`.
+fn table_options(table: &Table) -> Options {
+    let layout = table.layout();
+    Options { username_size: layout.username_size(), email_size: layout.email_size(), row_checksums: table.checksums(), ..Options::default() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::ExecuteError;
+    use crate::error::ErrorCode;
+    use crate::row::RowError;
+    use crate::testing::TempPath;
+    use std::fs::{self, OpenOptions};
+    use std::time::Duration;
+
+    #[test]
+    fn test_reopen_keeps_rows() {
+        let path = TempPath::new("reopen.db");
+
+        let mut db = Database::open(&path).unwrap();
+        for i in 0..20 {
+            db.execute(&format!("insert {} user{} user{}@example.com", i, i, i)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let rows = match db.execute("select").unwrap() {
+            QueryResult::Rows(rows) => rows,
+            other => panic!("select returned {:?}", other),
+        };
+        assert_eq!(rows.len(), 20);
+        assert_eq!(rows[19].id, 19);
+    }
+
+    #[test]
+    fn test_query_reads_pages_lazily() {
+        let path = TempPath::new("lazy.db");
+
+        let mut db = Database::open(&path).unwrap();
+        for i in 0..100 {
+            db.execute(&format!("insert {} user{} user{}@example.com", i, i, i)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        // Opening reads the header page only.
+        assert_eq!(db.table.pager().pages_read(), 1);
+
+        let first = db.query("select").unwrap().next().unwrap().unwrap();
+        assert_eq!(first.id, 0);
+        assert_eq!(db.table.pager().pages_read(), 2);
+
+        let count = db.query("select").unwrap().count();
+        assert_eq!(count, 100);
+        let data_pages = (100 * crate::row::ROW_SIZE).div_ceil(crate::pager::PAGE_SIZE);
+        assert_eq!(db.table.pager().pages_read() as usize, 1 + data_pages);
+    }
+
+    #[test]
+    fn test_execute_with_stops_early() {
+        let path = TempPath::new("scan.db");
+
+        let mut db = Database::open(&path).unwrap();
+        for i in 0..100 {
+            db.execute(&format!("insert {} user{} user{}@example.com", i, i, i)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let mut seen = 0;
+        let found = db.execute_with("select", |row| {
+            seen += 1;
+            if row.id() == 3 { ControlFlow::Break(Ok(row.id())) } else { ControlFlow::Continue(()) }
+        });
+
+        assert_eq!(found.unwrap(), Some(3));
+        assert_eq!(seen, 4);
+        // The header plus the first page of rows, not the whole table.
+        assert_eq!(db.table.pager().pages_read(), 2);
+
+        // An error the callback stops with fails the statement.
+        let mut seen = 0;
+        let err = db.execute_with::<()>("select", |_| {
+            seen += 1;
+            ControlFlow::Break(Err(DbError::Corrupt("bad row".to_string())))
+        });
+        assert!(matches!(err, Err(DbError::Corrupt(_))));
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn test_ids_beyond_u32_survive_reopen() {
+        let path = TempPath::new("u64.db");
+        let ids = [u32::MAX as u64 - 1, u32::MAX as u64, 1 << 32, 9999999999, u64::MAX];
+
+        let mut db = Database::open(&path).unwrap();
+        for id in ids {
+            db.execute(&format!("insert {} user user@example.com", id)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let selected: Vec<u64> = db.query("select").unwrap().map(|row| row.unwrap().id).collect();
+        assert_eq!(selected, ids);
+    }
+
+    #[test]
+    fn test_custom_column_widths_persist() {
+        let path = TempPath::new("widths.db");
+        let options = Options { username_size: 64, email_size: 20, ..Options::default() };
+        let username = "u".repeat(64);
+        let email = "e".repeat(20);
+
+        let mut db = Database::open_with(&path, options).unwrap();
+        db.execute(&format!("insert 1 {} {}", username, email)).unwrap();
+        assert!(matches!(
+            db.execute(&format!("insert 2 {} {}e", username, email)),
+            Err(DbError::Row(RowError::ValueTooLong { column: "email", max: 20, got: 21 }))
+        ));
+        db.close().unwrap();
+
+        // Reopening with the defaults still uses the widths from the file.
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.table.layout(), RowLayout::new(64, 20).unwrap());
+        let rows: Vec<_> = db.query("select").unwrap().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].username(), username);
+        assert_eq!(rows[0].email(), email);
+    }
+
+    #[test]
+    fn test_row_checksum_catches_single_corrupt_row() {
+        let path = TempPath::new("crc.db");
+        let options = Options { row_checksums: true, ..Options::default() };
+
+        let mut db = Database::open_with(&path, options).unwrap();
+        for i in 0..3 {
+            db.execute(&format!("insert {} user{} user{}@example.com", i, i, i)).unwrap();
+        }
+        db.close().unwrap();
+
+        // Flip a byte in the username of the second row.
+        let cell_size = crate::row::ROW_SIZE + 4;
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[crate::pager::PAGE_SIZE + cell_size + 10] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        assert!(db.table.checksums());
+        let results: Vec<_> = db.query("select").unwrap().collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().id, 0);
+        match &results[1] {
+            Err(err @ DbError::CorruptRow { row_num: 1, page_num: 1, err: RowError::ChecksumMismatch { id: 1 } }) => {
+                assert_eq!(err.code(), "CHECKSUM_MISMATCH");
+                assert_eq!(err.to_string(), "Database is corrupt: row 1 on page 1: checksum mismatch for row with id 1");
+            }
+            other => panic!("expected a checksum error, got {:?}", other),
+        }
+        assert_eq!(results[2].as_ref().unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_bad_stored_checksum_is_a_checksum_mismatch() {
+        let path = TempPath::new("bad-crc.db");
+        crate::testing::write_bad_checksum(&path);
+
+        let db = Database::open(&path).unwrap();
+        let err = db.select("select").unwrap().nth(1).unwrap().unwrap_err();
+        assert_eq!(err.error_code(), ErrorCode::ChecksumMismatch);
+        drop(db);
+    }
+
+    #[test]
+    fn test_unicode_values_survive_reopen() {
+        let path = TempPath::new("utf8.db");
+        let mut gen = crate::testing::Gen::new(2024);
+
+        let mut expected = Vec::new();
+        let mut db = Database::open(&path).unwrap();
+        for id in 0..300 {
+            let username = gen.word(DEFAULT_USERNAME_SIZE);
+            let email = gen.word(DEFAULT_EMAIL_SIZE);
+            db.execute(&format!("insert {} {} {}", id, username, email)).unwrap();
+            expected.push((username, email));
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let rows: Vec<_> = db.query("select").unwrap().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), expected.len());
+        for (row, (username, email)) in rows.iter().zip(&expected) {
+            assert_eq!(row.username().as_bytes(), username.as_bytes());
+            assert_eq!(row.email().as_bytes(), email.as_bytes());
+            assert_eq!(row.to_string(), format!("({}, {}, {}, 0)", row.id, username, email));
+        }
+    }
+
+    #[test]
+    fn test_limits_count_bytes_not_chars() {
+        let mut db = Database::open_in_memory();
+
+        // 8 four-byte characters fill the 32-byte username exactly.
+        let emoji = "😀".repeat(8);
+        db.execute(&format!("insert 1 {} a@x", emoji)).unwrap();
+        // 9 of them is only 9 characters but 36 bytes.
+        assert!(matches!(
+            db.execute(&format!("insert 2 {}😀 a@x", emoji)),
+            Err(DbError::Row(RowError::ValueTooLong { column: "username", max: 32, got: 36 }))
+        ));
+        // 32 one-byte characters also fit, 33 do not.
+        db.execute(&format!("insert 3 {} a@x", "a".repeat(32))).unwrap();
+        assert!(db.execute(&format!("insert 4 {} a@x", "a".repeat(33))).is_err());
+
+        // Combining marks and right-to-left text are stored as given, not
+        // normalized or reordered.
+        let combining = "e\u{301}\u{301}\u{301}";
+        let hebrew = "שלום@דוגמה.ישראל";
+        db.execute(&format!("insert 5 {} {}", combining, hebrew)).unwrap();
+
+        let rows: Vec<_> = db.query("select").unwrap().map(Result::unwrap).collect();
+        assert_eq!(rows[0].username(), emoji);
+        assert_eq!(rows[2].username(), combining);
+        assert_eq!(rows[2].email(), hebrew);
+    }
+
+    #[test]
+    fn test_open_rejects_old_format_version() {
+        let path = TempPath::new("v1.db");
+        let mut header = vec![0u8; crate::pager::PAGE_SIZE];
+        header[0..8].copy_from_slice(b"VoidDB\0\0");
+        header[8..12].copy_from_slice(&1u32.to_le_bytes());
+        std::fs::write(&path, &header).unwrap();
+
+        match Database::open(&path) {
+            Err(DbError::Corrupt(msg)) => assert!(msg.contains("format version 1"), "{}", msg),
+            other => panic!("expected a version error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_execute_reports_prepare_errors() {
+        let mut db = Database::open_in_memory();
+        assert!(matches!(db.execute("insert 1 a"), Err(DbError::Prepare(_))));
+        assert!(matches!(db.execute("update"), Err(DbError::Prepare(_))));
+    }
+
+    #[test]
+    fn test_interrupt_stops_scans() {
+        let mut db = Database::open_in_memory();
+        db.execute_batch("insert 1 a a@x\ninsert 2 b b@x\ninsert 3 c c@x").unwrap();
+        let interrupt = db.interrupt_handle();
+
+        // A query stops at the next row, and ends there.
+        let mut rows = db.query("select").unwrap();
+        assert_eq!(rows.next().unwrap().unwrap().id, 1);
+        interrupt.interrupt();
+        assert!(matches!(rows.next(), Some(Err(DbError::Interrupted))));
+        assert!(rows.next().is_none());
+        assert!(!interrupt.is_interrupted());
+
+        let mut seen = 0;
+        let result = db.execute_with::<()>("select", |_| {
+            seen += 1;
+            interrupt.interrupt();
+            ControlFlow::Continue(())
+        });
+        assert!(matches!(result, Err(DbError::Interrupted)));
+        assert_eq!(seen, 1);
+
+        // A request made before the statement starts stops nothing.
+        let remote = db.interrupt_handle();
+        std::thread::spawn(move || remote.interrupt()).join().unwrap();
+        assert!(matches!(db.execute("select"), Ok(QueryResult::Rows(rows)) if rows.len() == 3));
+        assert!(!interrupt.is_interrupted());
+
+        // A select through `&self` may start while another statement runs,
+        // so it leaves a request meant for that one in place.
+        interrupt.interrupt();
+        let _ = db.select("select where id = 2").unwrap();
+        assert!(interrupt.is_interrupted());
+    }
+
+    #[test]
+    fn test_selects_leave_interrupts_to_the_statement_they_stop() {
+        let connection = crate::Connection::open_in_memory();
+        for id in 0..200 {
+            connection.execute(&format!("insert {} user{} user{}@x", id, id, id)).unwrap();
+        }
+        let interrupt = connection.read().interrupt_handle();
+
+        // A scan is stopped just as another reader starts a select.
+        let reader = connection.read();
+        let mut rows = reader.select("select").unwrap();
+        assert!(rows.next().unwrap().is_ok());
+        interrupt.interrupt();
+        std::thread::scope(|scope| {
+            scope.spawn(|| drop(connection.read().select("select where id = 5").unwrap())).join().unwrap();
+        });
+        assert!(matches!(rows.next(), Some(Err(DbError::Interrupted))));
+    }
+
+    #[test]
+    fn test_interrupt_from_another_thread_stops_a_long_scan() {
+        let mut db = Database::open_in_memory();
+        let rows = (1..=1000).map(|id| Row::new(id, "user", "user@example.com"));
+        db.insert_rows(rows, OnConflict::Fail).unwrap();
+
+        let interrupt = db.interrupt_handle();
+        let (started, wait) = std::sync::mpsc::channel();
+        let stopper = std::thread::spawn(move || {
+            wait.recv().unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            interrupt.interrupt();
+        });
+        let start = Instant::now();
+        let mut seen = 0;
+        // At a millisecond a row the scan would take a second on its own.
+        let result = db.execute_with::<()>("select", |_| {
+            if seen == 0 {
+                started.send(()).unwrap();
+            }
+            seen += 1;
+            std::thread::sleep(Duration::from_millis(1));
+            ControlFlow::Continue(())
+        });
+        let elapsed = start.elapsed();
+        stopper.join().unwrap();
+
+        assert!(matches!(result, Err(DbError::Interrupted)), "{:?}", result);
+        assert!(seen < 1000, "saw {} rows", seen);
+        assert!(elapsed < Duration::from_millis(900), "took {:?}", elapsed);
+        assert_eq!(db.select("select").unwrap().count(), 1000);
+    }
+
+    #[test]
+    fn test_busy_timeout() {
+        let path = TempPath::new("busy.db");
+        let patient = Options::new().busy_timeout(Duration::from_secs(5));
+
+        // Freed part way through the wait.
+        let holder = Database::open(&path).unwrap();
+        assert!(matches!(Database::open(&path), Err(DbError::Locked)));
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            holder.close().unwrap();
+        });
+        let start = Instant::now();
+        let db = patient.open(&path).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40), "{:?}", start.elapsed());
+        release.join().unwrap();
+        assert_eq!(db.options().busy_timeout, Duration::from_secs(5));
+
+        // Never freed.
+        let start = Instant::now();
+        let err = Options::new().busy_timeout(Duration::from_millis(100)).open(&path).err().unwrap();
+        assert!(matches!(err, DbError::Locked));
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_millis(100) && waited < Duration::from_secs(2), "{:?}", waited);
+
+        // An attach waits too, until interrupted.
+        let mut other = patient.open_in_memory().unwrap();
+        let interrupt = other.interrupt_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            interrupt.interrupt();
+        });
+        let start = Instant::now();
+        assert!(matches!(other.attach(&path, "busy"), Err(DbError::Interrupted)));
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+        stopper.join().unwrap();
+
+        drop(db);
+        other.attach(&path, "busy").unwrap();
+        drop(other);
+    }
+
+    #[test]
+    fn test_options_set_the_layout_of_a_new_file() {
+        let path = TempPath::new("options-layout.db");
+        let narrow = Options::new().username_size(8).email_size(20).row_checksums(true);
+        let mut db = narrow.open(&path).unwrap();
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        assert_eq!(db.execute("insert 2 bartholomew bart@example.com").unwrap_err().code(), "VALUE_TOO_LONG");
+        db.close().unwrap();
+
+        // The file keeps its own layout whatever the options say.
+        let db = Options::new().username_size(64).open(&path).unwrap();
+        assert_eq!(db.options(), narrow);
+        drop(db);
+    }
+
+    #[test]
+    fn test_read_only_databases_share_the_file_and_only_select() {
+        let path = TempPath::new("read-only.db");
+        let mut db = Database::open(&path).unwrap();
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        db.close().unwrap();
+        let length = fs::metadata(&path).unwrap().len();
+
+        let reader = Options::new().read_only(true);
+        let mut first = reader.open(&path).unwrap();
+        let second = reader.open(&path).unwrap();
+        assert!(matches!(Database::open(&path), Err(DbError::Locked)));
+        assert_eq!(first.select("select").unwrap().count(), 1);
+        assert_eq!(second.select("select").unwrap().next().unwrap().unwrap().id, 1);
+        assert!(first.options().read_only);
+
+        assert!(matches!(first.execute("insert 2 bob bob@example.com"), Err(DbError::ReadOnly)));
+        assert!(matches!(first.execute("create table users (id integer, username varchar(8), email varchar(8), age integer)"), Err(DbError::ReadOnly)));
+        assert!(matches!(first.insert_rows([Row::new(3, "carol", "c@x")], OnConflict::Abort), Err(DbError::ReadOnly)));
+        first.close().unwrap();
+        drop(second);
+
+        // Nothing was written, and a writer can have the file back.
+        assert_eq!(fs::metadata(&path).unwrap().len(), length);
+        let mut db = Database::open(&path).unwrap();
+        assert_eq!(db.select("select").unwrap().count(), 1);
+        db.execute("insert 2 bob bob@example.com").unwrap();
+        drop(db);
+    }
+
+    #[test]
+    fn test_options_that_cannot_open_a_file() {
+        let path = TempPath::new("cannot-open.db");
+        let shown = path.display();
+
+        let err = Options::new().read_only(true).open(&path).err().unwrap();
+        assert_eq!(err.to_string(), format!("Error: Cannot open '{}': it does not exist, and a read-only database cannot create it.", shown));
+        let err = Options::new().read_only(true).create_if_missing(false).open(&path).err().unwrap();
+        assert_eq!(err.to_string(), format!("Error: Cannot open '{}': it does not exist, and create_if_missing is off.", shown));
+        let err = Options::new().create_if_missing(false).open(&path).err().unwrap();
+        assert_eq!(err.code(), "CANNOT_OPEN");
+        let err = Options::new().cache_pages(0).open(&path).err().unwrap();
+        assert_eq!(err.to_string(), format!("Error: Cannot open '{}': the cache must hold at least one page.", shown));
+        assert!(!path.exists());
+
+        // A read-only database attaches others read-only too.
+        Database::open(&path).unwrap().close().unwrap();
+        let mut reader = Options::new().read_only(true).open(&path).unwrap();
+        let err = reader.attach(path.with_extension("missing"), "other").err().unwrap();
+        assert_eq!(err.code(), "CANNOT_OPEN");
+        drop(reader);
+    }
+
+    #[test]
+    fn test_cache_pages_bounds_the_cache_after_a_flush() {
+        let path = TempPath::new("cache-pages.db");
+        let mut db = Options::new().cache_pages(4).open(&path).unwrap();
+        for id in 1..=300 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        assert!(db.stats().cached_pages > 4);
+        db.flush().unwrap();
+        assert_eq!(db.stats().cached_pages, 4);
+        assert_eq!(db.options().cache_pages, 4);
+
+        // The pages let go of are read back from the file.
+        let read = db.metrics().pages_read;
+        assert_eq!(db.select("select").unwrap().count(), 300);
+        assert!(db.metrics().pages_read - read >= db.stats().pages as u64 - 4);
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        db.select("select").unwrap().count();
+        db.flush().unwrap();
+        assert_eq!(db.stats().cached_pages, db.stats().pages);
+        drop(db);
+    }
+
+    #[test]
+    fn test_sync_mode_decides_whether_a_flush_syncs() {
+        let path = TempPath::new("sync.db");
+        for (mode, syncs) in [(SyncMode::Full, 2), (SyncMode::Normal, 2), (SyncMode::Off, 0)] {
+            let _ = fs::remove_file(&path);
+            let mut db = Options::new().sync(mode).open(&path).unwrap();
+            db.execute("insert 1 alice alice@example.com").unwrap();
+            db.flush().unwrap();
+            db.execute("insert 2 bob bob@example.com").unwrap();
+            db.flush().unwrap();
+            assert_eq!(db.table.pager().syncs(), syncs, "{:?}", mode);
+            db.close().unwrap();
+            assert_eq!(Database::open(&path).unwrap().select("select").unwrap().count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_clone_to() {
+        let dir = TempPath::new("clone");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (source, copy) = (dir.join("source.db"), dir.join("copy.db"));
+        let options = Options { username_size: 16, row_checksums: true, ..Options::default() };
+
+        let mut db = Database::open_with(&source, options.clone()).unwrap();
+        for i in 0..30 {
+            db.execute(&format!("insert {} user{} user{}@example.com {}", i * 7, i, i, i)).unwrap();
+        }
+        db.close().unwrap();
+        // Unused pages at the end of the file, which the clone leaves out.
+        let mut file = OpenOptions::new().append(true).open(&source).unwrap();
+        std::io::Write::write_all(&mut file, &[0; 3 * PAGE_SIZE]).unwrap();
+        drop(file);
+        let source_pages = std::fs::metadata(&source).unwrap().len() as usize / PAGE_SIZE;
+
+        let mut db = Database::open(&source).unwrap();
+        let summary = db.clone_to(&copy).unwrap();
+        assert_eq!(summary.rows, 30);
+        assert_eq!(summary.pages, source_pages - 3);
+        assert_eq!(std::fs::metadata(&copy).unwrap().len() as usize, summary.pages * PAGE_SIZE);
+
+        // The source is still open and usable.
+        db.execute("insert 1000 late late@example.com").unwrap();
+        let mut cloned = Database::open(&copy).unwrap();
+        assert_eq!(cloned.options(), options);
+        let rows: Vec<Row> = cloned.query("select").unwrap().map(Result::unwrap).collect();
+        let expected: Vec<Row> = db.query("select").unwrap().map(Result::unwrap).take(30).collect();
+        assert_eq!(rows, expected);
+
+        let before = std::fs::read(&copy).unwrap();
+        assert!(matches!(db.clone_to(&copy), Err(DbError::Io(err)) if err.kind() == std::io::ErrorKind::AlreadyExists));
+        assert_eq!(std::fs::read(&copy).unwrap(), before);
+    }
+
+    #[test]
+    fn test_attach_copies_rows_between_files() {
+        let dir = TempPath::new("attach");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (main, other) = (dir.join("main.db"), dir.join("other.db"));
+        let select = |path: &Path| -> Vec<u64> {
+            let mut db = Database::open(path).unwrap();
+            let ids = db.query("select").unwrap().map(|row| row.unwrap().id).collect();
+            ids
+        };
+
+        let mut db = Database::open(&main).unwrap();
+        db.execute("insert 1 alice a@x").unwrap();
+        db.attach(&other, "archive").unwrap();
+        db.execute("insert into archive.users 2 bob b@x").unwrap();
+        db.execute("insert into archive.users 3 carol c@x").unwrap();
+        assert_eq!(db.execute("insert into main.users select from archive.users").unwrap(), QueryResult::RowsAffected(2));
+        let ids: Vec<u64> = db.query("select from archive.users").unwrap().map(|row| row.unwrap().id).collect();
+        assert_eq!(ids, [2, 3]);
+
+        // A copy that hits a duplicate key copies nothing.
+        db.execute("insert into archive.users 4 dave d@x").unwrap();
+        let err = db.execute("insert into users select from archive.users").unwrap_err();
+        assert!(matches!(err, DbError::Execute(ExecuteError::DuplicateKey)));
+        assert_eq!(db.query("select").unwrap().count(), 3);
+
+        assert_eq!(db.databases(), [("main", Some(main.canonicalize().unwrap().as_path())), ("archive", Some(other.canonicalize().unwrap().as_path()))]);
+        db.detach("archive").unwrap();
+        assert!(matches!(db.execute("select from archive.users"), Err(DbError::NoSuchDatabase(name)) if name == "archive"));
+        db.close().unwrap();
+
+        assert_eq!(select(&main), [1, 2, 3]);
+        assert_eq!(select(&other), [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_stats_count_a_known_workload() {
+        let path = TempPath::new("stats.db");
+        let mut db = Database::open(&path).unwrap();
+        for id in 1..=3 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        db.execute("select").unwrap();
+        db.flush().unwrap();
+        // The first insert starts pages 0 and 1, two misses, then page 1
+        // serves three inserts and three reads; the flush writes the header
+        // to page 0 and writes both pages.
+        let stats = db.stats();
+        assert_eq!(
+            stats,
+            Stats {
+                path: Some(fs::canonicalize(&path).unwrap()),
+                file_size: 2 * PAGE_SIZE as u64,
+                pages: 2,
+                used_pages: 2,
+                free_pages: 0,
+                tables: vec![("users".to_string(), 3)],
+                cached_pages: 2,
+                cache_hits: 7,
+                cache_misses: 2,
+                bytes_read: 0,
+                bytes_written: 2 * PAGE_SIZE as u64,
+                statements: 4,
+            }
+        );
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        db.reset_stats();
+        db.execute("select").unwrap();
+        db.execute("select").unwrap();
+        let stats = db.stats();
+        assert_eq!((stats.cache_hits, stats.cache_misses, stats.bytes_read, stats.statements), (5, 1, PAGE_SIZE as u64, 2));
+
+        // The duplicate check reads every row once to index the ids.
+        db.execute("insert 4 d d@x").unwrap();
+        db.execute("insert 1 dup dup@x").unwrap_err();
+        let stats = db.stats();
+        assert_eq!((stats.cache_hits, stats.cache_misses, stats.statements), (9, 1, 4));
+        assert_eq!(stats.tables, [("users".to_string(), 4)]);
+
+        db.reset_stats();
+        assert_eq!((db.stats().cache_hits, db.stats().bytes_read, db.stats().statements), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_metrics_count_a_known_workload() {
+        let path = TempPath::new("metrics.db");
+        let mut db = Database::open(&path).unwrap();
+        for id in 1..=3 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        db.execute("insert 1 dup dup@x").unwrap_err();
+        db.execute("select").unwrap();
+        db.execute("select where id = 2").unwrap();
+        db.flush().unwrap();
+        // The first insert starts pages 0 and 1 and the rows go on page 1;
+        // the flush writes the header to page 0 and writes both.
+        assert_eq!(
+            db.metrics(),
+            Metrics {
+                rows_inserted: 3,
+                rows_deleted: 0,
+                rows_read: 4,
+                pages_allocated: 2,
+                pages_read: 0,
+                pages_written: 2,
+                cache_hits: 8,
+                cache_misses: 2,
+                bytes_flushed: 2 * PAGE_SIZE as u64,
+            }
+        );
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let before = db.metrics();
+        db.execute("select").unwrap();
+        db.truncate(1);
+        db.flush().unwrap();
+        let work = db.metrics() - before;
+        assert_eq!((work.rows_read, work.rows_deleted, work.pages_read, work.pages_allocated), (3, 2, 1, 0));
+        // Only the header changed, so only page 0 is written.
+        assert_eq!((work.pages_written, work.bytes_flushed), (1, PAGE_SIZE as u64));
+
+        db.reset_metrics();
+        assert_eq!(db.metrics(), Metrics::default());
+        db.execute("insert 5 e e@x").unwrap();
+        assert_eq!((db.metrics().rows_inserted, db.metrics().pages_allocated), (1, 0));
+    }
+
+    #[test]
+    fn test_stats_free_pages() {
+        let mut db = Database::open_in_memory();
+        let rows_per_page = PAGE_SIZE / crate::row::ROW_SIZE;
+        for id in 0..=rows_per_page {
+            db.execute(&format!("insert {} u u@x", id)).unwrap();
+        }
+        db.truncate(1);
+        let stats = db.stats();
+        assert_eq!((stats.pages, stats.used_pages, stats.free_pages), (3, 2, 1));
+        assert_eq!(stats.path, None);
+        assert_eq!(stats.file_size, 0);
+    }
+
+    #[test]
+    fn test_attach_errors() {
+        let dir = TempPath::new("attach-errors");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (main, other) = (dir.join("main.db"), dir.join("other.db"));
+        let message = |result: Result<(), DbError>| result.unwrap_err().to_string();
+
+        let mut db = Database::open(&main).unwrap();
+        db.attach(&other, "archive").unwrap();
+        assert_eq!(message(db.attach(dir.join("third.db"), "archive")), "Error: Database 'archive' is already attached.");
+        assert_eq!(message(db.attach(dir.join("third.db"), "main")), "Error: Database 'main' is already attached.");
+        assert_eq!(message(db.attach(dir.join("third.db"), "a.b")), "Error: 'a.b' is not a valid database name.");
+        let twice = dir.join(".").join("other.db");
+        assert_eq!(message(db.attach(&twice, "again")), format!("Error: '{}' is already attached as 'archive'.", twice.display()));
+        assert_eq!(message(db.attach(&main, "again")), format!("Error: '{}' is already attached as 'main'.", main.display()));
+        assert!(!dir.join("third.db").exists());
+
+        assert_eq!(message(db.detach("main")), "Error: The main database cannot be detached.");
+        assert!(matches!(db.detach("nope"), Err(DbError::NoSuchDatabase(_))));
+        assert!(matches!(db.execute("insert into nope.users 1 a a@x"), Err(DbError::NoSuchDatabase(_))));
+        db.detach("archive").unwrap();
+        assert!(matches!(db.detach("archive"), Err(DbError::NoSuchDatabase(_))));
+        // Once detached, the file can be attached again.
+        db.attach(&other, "archive").unwrap();
+    }
+}
@@ -0,0 +1,458 @@
+//! How the REPL renders statement results in each output mode.
+
+use std::io::{self, Write};
+
+use crate::compiler::StatementType;
+use crate::csv;
+use crate::json;
+use crate::output::Output;
+use crate::row::{Value, COLUMNS};
+use crate::style::{self, ColorChoice, Style};
+
+/// How many rows column mode looks at to size its columns before it starts
+/// printing. Later rows that do not fit are truncated.
+pub const COLUMN_SAMPLE_ROWS: usize = 100;
+
+/// How select results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// `(1, alice, alice@example.com, 34)`, one row per line.
+    List,
+    /// RFC 4180 CSV.
+    Csv,
+    /// A JSON array of objects, one per row.
+    Json,
+    /// Aligned columns under a header.
+    Column,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::List => "list",
+            Mode::Csv => "csv",
+            Mode::Json => "json",
+            Mode::Column => "column",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<Mode> {
+        match name {
+            "list" => Some(Mode::List),
+            "csv" => Some(Mode::Csv),
+            "json" => Some(Mode::Json),
+            "column" => Some(Mode::Column),
+            _ => None,
+        }
+    }
+}
+
+/// How errors are printed, set by `.errors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    /// One JSON object per error, with a stable code. JSON mode always
+    /// prints errors this way.
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorFormat::Text => "text",
+            ErrorFormat::Json => "json",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<ErrorFormat> {
+        match name {
+            "text" => Some(ErrorFormat::Text),
+            "json" => Some(ErrorFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Display settings changed by meta commands, kept per session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub mode: Mode,
+    pub headers: bool,
+    /// Shown in place of NULL in every mode but JSON, which writes `null`.
+    pub null_value: String,
+    /// Column mode widths set by `.width`, in column order. 0 sizes the
+    /// column to fit and a negative width right-aligns it; columns past the
+    /// end of the list are sized to fit.
+    pub widths: Vec<i32>,
+    /// Leave out the summary line after each statement, for scripts.
+    pub quiet: bool,
+    /// Stop after each screenful of rows when printing to a terminal.
+    pub pager: bool,
+    pub color: ColorChoice,
+    /// Print each line of input back before running it.
+    pub echo: bool,
+    pub errors: ErrorFormat,
+    /// Field separator for CSV output and `.import`.
+    pub separator: char,
+    /// Record interactive lines in the command history, and its file.
+    pub history: bool,
+    /// Stop a `.read` script at its first error.
+    pub bail: bool,
+    /// Print how long each statement took.
+    pub timer: bool,
+    /// Shown before each line typed; see `render_prompt`.
+    pub prompt: String,
+    /// Shown before the second and later lines of one statement.
+    pub continuation_prompt: String,
+}
+
+// The prompts a session starts with.
+pub const DEFAULT_PROMPT: &str = "db > ";
+pub const DEFAULT_CONTINUATION_PROMPT: &str = "...> ";
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { mode: Mode::List, headers: false, null_value: String::new(), widths: Vec::new(), quiet: false, pager: true, color: ColorChoice::Auto, echo: false, errors: ErrorFormat::Text, separator: csv::DEFAULT_SEPARATOR, history: true, bail: false, timer: false, prompt: DEFAULT_PROMPT.to_string(), continuation_prompt: DEFAULT_CONTINUATION_PROMPT.to_string() }
+    }
+}
+
+/// Prints the result of one statement. Rows are handed over one at a time
+/// as the scan reads them, and `finish` closes off whatever the mode opened.
+pub(crate) struct Printer<'s> {
+    settings: &'s Settings,
+    color: bool,
+    printed: usize,
+    /// Rendered rows held back by column mode until it knows how wide to
+    /// make them.
+    sample: Vec<Vec<String>>,
+    columns: Option<Vec<Column>>,
+}
+
+/// A column mode column once its size is settled.
+#[derive(Debug, Clone, Copy)]
+struct Column {
+    width: usize,
+    right: bool,
+}
+
+impl<'s> Printer<'s> {
+    pub(crate) fn new(settings: &'s Settings, color: bool) -> Self {
+        Printer { settings, color, printed: 0, sample: Vec::new(), columns: None }
+    }
+
+    /// How many rows have been printed so far.
+    pub(crate) fn printed(&self) -> usize {
+        self.printed
+    }
+
+    /// Prints anything held back, so the screen is up to date before the
+    /// pager stops to ask whether to go on.
+    pub(crate) fn flush(&mut self, output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Column if self.columns.is_none() => self.flush_sample(output),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reports a statement that changed rows rather than returning them.
+    pub(crate) fn affected(&self, statement_type: StatementType, count: usize, output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Json => writeln!(output.results(), "{{\"rows_affected\":{}}}", count),
+            _ if self.settings.quiet => Ok(()),
+            _ => {
+                let verb = match statement_type {
+                    StatementType::Insert | StatementType::InsertSelect => "inserted",
+                    StatementType::Select => "selected",
+                    // Nothing to count.
+                    StatementType::CreateTable => return Ok(()),
+                };
+                output.message(&format!("{} {}", plural(count), verb))
+            }
+        }
+    }
+
+    /// Reports how many rows a select printed, once it has finished.
+    pub(crate) fn summary(&self, output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Json => Ok(()),
+            _ if self.settings.quiet => Ok(()),
+            _ => output.message(&format!("({})", plural(self.printed))),
+        }
+    }
+
+    pub(crate) fn begin(&mut self, output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Csv if self.settings.headers => csv::write_record(output.results(), self.settings.separator, COLUMNS),
+            Mode::Json => output.results().write_all(b"["),
+            _ => Ok(()),
+        }
+    }
+
+    /// Prints one row, given as its values in `COLUMNS` order.
+    pub(crate) fn row(&mut self, values: &[Value<'_>], output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::List => {
+                let cells: Vec<String> = values.iter().map(|&value| self.cell(value)).collect();
+                writeln!(output.results(), "({})", cells.join(", "))?;
+            }
+            Mode::Csv => {
+                let cells = values.iter().map(|&value| self.cell(value));
+                csv::write_record(output.results(), self.settings.separator, cells)?
+            }
+            Mode::Json => {
+                // Each row is written as soon as it is read, so a large result
+                // streams out instead of being built up in memory.
+                let out = output.results();
+                if self.printed > 0 {
+                    out.write_all(b",\n")?;
+                }
+                json::write_object(out, COLUMNS.into_iter().zip(values.iter().copied()))?;
+            }
+            Mode::Column => {
+                let cells: Vec<String> = values.iter().map(|&value| self.cell(value)).collect();
+                match &self.columns {
+                    Some(columns) => write_columns(output.results(), columns, cells)?,
+                    None => {
+                        self.sample.push(cells);
+                        if self.sample.len() == COLUMN_SAMPLE_ROWS {
+                            self.flush_sample(output)?;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        self.printed += 1;
+        Ok(())
+    }
+
+    /// Closes whatever `begin` opened. Called even if the scan stopped early,
+    /// so the output stays well-formed.
+    pub(crate) fn finish(&mut self, output: &mut dyn Output) -> io::Result<()> {
+        match self.settings.mode {
+            Mode::Json => output.results().write_all(b"]\n"),
+            Mode::Column if self.columns.is_none() => self.flush_sample(output),
+            _ => Ok(()),
+        }
+    }
+
+    /// The text shown for `value` in the text modes.
+    fn cell(&self, value: Value<'_>) -> String {
+        match value {
+            Value::Null => self.settings.null_value.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    /// Sizes the columns from `.width`, or for auto columns from the header
+    /// and the sampled rows, then prints the header (if `.headers` is on)
+    /// and the sample.
+    fn flush_sample(&mut self, output: &mut dyn Output) -> io::Result<()> {
+        let columns: Vec<Column> = COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let setting = self.settings.widths.get(i).copied().unwrap_or(0);
+                let width = match setting.unsigned_abs() as usize {
+                    0 => self.sample.iter().map(|cells| display_width(&cells[i])).fold(display_width(name), usize::max),
+                    width => width,
+                };
+                Column { width, right: setting < 0 }
+            })
+            .collect();
+
+        if self.settings.headers {
+            let mut header = Vec::new();
+            write_columns(&mut header, &columns, COLUMNS)?;
+            let header = String::from_utf8(header).expect("column names are UTF-8");
+            let header = header.trim_end_matches('\n');
+            match self.color {
+                true => output.message(&style::paint(header, Style::Bold))?,
+                false => output.message(header)?,
+            }
+            write_columns(output.results(), &columns, columns.iter().map(|column| "-".repeat(column.width)))?;
+        }
+        for cells in std::mem::take(&mut self.sample) {
+            write_columns(output.results(), &columns, cells)?;
+            self.printed += 1;
+        }
+        self.columns = Some(columns);
+        Ok(())
+    }
+}
+
+pub(crate) fn plural(count: usize) -> String {
+    match count {
+        1 => "1 row".to_string(),
+        n => format!("{} rows", n),
+    }
+}
+
+/// Writes one line of column mode output, two spaces between columns. A
+/// left-aligned last column is not padded, so lines carry no trailing spaces.
+fn write_columns<I, F>(out: &mut dyn Write, columns: &[Column], fields: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = F>,
+    F: AsRef<str>,
+{
+    let mut line = String::new();
+    for (i, (field, column)) in fields.into_iter().zip(columns).enumerate() {
+        let last = i + 1 == columns.len();
+        if i > 0 {
+            line.push_str("  ");
+        }
+        if column.right {
+            let cell = fit(field.as_ref(), column.width, false);
+            line.extend(std::iter::repeat_n(' ', column.width.saturating_sub(display_width(&cell))));
+            line.push_str(&cell);
+        } else {
+            line.push_str(&fit(field.as_ref(), column.width, !last));
+        }
+    }
+    writeln!(out, "{}", line)
+}
+
+/// Truncates `s` to `width` display columns, ending it with `…` if anything
+/// was cut, and pads it with spaces to exactly `width` if `pad` is set.
+pub fn fit(s: &str, width: usize, pad: bool) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+
+    if display_width(s) <= width {
+        out.push_str(s);
+        used = display_width(s);
+    } else if width > 0 {
+        for c in s.chars() {
+            let w = char_width(c);
+            if used + w > width - 1 {
+                break;
+            }
+            out.push(c);
+            used += w;
+        }
+        out.push('…');
+        used += 1;
+    }
+
+    if pad {
+        out.extend(std::iter::repeat_n(' ', width.saturating_sub(used)));
+    }
+    out
+}
+
+/// Terminal columns taken up by `s`. See `char_width`.
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// Terminal columns taken up by `c`: 0 for control and combining
+/// characters, 2 for East Asian wide characters and emoji, 1 otherwise.
+///
+/// This covers the common blocks rather than the full Unicode width tables,
+/// which would need a dependency; characters it gets wrong are off by one
+/// column, never more.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x00..=0x1f | 0x7f..=0x9f => 0,
+        0x0300..=0x036f | 0x200b..=0x200f | 0x20d0..=0x20ff | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Captured;
+
+    /// Prints a row holding both a NULL and an empty string.
+    fn print_nulls(mode: Mode) -> String {
+        let settings = Settings { mode, null_value: "NULL".to_string(), ..Settings::default() };
+        let mut printer = Printer::new(&settings, false);
+        let mut output = Captured::new();
+        printer.begin(&mut output).unwrap();
+        printer.row(&[Value::Integer(1), Value::Null, Value::Text(""), Value::Integer(0)], &mut output).unwrap();
+        printer.finish(&mut output).unwrap();
+        output.results_str().to_string()
+    }
+
+    /// Prints `rows` copies of a one-row result.
+    fn print_rows(mode: Mode, headers: bool, rows: usize) -> String {
+        let settings = Settings { mode, headers, ..Settings::default() };
+        let mut printer = Printer::new(&settings, false);
+        let mut output = Captured::new();
+        printer.begin(&mut output).unwrap();
+        for _ in 0..rows {
+            printer.row(&[Value::Integer(1), Value::Text("alice"), Value::Text("a@x"), Value::Integer(34)], &mut output).unwrap();
+        }
+        printer.finish(&mut output).unwrap();
+        output.results_str().to_string()
+    }
+
+    #[test]
+    fn test_headers() {
+        let cases = [
+            (Mode::List, false, 0, ""),
+            (Mode::List, true, 0, ""),
+            (Mode::List, true, 1, "(1, alice, a@x, 34)\n"),
+            (Mode::Csv, false, 0, ""),
+            (Mode::Csv, false, 1, "1,alice,a@x,34\n"),
+            (Mode::Csv, true, 0, "id,username,email,age\n"),
+            (Mode::Csv, true, 1, "id,username,email,age\n1,alice,a@x,34\n"),
+            (Mode::Json, false, 0, "[]\n"),
+            (Mode::Json, true, 0, "[]\n"),
+            (Mode::Json, true, 1, "[{\"id\":1,\"username\":\"alice\",\"email\":\"a@x\",\"age\":34}]\n"),
+            (Mode::Column, false, 0, ""),
+            (Mode::Column, false, 1, "1   alice     a@x    34\n"),
+            (Mode::Column, true, 0, "id  username  email  age\n--  --------  -----  ---\n"),
+            (Mode::Column, true, 1, "id  username  email  age\n--  --------  -----  ---\n1   alice     a@x    34\n"),
+        ];
+        for (mode, headers, rows, expected) in cases {
+            assert_eq!(print_rows(mode, headers, rows), expected, "{:?} headers={} rows={}", mode, headers, rows);
+        }
+    }
+
+    #[test]
+    fn test_null_differs_from_empty_text() {
+        assert_eq!(print_nulls(Mode::List), "(1, NULL, , 0)\n");
+        assert_eq!(print_nulls(Mode::Csv), "1,NULL,,0\n");
+        assert_eq!(print_nulls(Mode::Json), "[{\"id\":1,\"username\":null,\"email\":\"\",\"age\":0}]\n");
+        assert_eq!(print_nulls(Mode::Column), "1   NULL             0\n");
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("alice"), 5);
+        assert_eq!(display_width("héllo"), 5);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("🦀"), 2);
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("alice", 8, true), "alice   ");
+        assert_eq!(fit("alice", 8, false), "alice");
+        assert_eq!(fit("alice", 5, true), "alice");
+        assert_eq!(fit("alice", 4, true), "ali…");
+        assert_eq!(fit("alice", 1, true), "…");
+        assert_eq!(fit("alice", 0, true), "");
+        // A wide character that would straddle the edge is dropped, and the
+        // gap is padded so the columns still line up.
+        assert_eq!(fit("日本語", 4, true), "日… ");
+        assert_eq!(display_width(&fit("日本語", 4, true)), 4);
+    }
+}
@@ -0,0 +1,560 @@
+//! A small line editor for the interactive prompt: cursor movement, history
+//! for the session and the usual Ctrl shortcuts. The terminal is put in raw
+//! mode with `stty` while a line is being edited, and redrawn with ANSI
+//! escapes after every key.
+//!
+//! Decoding keys and editing are kept apart from the terminal, so `edit`
+//! can be driven by any reader and writer.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::complete::Completion;
+use crate::display::display_width;
+use crate::input::DEFAULT_HISTORY_SIZE;
+
+/// A key press, decoded from the bytes a terminal sends in raw mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    /// Ctrl-U: delete from the start of the line to the cursor.
+    KillLine,
+    /// Ctrl-W: delete the word before the cursor.
+    KillWord,
+    /// Ctrl-C.
+    Interrupt,
+    /// Ctrl-D.
+    Eof,
+    /// Anything else, such as an escape sequence for a key with no binding.
+    Ignored,
+}
+
+/// Reads one key from `input`. `None` means the input ended.
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x02 => Key::Left,
+        0x06 => Key::Right,
+        0x10 => Key::Up,
+        0x0e => Key::Down,
+        0x15 => Key::KillLine,
+        0x17 => Key::KillWord,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x1b => read_escape(input)?,
+        0x00..=0x1f => Key::Ignored,
+        _ => read_char(byte, input)?,
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// The rest of a character whose first UTF-8 byte is `first`.
+fn read_char(first: u8, input: &mut impl Read) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = [first, 0, 0, 0];
+    input.read_exact(&mut bytes[1..len])?;
+    Ok(match std::str::from_utf8(&bytes[..len]) {
+        Ok(s) => s.chars().next().map_or(Key::Ignored, Key::Char),
+        Err(_) => Key::Ignored,
+    })
+}
+
+/// An escape sequence, whose `ESC` has been read: `ESC [ A` and friends
+/// for the arrows, `ESC [ 3 ~` for delete and so on.
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let Some(kind) = read_byte(input)? else {
+        return Ok(Key::Ignored);
+    };
+    if kind != b'[' && kind != b'O' {
+        return Ok(Key::Ignored);
+    }
+    let mut number = 0u32;
+    loop {
+        let Some(byte) = read_byte(input)? else {
+            return Ok(Key::Ignored);
+        };
+        return Ok(match byte {
+            b'0'..=b'9' => {
+                number = number * 10 + (byte - b'0') as u32;
+                continue;
+            }
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'~' => match number {
+                1 | 7 => Key::Home,
+                3 => Key::Delete,
+                4 | 8 => Key::End,
+                _ => Key::Ignored,
+            },
+            _ => Key::Ignored,
+        });
+    }
+}
+
+/// The text being edited and the cursor position in it, in characters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replaces the text, with the cursor at the end.
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    /// Replaces the text from character `start` up to the cursor with
+    /// `text`, leaving the cursor after it.
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        let len = text.chars().count();
+        self.chars.splice(start..self.cursor, text.chars());
+        self.cursor = start + len;
+    }
+
+    /// Applies an editing key. Keys that do not edit the line are ignored.
+    pub fn apply(&mut self, key: Key) {
+        match key {
+            Key::Char(c) => {
+                self.chars.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete | Key::Eof if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::KillLine => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::KillWord => {
+                let mut start = self.cursor;
+                while start > 0 && self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                while start > 0 && !self.chars[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                self.chars.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds completions for Tab, given the line and the cursor's byte offset
+/// in it.
+pub type Completer = fn(&str, usize) -> Completion;
+
+/// Reads lines from a terminal with editing and history.
+#[derive(Debug)]
+pub struct Editor {
+    history: Vec<String>,
+    max_history: usize,
+    /// Where history is saved, one line per entry, once loaded.
+    history_file: Option<PathBuf>,
+    completer: Option<Completer>,
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor { history: Vec::new(), max_history: DEFAULT_HISTORY_SIZE, history_file: None, completer: None }
+    }
+
+    /// Completes words with `completer` when Tab is pressed.
+    pub fn set_completer(&mut self, completer: Completer) {
+        self.completer = Some(completer);
+    }
+
+    /// Earlier lines, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Loads the history saved at `path` and saves lines added from now on
+    /// there, keeping the last `max` of them. A missing file is fine; one
+    /// that cannot be read is left alone, and the history stays in memory
+    /// only.
+    pub fn load_history(&mut self, path: &Path, max: usize) -> io::Result<()> {
+        self.max_history = max;
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        self.history_file = Some(path.to_path_buf());
+
+        let saved: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+        self.history = saved[saved.len().saturating_sub(max)..].iter().map(|line| line.to_string()).collect();
+        if saved.len() > max {
+            self.save_history()?;
+        }
+        Ok(())
+    }
+
+    /// Adds `line` to the history, unless it is blank or repeats the line
+    /// before it, and appends it to the history file if there is one.
+    pub fn add_history(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > self.max_history {
+            let excess = self.history.len() - self.max_history;
+            self.history.drain(..excess);
+            return self.save_history();
+        }
+
+        let Some(path) = &self.history_file else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", line)
+    }
+
+    /// Rewrites the history file with the history in memory.
+    fn save_history(&self) -> io::Result<()> {
+        let Some(path) = &self.history_file else {
+            return Ok(());
+        };
+        let mut file = io::BufWriter::new(File::create(path)?);
+        for line in &self.history {
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()
+    }
+
+    /// Shows `prompt` and reads a line from the terminal. `None` means
+    /// Ctrl-D on an empty line or the end of input. Fails with
+    /// `ErrorKind::Unsupported` if the terminal cannot be put in raw mode.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let raw = RawMode::enable()?;
+        let line = self.edit(prompt, &mut io::stdin().lock(), &mut io::stdout().lock());
+        drop(raw);
+        line
+    }
+
+    /// Edits one line, reading keys from `input` and drawing on `out`.
+    /// Ctrl-C abandons the line and fails with `ErrorKind::Interrupted`, as
+    /// a plain read does when the signal arrives.
+    pub fn edit(&mut self, prompt: &str, input: &mut impl Read, out: &mut impl Write) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        // Up and Down move through the history; `draft` keeps what was typed
+        // before moving, for coming back down to it.
+        let mut index = self.history.len();
+        let mut draft = String::new();
+
+        redraw(out, prompt, &line)?;
+        loop {
+            let Some(key) = read_key(input)? else {
+                write!(out, "\r\n")?;
+                out.flush()?;
+                return Ok(if line.chars.is_empty() { None } else { Some(line.text()) });
+            };
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(Some(line.text()));
+                }
+                Key::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    out.flush()?;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                Key::Eof if line.chars.is_empty() => {
+                    write!(out, "\r\n")?;
+                    out.flush()?;
+                    return Ok(None);
+                }
+                Key::Up if index > 0 => {
+                    if index == self.history.len() {
+                        draft = line.text();
+                    }
+                    index -= 1;
+                    line.set(&self.history[index]);
+                }
+                Key::Down if index < self.history.len() => {
+                    index += 1;
+                    line.set(self.history.get(index).unwrap_or(&draft));
+                }
+                Key::Tab => self.complete(&mut line, out)?,
+                key => line.apply(key),
+            }
+            redraw(out, prompt, &line)?;
+        }
+    }
+
+    /// Completes the word before the cursor. A single candidate is filled
+    /// in with a space after it; several are filled in as far as they
+    /// agree, or listed below the line if that adds nothing.
+    fn complete(&self, line: &mut Line, out: &mut impl Write) -> io::Result<()> {
+        let Some(completer) = self.completer else {
+            return Ok(());
+        };
+        let text = line.text();
+        let cursor = text.char_indices().nth(line.cursor).map_or(text.len(), |(i, _)| i);
+        let completion = completer(&text, cursor);
+        let start = text[..completion.start].chars().count();
+
+        match completion.candidates.as_slice() {
+            [] => write!(out, "\x07"),
+            [only] => {
+                line.replace_before_cursor(start, &format!("{} ", only));
+                Ok(())
+            }
+            candidates => match completion.common_prefix() {
+                prefix if prefix.len() > cursor - completion.start => {
+                    line.replace_before_cursor(start, prefix);
+                    Ok(())
+                }
+                _ => write!(out, "\r\n{}\r\n", candidates.join("  ")),
+            },
+        }
+    }
+}
+
+/// Redraws the prompt and line in place and puts the terminal cursor where
+/// the line's cursor is.
+fn redraw(out: &mut impl Write, prompt: &str, line: &Line) -> io::Result<()> {
+    write!(out, "\r{}{}\x1b[K", prompt, line.text())?;
+    let after: String = line.chars[line.cursor..].iter().collect();
+    let back = display_width(&after);
+    if back > 0 {
+        write!(out, "\x1b[{}D", back)?;
+    }
+    out.flush()
+}
+
+/// Keeps the terminal in raw mode until dropped, so keys arrive one at a
+/// time without being echoed.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|err| io::Error::new(io::ErrorKind::Unsupported, err))?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        std::iter::from_fn(|| read_key(&mut input).unwrap()).collect()
+    }
+
+    fn edit(editor: &mut Editor, bytes: &[u8]) -> Option<String> {
+        editor.edit("db > ", &mut &bytes[..], &mut Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn test_read_key() {
+        assert_eq!(
+            keys(b"a\xc3\xa9\x1b[A\x1b[B\x1b[C\x1b[D\x1b[3~\x1bOH\x1b[4~\x7f\x15\x17\x03\x04\r\t\x1b[5~"),
+            [
+                Key::Char('a'),
+                Key::Char('é'),
+                Key::Up,
+                Key::Down,
+                Key::Right,
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::End,
+                Key::Backspace,
+                Key::KillLine,
+                Key::KillWord,
+                Key::Interrupt,
+                Key::Eof,
+                Key::Enter,
+                Key::Tab,
+                Key::Ignored,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = Editor::new();
+        // Type, go left twice, insert, go home, delete a character.
+        assert_eq!(edit(&mut editor, b"selct\x1b[D\x1b[De\x01\x1b[3~s\r"), Some("select".to_string()));
+        assert_eq!(edit(&mut editor, b"insert 1 a b\x17\x17c d\r"), Some("insert 1 c d".to_string()));
+        assert_eq!(edit(&mut editor, b"garbage\x1b[D\x1b[D\x15xy\r"), Some("xyge".to_string()));
+        let interrupted = editor.edit("db > ", &mut &b"half a line\x03"[..], &mut Vec::new());
+        assert_eq!(interrupted.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(edit(&mut editor, b"\x04"), None);
+        assert_eq!(edit(&mut editor, b"ab\x01\x04\r"), Some("b".to_string()));
+        assert_eq!(edit(&mut editor, b"unfinished"), Some("unfinished".to_string()));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = Editor::new();
+        for line in ["insert 1 a a@x", "select", "select", "  "] {
+            editor.add_history(line).unwrap();
+        }
+        assert_eq!(editor.history(), ["insert 1 a a@x", "select"]);
+
+        // Up twice recalls the insert; Down returns to what was typed.
+        assert_eq!(edit(&mut editor, b"\x1b[A\x1b[A\r"), Some("insert 1 a a@x".to_string()));
+        assert_eq!(edit(&mut editor, b"sel\x1b[A\x1b[A\x1b[A\x1b[B\x1b[B\r"), Some("sel".to_string()));
+        assert_eq!(edit(&mut editor, b"\x1b[A\x1b[A\x17\x17\x172 b b@x\r"), Some("insert 2 b b@x".to_string()));
+    }
+
+    #[test]
+    fn test_tab_completion() {
+        let mut editor = Editor::new();
+        // Without a completer Tab does nothing.
+        assert_eq!(edit(&mut editor, b"ins\t\r"), Some("ins".to_string()));
+
+        editor.set_completer(crate::complete::complete);
+        assert_eq!(edit(&mut editor, b"ins\t1 a a@x\r"), Some("insert 1 a a@x".to_string()));
+        assert_eq!(edit(&mut editor, b".mode c\to\t\r"), Some(".mode column ".to_string()));
+        // Completes the word before the cursor, not the end of the line.
+        assert_eq!(edit(&mut editor, b".hea on\x1b[D\x1b[D\x1b[D\t\r"), Some(".headers  on".to_string()));
+
+        // Several candidates that agree no further are listed, and the line
+        // is drawn again below them.
+        let mut out = Vec::new();
+        let line = editor.edit("db > ", &mut &b".ex\t\r"[..], &mut out).unwrap();
+        assert_eq!(line, Some(".ex".to_string()));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\r\n.exit  .export\r\n\rdb > .ex\x1b[K"), "{:?}", out);
+
+        let mut out = Vec::new();
+        editor.edit("db > ", &mut &b"drop\t\r"[..], &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains('\x07'));
+    }
+
+    #[test]
+    fn test_redraw_puts_cursor_in_place() {
+        let mut out = Vec::new();
+        Editor::new().edit("> ", &mut &b"ab\x1b[D"[..], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("\r> ab\x1b[K\x1b[1D\r\n"), "{:?}", out);
+    }
+
+    #[test]
+    fn test_history_file() {
+        let dir = TempPath::new("history");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+
+        let mut first = Editor::new();
+        first.load_history(&path, 3).unwrap();
+        for line in ["insert 1 a a@x", "insert 2 b b@x", "select"] {
+            first.add_history(line).unwrap();
+        }
+
+        // A later session recalls what the first one ran.
+        let mut second = Editor::new();
+        second.load_history(&path, 3).unwrap();
+        assert_eq!(edit(&mut second, b"\x1b[A\x1b[A\r"), Some("insert 2 b b@x".to_string()));
+
+        // Past the cap the oldest lines go, in memory and on disk.
+        second.add_history(".mode csv").unwrap();
+        assert_eq!(second.history(), ["insert 2 b b@x", "select", ".mode csv"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "insert 2 b b@x\nselect\n.mode csv\n");
+        let mut third = Editor::new();
+        third.load_history(&path, 2).unwrap();
+        assert_eq!(third.history(), ["select", ".mode csv"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "select\n.mode csv\n");
+    }
+
+    #[test]
+    fn test_unreadable_history_file_is_left_alone() {
+        let path = TempPath::new("history-bad");
+        std::fs::write(&path, b"select\n\xff\xfe\n").unwrap();
+
+        let mut editor = Editor::new();
+        assert_eq!(editor.load_history(&path, 10).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        editor.add_history("select").unwrap();
+        assert_eq!(editor.history(), ["select"]);
+        assert_eq!(std::fs::read(&path).unwrap(), b"select\n\xff\xfe\n");
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::io;
+
+use crate::compiler::{ExecuteError, PrepareError};
+use crate::row::RowError;
+
+#[derive(Debug)]
+pub enum DbError {
+    Prepare(PrepareError),
+    Execute(ExecuteError),
+    Row(RowError),
+    Io(io::Error),
+    Corrupt(String),
+    /// A stored row that cannot be read back: it does not decode, or its
+    /// checksum does not match. Its code is the `RowError`'s, so a bad
+    /// checksum is `CHECKSUM_MISMATCH` rather than `CORRUPT`.
+    CorruptRow { row_num: usize, page_num: usize, err: RowError },
+    /// The statement was stopped through an `Interrupt`.
+    Interrupted,
+    /// A database could not be attached or detached: the name is taken or
+    /// not allowed, or the file is already open.
+    Attach(String),
+    /// A statement or `detach` named a database that is not attached.
+    NoSuchDatabase(String),
+    /// A `create table` gave other columns to a table that has rows.
+    TableExists(String),
+    /// No table has this name.
+    NoSuchTable(String),
+    /// A statement other than a select was run through a shared
+    /// reference, which can only read, or on a database opened read-only.
+    ReadOnly,
+    /// The database file is held by another connection.
+    Locked,
+    /// The options a database was opened with do not fit the file: it is
+    /// missing and may not be created, or the options ask for something
+    /// impossible.
+    CannotOpen(String),
+}
+
+/// The kind of a `DbError`, for code that branches on errors or passes them
+/// on: the shell's machine-readable output and the C interface. Both the
+/// numbers and the names are stable; new kinds are added at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum ErrorCode {
+    UnrecognizedStatement = 1,
+    SyntaxError = 2,
+    TableFull = 3,
+    DuplicateKey = 4,
+    IoError = 5,
+    /// Stored data that cannot be decoded, or a file that is not a
+    /// database.
+    Corrupt = 6,
+    Interrupted = 7,
+    AttachError = 8,
+    NoSuchDatabase = 9,
+    TableExists = 10,
+    NoSuchTable = 11,
+    ReadOnly = 12,
+    ValueTooLong = 13,
+    BadLayout = 14,
+    ChecksumMismatch = 15,
+    Locked = 16,
+    NoSuchColumn = 17,
+    TypeMismatch = 18,
+    CannotOpen = 19,
+}
+
+impl ErrorCode {
+    /// Every code, in order.
+    pub const ALL: [ErrorCode; 19] = [
+        ErrorCode::UnrecognizedStatement,
+        ErrorCode::SyntaxError,
+        ErrorCode::TableFull,
+        ErrorCode::DuplicateKey,
+        ErrorCode::IoError,
+        ErrorCode::Corrupt,
+        ErrorCode::Interrupted,
+        ErrorCode::AttachError,
+        ErrorCode::NoSuchDatabase,
+        ErrorCode::TableExists,
+        ErrorCode::NoSuchTable,
+        ErrorCode::ReadOnly,
+        ErrorCode::ValueTooLong,
+        ErrorCode::BadLayout,
+        ErrorCode::ChecksumMismatch,
+        ErrorCode::Locked,
+        ErrorCode::NoSuchColumn,
+        ErrorCode::TypeMismatch,
+        ErrorCode::CannotOpen,
+    ];
+
+    /// The code named `name`, as `as_str` gives it.
+    pub fn parse(name: &str) -> Option<ErrorCode> {
+        Self::ALL.into_iter().find(|code| code.as_str() == name)
+    }
+
+    /// The name scripts match on, such as `DUPLICATE_KEY`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnrecognizedStatement => "UNRECOGNIZED_STATEMENT",
+            ErrorCode::SyntaxError => "SYNTAX_ERROR",
+            ErrorCode::TableFull => "TABLE_FULL",
+            ErrorCode::DuplicateKey => "DUPLICATE_KEY",
+            ErrorCode::IoError => "IO_ERROR",
+            ErrorCode::Corrupt => "CORRUPT",
+            ErrorCode::Interrupted => "INTERRUPTED",
+            ErrorCode::AttachError => "ATTACH_ERROR",
+            ErrorCode::NoSuchDatabase => "NO_SUCH_DATABASE",
+            ErrorCode::TableExists => "TABLE_EXISTS",
+            ErrorCode::NoSuchTable => "NO_SUCH_TABLE",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::ValueTooLong => "VALUE_TOO_LONG",
+            ErrorCode::BadLayout => "BAD_LAYOUT",
+            ErrorCode::ChecksumMismatch => "CHECKSUM_MISMATCH",
+            ErrorCode::Locked => "LOCKED",
+            ErrorCode::NoSuchColumn => "NO_SUCH_COLUMN",
+            ErrorCode::TypeMismatch => "TYPE_MISMATCH",
+            ErrorCode::CannotOpen => "CANNOT_OPEN",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl DbError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            DbError::Prepare(PrepareError::UnrecognizedStatement) => ErrorCode::UnrecognizedStatement,
+            DbError::Prepare(PrepareError::SyntaxError) => ErrorCode::SyntaxError,
+            DbError::Execute(ExecuteError::DuplicateKey) => ErrorCode::DuplicateKey,
+            DbError::Execute(ExecuteError::TableFull) => ErrorCode::TableFull,
+            DbError::Row(err) => err.error_code(),
+            DbError::Io(_) => ErrorCode::IoError,
+            DbError::Corrupt(_) => ErrorCode::Corrupt,
+            DbError::CorruptRow { err, .. } => err.error_code(),
+            DbError::Interrupted => ErrorCode::Interrupted,
+            DbError::Attach(_) => ErrorCode::AttachError,
+            DbError::NoSuchDatabase(_) => ErrorCode::NoSuchDatabase,
+            DbError::TableExists(_) => ErrorCode::TableExists,
+            DbError::NoSuchTable(_) => ErrorCode::NoSuchTable,
+            DbError::ReadOnly => ErrorCode::ReadOnly,
+            DbError::Locked => ErrorCode::Locked,
+            DbError::CannotOpen(_) => ErrorCode::CannotOpen,
+        }
+    }
+
+    /// A short, stable name for the kind of error, for scripts to match on
+    /// instead of the message; see `ErrorCode::as_str`.
+    pub fn code(&self) -> &'static str {
+        self.error_code().as_str()
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Prepare(PrepareError::UnrecognizedStatement) => write!(f, "Unrecognized statement"),
+            DbError::Prepare(PrepareError::SyntaxError) => write!(f, "Syntax error. Could not parse statement."),
+            DbError::Execute(ExecuteError::TableFull) => write!(f, "Error: Table full."),
+            DbError::Execute(ExecuteError::DuplicateKey) => write!(f, "Error: Duplicate key."),
+            DbError::Row(err) => write!(f, "Error: {}.", err),
+            DbError::Io(err) => write!(f, "I/O error: {}", err),
+            DbError::Corrupt(msg) => write!(f, "Database is corrupt: {}", msg),
+            DbError::CorruptRow { row_num, page_num, err } => write!(f, "Database is corrupt: row {} on page {}: {}", row_num, page_num, err),
+            DbError::Interrupted => write!(f, "Interrupted."),
+            DbError::Attach(msg) => write!(f, "Error: {}", msg),
+            DbError::NoSuchDatabase(name) => write!(f, "Error: No such database '{}'.", name),
+            DbError::TableExists(name) => write!(f, "Error: Table '{}' already has rows with other columns.", name),
+            DbError::NoSuchTable(name) => write!(f, "Error: No such table '{}'.", name),
+            DbError::ReadOnly => write!(f, "Error: Only a select can run without write access."),
+            DbError::Locked => write!(f, "Error: Database is locked."),
+            DbError::CannotOpen(msg) => write!(f, "Error: Cannot open {}.", msg),
+        }
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Io(err) => Some(err),
+            DbError::Row(err) | DbError::CorruptRow { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DbError {
+    fn from(err: io::Error) -> Self {
+        DbError::Io(err)
+    }
+}
+
+impl From<PrepareError> for DbError {
+    fn from(err: PrepareError) -> Self {
+        DbError::Prepare(err)
+    }
+}
+
+impl From<ExecuteError> for DbError {
+    fn from(err: ExecuteError) -> Self {
+        DbError::Execute(err)
+    }
+}
+
+impl From<RowError> for DbError {
+    fn from(err: RowError) -> Self {
+        DbError::Row(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(DbError::Prepare(PrepareError::SyntaxError).code(), "SYNTAX_ERROR");
+        assert_eq!(DbError::Prepare(PrepareError::UnrecognizedStatement).code(), "UNRECOGNIZED_STATEMENT");
+        assert_eq!(DbError::Execute(ExecuteError::TableFull).code(), "TABLE_FULL");
+        assert_eq!(DbError::Execute(ExecuteError::DuplicateKey).code(), "DUPLICATE_KEY");
+        assert_eq!(DbError::Io(io::Error::other("disk on fire")).code(), "IO_ERROR");
+        assert_eq!(DbError::Corrupt("bad magic".to_string()).code(), "CORRUPT");
+        assert_eq!(DbError::Interrupted.code(), "INTERRUPTED");
+        assert_eq!(DbError::Attach("Database 'a' is already attached.".to_string()).code(), "ATTACH_ERROR");
+        assert_eq!(DbError::NoSuchDatabase("a".to_string()).code(), "NO_SUCH_DATABASE");
+        assert_eq!(DbError::TableExists("users".to_string()).code(), "TABLE_EXISTS");
+        assert_eq!(DbError::NoSuchTable("orders".to_string()).code(), "NO_SUCH_TABLE");
+        let too_long = RowError::ValueTooLong { column: "email", max: 255, got: 300 };
+        assert_eq!(DbError::Row(too_long).code(), "VALUE_TOO_LONG");
+        assert_eq!(DbError::Row(RowError::ChecksumMismatch { id: 1 }).code(), "CHECKSUM_MISMATCH");
+        assert_eq!(DbError::ReadOnly.code(), "READ_ONLY");
+        assert_eq!(DbError::Locked.code(), "LOCKED");
+        assert_eq!(DbError::CannotOpen("'a.db': it does not exist".to_string()).code(), "CANNOT_OPEN");
+    }
+
+    #[test]
+    fn test_error_code_numbers() {
+        assert_eq!(ErrorCode::UnrecognizedStatement as u16, 1);
+        assert_eq!(ErrorCode::DuplicateKey as u16, 4);
+        assert_eq!(ErrorCode::Locked as u16, 16);
+        assert_eq!(ErrorCode::TypeMismatch as u16, 18);
+        assert_eq!(ErrorCode::CannotOpen as u16, 19);
+        assert_eq!(DbError::Execute(ExecuteError::DuplicateKey).error_code(), ErrorCode::DuplicateKey);
+        assert_eq!(DbError::Row(RowError::InvalidUtf8 { column: "email" }).error_code(), ErrorCode::Corrupt);
+        assert_eq!(ErrorCode::TableFull.to_string(), "TABLE_FULL");
+        for (number, code) in ErrorCode::ALL.into_iter().enumerate() {
+            assert_eq!(code as usize, number + 1);
+            assert_eq!(ErrorCode::parse(code.as_str()), Some(code));
+        }
+        assert_eq!(ErrorCode::parse("table_full"), None);
+    }
+
+    #[test]
+    fn test_io_failure_is_the_source() {
+        use crate::database::Database;
+        use std::error::Error;
+
+        // A directory cannot be opened as a database file.
+        let err = Database::open(std::env::temp_dir()).err().unwrap();
+        assert_eq!(err.error_code(), ErrorCode::IoError);
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.to_string(), format!("I/O error: {}", source));
+
+        let err: DbError = RowError::ChecksumMismatch { id: 7 }.into();
+        assert_eq!(err.source().unwrap().to_string(), "checksum mismatch for row with id 7");
+        assert!(DbError::from(PrepareError::SyntaxError).source().is_none());
+    }
+}
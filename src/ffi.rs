@@ -0,0 +1,394 @@
+//! A C interface, declared in `include/voiddb.h`.
+//!
+//! Build the shared library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib`, which
+//! leaves `libVoidDB.so` (or `.dylib`, `VoidDB.dll`) in `target/release`.
+//! Every function catches panics, so none unwinds into C.
+//!
+//! Strings passed in must be NUL-terminated UTF-8. Strings passed out are
+//! NUL-terminated UTF-8 owned by the library: `voiddb_errmsg` is valid until
+//! the next call on the same handle, row values until the callback returns.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ops::ControlFlow;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::database::Database;
+use crate::error::{DbError, ErrorCode};
+use crate::row::{Value, COLUMNS};
+
+/// What a call returned. The numbers are part of the interface and never
+/// change; new codes are added at the end.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoidDbCode {
+    Ok = 0,
+    /// Not returned: every error has a code of its own. Kept so that the
+    /// numbers after it stay put.
+    Error = 1,
+    UnrecognizedStatement = 2,
+    SyntaxError = 3,
+    TableFull = 4,
+    DuplicateKey = 5,
+    IoError = 6,
+    Corrupt = 7,
+    Interrupted = 8,
+    AttachError = 9,
+    NoSuchDatabase = 10,
+    TableExists = 11,
+    NoSuchTable = 12,
+    ReadOnly = 13,
+    ValueTooLong = 14,
+    BadLayout = 15,
+    ChecksumMismatch = 16,
+    /// A NULL or non-UTF-8 argument, or a handle whose open failed.
+    Misuse = 17,
+    /// The row callback returned non-zero.
+    Abort = 18,
+    /// A bug in VoidDB; the handle should be closed.
+    Internal = 19,
+    Locked = 20,
+    NoSuchColumn = 21,
+    TypeMismatch = 22,
+    CannotOpen = 23,
+}
+
+impl From<&DbError> for VoidDbCode {
+    fn from(err: &DbError) -> Self {
+        match err.error_code() {
+            ErrorCode::UnrecognizedStatement => VoidDbCode::UnrecognizedStatement,
+            ErrorCode::SyntaxError => VoidDbCode::SyntaxError,
+            ErrorCode::TableFull => VoidDbCode::TableFull,
+            ErrorCode::DuplicateKey => VoidDbCode::DuplicateKey,
+            ErrorCode::IoError => VoidDbCode::IoError,
+            ErrorCode::Corrupt => VoidDbCode::Corrupt,
+            ErrorCode::Interrupted => VoidDbCode::Interrupted,
+            ErrorCode::AttachError => VoidDbCode::AttachError,
+            ErrorCode::NoSuchDatabase => VoidDbCode::NoSuchDatabase,
+            ErrorCode::TableExists => VoidDbCode::TableExists,
+            ErrorCode::NoSuchTable => VoidDbCode::NoSuchTable,
+            ErrorCode::ReadOnly => VoidDbCode::ReadOnly,
+            ErrorCode::ValueTooLong => VoidDbCode::ValueTooLong,
+            ErrorCode::BadLayout => VoidDbCode::BadLayout,
+            ErrorCode::ChecksumMismatch => VoidDbCode::ChecksumMismatch,
+            ErrorCode::Locked => VoidDbCode::Locked,
+            ErrorCode::NoSuchColumn => VoidDbCode::NoSuchColumn,
+            ErrorCode::TypeMismatch => VoidDbCode::TypeMismatch,
+            ErrorCode::CannotOpen => VoidDbCode::CannotOpen,
+        }
+    }
+}
+
+/// Called once per row of a select with the number of columns, their
+/// values (NULL for a null value) and their names. Returning non-zero stops
+/// the select, and `voiddb_exec` returns `Abort`.
+pub type VoidDbCallback = extern "C" fn(user_data: *mut c_void, columns: c_int, values: *const *const c_char, names: *const *const c_char) -> c_int;
+
+/// An open database, as C sees it.
+pub struct VoidDb {
+    /// `None` when the open failed, so the handle only carries its error.
+    db: Option<Database>,
+    errmsg: CString,
+}
+
+impl VoidDb {
+    fn new(db: Option<Database>) -> Self {
+        VoidDb { db, errmsg: CString::default() }
+    }
+
+    /// Records the outcome of a call for `voiddb_errmsg`.
+    fn set(&mut self, code: VoidDbCode, message: &str) -> VoidDbCode {
+        self.errmsg = c_string(if code == VoidDbCode::Ok { "not an error" } else { message });
+        code
+    }
+}
+
+/// `s` as a C string, cut at the first NUL byte, which C could not see past.
+fn c_string(s: &str) -> CString {
+    let end = s.find('\0').unwrap_or(s.len());
+    CString::new(&s[..end]).unwrap_or_default()
+}
+
+/// The UTF-8 string at `s`, or `None` if it is NULL or not UTF-8.
+///
+/// # Safety
+///
+/// `s` must be NULL or point to a NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Runs `f`, turning a panic into `Internal`.
+fn guard(f: impl FnOnce() -> VoidDbCode) -> VoidDbCode {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(VoidDbCode::Internal)
+}
+
+/// Opens the database file at `path`, creating it if it does not exist, or
+/// an in-memory database for `":memory:"`. Stores a handle in `*db` even
+/// when the open fails, so `voiddb_errmsg` can explain; it must be passed
+/// to `voiddb_close` either way.
+///
+/// # Safety
+///
+/// `path` must be NULL or a NUL-terminated string, and `db` NULL or valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_open(path: *const c_char, db: *mut *mut VoidDb) -> VoidDbCode {
+    if db.is_null() {
+        return VoidDbCode::Misuse;
+    }
+    *db = ptr::null_mut();
+    let mut handle = Box::new(VoidDb::new(None));
+    let code = guard(|| match str_arg(path) {
+        None => handle.set(VoidDbCode::Misuse, "path is NULL or not UTF-8"),
+        Some(":memory:") => {
+            handle.db = Some(Database::open_in_memory());
+            handle.set(VoidDbCode::Ok, "")
+        }
+        Some(path) => match Database::open(path) {
+            Ok(opened) => {
+                handle.db = Some(opened);
+                handle.set(VoidDbCode::Ok, "")
+            }
+            Err(err) => handle.set((&err).into(), &err.to_string()),
+        },
+    });
+    *db = Box::into_raw(handle);
+    code
+}
+
+/// Runs one statement. For a select, calls `callback`, if not NULL, with
+/// each row and `user_data`.
+///
+/// # Safety
+///
+/// `db` must be NULL or a handle from `voiddb_open` that has not been
+/// closed, and `sql` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_exec(db: *mut VoidDb, sql: *const c_char, callback: Option<VoidDbCallback>, user_data: *mut c_void) -> VoidDbCode {
+    let Some(handle) = db.as_mut() else {
+        return VoidDbCode::Misuse;
+    };
+    guard(|| {
+        let Some(sql) = str_arg(sql) else {
+            return handle.set(VoidDbCode::Misuse, "sql is NULL or not UTF-8");
+        };
+        let Some(database) = handle.db.as_mut() else {
+            return handle.set(VoidDbCode::Misuse, "the database did not open");
+        };
+        let names: Vec<CString> = COLUMNS.iter().map(|name| c_string(name)).collect();
+        let names: Vec<*const c_char> = names.iter().map(|name| name.as_ptr()).collect();
+        let result = database.execute_with(sql, |row| {
+            let Some(callback) = callback else {
+                return ControlFlow::Continue(());
+            };
+            let row = match row.to_row() {
+                Ok(row) => row,
+                Err(err) => return ControlFlow::Break(Err(DbError::Row(err))),
+            };
+            let values: Vec<Option<CString>> = row
+                .values()
+                .iter()
+                .map(|value| match value {
+                    Value::Null => None,
+                    value => Some(c_string(&value.to_string())),
+                })
+                .collect();
+            let values: Vec<*const c_char> = values.iter().map(|value| value.as_ref().map_or(ptr::null(), |value| value.as_ptr())).collect();
+            match callback(user_data, values.len() as c_int, values.as_ptr(), names.as_ptr()) {
+                0 => ControlFlow::Continue(()),
+                _ => ControlFlow::Break(Ok(())),
+            }
+        });
+        match result {
+            Ok(None) => handle.set(VoidDbCode::Ok, ""),
+            Ok(Some(())) => handle.set(VoidDbCode::Abort, "callback requested abort"),
+            Err(err) => handle.set((&err).into(), &err.to_string()),
+        }
+    })
+}
+
+/// The message of the last call on `db`, or `"not an error"` if it
+/// succeeded.
+///
+/// # Safety
+///
+/// `db` must be NULL or a handle from `voiddb_open` that has not been
+/// closed.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_errmsg(db: *const VoidDb) -> *const c_char {
+    match db.as_ref() {
+        Some(handle) => handle.errmsg.as_ptr(),
+        None => c"no database handle".as_ptr(),
+    }
+}
+
+/// Writes out everything buffered and frees the handle, which must not be
+/// used again even if writing fails. A NULL handle is ignored.
+///
+/// # Safety
+///
+/// `db` must be NULL or a handle from `voiddb_open` that has not been
+/// closed.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_close(db: *mut VoidDb) -> VoidDbCode {
+    if db.is_null() {
+        return VoidDbCode::Ok;
+    }
+    let handle = Box::from_raw(db);
+    guard(move || match handle.db.map(Database::close) {
+        Some(Err(err)) => (&err).into(),
+        _ => VoidDbCode::Ok,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    fn open(path: &CStr) -> (VoidDbCode, *mut VoidDb) {
+        let mut db = ptr::null_mut();
+        let code = unsafe { voiddb_open(path.as_ptr(), &mut db) };
+        (code, db)
+    }
+
+    fn errmsg(db: *const VoidDb) -> String {
+        unsafe { CStr::from_ptr(voiddb_errmsg(db)) }.to_str().unwrap().to_string()
+    }
+
+    /// Collects each row as its values joined by `|`, stopping after
+    /// `user_data.1` rows if that is non-zero.
+    extern "C" fn collect(user_data: *mut c_void, columns: c_int, values: *const *const c_char, names: *const *const c_char) -> c_int {
+        let (rows, limit) = unsafe { &mut *(user_data as *mut (Vec<String>, usize)) };
+        let values = unsafe { std::slice::from_raw_parts(values, columns as usize) };
+        let names = unsafe { std::slice::from_raw_parts(names, columns as usize) };
+        assert_eq!(unsafe { CStr::from_ptr(names[1]) }, c"username");
+        let values: Vec<&str> = values.iter().map(|value| if value.is_null() { "NULL" } else { unsafe { CStr::from_ptr(*value) }.to_str().unwrap() }).collect();
+        rows.push(values.join("|"));
+        (*limit != 0 && rows.len() >= *limit) as c_int
+    }
+
+    fn exec(db: *mut VoidDb, sql: &CStr, limit: usize) -> (VoidDbCode, Vec<String>) {
+        let mut rows = (Vec::new(), limit);
+        let code = unsafe { voiddb_exec(db, sql.as_ptr(), Some(collect), &mut rows as *mut _ as *mut c_void) };
+        (code, rows.0)
+    }
+
+    #[test]
+    fn test_exec() {
+        let (code, db) = open(c":memory:");
+        assert_eq!(code, VoidDbCode::Ok);
+        assert_eq!(exec(db, c"insert 1 alice alice@example.com", 0), (VoidDbCode::Ok, vec![]));
+        assert_eq!(exec(db, c"insert 2 bob bob@example.com", 0).0, VoidDbCode::Ok);
+        assert_eq!(errmsg(db), "not an error");
+        assert_eq!(exec(db, c"select", 0), (VoidDbCode::Ok, vec!["1|alice|alice@example.com|0".to_string(), "2|bob|bob@example.com|0".to_string()]));
+        assert_eq!(exec(db, c"select", 1), (VoidDbCode::Abort, vec!["1|alice|alice@example.com|0".to_string()]));
+        assert_eq!(unsafe { voiddb_exec(db, c"select".as_ptr(), None, ptr::null_mut()) }, VoidDbCode::Ok);
+        assert_eq!(unsafe { voiddb_close(db) }, VoidDbCode::Ok);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (_, db) = open(c":memory:");
+        assert_eq!(exec(db, c"insert 1 a a@x", 0).0, VoidDbCode::Ok);
+        assert_eq!(exec(db, c"insert 1 a a@x", 0).0, VoidDbCode::DuplicateKey);
+        assert_eq!(errmsg(db), "Error: Duplicate key.");
+        assert_eq!(exec(db, c"bogus", 0).0, VoidDbCode::UnrecognizedStatement);
+        assert_eq!(exec(db, c"insert 2 a", 0).0, VoidDbCode::SyntaxError);
+        assert_eq!(exec(db, c"select from other.users", 0).0, VoidDbCode::NoSuchDatabase);
+        let long = CString::new(format!("insert 3 {} a@x", "a".repeat(40))).unwrap();
+        assert_eq!(exec(db, &long, 0).0, VoidDbCode::ValueTooLong);
+
+        assert_eq!(exec(db, c"select \xff", 0).0, VoidDbCode::Misuse);
+        assert_eq!(errmsg(db), "sql is NULL or not UTF-8");
+        assert_eq!(unsafe { voiddb_exec(db, ptr::null(), None, ptr::null_mut()) }, VoidDbCode::Misuse);
+        assert_eq!(unsafe { voiddb_exec(ptr::null_mut(), c"select".as_ptr(), None, ptr::null_mut()) }, VoidDbCode::Misuse);
+        assert_eq!(errmsg(ptr::null()), "no database handle");
+        assert_eq!(unsafe { voiddb_open(c":memory:".as_ptr(), ptr::null_mut()) }, VoidDbCode::Misuse);
+        assert_eq!(unsafe { voiddb_close(db) }, VoidDbCode::Ok);
+        assert_eq!(unsafe { voiddb_close(ptr::null_mut()) }, VoidDbCode::Ok);
+    }
+
+    #[test]
+    fn test_failed_open_keeps_its_error() {
+        let (code, db) = open(c"/nonexistent/dir/test.db");
+        assert_eq!(code, VoidDbCode::IoError);
+        assert!(!db.is_null());
+        assert!(errmsg(db).starts_with("I/O error:"), "{}", errmsg(db));
+        assert_eq!(exec(db, c"select", 0).0, VoidDbCode::Misuse);
+        assert_eq!(unsafe { voiddb_close(db) }, VoidDbCode::Ok);
+
+        let (code, db) = open(c"\xff.db");
+        assert_eq!(code, VoidDbCode::Misuse);
+        assert_eq!(errmsg(db), "path is NULL or not UTF-8");
+        unsafe { voiddb_close(db) };
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let path = TempPath::new("ffi.db");
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let (_, db) = open(&c_path);
+        assert_eq!(exec(db, c"insert 7 carol carol@example.com", 0).0, VoidDbCode::Ok);
+        assert_eq!(unsafe { voiddb_close(db) }, VoidDbCode::Ok);
+        let (_, db) = open(&c_path);
+        assert_eq!(exec(db, c"select", 0).1, vec!["7|carol|carol@example.com|0".to_string()]);
+        unsafe { voiddb_close(db) };
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let path = TempPath::new("ffi-crc.db");
+        crate::testing::write_bad_checksum(&path);
+        let (_, db) = open(&CString::new(path.to_str().unwrap()).unwrap());
+        assert_eq!(exec(db, c"select", 0), (VoidDbCode::ChecksumMismatch, vec!["1|user1|user1@example.com|0".to_string()]));
+        assert_eq!(errmsg(db), "Database is corrupt: row 1 on page 1: checksum mismatch for row with id 2");
+        unsafe { voiddb_close(db) };
+    }
+
+    #[test]
+    fn test_values_are_cut_at_nul() {
+        assert_eq!(c_string("a\0b").as_bytes(), b"a");
+        assert_eq!(c_string("ab").as_bytes(), b"ab");
+    }
+
+    #[test]
+    fn test_codes_match_the_header() {
+        let header = include_str!("../include/voiddb.h");
+        let codes = [
+            ("VOIDDB_OK", VoidDbCode::Ok),
+            ("VOIDDB_ERROR", VoidDbCode::Error),
+            ("VOIDDB_UNRECOGNIZED_STATEMENT", VoidDbCode::UnrecognizedStatement),
+            ("VOIDDB_SYNTAX_ERROR", VoidDbCode::SyntaxError),
+            ("VOIDDB_TABLE_FULL", VoidDbCode::TableFull),
+            ("VOIDDB_DUPLICATE_KEY", VoidDbCode::DuplicateKey),
+            ("VOIDDB_IO_ERROR", VoidDbCode::IoError),
+            ("VOIDDB_CORRUPT", VoidDbCode::Corrupt),
+            ("VOIDDB_INTERRUPTED", VoidDbCode::Interrupted),
+            ("VOIDDB_ATTACH_ERROR", VoidDbCode::AttachError),
+            ("VOIDDB_NO_SUCH_DATABASE", VoidDbCode::NoSuchDatabase),
+            ("VOIDDB_TABLE_EXISTS", VoidDbCode::TableExists),
+            ("VOIDDB_NO_SUCH_TABLE", VoidDbCode::NoSuchTable),
+            ("VOIDDB_READ_ONLY", VoidDbCode::ReadOnly),
+            ("VOIDDB_VALUE_TOO_LONG", VoidDbCode::ValueTooLong),
+            ("VOIDDB_BAD_LAYOUT", VoidDbCode::BadLayout),
+            ("VOIDDB_CHECKSUM_MISMATCH", VoidDbCode::ChecksumMismatch),
+            ("VOIDDB_MISUSE", VoidDbCode::Misuse),
+            ("VOIDDB_ABORT", VoidDbCode::Abort),
+            ("VOIDDB_INTERNAL", VoidDbCode::Internal),
+            ("VOIDDB_LOCKED", VoidDbCode::Locked),
+            ("VOIDDB_NO_SUCH_COLUMN", VoidDbCode::NoSuchColumn),
+            ("VOIDDB_TYPE_MISMATCH", VoidDbCode::TypeMismatch),
+            ("VOIDDB_CANNOT_OPEN", VoidDbCode::CannotOpen),
+        ];
+        for (name, code) in codes {
+            assert!(header.contains(&format!("    {} = {},", name, code as i32)), "{} = {}", name, code as i32);
+        }
+        assert_eq!(header.matches("    VOIDDB_").count(), codes.len());
+    }
+}
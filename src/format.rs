@@ -0,0 +1,165 @@
+//! The on-disk format, byte for byte. Every multi-byte integer is stored
+//! little-endian regardless of the host, so files move freely between
+//! machines. Any change to what is written here must bump `FORMAT_VERSION`
+//! and regenerate the fixtures under `tests/fixtures`.
+//!
+//! A file is a sequence of `PAGE_SIZE` pages. Page 0 holds the header;
+//! from page 1 onwards the pages are one run of fixed-size cells packed end
+//! to end, so a cell may start on one page and finish on the next.
+
+use crate::error::DbError;
+
+pub const FILE_MAGIC: &[u8; 8] = b"VoidDB\0\0";
+pub const FORMAT_VERSION: u32 = 7;
+
+// Header fields on page 0.
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = MAGIC_OFFSET + 8;
+const NUM_ROWS_OFFSET: usize = VERSION_OFFSET + 4;
+const USERNAME_SIZE_OFFSET: usize = NUM_ROWS_OFFSET + 4;
+const EMAIL_SIZE_OFFSET: usize = USERNAME_SIZE_OFFSET + 2;
+const FLAGS_OFFSET: usize = EMAIL_SIZE_OFFSET + 2;
+pub const HEADER_SIZE: usize = FLAGS_OFFSET + 4;
+
+/// Header flag: every cell ends with a CRC-32 of the row payload.
+pub const FLAG_ROW_CHECKSUMS: u32 = 1;
+
+// Row payload fields. String columns are a length followed by the bytes,
+// zero-padded to the column width chosen when the file was created.
+pub const ID_SIZE: usize = 8;
+pub const USERNAME_LEN_SIZE: usize = 1;
+pub const EMAIL_LEN_SIZE: usize = 2;
+pub const AGE_SIZE: usize = 2;
+pub const ID_OFFSET: usize = 0;
+pub const USERNAME_LEN_OFFSET: usize = ID_OFFSET + ID_SIZE;
+pub const USERNAME_OFFSET: usize = USERNAME_LEN_OFFSET + USERNAME_LEN_SIZE;
+
+/// Size of the CRC-32 appended to each cell when `FLAG_ROW_CHECKSUMS` is set.
+pub const CHECKSUM_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub num_rows: u32,
+    pub username_size: u16,
+    pub email_size: u16,
+    pub flags: u32,
+}
+
+impl Header {
+    pub fn decode(page: &[u8]) -> Result<Self, DbError> {
+        if &page[MAGIC_OFFSET..VERSION_OFFSET] != FILE_MAGIC {
+            return Err(DbError::Corrupt("not a VoidDB file".to_string()));
+        }
+
+        let version = read_u32(page, VERSION_OFFSET);
+        if version < FORMAT_VERSION {
+            return Err(DbError::Corrupt(format!(
+                "file uses format version {}, which this version of VoidDB can no longer read (expected {}); \
+                 recreate it with the VoidDB release that wrote it and reload the rows",
+                version, FORMAT_VERSION
+            )));
+        }
+        if version > FORMAT_VERSION {
+            return Err(DbError::Corrupt(format!(
+                "file uses format version {}, newer than this version of VoidDB supports ({})",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        Ok(Header {
+            num_rows: read_u32(page, NUM_ROWS_OFFSET),
+            username_size: read_u16(page, USERNAME_SIZE_OFFSET),
+            email_size: read_u16(page, EMAIL_SIZE_OFFSET),
+            flags: read_u32(page, FLAGS_OFFSET),
+        })
+    }
+
+    pub fn encode(&self, page: &mut [u8]) {
+        page[MAGIC_OFFSET..VERSION_OFFSET].copy_from_slice(FILE_MAGIC);
+        page[VERSION_OFFSET..NUM_ROWS_OFFSET].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        page[NUM_ROWS_OFFSET..USERNAME_SIZE_OFFSET].copy_from_slice(&self.num_rows.to_le_bytes());
+        page[USERNAME_SIZE_OFFSET..EMAIL_SIZE_OFFSET].copy_from_slice(&self.username_size.to_le_bytes());
+        page[EMAIL_SIZE_OFFSET..FLAGS_OFFSET].copy_from_slice(&self.email_size.to_le_bytes());
+        page[FLAGS_OFFSET..HEADER_SIZE].copy_from_slice(&self.flags.to_le_bytes());
+    }
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::pager::PAGE_SIZE;
+    use crate::testing::TempPath;
+    use crate::QueryResult;
+
+    const FIXTURE: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/users-v7.db"));
+
+    // The statements that produced FIXTURE.
+    const FIXTURE_SCRIPT: &str = "insert 1 alice alice@example.com 34\n\
+                                  insert 2 bob bob@example.com\n\
+                                  insert 4294967296 carol carol@example.com 65535";
+
+    #[test]
+    fn test_header_layout() {
+        let mut page = vec![0u8; PAGE_SIZE];
+        Header { num_rows: 0x01020304, username_size: 32, email_size: 255, flags: FLAG_ROW_CHECKSUMS }.encode(&mut page);
+
+        assert_eq!(HEADER_SIZE, 24);
+        assert_eq!(
+            &page[..HEADER_SIZE],
+            &[
+                b'V', b'o', b'i', b'd', b'D', b'B', 0, 0,
+                7, 0, 0, 0,
+                4, 3, 2, 1,
+                32, 0,
+                255, 0,
+                1, 0, 0, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reads_fixture() {
+        let path = TempPath::new("fixture-read.db");
+        std::fs::write(&path, FIXTURE).unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let rows = match db.execute("select").unwrap() {
+            QueryResult::Rows(rows) => rows,
+            other => panic!("unexpected result {:?}", other),
+        };
+        let rendered: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        assert_eq!(
+            rendered,
+            [
+                "(1, alice, alice@example.com, 34)",
+                "(2, bob, bob@example.com, 0)",
+                "(4294967296, carol, carol@example.com, 65535)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_writes_fixture_byte_for_byte() {
+        let path = TempPath::new("fixture-write.db");
+
+        let mut db = Database::open(&path).unwrap();
+        db.execute_batch(FIXTURE_SCRIPT).unwrap();
+        db.close().unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        assert!(written == FIXTURE, "file format changed; bump FORMAT_VERSION and regenerate the fixture");
+    }
+}
@@ -0,0 +1,581 @@
+//! `VoidDB serve --http`: runs statements sent as JSON over HTTP against
+//! one database, for tools that would rather not speak the line protocol.
+//!
+//! Two endpoints:
+//!
+//! - `POST /query` with a body of `{"sql": "..."}` runs the statements in
+//!   `sql`, separated by `;`, stopping at the first that fails. The
+//!   response is that of the last one run: `{"rows":[...]}` for a select,
+//!   `{"rows_affected":N}` for anything else, or
+//!   `{"error":{"code":"...","message":"..."}}` with a 4xx or 5xx status.
+//! - `GET /health` answers `{"status":"ok"}`.
+//!
+//! A server given a token with `auth_token` wants it in an `Authorization:
+//! Bearer <token>` header on every request but those to `/health`.
+//!
+//! Error codes are those listed on `DbError::code`, `TIMEOUT` for a
+//! statement that ran past the statement timeout, and the server's own
+//! for requests it could not take: `AUTH_FAILED`, `BAD_REQUEST`,
+//! `NOT_FOUND`, `METHOD_NOT_ALLOWED`, `LENGTH_REQUIRED`,
+//! `PAYLOAD_TOO_LARGE`, `HEADERS_TOO_LARGE` and `REQUEST_TIMEOUT`.
+//!
+//! Each connection carries one request, and is closed after the response.
+//! Requests are served one at a time, in the order they connect.
+//!
+//! ```text
+//! $ curl -d '{"sql": "select where id = 1"}' http://127.0.0.1:8080/query
+//! {"rows":[{"id":1,"username":"alice","email":"alice@example.com","age":0}]}
+//! ```
+
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::Shutdown;
+use std::time::{Duration, Instant};
+
+use crate::auth::Token;
+use crate::compiler::{split_statements, PrepareError, QueryResult};
+use crate::connection::Connection;
+use crate::error::{DbError, ErrorCode};
+use crate::interrupt::Interrupt;
+use crate::json::{self, Json};
+use crate::net::{Address, Listener, Stream};
+use crate::row::{Value, COLUMNS};
+use crate::server::{accept_until_stopped, Draining, Log, DEFAULT_GRACE_PERIOD, POLL_INTERVAL};
+
+/// The largest request body taken unless told otherwise.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// How long a statement may run unless told otherwise.
+pub const DEFAULT_STATEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most bytes the request line and headers together may take.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// How long a client has to send the whole of its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the rest of a request turned away unread is read and thrown
+/// away for. Closing a connection with data still to read resets it, and
+/// the client may then never see the response.
+const LINGER: Duration = Duration::from_secs(1);
+
+/// A listening HTTP server.
+pub struct HttpServer {
+    listener: Listener,
+    connection: Connection,
+    stop: Interrupt,
+    max_request_size: usize,
+    statement_timeout: Duration,
+    grace_period: Duration,
+    token: Option<Token>,
+    log: Log,
+}
+
+impl HttpServer {
+    /// Listens on `address`, as `Listener::bind` does.
+    pub fn bind(address: &Address, connection: Connection) -> io::Result<HttpServer> {
+        let listener = Listener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(HttpServer {
+            listener,
+            connection,
+            stop: Interrupt::default(),
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            statement_timeout: DEFAULT_STATEMENT_TIMEOUT,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            token: None,
+            log: Log::default(),
+        })
+    }
+
+    /// Runs statements only for requests with an `Authorization: Bearer
+    /// <token>` header, and answers others with `401 Unauthorized`.
+    /// `/health` stays open, for load balancers and the like.
+    pub fn auth_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Notes each request turned away for want of the token on `out`, a
+    /// line each.
+    pub fn log(mut self, out: impl Write + Send + 'static) -> Self {
+        self.log = Log::new(out);
+        self
+    }
+
+    /// Turns away request bodies of more than `bytes` with `413 Payload
+    /// Too Large`, without reading them.
+    pub fn max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = bytes;
+        self
+    }
+
+    /// Interrupts a statement that runs longer than `timeout`, which then
+    /// fails with `TIMEOUT`. Any statements after it in the request are
+    /// not run.
+    pub fn statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = timeout;
+        self
+    }
+
+    /// Once stopped, lets the statement being run go on for up to
+    /// `grace_period` before interrupting it.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<Address> {
+        self.listener.local_addr()
+    }
+
+    /// A handle that makes `run` stop accepting requests once it is
+    /// interrupted, and return after the request being run, if any, has
+    /// its response.
+    pub fn stop_handle(&self) -> Interrupt {
+        self.stop.clone()
+    }
+
+    /// Serves requests until stopped.
+    pub fn run(&self) -> io::Result<()> {
+        // One at a time: the statement timeout interrupts whatever runs on
+        // the database, which must be only the statement it is for.
+        let draining = Draining { statements: self.connection.read().interrupt_handle(), grace_period: self.grace_period };
+        accept_until_stopped(&self.listener, &self.stop, 1, &draining, |stream| self.serve(stream))
+    }
+
+    fn serve(&self, stream: Stream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let peer = stream.peer();
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        let (response, unread) = match self.read_request(&mut reader, &mut writer) {
+            Ok(Ok(request)) => (self.answer(&request, &peer), false),
+            Ok(Err(response)) => (response, true),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => (Response::error(408, "REQUEST_TIMEOUT", "The request took too long to arrive."), true),
+            // The client went away, or the stream failed: no one to answer.
+            Err(err) => return Err(err),
+        };
+        response.write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().shutdown(Shutdown::Write)?;
+        if unread {
+            discard(&mut reader);
+        }
+        Ok(())
+    }
+
+    /// Reads a request, or the response that turns it away. A client that
+    /// hangs up before sending anything is an `UnexpectedEof` error, and
+    /// one that takes longer than `REQUEST_TIMEOUT` a `TimedOut` error.
+    fn read_request(&self, reader: &mut dyn BufRead, writer: &mut dyn Write) -> io::Result<Result<Request, Response>> {
+        let started = Instant::now();
+        let mut head = Vec::new();
+        while !(head.ends_with(b"\n\n") || head.ends_with(b"\r\n\r\n")) {
+            if head.len() > MAX_HEAD_SIZE {
+                return Ok(Err(Response::error(431, "HEADERS_TOO_LARGE", "The request headers are too large.")));
+            }
+            let limit = (MAX_HEAD_SIZE + 1 - head.len()) as u64;
+            if self.retry(started, || (&mut *reader).take(limit).read_until(b'\n', &mut head))? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the client closed the connection"));
+            }
+        }
+        let Ok(head) = String::from_utf8(head) else {
+            return Ok(Err(Response::bad_request("The request headers must be UTF-8.")));
+        };
+        let mut lines = head.lines().skip_while(|line| line.is_empty());
+        let request_line = lines.next().unwrap_or_default();
+        let [method, target, version] = request_line.split(' ').collect::<Vec<_>>()[..] else {
+            return Ok(Err(Response::bad_request(&format!("Malformed request line '{}'.", request_line))));
+        };
+        if !version.starts_with("HTTP/1.") {
+            return Ok(Err(Response::bad_request(&format!("Unsupported version '{}'.", version))));
+        }
+        let mut content_length = None;
+        let mut expect_continue = false;
+        let mut authorization = None;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                return Ok(Err(Response::bad_request(&format!("Malformed header '{}'.", line))));
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.parse::<usize>() {
+                    Ok(length) => content_length = Some(length),
+                    Err(_) => return Ok(Err(Response::bad_request(&format!("Bad Content-Length '{}'.", value)))),
+                }
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                content_length = None;
+                break;
+            } else if name.eq_ignore_ascii_case("expect") {
+                expect_continue = value.eq_ignore_ascii_case("100-continue");
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.to_string());
+            }
+        }
+
+        let path = target.split('?').next().unwrap_or_default().to_string();
+        let mut request = Request { method: method.to_string(), path, authorization, body: Vec::new() };
+        if method != "POST" {
+            return Ok(Ok(request));
+        }
+        let Some(length) = content_length else {
+            return Ok(Err(Response::error(411, "LENGTH_REQUIRED", "A request body needs a Content-Length.")));
+        };
+        if length > self.max_request_size {
+            let message = format!("The request body is {} bytes; at most {} are taken.", length, self.max_request_size);
+            return Ok(Err(Response::error(413, "PAYLOAD_TOO_LARGE", &message)));
+        }
+        if expect_continue {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+        while request.body.len() < length {
+            let limit = (length - request.body.len()) as u64;
+            if self.retry(started, || (&mut *reader).take(limit).read_to_end(&mut request.body))? == 0 {
+                return Ok(Err(Response::bad_request("The request body is shorter than its Content-Length.")));
+            }
+        }
+        Ok(Ok(request))
+    }
+
+    /// Runs `read` until it does not time out, which it does every
+    /// `POLL_INTERVAL` so that a stop is noticed, failing with `TimedOut`
+    /// once the request has taken `REQUEST_TIMEOUT` or the server is
+    /// stopped. `read` must keep what it reads before timing out.
+    fn retry(&self, started: Instant, mut read: impl FnMut() -> io::Result<usize>) -> io::Result<usize> {
+        loop {
+            match read() {
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {
+                    if self.stop.is_interrupted() || started.elapsed() > REQUEST_TIMEOUT {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "the request took too long"));
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The response to a request from `peer`, once its token is checked.
+    fn answer(&self, request: &Request, peer: &str) -> Response {
+        match self.authorize(request) {
+            Ok(()) => self.respond(request),
+            Err(message) => {
+                self.log.entry(&format!("Turned away {}: {}", peer, message));
+                Response { header: Some(("WWW-Authenticate", "Bearer")), ..Response::error(401, "AUTH_FAILED", message) }
+            }
+        }
+    }
+
+    /// Checks the token of a request that needs one, or says why it will
+    /// not do.
+    fn authorize(&self, request: &Request) -> Result<(), &'static str> {
+        let Some(token) = &self.token else {
+            return Ok(());
+        };
+        if request.path == "/health" {
+            return Ok(());
+        }
+        let Some(authorization) = &request.authorization else {
+            return Err("Authentication required.");
+        };
+        match authorization.split_once(' ') {
+            Some((scheme, given)) if scheme.eq_ignore_ascii_case("bearer") && token.matches(given.trim()) => Ok(()),
+            _ => Err("Authentication failed."),
+        }
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        match (request.path.as_str(), request.method.as_str()) {
+            ("/health", "GET") => Response { status: 200, body: "{\"status\":\"ok\"}".to_string(), header: None },
+            ("/query", "POST") => self.query(&request.body),
+            ("/health", _) => Response { header: Some(("Allow", "GET")), ..Response::error(405, "METHOD_NOT_ALLOWED", "Use GET for /health.") },
+            ("/query", _) => Response { header: Some(("Allow", "POST")), ..Response::error(405, "METHOD_NOT_ALLOWED", "Use POST for /query.") },
+            (path, _) => Response::error(404, "NOT_FOUND", &format!("No endpoint '{}'.", path)),
+        }
+    }
+
+    fn query(&self, body: &[u8]) -> Response {
+        let Ok(body) = std::str::from_utf8(body) else {
+            return Response::bad_request("The request body must be UTF-8.");
+        };
+        let sql = match json::parse(body) {
+            Ok(Json::Object(fields)) => match fields.into_iter().find(|(name, _)| name == "sql") {
+                Some((_, Json::String(sql))) => sql,
+                _ => return Response::bad_request("The request body must be an object with an \"sql\" string."),
+            },
+            Ok(_) => return Response::bad_request("The request body must be an object with an \"sql\" string."),
+            Err(err) => return Response::bad_request(&format!("Malformed JSON: {}", err)),
+        };
+        let statements = split_statements(&sql);
+        if statements.is_empty() {
+            return Response::bad_request("No statement to run.");
+        }
+        let mut result = QueryResult::Done;
+        for sql in statements {
+            result = match self.statement(sql) {
+                Ok(result) => result,
+                Err(response) => return response,
+            };
+        }
+
+        let body = match result {
+            QueryResult::Rows(rows) => {
+                let mut body = b"{\"rows\":[".to_vec();
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        body.push(b',');
+                    }
+                    let _ = json::write_object(&mut body, COLUMNS.into_iter().zip(row.values()));
+                }
+                body.extend_from_slice(b"]}");
+                String::from_utf8_lossy(&body).into_owned()
+            }
+            QueryResult::RowsAffected(count) => format!("{{\"rows_affected\":{}}}", count),
+            // A create table changes no rows.
+            QueryResult::Done => "{\"rows_affected\":0}".to_string(),
+        };
+        Response { status: 200, body, header: None }
+    }
+
+    /// Runs one statement within the statement timeout, writing out
+    /// anything it changed before it answers, as `Session` does.
+    fn statement(&self, sql: &str) -> Result<QueryResult, Response> {
+        let deadline = self.connection.read().interrupt_handle().after(self.statement_timeout);
+        let result = self.connection.execute(sql).and_then(|result| match result {
+            QueryResult::Rows(rows) => Ok(QueryResult::Rows(rows)),
+            result => self.connection.flush().map(|()| result),
+        });
+        let timed_out = deadline.expired();
+        drop(deadline);
+        result.map_err(|err| match err {
+            DbError::Interrupted if timed_out => {
+                let message = format!("The statement ran longer than the {} ms statement timeout.", self.statement_timeout.as_millis());
+                Response::error(503, "TIMEOUT", &message)
+            }
+            DbError::Prepare(PrepareError::UnrecognizedStatement) => {
+                Response::error(400, err.code(), &format!("Unrecognized keyword at start of '{}'.", sql))
+            }
+            err => Response::error(status(err.error_code()), err.code(), &err.to_string()),
+        })
+    }
+}
+
+/// Reads and throws away whatever the client still sends, until it hangs
+/// up or for `LINGER` at most.
+fn discard(reader: &mut dyn BufRead) {
+    let started = Instant::now();
+    let mut buf = [0; 8192];
+    while started.elapsed() < LINGER {
+        match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// The HTTP status for a statement that failed with `code`: 4xx if the
+/// statement is at fault, 5xx if the server is.
+fn status(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::DuplicateKey | ErrorCode::TableExists => 409,
+        ErrorCode::ReadOnly => 403,
+        ErrorCode::Locked | ErrorCode::Interrupted => 503,
+        ErrorCode::TableFull => 507,
+        ErrorCode::IoError | ErrorCode::Corrupt | ErrorCode::ChecksumMismatch | ErrorCode::CannotOpen => 500,
+        _ => 400,
+    }
+}
+
+/// The parts of a request the endpoints look at.
+struct Request {
+    method: String,
+    /// The target without its query string.
+    path: String,
+    /// The value of the `Authorization` header.
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    /// JSON, without a line ending.
+    body: String,
+    /// A header to send besides the usual ones: `Allow` for a `405`, or
+    /// `WWW-Authenticate` for a `401`.
+    header: Option<(&'static str, &'static str)>,
+}
+
+impl Response {
+    fn error(status: u16, code: &str, message: &str) -> Response {
+        let mut body = b"{\"error\":".to_vec();
+        let _ = json::write_object(&mut body, [("code", Value::Text(code)), ("message", Value::Text(message))]);
+        body.push(b'}');
+        Response { status, body: String::from_utf8_lossy(&body).into_owned(), header: None }
+    }
+
+    fn bad_request(message: &str) -> Response {
+        Response::error(400, "BAD_REQUEST", message)
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        write!(out, "Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n", self.body.len() + 1)?;
+        if let Some((name, value)) = self.header {
+            write!(out, "{}: {}\r\n", name, value)?;
+        }
+        write!(out, "\r\n{}\n", self.body)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        507 => "Insufficient Storage",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::database::Options;
+    use crate::import::OnConflict;
+    use crate::row::Row;
+    use crate::testing::{FaultyVfs, TempPath};
+    use crate::vfs::MemoryVfs;
+
+    fn server(connection: &Connection) -> HttpServer {
+        HttpServer::bind(&Address::Tcp("127.0.0.1:0".to_string()), connection.clone()).unwrap()
+    }
+
+    /// Sends `request` as is and returns the status and body of the
+    /// response.
+    fn send(server: &HttpServer, request: &str) -> (u16, String) {
+        let mut reader = request.as_bytes();
+        let mut wire = Vec::new();
+        let response = match server.read_request(&mut reader, &mut wire).unwrap() {
+            Ok(request) => server.answer(&request, "a test"),
+            Err(response) => response,
+        };
+        response.write(&mut wire).unwrap();
+        let wire = String::from_utf8(wire).unwrap();
+        let (head, body) = wire.split_once("\r\n\r\n").unwrap();
+        (head[9..12].parse().unwrap(), body.trim_end().to_string())
+    }
+
+    fn post(server: &HttpServer, body: &str) -> (u16, String) {
+        send(server, &format!("POST /query HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n{}", body.len(), body))
+    }
+
+    #[test]
+    fn test_queries() {
+        let connection = Connection::open_in_memory();
+        let server = server(&connection);
+        assert_eq!(post(&server, r#"{"sql": "insert 1 alice a@x; insert 2 bob b@x"}"#), (200, r#"{"rows_affected":1}"#.to_string()));
+        assert_eq!(
+            post(&server, r#"{"sql": "select where id = 2"}"#),
+            (200, r#"{"rows":[{"id":2,"username":"bob","email":"b@x","age":0}]}"#.to_string())
+        );
+        assert_eq!(post(&server, r#"{"sql": "select where id = 3"}"#), (200, r#"{"rows":[]}"#.to_string()));
+        assert_eq!(
+            post(&server, r#"{"sql": "insert 1 carol c@x"}"#),
+            (409, r#"{"error":{"code":"DUPLICATE_KEY","message":"Error: Duplicate key."}}"#.to_string())
+        );
+        assert_eq!(
+            post(&server, r#"{"sql": "update"}"#),
+            (400, r#"{"error":{"code":"UNRECOGNIZED_STATEMENT","message":"Unrecognized keyword at start of 'update'."}}"#.to_string())
+        );
+        assert_eq!(send(&server, "GET /health HTTP/1.1\r\n\r\n"), (200, r#"{"status":"ok"}"#.to_string()));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let path = TempPath::new("http-crc.db");
+        crate::testing::write_bad_checksum(&path);
+        let connection = Connection::open(&path).unwrap();
+        let server = server(&connection);
+        assert_eq!(
+            post(&server, r#"{"sql": "select"}"#),
+            (500, r#"{"error":{"code":"CHECKSUM_MISMATCH","message":"Database is corrupt: row 1 on page 1: checksum mismatch for row with id 2"}}"#.to_string())
+        );
+        drop((server, connection));
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let connection = Connection::open_in_memory();
+        let server = server(&connection).max_request_size(64);
+        let code = |(status, body): (u16, String)| (status, body.split('"').nth(5).unwrap_or_default().to_string());
+        assert_eq!(code(post(&server, "{\"sql\": ")), (400, "BAD_REQUEST".to_string()));
+        assert_eq!(code(post(&server, "[\"select\"]")), (400, "BAD_REQUEST".to_string()));
+        assert_eq!(code(post(&server, "{\"sql\": \" ; \"}")), (400, "BAD_REQUEST".to_string()));
+        assert_eq!(code(post(&server, &format!("{{\"sql\": \"{}\"}}", "x".repeat(64)))), (413, "PAYLOAD_TOO_LARGE".to_string()));
+        assert_eq!(code(send(&server, "POST /query HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")), (411, "LENGTH_REQUIRED".to_string()));
+        assert_eq!(code(send(&server, "GET /query HTTP/1.1\r\n\r\n")), (405, "METHOD_NOT_ALLOWED".to_string()));
+        assert_eq!(code(send(&server, "GET /tables HTTP/1.1\r\n\r\n")), (404, "NOT_FOUND".to_string()));
+        assert_eq!(code(send(&server, "GET /health\r\n\r\n")), (400, "BAD_REQUEST".to_string()));
+        let huge = format!("GET /health HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(MAX_HEAD_SIZE));
+        assert_eq!(code(send(&server, &huge)), (431, "HEADERS_TOO_LARGE".to_string()));
+        let short = "POST /query HTTP/1.1\r\nContent-Length: 20\r\n\r\n{\"sql\": \"select\"}";
+        assert_eq!(code(send(&server, short)), (400, "BAD_REQUEST".to_string()));
+    }
+
+    #[test]
+    fn test_bearer_tokens() {
+        let connection = Connection::open_in_memory();
+        let server = server(&connection).auth_token(Token::new("s3cret").unwrap());
+        let query = |authorization: &str| {
+            let body = r#"{"sql": "select"}"#;
+            let request = format!("POST /query HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}", authorization, body.len(), body);
+            send(&server, &request)
+        };
+        let turned_away = |message: &str| (401, format!(r#"{{"error":{{"code":"AUTH_FAILED","message":"{}"}}}}"#, message));
+        assert_eq!(query(""), turned_away("Authentication required."));
+        assert_eq!(query("Authorization: Bearer s3cre\r\n"), turned_away("Authentication failed."));
+        assert_eq!(query("Authorization: Basic s3cret\r\n"), turned_away("Authentication failed."));
+        assert_eq!(query("Authorization: Bearer s3cret\r\n"), (200, r#"{"rows":[]}"#.to_string()));
+        assert_eq!(query("authorization: bearer  s3cret \r\n"), (200, r#"{"rows":[]}"#.to_string()));
+        assert_eq!(send(&server, "GET /health HTTP/1.1\r\n\r\n").0, 200);
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        let files = Arc::new(MemoryVfs::new());
+        let mut db = Options::new().vfs(files.clone()).open("slow.db").unwrap();
+        db.insert_rows((1..=500).map(|id| Row::new(id, "user", "user@example.com")), OnConflict::Fail).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        // Every page the scan loads takes a while to arrive.
+        let slow = Arc::new(FaultyVfs::new(files));
+        let connection = Connection::new(Options::new().vfs(slow.clone()).open("slow.db").unwrap());
+        slow.slow_reads(Duration::from_millis(5));
+        let server = server(&connection).statement_timeout(Duration::from_millis(20));
+        let (status, body) = post(&server, r#"{"sql": "select; insert 501 late l@x"}"#);
+        assert_eq!(status, 503, "{}", body);
+        assert!(body.contains("\"TIMEOUT\""), "{}", body);
+        assert!(body.contains("20 ms"), "{}", body);
+
+        // The statements after it did not run, and the next request is
+        // given its own time.
+        slow.slow_reads(Duration::ZERO);
+        assert_eq!(post(&server, r#"{"sql": "select where id = 501"}"#), (200, r#"{"rows":[]}"#.to_string()));
+    }
+}
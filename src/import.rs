@@ -0,0 +1,588 @@
+//! Bulk loading of rows from files. Each format turns its input into a
+//! stream of parsed rows, and `import_rows` inserts them, collecting the
+//! lines that could not be loaded instead of stopping at the first one.
+
+use std::io;
+#[cfg(any(feature = "cli", test))]
+use std::io::BufRead;
+use std::ops::ControlFlow;
+
+use crate::compiler::ExecuteError;
+#[cfg(any(feature = "cli", test))]
+use crate::csv;
+use crate::database::Database;
+use crate::error::DbError;
+#[cfg(any(feature = "cli", test))]
+use crate::json::{self, Json};
+use crate::progress::Tracker;
+use crate::row::Row;
+
+/// A line that could not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// What an import does with a row whose id is already in the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Report the line like any other bad line.
+    #[default]
+    Fail,
+    /// Stop, and take back every row the import inserted.
+    Abort,
+    /// Keep the row already there and count the line as skipped.
+    Skip,
+    /// Overwrite the row already there.
+    Replace,
+}
+
+impl OnConflict {
+    pub fn name(self) -> &'static str {
+        match self {
+            OnConflict::Fail => "fail",
+            OnConflict::Abort => "abort",
+            OnConflict::Skip => "skip",
+            OnConflict::Replace => "replace",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "fail" => Some(OnConflict::Fail),
+            "abort" => Some(OnConflict::Abort),
+            "skip" => Some(OnConflict::Skip),
+            "replace" => Some(OnConflict::Replace),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Rows added under a new id.
+    pub inserted: usize,
+    /// Rows that overwrote one with the same id.
+    pub replaced: usize,
+    /// Rows left out because their id was taken.
+    pub skipped: usize,
+    pub errors: Vec<ImportError>,
+    /// Whether a conflict under `OnConflict::Abort` stopped the import and
+    /// took its rows back out. The conflicting line is the last error.
+    pub aborted: bool,
+    /// Whether the progress callback or an `Interrupt` stopped the import.
+    /// Rows imported up to then are kept.
+    pub cancelled: bool,
+}
+
+/// One parsed line of input: the row it holds, or why it holds none.
+pub(crate) type SourceRow = (usize, Result<Row, String>);
+
+/// What became of one line.
+enum Outcome {
+    Inserted,
+    Replaced,
+    Skipped,
+    Bad(String),
+    /// A taken id under `OnConflict::Abort`.
+    Abort(String),
+}
+
+/// Inserts every row from `source`. Bad lines are collected in the summary,
+/// or with `strict` stop the import after being recorded. Rows whose id is
+/// already taken are dealt with as `on_conflict` says, and `tracker` hears
+/// about progress after each line. Errors that are not the line's fault,
+/// such as a full table or an unreadable file, end the import and are
+/// returned.
+pub(crate) fn import_rows<I>(
+    db: &mut Database,
+    source: I,
+    strict: bool,
+    on_conflict: OnConflict,
+    tracker: &mut Tracker<'_>,
+) -> Result<ImportSummary, DbError>
+where
+    I: IntoIterator<Item = io::Result<SourceRow>>,
+{
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+    let summary = insert_each(db, source, strict, on_conflict, tracker, &mut batch);
+    // Rows counted before an error are kept, so they are written and
+    // reported too.
+    let appended = db.append_rows(&mut batch);
+    db.notify_changes();
+    let summary = summary?;
+    appended.map(|_| summary)
+}
+
+/// The most new rows an import holds back to write together.
+const BATCH_ROWS: usize = 64;
+
+fn insert_each<I>(
+    db: &mut Database,
+    source: I,
+    strict: bool,
+    on_conflict: OnConflict,
+    tracker: &mut Tracker<'_>,
+    batch: &mut Vec<Row>,
+) -> Result<ImportSummary, DbError>
+where
+    I: IntoIterator<Item = io::Result<SourceRow>>,
+{
+    db.interrupt_handle().clear();
+    let start = db.row_count();
+    let mut summary = ImportSummary::default();
+    for item in source {
+        let (line, row) = item?;
+        let outcome = match row {
+            // New rows wait in `batch` to be written a page at a time; the
+            // rest go one by one after it, to keep the order.
+            Ok(row) if on_conflict != OnConflict::Replace && db.can_append(&row, batch)? => {
+                batch.push(row);
+                if batch.len() == BATCH_ROWS {
+                    db.append_rows(batch)?;
+                }
+                Outcome::Inserted
+            }
+            Ok(row) => {
+                db.append_rows(batch)?;
+                insert(db, &row, on_conflict)?
+            }
+            Err(message) => Outcome::Bad(message),
+        };
+
+        match outcome {
+            Outcome::Inserted => summary.inserted += 1,
+            Outcome::Replaced => summary.replaced += 1,
+            Outcome::Skipped => summary.skipped += 1,
+            Outcome::Bad(message) => {
+                summary.errors.push(ImportError { line, message });
+                if strict {
+                    break;
+                }
+            }
+            Outcome::Abort(message) => {
+                // Abort never replaces, so the import has only appended
+                // rows and dropping them undoes it.
+                db.truncate(start);
+                summary.errors.push(ImportError { line, message });
+                summary.inserted = 0;
+                summary.aborted = true;
+                break;
+            }
+        }
+
+        let rows = summary.inserted + summary.replaced + summary.skipped + summary.errors.len();
+        if tracker.tick(rows, summary.errors.len()) == ControlFlow::Break(()) || db.interrupted() {
+            summary.cancelled = true;
+            break;
+        }
+    }
+    Ok(summary)
+}
+
+fn insert(db: &mut Database, row: &Row, on_conflict: OnConflict) -> Result<Outcome, DbError> {
+    if on_conflict == OnConflict::Replace {
+        return match db.upsert_row(row) {
+            Ok(true) => Ok(Outcome::Replaced),
+            Ok(false) => Ok(Outcome::Inserted),
+            Err(DbError::Row(err)) => Ok(Outcome::Bad(err.to_string())),
+            Err(err) => Err(err),
+        };
+    }
+    match db.insert_row(row) {
+        Ok(_) => Ok(Outcome::Inserted),
+        Err(DbError::Row(err)) => Ok(Outcome::Bad(err.to_string())),
+        Err(DbError::Execute(ExecuteError::DuplicateKey)) => {
+            let message = format!("duplicate id {}", row.id);
+            Ok(match on_conflict {
+                OnConflict::Abort => Outcome::Abort(message),
+                OnConflict::Skip => Outcome::Skipped,
+                _ => Outcome::Bad(message),
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The rows of a CSV file with `id,username,email[,age]` records.
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn csv_rows<R: BufRead>(
+    reader: csv::Reader<R>,
+    skip_header: bool,
+) -> impl Iterator<Item = io::Result<SourceRow>> {
+    reader.skip(skip_header as usize).map(|record| {
+        let record = record?;
+        Ok((record.line, csv_row(&record.fields)))
+    })
+}
+
+#[cfg(any(feature = "cli", test))]
+fn csv_row(fields: &[String]) -> Result<Row, String> {
+    let (id, username, email, age) = match fields {
+        [id, username, email] => (id, username, email, None),
+        [id, username, email, age] => (id, username, email, Some(age)),
+        _ => return Err(format!("expected 3 or 4 columns, found {}", fields.len())),
+    };
+    let id = id.parse().map_err(|_| format!("bad id '{}'", id))?;
+    let age = match age {
+        Some(age) => age.parse().map_err(|_| format!("bad age '{}'", age))?,
+        None => 0,
+    };
+    Ok(Row::new(id, username, email).with_age(age))
+}
+
+/// The rows of a JSON Lines file holding one object per line, with fields
+/// `id`, `username`, `email` and optionally `age`. Blank lines are skipped.
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn jsonl_rows<R: BufRead>(reader: R) -> impl Iterator<Item = io::Result<SourceRow>> {
+    reader.lines().enumerate().filter_map(|(index, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(Ok((index + 1, jsonl_row(&line)))),
+        Err(err) => Some(Err(err)),
+    })
+}
+
+#[cfg(any(feature = "cli", test))]
+fn jsonl_row(line: &str) -> Result<Row, String> {
+    json_row(json::parse(line)?)
+}
+
+/// The row a JSON object with the fields listed on `jsonl_rows` holds.
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn json_row(value: Json) -> Result<Row, String> {
+    let Json::Object(fields) = value else {
+        return Err("expected an object".to_string());
+    };
+    let (mut id, mut username, mut email, mut age) = (None, None, None, None);
+    for (name, value) in fields {
+        let slot = match name.as_str() {
+            "id" => &mut id,
+            "username" => &mut username,
+            "email" => &mut email,
+            "age" => &mut age,
+            _ => return Err(format!("unknown field '{}'", name)),
+        };
+        if slot.replace(value).is_some() {
+            return Err(format!("duplicate field '{}'", name));
+        }
+    }
+
+    let id = match id {
+        Some(Json::Number(id)) => id.parse().map_err(|_| format!("bad id {}", id))?,
+        Some(_) => return Err("id must be a number".to_string()),
+        None => return Err("missing field 'id'".to_string()),
+    };
+    let text = |value: Option<Json>, name: &str| match value {
+        Some(Json::String(text)) => Ok(text),
+        Some(_) => Err(format!("{} must be a string", name)),
+        None => Err(format!("missing field '{}'", name)),
+    };
+    let username = text(username, "username")?;
+    let email = text(email, "email")?;
+    let age = match age {
+        Some(Json::Number(age)) => age.parse().map_err(|_| format!("bad age {}", age))?,
+        Some(Json::Null) | None => 0,
+        Some(_) => return Err("age must be a number".to_string()),
+    };
+    Ok(Row::new(id, &username, &email).with_age(age))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::database::Options;
+
+    fn import_csv(db: &mut Database, input: &str, skip_header: bool, strict: bool) -> ImportSummary {
+        let reader = csv::Reader::new(input.as_bytes(), ',');
+        import_rows(db, csv_rows(reader, skip_header), strict, OnConflict::Fail, &mut Tracker::new(None)).unwrap()
+    }
+
+    fn import_jsonl(db: &mut Database, input: &str, on_conflict: OnConflict) -> ImportSummary {
+        import_rows(db, jsonl_rows(input.as_bytes()), false, on_conflict, &mut Tracker::new(None)).unwrap()
+    }
+
+    fn select(db: &mut Database) -> Vec<Row> {
+        db.query("select").unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let mut db = Database::open_in_memory();
+        let summary = import_csv(&mut db, "1,alice,a@x.com,34\n2,\"bob, jr\",b@x.com\n", false, false);
+        assert_eq!(summary, ImportSummary { inserted: 2, ..ImportSummary::default() });
+
+        let mut reference = Database::open_in_memory();
+        reference.execute("insert 1 alice a@x.com 34").unwrap();
+        reference.execute("insert 2 'bob, jr' b@x.com").unwrap();
+        assert_eq!(select(&mut db), select(&mut reference));
+    }
+
+    #[test]
+    fn test_import_csv_skips_header() {
+        let mut db = Database::open_in_memory();
+        let summary = import_csv(&mut db, "id,username,email,age\n1,alice,a@x.com,34\n", true, false);
+        assert_eq!(summary.inserted, 1);
+        assert_eq!(select(&mut db), vec![Row::new(1, "alice", "a@x.com").with_age(34)]);
+    }
+
+    #[test]
+    fn test_import_csv_collects_bad_lines() {
+        let input = format!("1,a,a@x\nx,b,b@x\n3,c\n4,{},d@x\n5,e,e@x,old\n6,f,f@x\n", "n".repeat(33));
+
+        let mut db = Database::open_in_memory();
+        let summary = import_csv(&mut db, &input, false, false);
+        assert_eq!(summary.inserted, 2);
+        assert_eq!(
+            summary.errors,
+            vec![
+                ImportError { line: 2, message: "bad id 'x'".to_string() },
+                ImportError { line: 3, message: "expected 3 or 4 columns, found 2".to_string() },
+                ImportError { line: 4, message: "username is 33 bytes, at most 32 allowed".to_string() },
+                ImportError { line: 5, message: "bad age 'old'".to_string() },
+            ]
+        );
+        assert_eq!(select(&mut db).iter().map(|row| row.id).collect::<Vec<_>>(), [1, 6]);
+
+        let mut db = Database::open_in_memory();
+        let summary = import_csv(&mut db, &input, false, true);
+        assert_eq!(summary.inserted, 1);
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(select(&mut db).len(), 1);
+    }
+
+    #[test]
+    fn test_import_jsonl() {
+        let input = concat!(
+            r#"{"id": 1, "username": "alice", "email": "a@x.com", "age": 34}"#,
+            "\n\n",
+            r#"{"email": "b@x.com", "username": "bob \"jr\"", "id": 2}"#,
+            "\n",
+        );
+        let mut db = Database::open_in_memory();
+        let summary = import_jsonl(&mut db, input, OnConflict::Fail);
+        assert_eq!(summary, ImportSummary { inserted: 2, ..ImportSummary::default() });
+        assert_eq!(
+            select(&mut db),
+            vec![Row::new(1, "alice", "a@x.com").with_age(34), Row::new(2, "bob \"jr\"", "b@x.com")]
+        );
+    }
+
+    #[test]
+    fn test_import_jsonl_collects_bad_lines() {
+        let lines = [
+            r#"{"id": 1, "username": "a", "email": "a@x"}"#.to_string(),
+            r#"{"id": 2, "username": "b""#.to_string(),
+            r#"[1, "c", "c@x"]"#.to_string(),
+            r#"{"id": "4", "username": "d", "email": "d@x"}"#.to_string(),
+            r#"{"id": -5, "username": "e", "email": "e@x"}"#.to_string(),
+            r#"{"id": 6, "username": "f"}"#.to_string(),
+            r#"{"id": 7, "username": "g", "email": "g@x", "age": 70000}"#.to_string(),
+            r#"{"id": 8, "username": "h", "email": "h@x", "nick": "hh"}"#.to_string(),
+            format!(r#"{{"id": 9, "username": "{}", "email": "i@x"}}"#, "n".repeat(33)),
+            r#"{"id": 1, "username": "j", "email": "j@x"}"#.to_string(),
+            r#"{"id": 11, "username": "k", "email": "k@x", "age": null}"#.to_string(),
+        ];
+        let mut db = Database::open_in_memory();
+        let summary = import_jsonl(&mut db, &lines.join("\n"), OnConflict::Fail);
+        assert_eq!(summary.inserted, 2);
+        let messages: Vec<(usize, &str)> = summary.errors.iter().map(|err| (err.line, err.message.as_str())).collect();
+        assert_eq!(
+            messages,
+            [
+                (2, "expected ',' or '}' at byte 25"),
+                (3, "expected an object"),
+                (4, "id must be a number"),
+                (5, "bad id -5"),
+                (6, "missing field 'email'"),
+                (7, "bad age 70000"),
+                (8, "unknown field 'nick'"),
+                (9, "username is 33 bytes, at most 32 allowed"),
+                (10, "duplicate id 1"),
+            ]
+        );
+        assert_eq!(select(&mut db), vec![Row::new(1, "a", "a@x"), Row::new(11, "k", "k@x")]);
+    }
+
+    #[test]
+    fn test_import_jsonl_replace() {
+        let mut db = Database::open_in_memory();
+        db.execute("insert 1 alice a@x.com 34").unwrap();
+        let input = "{\"id\": 2, \"username\": \"bob\", \"email\": \"b@x\"}\n{\"id\": 1, \"username\": \"al\", \"email\": \"al@x\"}\n";
+        let summary = import_jsonl(&mut db, input, OnConflict::Replace);
+        assert_eq!(summary, ImportSummary { inserted: 1, replaced: 1, ..ImportSummary::default() });
+        assert_eq!(select(&mut db), vec![Row::new(1, "al", "al@x"), Row::new(2, "bob", "b@x")]);
+    }
+
+    #[test]
+    fn test_import_jsonl_streams_large_files() {
+        let count = 1000;
+        let mut input = Vec::new();
+        for id in 0..count {
+            writeln!(input, r#"{{"id": {}, "username": "user{}", "email": "user{}@example.com", "age": {}}}"#, id, id, id, id % 100).unwrap();
+        }
+        let mut db = Database::open_in_memory();
+        let source = jsonl_rows(io::BufReader::with_capacity(64, &input[..]));
+        let summary = import_rows(&mut db, source, true, OnConflict::Fail, &mut Tracker::new(None)).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: count, ..ImportSummary::default() });
+        let rows = select(&mut db);
+        assert_eq!(rows.len(), count);
+        assert_eq!(rows[count - 1], Row::new(999, "user999", "user999@example.com").with_age(99));
+    }
+
+    #[test]
+    fn test_import_on_conflict() {
+        let input = "1,a,a@x\n2,b,b@x\n3,c,c@x\n4,d,d@x\n";
+        let setup = |db: &mut Database| {
+            db.execute("insert 3 old old@x").unwrap();
+            db.execute("insert 9 keep keep@x").unwrap();
+        };
+        let ids_and_names = |db: &mut Database| -> Vec<(u64, String)> {
+            select(db).into_iter().map(|row| (row.id, row.username().to_string())).collect()
+        };
+        let run = |on_conflict| {
+            let mut db = Database::open_in_memory();
+            setup(&mut db);
+            let reader = csv::Reader::new(input.as_bytes(), ',');
+            let summary = import_rows(&mut db, csv_rows(reader, false), false, on_conflict, &mut Tracker::new(None)).unwrap();
+            (summary, ids_and_names(&mut db))
+        };
+        let row = |id: u64, name: &str| (id, name.to_string());
+        let duplicate = ImportError { line: 3, message: "duplicate id 3".to_string() };
+
+        let (summary, rows) = run(OnConflict::Fail);
+        assert_eq!(summary, ImportSummary { inserted: 3, errors: vec![duplicate.clone()], ..ImportSummary::default() });
+        assert_eq!(rows, [row(3, "old"), row(9, "keep"), row(1, "a"), row(2, "b"), row(4, "d")]);
+
+        let (summary, rows) = run(OnConflict::Abort);
+        assert_eq!(summary, ImportSummary { errors: vec![duplicate], aborted: true, ..ImportSummary::default() });
+        assert_eq!(rows, [row(3, "old"), row(9, "keep")]);
+
+        let (summary, rows) = run(OnConflict::Skip);
+        assert_eq!(summary, ImportSummary { inserted: 3, skipped: 1, ..ImportSummary::default() });
+        assert_eq!(rows, [row(3, "old"), row(9, "keep"), row(1, "a"), row(2, "b"), row(4, "d")]);
+
+        let (summary, rows) = run(OnConflict::Replace);
+        assert_eq!(summary, ImportSummary { inserted: 3, replaced: 1, ..ImportSummary::default() });
+        assert_eq!(rows, [row(3, "c"), row(9, "keep"), row(1, "a"), row(2, "b"), row(4, "d")]);
+    }
+
+    #[test]
+    fn test_insert_rows_checks_each_row() {
+        let run = |on_conflict| {
+            let options = Options { username_size: 5, ..Options::default() };
+            let mut db = Database::open_in_memory_with(options).unwrap();
+            db.execute("insert 2 old old@x").unwrap();
+            let rows = [Row::new(1, "a", "a@x"), Row::new(2, "b", "b@x"), Row::new(3, "toolong", "c@x"), Row::new(1, "dup", "d@x"), Row::new(4, "e", "e@x")];
+            let summary = db.insert_rows(rows, on_conflict).unwrap();
+            let rows: Vec<(u64, String)> = select(&mut db).into_iter().map(|row| (row.id, row.username().to_string())).collect();
+            (summary, rows)
+        };
+        let row = |id: u64, name: &str| (id, name.to_string());
+        let error = |line, message: &str| ImportError { line, message: message.to_string() };
+        let too_long = error(3, "username is 7 bytes, at most 5 allowed");
+
+        let (summary, rows) = run(OnConflict::Fail);
+        assert_eq!(
+            summary,
+            ImportSummary { inserted: 2, errors: vec![error(2, "duplicate id 2"), too_long.clone(), error(4, "duplicate id 1")], ..ImportSummary::default() }
+        );
+        assert_eq!(rows, [row(2, "old"), row(1, "a"), row(4, "e")]);
+
+        let (summary, rows) = run(OnConflict::Abort);
+        assert_eq!(summary, ImportSummary { errors: vec![error(2, "duplicate id 2")], aborted: true, ..ImportSummary::default() });
+        assert_eq!(rows, [row(2, "old")]);
+
+        let (summary, rows) = run(OnConflict::Skip);
+        assert_eq!(summary, ImportSummary { inserted: 2, skipped: 2, errors: vec![too_long.clone()], ..ImportSummary::default() });
+        assert_eq!(rows, [row(2, "old"), row(1, "a"), row(4, "e")]);
+
+        let (summary, rows) = run(OnConflict::Replace);
+        assert_eq!(summary, ImportSummary { inserted: 2, replaced: 2, errors: vec![too_long], ..ImportSummary::default() });
+        assert_eq!(rows, [row(2, "b"), row(1, "dup"), row(4, "e")]);
+    }
+
+    #[test]
+    fn test_insert_rows_writes_what_single_inserts_write() {
+        // Odd cell sizes, so some rows straddle two pages, and every tenth
+        // row repeats an id from just before it.
+        let vfs = std::sync::Arc::new(crate::vfs::MemoryVfs::new());
+        let options = Options { username_size: 7, email_size: 45, row_checksums: true, vfs: vfs.clone(), ..Options::default() };
+        let rows: Vec<Row> = (0..8000).map(|n| Row::new(if n % 10 == 9 { n - 3 } else { n }, &format!("u{}", n), &format!("u{}@x", n))).collect();
+
+        let mut bulk = options.open("bulk.db").unwrap();
+        assert_eq!(bulk.insert_rows(rows.clone(), OnConflict::Skip).unwrap_err().code(), "TABLE_FULL");
+        let mut single = options.open("single.db").unwrap();
+        for row in &rows {
+            match single.insert_row(row) {
+                Ok(()) | Err(DbError::Execute(ExecuteError::DuplicateKey)) => {}
+                Err(err) => {
+                    assert_eq!(err.code(), "TABLE_FULL");
+                    break;
+                }
+            }
+        }
+
+        bulk.close().unwrap();
+        single.close().unwrap();
+        let bulk = vfs.contents(std::path::Path::new("bulk.db")).unwrap();
+        assert!(bulk == vfs.contents(std::path::Path::new("single.db")).unwrap());
+        assert!(bulk.len() > 90 * crate::pager::PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_aborted_import_can_be_retried() {
+        let mut db = Database::open_in_memory();
+        db.execute("insert 2 b b@x").unwrap();
+        let rows = [Row::new(1, "a", "a@x"), Row::new(2, "b", "b@x")];
+        assert!(db.insert_rows(rows.clone(), OnConflict::Abort).unwrap().aborted);
+
+        // The rolled back ids are free again.
+        let summary = db.insert_rows(rows, OnConflict::Skip).unwrap();
+        assert_eq!(summary, ImportSummary { inserted: 1, skipped: 1, ..ImportSummary::default() });
+        assert_eq!(select(&mut db).iter().map(|row| row.id).collect::<Vec<_>>(), [2, 1]);
+    }
+
+    #[test]
+    fn test_import_cancelled_by_progress_callback() {
+        let input: String = (0..1200).map(|id| format!("{},user{},user{}@x\n", id, id, id)).collect();
+        let mut reported = Vec::new();
+        let mut callback = |progress: crate::progress::Progress| {
+            reported.push(progress);
+            ControlFlow::Break(())
+        };
+        let (input, bytes) = crate::progress::Counted::new(input.as_bytes());
+        let mut tracker = Tracker::new(Some(&mut callback)).with_bytes(bytes);
+
+        let mut db = Database::open_in_memory();
+        let reader = csv::Reader::new(io::BufReader::new(input), ',');
+        let summary = import_rows(&mut db, csv_rows(reader, false), false, OnConflict::Fail, &mut tracker).unwrap();
+        drop(tracker);
+
+        // Cancelled at the first report, keeping exactly the rows it saw.
+        assert_eq!(reported.len(), 1);
+        assert!(summary.cancelled);
+        assert_eq!(summary.inserted, reported[0].rows);
+        assert!(reported[0].bytes > 0);
+        assert_eq!(select(&mut db).len(), reported[0].rows);
+    }
+
+    #[test]
+    fn test_import_stopped_by_interrupt() {
+        let mut db = Database::open_in_memory();
+        let interrupt = db.interrupt_handle();
+        let rows = (1..=10).map(|id| Row::new(id, "user", "user@x")).inspect(|row| {
+            if row.id == 4 {
+                interrupt.interrupt();
+            }
+        });
+
+        let summary = db.insert_rows(rows, OnConflict::Fail).unwrap();
+        assert!(summary.cancelled);
+        assert_eq!(summary.inserted, 4);
+        assert!(!interrupt.is_interrupted());
+        assert_eq!(select(&mut db).len(), 4);
+    }
+}
@@ -1,30 +1,271 @@
-use std::io::{self, Write};
+use std::io::{self, BufRead};
+use std::path::Path;
 
+#[cfg(feature = "readline")]
+use crate::editor::Editor;
+
+/// How many lines of history are kept unless `VOIDDB_HISTORY_SIZE` says.
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Reads the shell's input a line at a time: from a line editor at an
+/// interactive terminal, or as plain lines from stdin or any other reader.
+///
+/// Only the line editor shows a prompt; plain sources print nothing, so a
+/// caller reading from a terminal without one prints the prompt itself.
+///
+/// Each line is read into the same buffer, which keeps its capacity, so
+/// reading a long piped script allocates only for lines longer than any
+/// before them.
 pub struct InputBuffer {
     pub buffer: String,
+    source: Source,
+    /// Set once the source has run out.
+    eof: bool,
+    #[cfg(feature = "readline")]
+    editor: Option<Editor>,
+}
+
+/// Where plain lines come from.
+enum Source {
+    /// Stdin, locked only while a line is read so the pager and the line
+    /// editor can read it too.
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::from_stdin()
+    }
 }
 
 impl InputBuffer {
-    pub fn new() -> InputBuffer {
+    /// Reads plain lines from stdin.
+    pub fn from_stdin() -> InputBuffer {
+        Self::with_source(Source::Stdin)
+    }
+
+    /// Reads plain lines from `reader`: a script, a socket or, in tests, a
+    /// `Cursor` over a string.
+    pub fn from_reader(reader: impl BufRead + 'static) -> InputBuffer {
+        Self::with_source(Source::Reader(Box::new(reader)))
+    }
+
+    fn with_source(source: Source) -> InputBuffer {
         InputBuffer {
             buffer: String::new(),
+            source,
+            eof: false,
+            #[cfg(feature = "readline")]
+            editor: None,
         }
     }
 
-    pub fn read_input(&mut self) {
-        self.buffer.clear();
+    /// Reads with the line editor when the `readline` feature is on and
+    /// stdin is a terminal, and like `from_stdin` otherwise.
+    pub fn interactive() -> InputBuffer {
+        #[cfg(feature = "readline")]
+        {
+            use std::io::IsTerminal;
+
+            if io::stdin().is_terminal() {
+                let mut editor = Editor::new();
+                editor.set_completer(crate::complete::complete);
+                return InputBuffer { editor: Some(editor), ..Self::from_stdin() };
+            }
+        }
+        Self::from_stdin()
+    }
+
+    /// Whether `read_line` shows the prompt it is given, which only the
+    /// line editor does.
+    pub fn shows_prompt(&self) -> bool {
+        #[cfg(feature = "readline")]
+        if self.editor.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// Whether the input has run out: `read_line` has returned `None`.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// The line editor, if lines are read with one.
+    #[cfg(feature = "readline")]
+    pub fn editor(&mut self) -> Option<&mut Editor> {
+        self.editor.as_mut()
+    }
+
+    /// Loads the line editor's history from `path` and keeps saving it
+    /// there, up to `max` lines. Does nothing without a line editor.
+    pub fn load_history(&mut self, path: &Path, max: usize) -> io::Result<()> {
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            return editor.load_history(path, max);
+        }
+        let _ = (path, max);
+        Ok(())
+    }
+
+    /// Adds the line just read to the line editor's history. Does nothing
+    /// without a line editor.
+    pub fn record(&mut self) -> io::Result<()> {
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            return editor.add_history(&self.buffer);
+        }
+        Ok(())
+    }
 
-        print!("db > ");
-        io::stdout().flush().unwrap();
+    /// Reads the next line without a prompt.
+    pub fn read_input(&mut self) -> io::Result<Option<&str>> {
+        self.read_line("")
+    }
 
-        io::stdin()
-            .read_line(&mut self.buffer)
-            .expect("Error reading input");
+    /// Reads the next line into the buffer, the line editor showing
+    /// `prompt`. Returns the trimmed line, or `None` once input is exhausted
+    /// (end of a piped script, or Ctrl-D at a terminal), and from then on.
+    /// Fails with `ErrorKind::Interrupted` on Ctrl-C, dropping the line.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<&str>> {
+        if self.eof {
+            return Ok(None);
+        }
+        #[cfg(feature = "readline")]
+        if let Some(editor) = self.editor.as_mut() {
+            match editor.read_line(prompt) {
+                Ok(Some(line)) => {
+                    self.buffer.clear();
+                    self.buffer.push_str(line.trim());
+                    return Ok(Some(&self.buffer));
+                }
+                Ok(None) => {
+                    self.eof = true;
+                    return Ok(None);
+                }
+                // No raw mode on this terminal; read plain lines from now on,
+                // showing the prompt this once since the caller expected the
+                // editor to.
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    self.editor = None;
+                    print!("{}", prompt);
+                    io::Write::flush(&mut io::stdout())?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        #[cfg(not(feature = "readline"))]
+        let _ = prompt;
 
-        self.buffer = self.buffer.trim().to_string();
+        self.buffer.clear();
+        let read = match &mut self.source {
+            Source::Stdin => read_line_interruptibly(&mut io::stdin().lock(), &mut self.buffer)?,
+            Source::Reader(reader) => read_line_interruptibly(reader, &mut self.buffer)?,
+        };
+        if read == 0 {
+            self.eof = true;
+            return Ok(None);
+        }
+
+        trim_in_place(&mut self.buffer);
+        Ok(Some(&self.buffer))
     }
 
     pub fn close(&mut self) {
         self.buffer.clear();
     }
 }
+
+/// Takes the whitespace off both ends of `line` without reallocating it.
+fn trim_in_place(line: &mut String) {
+    line.truncate(line.trim_end().len());
+    let start = line.len() - line.trim_start().len();
+    line.drain(..start);
+}
+
+/// Like `BufRead::read_line`, but a signal arriving while it waits fails it
+/// with `ErrorKind::Interrupted` instead of waiting on. The part of the line
+/// read so far is dropped, as is all of `line` if it is not UTF-8.
+fn read_line_interruptibly(input: &mut (impl BufRead + ?Sized), line: &mut String) -> io::Result<usize> {
+    // Read straight into the line's own bytes, which are checked once the
+    // line is whole.
+    let mut bytes = std::mem::take(line).into_bytes();
+    let start = bytes.len();
+    loop {
+        let available = input.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                bytes.extend_from_slice(&available[..=end]);
+                input.consume(end + 1);
+                break;
+            }
+            None => {
+                let len = available.len();
+                bytes.extend_from_slice(available);
+                input.consume(len);
+            }
+        }
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => {
+            *line = text;
+            Ok(line.len() - start)
+        }
+        Err(err) => {
+            let invalid = err.utf8_error();
+            let mut bytes = err.into_bytes();
+            bytes.clear();
+            *line = String::from_utf8(bytes).unwrap_or_default();
+            Err(io::Error::new(io::ErrorKind::InvalidData, invalid))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_from_reader() {
+        let mut input = InputBuffer::from_reader(Cursor::new("  select  \r\n\n.exit"));
+        assert!(!input.shows_prompt());
+        assert_eq!(input.read_line("db > ").unwrap(), Some("select"));
+        assert_eq!(input.read_input().unwrap(), Some(""));
+        // The last line needs no newline.
+        assert_eq!(input.read_input().unwrap(), Some(".exit"));
+        assert!(!input.is_eof());
+        assert_eq!(input.read_input().unwrap(), None);
+        assert!(input.is_eof());
+        assert_eq!(input.read_input().unwrap(), None);
+    }
+
+    #[test]
+    fn test_lines_reuse_the_buffer() {
+        let script: String = (1..=100).map(|id| format!("  insert {} user{} user{}@example.com\t\r\n", id, id, id)).collect();
+        let mut input = InputBuffer::from_reader(Cursor::new(format!("{}select\n", script)));
+        assert_eq!(input.read_input().unwrap(), Some("insert 1 user1 user1@example.com"));
+        let (lines, allocations) = crate::alloc_counter::allocations_during(|| {
+            let mut lines = 1;
+            while input.read_input().unwrap().is_some_and(|line| line.starts_with("insert ")) {
+                lines += 1;
+            }
+            lines
+        });
+        assert_eq!(lines, 100);
+        // Only the lines that are longer than any before them.
+        assert!(allocations <= 2, "{} allocations", allocations);
+        assert_eq!(input.buffer, "select");
+    }
+
+    #[test]
+    fn test_read_invalid_utf8() {
+        let mut input = InputBuffer::from_reader(Cursor::new(b"ok\n\xff\n".to_vec()));
+        assert_eq!(input.read_input().unwrap(), Some("ok"));
+        assert_eq!(input.read_input().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,268 @@
+//! Cancelling a running statement from elsewhere: another thread, or the
+//! shell's Ctrl-C handler.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Asks the statement running on a database to stop. Scans check it before
+/// each row and fail with `DbError::Interrupted`; imports stop with
+/// `cancelled` set in their summary. Each statement and import run through
+/// `&mut Database` starts by clearing it, so a request made while nothing
+/// runs stops nothing. A `select` through `&Database` leaves it alone, as
+/// it may be meant for another statement still running.
+///
+/// Writes are never left half done. A single insert does not check, and
+/// `insert .. select` can only be stopped while it reads, before it has
+/// written anything. An import keeps the rows it inserted before it
+/// stopped.
+///
+/// ```
+/// use std::ops::ControlFlow;
+/// use VoidDB::{Database, DbError};
+///
+/// let mut db = Database::open_in_memory();
+/// db.execute("insert 1 alice alice@example.com").unwrap();
+/// db.execute("insert 2 bob bob@example.com").unwrap();
+///
+/// let interrupt = db.interrupt_handle();
+/// let mut seen = 0;
+/// let result = db.execute_with::<()>("select", |_| {
+///     seen += 1;
+///     interrupt.interrupt();
+///     ControlFlow::Continue(())
+/// });
+/// assert!(matches!(result, Err(DbError::Interrupted)));
+/// assert_eq!(seen, 1);
+/// assert!(db.execute("select").is_ok());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    requested: AtomicBool,
+    /// The time limits set with `after` and not yet dropped, as `ticks`.
+    deadlines: Mutex<Vec<u64>>,
+    /// The earliest of `deadlines`, or 0 for none, so that a check only
+    /// has to read this.
+    earliest: AtomicU64,
+}
+
+impl Interrupt {
+    pub fn interrupt(&self) {
+        self.0.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Withdraws a request that no statement has acted on. A time limit
+    /// that has run out still stops statements until it is dropped.
+    pub fn clear(&self) {
+        self.0.requested.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.0.requested.load(Ordering::SeqCst) || self.past_deadline()
+    }
+
+    /// Whether a request is pending, clearing it.
+    pub(crate) fn take(&self) -> bool {
+        self.0.requested.swap(false, Ordering::SeqCst) || self.past_deadline()
+    }
+
+    /// Whether a time limit set with `after` has run out. Only reads the
+    /// clock while there is one.
+    fn past_deadline(&self) -> bool {
+        match self.0.earliest.load(Ordering::SeqCst) {
+            0 => false,
+            earliest => ticks(Instant::now()) >= earliest,
+        }
+    }
+
+    /// Adds `deadline` to the time limits, or takes it out again.
+    fn set_deadline(&self, deadline: u64, on: bool) {
+        let mut deadlines = self.0.deadlines.lock().unwrap_or_else(PoisonError::into_inner);
+        match on {
+            true => deadlines.push(deadline),
+            false => {
+                if let Some(i) = deadlines.iter().position(|&other| other == deadline) {
+                    deadlines.swap_remove(i);
+                }
+            }
+        }
+        self.0.earliest.store(deadlines.iter().copied().min().unwrap_or(0), Ordering::SeqCst);
+    }
+
+    /// Interrupts this once `timeout` has passed, unless the returned
+    /// `Deadline` is dropped first, to put a time limit on a statement
+    /// started right after. There is no timer: scans see the time is up
+    /// when they next check, before a row.
+    ///
+    /// Once the time is up every check is interrupted until the deadline is
+    /// dropped, so a statement that had not yet started, and clears the
+    /// interrupt when it does, is stopped all the same. With several time
+    /// limits set, the first to run out counts.
+    pub fn after(&self, timeout: Duration) -> Deadline {
+        let at = Instant::now() + timeout;
+        self.set_deadline(ticks(at), true);
+        Deadline { interrupt: self.clone(), at }
+    }
+}
+
+/// When the first time limit was set, which the others are counted from.
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// `at` as stored in `State::deadlines`: never 0, which means no limit.
+fn ticks(at: Instant) -> u64 {
+    at.saturating_duration_since(epoch()).as_nanos() as u64 + 1
+}
+
+/// A time limit set with `Interrupt::after`. Dropping it lifts the limit.
+#[derive(Debug)]
+pub struct Deadline {
+    interrupt: Interrupt,
+    at: Instant,
+}
+
+impl Deadline {
+    /// Whether the time ran out, so an `Interrupted` error is this
+    /// deadline's doing.
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        self.interrupt.set_deadline(ticks(self.at), false);
+    }
+}
+
+/// Ctrl-C presses since `take_sigints` was last called.
+static SIGINTS: AtomicUsize = AtomicUsize::new(0);
+static SIGINT_TARGET: OnceLock<Interrupt> = OnceLock::new();
+
+/// Catches SIGINT from now on: each one interrupts `target` and is counted
+/// for `take_sigints` instead of killing the process. A read from a terminal
+/// or pipe that is waiting when it arrives fails with
+/// `ErrorKind::Interrupted` rather than carrying on. Only the first call
+/// has any effect.
+#[cfg(unix)]
+pub fn catch_sigint(target: Interrupt) {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn siginterrupt(signum: i32, flag: i32) -> i32;
+    }
+    extern "C" fn on_sigint(_: i32) {
+        // Only atomics here: nothing else is safe in a signal handler.
+        SIGINTS.fetch_add(1, Ordering::SeqCst);
+        if let Some(target) = SIGINT_TARGET.get() {
+            target.interrupt();
+        }
+    }
+
+    if SIGINT_TARGET.set(target).is_ok() {
+        // SAFETY: the handler only touches atomics and an initialized
+        // OnceLock, which are async-signal-safe.
+        unsafe {
+            signal(SIGINT, on_sigint);
+            siginterrupt(SIGINT, 1);
+        }
+    }
+}
+
+/// Without Unix signals, Ctrl-C keeps its default behaviour.
+#[cfg(not(unix))]
+pub fn catch_sigint(target: Interrupt) {
+    let _ = target;
+}
+
+static SIGTERM_TARGET: OnceLock<Interrupt> = OnceLock::new();
+
+/// Catches SIGTERM from now on: each one interrupts `target` instead of
+/// killing the process, so a server can shut down cleanly. Only the first
+/// call has any effect.
+#[cfg(unix)]
+pub fn catch_sigterm(target: Interrupt) {
+    const SIGTERM: i32 = 15;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_sigterm(_: i32) {
+        if let Some(target) = SIGTERM_TARGET.get() {
+            target.interrupt();
+        }
+    }
+
+    if SIGTERM_TARGET.set(target).is_ok() {
+        // SAFETY: as for `catch_sigint`.
+        unsafe {
+            signal(SIGTERM, on_sigterm);
+        }
+    }
+}
+
+/// Without Unix signals there is no SIGTERM to catch.
+#[cfg(not(unix))]
+pub fn catch_sigterm(target: Interrupt) {
+    let _ = target;
+}
+
+/// How many times Ctrl-C was pressed since the last call.
+pub fn take_sigints() -> usize {
+    SIGINTS.swap(0, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_deadlines() {
+        let interrupt = Interrupt::default();
+        let deadline = interrupt.after(Duration::from_secs(60));
+        drop(deadline);
+        assert!(!interrupt.is_interrupted());
+
+        let start = Instant::now();
+        let deadline = interrupt.after(Duration::from_millis(10));
+        while !interrupt.is_interrupted() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::yield_now();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        // Cleared, as by a statement starting, it is interrupted again.
+        interrupt.clear();
+        while !interrupt.is_interrupted() {
+            std::thread::yield_now();
+        }
+        assert!(deadline.expired());
+        drop(deadline);
+        assert!(!interrupt.is_interrupted());
+        assert!(!interrupt.take());
+
+        // A deadline does not withdraw a request made by anyone else.
+        let deadline = interrupt.after(Duration::ZERO);
+        assert!(interrupt.take());
+        interrupt.interrupt();
+        drop(deadline);
+        assert!(interrupt.take());
+        assert!(!interrupt.take());
+
+        // Nor one set by someone else, however they overlap.
+        let expired = interrupt.after(Duration::ZERO);
+        let pending = interrupt.after(Duration::from_secs(60));
+        assert!(interrupt.is_interrupted());
+        drop(pending);
+        assert!(interrupt.is_interrupted());
+        let pending = interrupt.after(Duration::from_secs(60));
+        drop(expired);
+        assert!(!interrupt.is_interrupted());
+        drop(pending);
+    }
+}
@@ -0,0 +1,321 @@
+//! Just enough JSON for the REPL: writing values for the output modes and
+//! exports, and parsing lines for `.import jsonl`.
+
+use std::io::{self, Write};
+
+use crate::row::Value;
+
+/// Writes `s` as a JSON string literal, escaping quotes, backslashes and
+/// control characters.
+pub fn write_string<W: Write + ?Sized>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escape: Option<&[u8]> = match c {
+            '"' => Some(b"\\\""),
+            '\\' => Some(b"\\\\"),
+            '\n' => Some(b"\\n"),
+            '\r' => Some(b"\\r"),
+            '\t' => Some(b"\\t"),
+            '\x08' => Some(b"\\b"),
+            '\x0c' => Some(b"\\f"),
+            c if c < ' ' => None,
+            _ => continue,
+        };
+        out.write_all(&s.as_bytes()[start..i])?;
+        match escape {
+            Some(escape) => out.write_all(escape)?,
+            None => write!(out, "\\u{:04x}", c as u32)?,
+        }
+        start = i + c.len_utf8();
+    }
+    out.write_all(&s.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
+
+pub fn write_value<W: Write + ?Sized>(out: &mut W, value: Value<'_>) -> io::Result<()> {
+    match value {
+        Value::Null => out.write_all(b"null"),
+        Value::Integer(n) => write!(out, "{}", n),
+        Value::Text(s) => write_string(out, s),
+    }
+}
+
+/// Writes `{"name":value,...}` with the fields in the order given.
+pub fn write_object<'a, W, I>(out: &mut W, fields: I) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    I: IntoIterator<Item = (&'a str, Value<'a>)>,
+{
+    out.write_all(b"{")?;
+    for (i, (name, value)) in fields.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write_string(out, name)?;
+        out.write_all(b":")?;
+        write_value(out, value)?;
+    }
+    out.write_all(b"}")
+}
+
+/// A parsed JSON value. Numbers keep their text so that integers beyond
+/// what an `f64` holds exactly survive intact.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Parses `text`, which must hold exactly one JSON value.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("end of input"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            _ if self.eat("null") => Ok(Json::Null),
+            _ if self.eat("true") => Ok(Json::Bool(true)),
+            _ if self.eat("false") => Ok(Json::Bool(false)),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("a field name"));
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(":") {
+                return Err(self.error("':'"));
+            }
+            fields.push((name, self.value()?));
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Json::Object(fields));
+            }
+            if !self.eat(",") {
+                return Err(self.error("',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error("',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("closing '\"'"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.error("an escape"))?;
+                    self.pos += escape.len_utf8();
+                    out.push(match escape {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\x08',
+                        'f' => '\x0c',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("a valid escape")),
+                    });
+                }
+                c if c < ' ' => return Err(self.error("no control characters in a string")),
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// The character of a `\uXXXX` escape, whose `\u` has been read,
+    /// including a following low surrogate if this is a high one.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..=0xdbff => {
+                if !self.eat("\\u") {
+                    return Err(self.error("a low surrogate"));
+                }
+                let low = self.hex4()?;
+                if !(0xdc00..=0xdfff).contains(&low) {
+                    return Err(self.error("a low surrogate"));
+                }
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("a valid code point"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("four hex digits"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("four hex digits"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        self.eat("-");
+        if !self.digits() {
+            return Err(self.error("a digit"));
+        }
+        if self.eat(".") && !self.digits() {
+            return Err(self.error("a digit"));
+        }
+        if self.eat("e") || self.eat("E") {
+            let _ = self.eat("+") || self.eat("-");
+            if !self.digits() {
+                return Err(self.error("a digit"));
+            }
+        }
+        Ok(Json::Number(self.text[start..self.pos].to_string()))
+    }
+
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.text[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn error(&self, expected: &str) -> String {
+        format!("expected {} at byte {}", expected, self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> String {
+        let mut out = Vec::new();
+        write_string(&mut out, s).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(string("alice"), r#""alice""#);
+        assert_eq!(string(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+        assert_eq!(string("a\nb\tc\r\x08\x0c"), r#""a\nb\tc\r\b\f""#);
+        assert_eq!(string("\0\x1f"), r#""\u0000\u001f""#);
+        assert_eq!(string("héllo 日本 🦀"), "\"héllo 日本 🦀\"");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(r#" {"id": 18446744073709551615, "name": "a\"b\u00e9\ud83e\udd80", "tags": [true, null, -1.5e3], "o": {}} "#),
+            Ok(Json::Object(vec![
+                ("id".to_string(), Json::Number("18446744073709551615".to_string())),
+                ("name".to_string(), Json::String("a\"bé🦀".to_string())),
+                (
+                    "tags".to_string(),
+                    Json::Array(vec![Json::Bool(true), Json::Null, Json::Number("-1.5e3".to_string())])
+                ),
+                ("o".to_string(), Json::Object(vec![])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(r#"{"id": 1"#), Err("expected ',' or '}' at byte 8".to_string()));
+        assert_eq!(parse(r#"{"id": 1} x"#), Err("expected end of input at byte 10".to_string()));
+        assert_eq!(parse(r#""\x""#), Err("expected a valid escape at byte 3".to_string()));
+        assert_eq!(parse("\"a\nb\""), Err("expected no control characters in a string at byte 3".to_string()));
+        assert_eq!(parse("-"), Err("expected a digit at byte 1".to_string()));
+        assert_eq!(parse(""), Err("expected a value at byte 0".to_string()));
+    }
+
+    #[test]
+    fn test_parse_round_trips_written_strings() {
+        let mut gen = crate::testing::Gen::new(139);
+        for _ in 0..500 {
+            let text = gen.text(64);
+            let mut out = Vec::new();
+            write_string(&mut out, &text).unwrap();
+            assert_eq!(parse(std::str::from_utf8(&out).unwrap()), Ok(Json::String(text)));
+        }
+    }
+
+    #[test]
+    fn test_object() {
+        let mut out = Vec::new();
+        write_object(&mut out, [("id", Value::Integer(1)), ("name", Value::Text("a\"b"))]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#"{"id":1,"name":"a\"b"}"#);
+    }
+}
@@ -1,2 +1,80 @@
-pub mod input;        
+#![allow(non_snake_case)]
+
+#[cfg(feature = "cli")]
+pub mod input;
+#[cfg(feature = "async")]
+pub mod async_database;
+#[cfg(feature = "cli")]
+pub mod auth;
+#[cfg(feature = "cli")]
+pub mod client;
+#[cfg(feature = "cli")]
+pub mod codec;
+pub mod changes;
 pub mod compiler;
+#[cfg(feature = "cli")]
+pub mod complete;
+pub mod connection;
+pub mod csv;
+pub mod cursor;
+pub mod database;
+#[cfg(feature = "cli")]
+pub mod display;
+#[cfg(feature = "readline")]
+pub mod editor;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod import;
+pub mod interrupt;
+pub mod json;
+#[cfg(feature = "cli")]
+pub mod output;
+#[cfg(feature = "cli")]
+pub mod net;
+pub mod pager;
+pub mod progress;
+#[cfg(feature = "cli")]
+pub mod protocol;
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "cli")]
+pub mod replication;
+pub mod row;
+#[cfg(feature = "cli")]
+pub mod seed;
+#[cfg(feature = "cli")]
+pub mod server;
+pub mod snapshot;
+#[cfg(feature = "cli")]
+pub mod style;
+pub mod trace;
+pub mod typed;
+pub mod vfs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+mod checksum;
+#[cfg(any(feature = "cli", test, feature = "test-utils"))]
+mod rng;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+#[cfg(test)]
+mod alloc_counter;
+
+#[cfg(feature = "async")]
+pub use async_database::{AsyncDatabase, RowStream};
+pub use connection::Connection;
+pub use database::{Database, Metrics, Options, Stats};
+pub use pager::SyncMode;
+pub use compiler::QueryResult;
+pub use cursor::Rows;
+pub use snapshot::Snapshot;
+pub use error::{DbError, ErrorCode};
+pub use trace::StatementTrace;
+pub use import::{ImportSummary, OnConflict};
@@ -1,33 +1,567 @@
-use VoidDB::input::InputBuffer;
-use VoidDB::compiler::*;
+#![allow(non_snake_case)]
+
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use VoidDB::input::{InputBuffer, DEFAULT_HISTORY_SIZE};
+use VoidDB::interrupt;
+use VoidDB::auth::{Token, TOKEN_VAR};
+use VoidDB::client::Client;
+use VoidDB::net::Address;
+use VoidDB::output::{Console, Output};
+use VoidDB::protocol::DEFAULT_ADDRESS;
+use VoidDB::repl::Repl;
+use VoidDB::replication::{Follower, Leader};
+use VoidDB::server::{parse_duration, Server, DEFAULT_GRACE_PERIOD, DEFAULT_MAX_CONNECTIONS};
+use VoidDB::{Connection, Database};
+
+const USAGE: &str = concat!(
+    "Usage: VoidDB [--interactive | --batch] [--continue-on-error] [--init FILE] [--no-rc] ",
+    "[--history-file PATH] [--prompt TEXT] [--no-shell] [--connect ADDRESS [--binary] [--token-file PATH]] [-c SQL]... [FILE [SQL]]\n",
+    "       VoidDB serve FILE [--listen ADDRESS [--max-connections N] | --http ADDRESS] [--auth-token-file PATH] [--statement-timeout DURATION] [--grace-period SECONDS] [--replicate-to ADDRESS]\n",
+    "       VoidDB follow FILE [--listen ADDRESS [--max-connections N]] [--auth-token-file PATH] [--statement-timeout DURATION] [--grace-period SECONDS]"
+);
+
+// Exit statuses.
+/// A statement or meta command failed.
+const EXIT_ERROR: i32 = 1;
+/// The command line could not be understood.
+const EXIT_USAGE: i32 = 2;
+/// The database file, the input or the output could not be read or written.
+const EXIT_IO_ERROR: i32 = 3;
+/// Stopped by Ctrl-C, as a shell reports a process killed by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
 
 fn main() {
-    let mut input_buffer = InputBuffer::new();
+    let mut output = Console::new();
+
+    // Prompts only make sense when someone is typing; piped input gets
+    // none unless `--interactive` asks for them.
+    let mut interactive = None;
+    let mut continue_on_error = false;
+    let mut history_file = None;
+    let mut init = None;
+    let mut no_rc = false;
+    let mut no_shell = false;
+    let mut prompt = None;
+    let mut path = None;
+    // A server to run statements on instead of a file, whether to speak
+    // the binary protocol to it, and where to find the token it asks for.
+    let mut connect = None;
+    let mut binary = false;
+    let mut token_file = None;
+    // Statements from `-c` or after FILE, run instead of reading stdin.
+    let mut commands = Vec::new();
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if_eq("serve").is_some() {
+        serve(args, false, &mut output);
+    }
+    if args.next_if_eq("follow").is_some() {
+        serve(args, true, &mut output);
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interactive" => interactive = Some(true),
+            "--batch" => interactive = Some(false),
+            "--continue-on-error" => continue_on_error = true,
+            "--history-file" => match args.next() {
+                Some(file) => history_file = Some(PathBuf::from(file)),
+                None => usage(&mut output),
+            },
+            "--init" => match args.next() {
+                Some(file) => init = Some(file),
+                None => usage(&mut output),
+            },
+            "--connect" => match args.next() {
+                Some(address) => connect = Some(address),
+                None => usage(&mut output),
+            },
+            "--binary" => binary = true,
+            "--token-file" => match args.next() {
+                Some(file) => token_file = Some(file),
+                None => usage(&mut output),
+            },
+            "--no-rc" => no_rc = true,
+            "--no-shell" => no_shell = true,
+            "--prompt" => match args.next() {
+                Some(text) => prompt = Some(text),
+                None => usage(&mut output),
+            },
+            "-c" => match args.next() {
+                Some(sql) => commands.push(sql),
+                None => usage(&mut output),
+            },
+            _ if arg.starts_with('-') => usage(&mut output),
+            _ if path.is_none() => path = Some(arg),
+            _ if commands.is_empty() => commands.push(arg),
+            _ => usage(&mut output),
+        }
+    }
+    let interactive = interactive.unwrap_or_else(|| io::stdin().is_terminal());
+    // A script stops at its first error unless asked to carry on; someone
+    // at the prompt just tries again.
+    let stop_on_error = !interactive && !continue_on_error;
+
+    if (token_file.is_some() || binary) && connect.is_none() {
+        usage(&mut output);
+    }
+
+    let mut repl = match (path, connect) {
+        (Some(_), Some(_)) => usage(&mut output),
+        (None, Some(address)) => match connect_to(&address, binary, client_token(token_file, &mut output).as_ref()) {
+            Ok(client) => Repl::connected(client),
+            Err(err) => {
+                let _ = output.error(&format!("Unable to connect to {}: {}", address, err));
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        },
+        (Some(path), None) => match Database::open(&path) {
+            Ok(db) => Repl::new(db),
+            Err(err) => {
+                let _ = output.error(&format!("Unable to open '{}': {}", path, err));
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        },
+        (None, None) => Repl::new(Database::open_in_memory()),
+    };
+    repl.settings_mut().bail = stop_on_error;
+    // Before the startup scripts, so they cannot run commands either.
+    if no_shell {
+        repl.disable_shell();
+    }
+
+    // Startup scripts: `~/.voiddbrc` unless `--no-rc`, then `--init`. An
+    // error in one stops a non-interactive run before it starts, leaving
+    // the database file as it was.
+    let rc = default_rc_file().filter(|rc| !no_rc && rc.exists());
+    let scripts = rc.map(|rc| rc.to_string_lossy().into_owned()).into_iter().chain(init);
+    for script in scripts {
+        if let Err(err) = repl.run_file(&script, &mut output) {
+            eprintln!("Error writing output: {}", err);
+        }
+    }
+    if repl.exit_code().is_some() {
+        exit(&mut repl, &mut output);
+    }
+    // The command line wins over a `.prompt` in the startup scripts.
+    if let Some(prompt) = prompt {
+        repl.settings_mut().prompt = prompt;
+    }
+    if repl.errors() > 0 && (!interactive || !commands.is_empty()) {
+        std::process::exit(error_status(&repl));
+    }
+
+    if !commands.is_empty() {
+        interrupt::catch_sigint(repl.database().interrupt_handle());
+        run_commands(&mut repl, &commands, &mut output);
+    }
+
+    let mut input_buffer = if interactive { InputBuffer::interactive() } else { InputBuffer::from_stdin() };
+    if let Some(history_file) = history_file.or_else(default_history_file) {
+        let size = std::env::var("VOIDDB_HISTORY_SIZE").ok().and_then(|size| size.parse().ok());
+        if let Err(err) = input_buffer.load_history(&history_file, size.unwrap_or(DEFAULT_HISTORY_SIZE)) {
+            let message = format!("Warning: cannot read history file '{}': {}", history_file.display(), err);
+            let _ = output.error(&message);
+        }
+    }
+    let mut record_history = true;
 
-    let mut table = Table::new();
+    // Ctrl-C stops the running statement instead of killing the shell with
+    // pages unwritten; see `interrupted` for what a second one does.
+    let interrupt = repl.database().interrupt_handle();
+    interrupt::catch_sigint(interrupt.clone());
 
+    let mut number = 0;
     loop {
-        input_buffer.read_input();
-
-        if input_buffer.buffer.starts_with('.') {
-            match do_meta_command(&mut input_buffer) {
-                MetaCommandResult::Success => continue,
-                MetaCommandResult::UnrecognizedCommand => {
-                    println!("Unrecognized command '{}'", input_buffer.buffer);
-                    continue;
+        let prompt = if interactive { repl.prompt(&output) } else { String::new() };
+        // The line editor draws its own prompt; plain stdin prints nothing.
+        if !input_buffer.shows_prompt() && !prompt.is_empty() {
+            let _ = write!(output.results(), "{}", prompt).and_then(|()| output.results().flush());
+        }
+        let line = match input_buffer.read_line(&prompt) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            // Ctrl-C at a prompt: the line is thrown away, and with it any
+            // statement begun on the lines before, so start a fresh one.
+            Err(err) if err.kind() == io::ErrorKind::Interrupted && interactive => {
+                interrupt::take_sigints();
+                repl.cancel_statement();
+                // The line editor has already moved to a new line.
+                if !input_buffer.shows_prompt() {
+                    let _ = output.message("");
                 }
+                continue;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => interrupted(&mut repl, &mut output),
+            Err(err) => {
+                let _ = output.error(&format!("Error reading input: {}", err));
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        };
+        number += 1;
+
+        interrupt.clear();
+        interrupt::take_sigints();
+        // Piped lines are numbered in errors, as lines of a script are.
+        let result = match interactive {
+            true => repl.run_line(line, &mut output),
+            false => repl.run_numbered_line("<stdin>", number, line, &mut output),
+        };
+        match result {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                eprintln!("Error writing output: {}", err);
+                if stop_on_error {
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            }
+        }
+        if stop_on_error && repl.errors() > 0 {
+            // What ran before the error stays; nothing after it runs.
+            if let Err(err) = repl.finish() {
+                let _ = output.error(&err.to_string());
             }
+            std::process::exit(error_status(&repl));
         }
+        // One Ctrl-C at the terminal has stopped the statement, which said
+        // so; a second, or any in batch mode, ends the shell.
+        match interrupt::take_sigints() {
+            0 => {}
+            1 if interactive => {}
+            _ => interrupted(&mut repl, &mut output),
+        }
+        if record_history && repl.settings().history {
+            if let Err(err) = input_buffer.record() {
+                let _ = output.error(&format!("Warning: cannot save history: {}", err));
+                record_history = false;
+            }
+        }
+        if repl.exit_code().is_some() {
+            break;
+        }
+    }
+
+    // A statement the last line left open fails as it stands, and in batch
+    // mode fails the run.
+    let errors = repl.errors();
+    if let Err(err) = repl.end_of_input(&mut output) {
+        eprintln!("Error writing output: {}", err);
+    }
+    if stop_on_error && repl.errors() > errors {
+        if let Err(err) = repl.finish() {
+            let _ = output.error(&err.to_string());
+        }
+        std::process::exit(error_status(&repl));
+    }
+
+    // End of input: leave the prompt's line and exit as `.exit` would.
+    if interactive && output.is_terminal() && repl.exit_code().is_none() {
+        let _ = output.message("");
+    }
+    exit(&mut repl, &mut output);
+}
+
+/// `VoidDB serve`: serves the database at FILE over TCP, or on a Unix
+/// socket given as `unix:PATH`, until Ctrl-C, then writes it out and exits.
+/// With `--http`, serves JSON over HTTP instead of the line protocol. With
+/// `--replicate-to`, also keeps a follower up to date.
+///
+/// `VoidDB follow` (`follow` set) serves a read-only copy at FILE instead,
+/// which a leader keeps up to date.
+fn serve(mut args: impl Iterator<Item = String>, follow: bool, output: &mut Console) -> ! {
+    let mut path = None;
+    let mut listen = None;
+    let mut max_connections = None;
+    let mut http = None;
+    let mut token = None;
+    let mut statement_timeout = None;
+    let mut grace_period = DEFAULT_GRACE_PERIOD;
+    let mut replicate_to = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => match args.next() {
+                Some(address) => listen = Some(address),
+                None => usage(output),
+            },
+            "--max-connections" => match args.next().and_then(|max| max.parse::<usize>().ok()) {
+                Some(max) if max > 0 => max_connections = Some(max),
+                _ => usage(output),
+            },
+            "--http" => match args.next() {
+                Some(address) => http = Some(address),
+                None => usage(output),
+            },
+            "--auth-token-file" => match args.next() {
+                Some(file) => token = Some(read_token(&file, output)),
+                None => usage(output),
+            },
+            "--statement-timeout" => match args.next().as_deref().and_then(parse_duration) {
+                Some(timeout) => statement_timeout = Some(timeout),
+                None => usage(output),
+            },
+            "--grace-period" => match args.next().and_then(|seconds| seconds.parse::<u64>().ok()) {
+                Some(seconds) => grace_period = Duration::from_secs(seconds),
+                None => usage(output),
+            },
+            "--replicate-to" => match args.next() {
+                Some(address) => replicate_to = Some(address),
+                None => usage(output),
+            },
+            _ if arg.starts_with('-') => usage(output),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(output),
+        }
+    }
+    let Some(path) = path else { usage(output) };
+    if (listen.is_some() || max_connections.is_some()) && http.is_some() {
+        usage(output);
+    }
+    if follow && (http.is_some() || replicate_to.is_some()) {
+        usage(output);
+    }
+    #[cfg(not(feature = "http"))]
+    if http.is_some() {
+        let _ = output.error("--http needs VoidDB built with the http feature.");
+        std::process::exit(EXIT_USAGE);
+    }
 
-        match prepare_statement(&input_buffer) {
-            Ok(statement) => {
-                execute_statement(&statement, &mut table);
-                println!("Executed.");
+    let authenticated = token.is_some();
+
+    let mut follower = None;
+    let mut leader = None;
+    let opened = if follow {
+        Follower::open(&path).map(|opened| {
+            let opened = Arc::new(opened.log(io::stderr()));
+            follower = Some(opened.clone());
+            opened.connection()
+        })
+    } else if let Some(address) = replicate_to {
+        Leader::open(&path, Address::parse(&address)).map(|opened| {
+            let opened = match token.clone() {
+                Some(token) => opened.auth_token(token),
+                None => opened,
+            };
+            let opened = opened.log(io::stderr());
+            let connection = opened.connection();
+            leader = Some(opened);
+            connection
+        })
+    } else {
+        Connection::open(&path)
+    };
+    let connection = match opened {
+        Ok(connection) => connection,
+        Err(err) => {
+            let _ = output.error(&format!("Unable to open '{}': {}", path, err));
+            std::process::exit(EXIT_IO_ERROR);
+        }
+    };
+    #[cfg(feature = "http")]
+    if let Some(address) = http {
+        let server = match VoidDB::http::HttpServer::bind(&Address::parse(&address), connection.clone()) {
+            Ok(server) => {
+                let server = server.grace_period(grace_period);
+                let server = match statement_timeout {
+                    Some(timeout) => server.statement_timeout(timeout),
+                    None => server,
+                };
+                match token {
+                    Some(token) => server.auth_token(token).log(io::stderr()),
+                    None => server,
+                }
             }
-            Err(PrepareResult::UnrecognizedStatement) => {
-                println!("Unrecognized keyword at start of '{}'.", input_buffer.buffer);
+            Err(err) => {
+                let _ = output.error(&format!("Unable to listen on {}: {}", address, err));
+                std::process::exit(EXIT_IO_ERROR);
             }
-            _ => {}
+        };
+        interrupt::catch_sigint(server.stop_handle());
+        interrupt::catch_sigterm(server.stop_handle());
+        listening(server.local_addr(), authenticated, output);
+        let result = replicating(leader, &server.stop_handle(), || server.run());
+        drop(server);
+        shut_down(result, connection, output);
+    }
+    let address = listen.unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let server = match Server::bind(&Address::parse(&address), connection.clone()) {
+        Ok(server) => {
+            let server = server.max_connections(max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS)).grace_period(grace_period);
+            let server = match follower {
+                Some(follower) => server.follower(follower),
+                None => server,
+            };
+            let server = match statement_timeout {
+                Some(timeout) => server.statement_timeout(timeout),
+                None => server,
+            };
+            match token {
+                Some(token) => server.auth_token(token).log(io::stderr()),
+                None => server,
+            }
+        }
+        Err(err) => {
+            let _ = output.error(&format!("Unable to listen on {}: {}", address, err));
+            std::process::exit(EXIT_IO_ERROR);
+        }
+    };
+    // Caught before the address is printed, so that a signal sent as soon
+    // as it is seen shuts the server down cleanly.
+    interrupt::catch_sigint(server.stop_handle());
+    interrupt::catch_sigterm(server.stop_handle());
+    listening(server.local_addr(), authenticated, output);
+    let result = replicating(leader, &server.stop_handle(), || server.run());
+    drop(server);
+    shut_down(result, connection, output);
+}
+
+/// Runs `serve`, with `leader`, if there is one, keeping its follower up to
+/// date on a thread of its own until `stop` is interrupted.
+fn replicating(leader: Option<Leader>, stop: &interrupt::Interrupt, serve: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    let Some(leader) = leader else { return serve() };
+    std::thread::scope(|scope| {
+        scope.spawn(|| leader.run(stop));
+        let result = serve();
+        // A server that failed leaves `stop` alone.
+        stop.interrupt();
+        result
+    })
+}
+
+/// Says where a server listens: the actual address, which says which port
+/// `:0` got. Warns about a server that asks for no token and that other
+/// machines can reach.
+fn listening(address: io::Result<Address>, authenticated: bool, output: &mut Console) {
+    if let Ok(address) = address {
+        if !authenticated && !address.is_local() {
+            let _ = output.error(&format!(
+                "WARNING: {} can be reached from other machines, and without --auth-token-file anyone who connects can read and change the database.",
+                address
+            ));
+        }
+        let _ = output.message(&format!("Listening on {}", address)).and_then(|()| output.results().flush());
+    }
+}
+
+/// The token in the file at `path`, for `serve`; exits if it cannot be
+/// read.
+fn read_token(path: &str, output: &mut Console) -> Token {
+    match Token::read(path) {
+        Ok(token) => token,
+        Err(err) => {
+            let _ = output.error(&format!("Unable to read token file '{}': {}", path, err));
+            std::process::exit(EXIT_IO_ERROR);
+        }
+    }
+}
+
+/// Connects `--connect` to its server, over the binary protocol if asked.
+fn connect_to(address: &str, binary: bool, token: Option<&Token>) -> io::Result<Client> {
+    if binary {
+        Client::connect_binary(address, token)
+    } else {
+        Client::connect(address, token)
+    }
+}
+
+/// The token `--connect` presents: from `--token-file`, or else from
+/// `VOIDDB_TOKEN`, if either is set.
+fn client_token(token_file: Option<String>, output: &mut Console) -> Option<Token> {
+    match token_file {
+        Some(path) => Some(read_token(&path, output)),
+        None => std::env::var(TOKEN_VAR).ok().and_then(|token| Token::new(&token)),
+    }
+}
+
+/// Exits once a server has stopped, after writing out the database.
+fn shut_down(result: io::Result<()>, connection: Connection, output: &mut Console) -> ! {
+    if let Err(err) = result {
+        let _ = output.error(&format!("Error accepting connections: {}", err));
+        let _ = connection.close();
+        std::process::exit(EXIT_IO_ERROR);
+    }
+    match connection.close() {
+        Ok(()) => std::process::exit(0),
+        Err(err) => {
+            let _ = output.error(&err.to_string());
+            std::process::exit(EXIT_IO_ERROR);
         }
     }
 }
+
+fn usage(output: &mut Console) -> ! {
+    let _ = output.error(USAGE);
+    std::process::exit(EXIT_USAGE);
+}
+
+/// The exit status for the last error the shell reported.
+fn error_status(repl: &Repl) -> i32 {
+    match repl.last_error() {
+        Some("IO_ERROR") => EXIT_IO_ERROR,
+        _ => EXIT_ERROR,
+    }
+}
+
+/// Runs statements given on the command line, without reading stdin: each
+/// line of each command is a meta command, or statements separated by `;`.
+/// Exits 0 if they all succeed, and with the status for the last error
+/// otherwise.
+fn run_commands(repl: &mut Repl, commands: &[String], output: &mut Console) -> ! {
+    // The pager would wait for an answer on stdin.
+    repl.settings_mut().pager = false;
+    for line in commands.iter().flat_map(|command| command.lines()) {
+        if let Err(err) = repl.run_line(line.trim(), output) {
+            eprintln!("Error writing output: {}", err);
+            std::process::exit(EXIT_IO_ERROR);
+        }
+        if interrupt::take_sigints() > 0 {
+            interrupted(repl, output);
+        }
+        if repl.exit_code().is_some() {
+            exit(repl, output);
+        }
+    }
+    if let Err(err) = repl.end_of_input(output) {
+        eprintln!("Error writing output: {}", err);
+        std::process::exit(EXIT_IO_ERROR);
+    }
+    if let Err(err) = repl.finish() {
+        let _ = output.error(&err.to_string());
+        std::process::exit(EXIT_IO_ERROR);
+    }
+    std::process::exit(if repl.errors() == 0 { 0 } else { error_status(repl) });
+}
+
+/// Writes out what the shell has buffered and exits with the status `.exit`
+/// asked for, or 0 at the end of input.
+fn exit(repl: &mut Repl, output: &mut Console) -> ! {
+    if let Err(err) = repl.finish() {
+        let _ = output.error(&err.to_string());
+        std::process::exit(EXIT_IO_ERROR);
+    }
+    std::process::exit(repl.exit_code().unwrap_or(0));
+}
+
+/// Writes out what the shell has buffered and exits with the status of a
+/// process killed by SIGINT.
+fn interrupted(repl: &mut Repl, output: &mut Console) -> ! {
+    if let Err(err) = repl.finish() {
+        let _ = output.error(&err.to_string());
+    }
+    std::process::exit(EXIT_INTERRUPTED);
+}
+
+/// `.voiddbrc` in the home directory.
+fn default_rc_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".voiddbrc"))
+}
+
+/// `VOIDDB_HISTORY`, or `.voiddb_history` in the home directory.
+fn default_history_file() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("VOIDDB_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".voiddb_history"))
+}
@@ -0,0 +1,273 @@
+//! Where a server listens and a client connects: a TCP `host:port`, or on
+//! Unix a socket file given as `unix:PATH`. Both speak the same protocol
+//! over a `Stream`.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// The part of an address that names a Unix socket file.
+const UNIX_PREFIX: &str = "unix:";
+
+/// An address as given to `--listen` and `--connect`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// `host:port`.
+    Tcp(String),
+    /// `unix:PATH`.
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn parse(text: &str) -> Address {
+        match text.strip_prefix(UNIX_PREFIX) {
+            Some(path) => Address::Unix(PathBuf::from(path)),
+            None => Address::Tcp(text.to_string()),
+        }
+    }
+
+    /// Whether only this machine can reach it: a loopback TCP address or a
+    /// Unix socket.
+    pub fn is_local(&self) -> bool {
+        match self {
+            Address::Tcp(address) => address.parse::<std::net::SocketAddr>().is_ok_and(|address| address.ip().is_loopback()),
+            Address::Unix(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => f.write_str(address),
+            Address::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// The error for a Unix socket where there are none.
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not supported on this platform")
+}
+
+/// A connection between a client and a server.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn connect(address: &Address) -> io::Result<Stream> {
+        match address {
+            Address::Tcp(address) => TcpStream::connect(address).map(Stream::Tcp),
+            #[cfg(unix)]
+            Address::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    /// Another handle to the same connection, to read with while this one
+    /// writes.
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn set_nonblocking(&self, on: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(on),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(on),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    /// Who is at the other end, for logs: the client's address over TCP.
+    pub fn peer(&self) -> String {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map_or_else(|_| "an unknown client".to_string(), |address| address.to_string()),
+            #[cfg(unix)]
+            Stream::Unix(_) => "a client on the socket".to_string(),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A socket a server accepts connections on.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    /// A socket file, removed again when the listener is dropped.
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Listens on `address`. A TCP address may give port 0 for any free
+    /// port; see `local_addr`.
+    ///
+    /// A socket file left behind by a server that is gone is removed first,
+    /// while one that a server still answers on fails with `AddrInUse`. The
+    /// new file can be reached by its owner only.
+    pub fn bind(address: &Address) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(address) => TcpListener::bind(address).map(Listener::Tcp),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file that is not a socket is in the way"));
+                    }
+                    if UnixStream::connect(path).is_ok() {
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse, "another server is listening on it"));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                let listener = Listener::Unix(listener, path.clone());
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+                Ok(listener)
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unix_unsupported()),
+        }
+    }
+
+    /// The address clients reach this on, with the port a TCP listener
+    /// bound to port 0 got.
+    pub fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    pub fn set_nonblocking(&self, on: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(on),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(on),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(Address::parse("127.0.0.1:7878"), Address::Tcp("127.0.0.1:7878".to_string()));
+        assert_eq!(Address::parse("unix:/run/voiddb.sock"), Address::Unix(PathBuf::from("/run/voiddb.sock")));
+        assert_eq!(Address::parse("unix:/run/voiddb.sock").to_string(), "unix:/run/voiddb.sock");
+        assert!(Address::parse("127.0.0.1:7878").is_local());
+        assert!(Address::parse("[::1]:7878").is_local());
+        assert!(Address::parse("unix:voiddb.sock").is_local());
+        assert!(!Address::parse("0.0.0.0:7878").is_local());
+        assert!(!Address::parse("192.168.1.2:7878").is_local());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = TempPath::new("net.sock");
+        let address = Address::Unix(path.to_path_buf());
+
+        let listener = Listener::bind(&address).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let mut client = Stream::connect(&address).unwrap();
+        let mut server = listener.accept().unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(Listener::bind(&address).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        drop((client, server, listener));
+        assert!(!path.exists());
+
+        // A file left by a server that died is in no one's way.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        drop(Listener::bind(&address).unwrap());
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert_eq!(Listener::bind(&address).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,297 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::row::Row;
+
+/// Where the REPL sends what it prints. Results (rows and status lines) and
+/// errors are separate channels so either can be redirected or captured on
+/// its own.
+pub trait Output {
+    fn results(&mut self) -> &mut dyn Write;
+    fn errors(&mut self) -> &mut dyn Write;
+
+    fn row(&mut self, row: &Row) -> io::Result<()> {
+        writeln!(self.results(), "{}", row)
+    }
+
+    fn message(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.results(), "{}", text)
+    }
+
+    fn error(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.errors(), "{}", text)
+    }
+
+    /// Whether results are going to an interactive terminal.
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    /// How many lines fit on the screen, if results are going to an
+    /// interactive terminal. `None` means never page.
+    fn page_height(&self) -> Option<usize> {
+        None
+    }
+
+    /// Asks whether to show another page of results.
+    fn more(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Results to stdout, errors to stderr.
+pub struct Console {
+    stdout: io::Stdout,
+    stderr: io::Stderr,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Console { stdout: io::stdout(), stderr: io::stderr() }
+    }
+}
+
+impl Output for Console {
+    fn results(&mut self) -> &mut dyn Write {
+        &mut self.stdout
+    }
+
+    fn errors(&mut self) -> &mut dyn Write {
+        &mut self.stderr
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.stdout.is_terminal()
+    }
+
+    /// The terminal height comes from `LINES`, since reading it from the
+    /// terminal itself is platform specific; 24 if that is not set.
+    fn page_height(&self) -> Option<usize> {
+        if !self.is_terminal() || !io::stdin().is_terminal() {
+            return None;
+        }
+        let lines = std::env::var("LINES").ok().and_then(|lines| lines.parse().ok());
+        Some(lines.unwrap_or(24))
+    }
+
+    fn more(&mut self) -> io::Result<bool> {
+        write!(self.stdout, "-- More -- (q to quit, Enter for next page)")?;
+        self.stdout.flush()?;
+
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        Ok(!answer.trim().eq_ignore_ascii_case("q"))
+    }
+}
+
+/// Sends results to `results` and everything else to `inner`, for
+/// `.output` redirection.
+pub struct Redirect<'a> {
+    results: &'a mut dyn Write,
+    inner: &'a mut dyn Output,
+}
+
+impl<'a> Redirect<'a> {
+    pub fn new(results: &'a mut dyn Write, inner: &'a mut dyn Output) -> Self {
+        Redirect { results, inner }
+    }
+}
+
+impl Output for Redirect<'_> {
+    fn results(&mut self) -> &mut dyn Write {
+        self.results
+    }
+
+    fn errors(&mut self) -> &mut dyn Write {
+        self.inner.errors()
+    }
+}
+
+/// How many bytes of results `Buffered` holds back at most.
+const BUFFER_SIZE: usize = 4 * 1024;
+
+/// Holds back results bound for `inner` and writes them out a block at a
+/// time, so printing many rows to stdout takes a write per block rather
+/// than a write, and a lock, per line. Anything else that goes to `inner`,
+/// an error or the pager's prompt, first writes out what is held back, so
+/// the order is kept.
+///
+/// `finish` writes out the rest; dropping it does too, without saying
+/// whether that worked.
+pub struct Buffered<'a> {
+    inner: &'a mut dyn Output,
+    buffer: Vec<u8>,
+}
+
+impl<'a> Buffered<'a> {
+    pub fn new(inner: &'a mut dyn Output) -> Self {
+        Buffered { inner, buffer: Vec::new() }
+    }
+
+    /// Writes out what is held back.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_out()
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = self.inner.results().write_all(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+}
+
+impl Write for Buffered<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() + buf.len() > BUFFER_SIZE {
+            self.write_out()?;
+        }
+        if buf.len() >= BUFFER_SIZE {
+            return self.inner.results().write(buf);
+        }
+        // Allocated once, and only for a statement that prints something.
+        if self.buffer.capacity() == 0 {
+            self.buffer.reserve_exact(BUFFER_SIZE);
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out()?;
+        self.inner.results().flush()
+    }
+}
+
+impl Output for Buffered<'_> {
+    fn results(&mut self) -> &mut dyn Write {
+        self
+    }
+
+    fn errors(&mut self) -> &mut dyn Write {
+        // A failed write shows up again on the next one.
+        let _ = self.write_out();
+        self.inner.errors()
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.inner.is_terminal()
+    }
+
+    fn page_height(&self) -> Option<usize> {
+        self.inner.page_height()
+    }
+
+    fn more(&mut self) -> io::Result<bool> {
+        self.write_out()?;
+        self.inner.more()
+    }
+}
+
+impl Drop for Buffered<'_> {
+    fn drop(&mut self) {
+        let _ = self.write_out();
+    }
+}
+
+/// Collects both channels in memory.
+#[derive(Debug, Default)]
+pub struct Captured {
+    pub results: Vec<u8>,
+    pub errors: Vec<u8>,
+}
+
+impl Captured {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn results_str(&self) -> &str {
+        std::str::from_utf8(&self.results).unwrap()
+    }
+
+    pub fn errors_str(&self) -> &str {
+        std::str::from_utf8(&self.errors).unwrap()
+    }
+}
+
+impl Output for Captured {
+    fn results(&mut self) -> &mut dyn Write {
+        &mut self.results
+    }
+
+    fn errors(&mut self) -> &mut dyn Write {
+        &mut self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both channels in one, to see what order things arrive in.
+    #[derive(Default)]
+    struct Interleaved {
+        out: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for Interleaved {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output for Interleaved {
+        fn results(&mut self) -> &mut dyn Write {
+            self
+        }
+
+        fn errors(&mut self) -> &mut dyn Write {
+            self
+        }
+    }
+
+    #[test]
+    fn test_buffered_keeps_the_order() {
+        let mut inner = Interleaved::default();
+        let mut buffered = Buffered::new(&mut inner);
+        for i in 0..1000 {
+            buffered.message(&format!("row {}", i)).unwrap();
+        }
+        buffered.error("Error: oops").unwrap();
+        buffered.message("(1000 rows)").unwrap();
+        buffered.finish().unwrap();
+
+        let out = String::from_utf8(inner.out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 1002);
+        assert!(lines[..1000].iter().enumerate().all(|(i, line)| *line == format!("row {}", i)));
+        assert_eq!(lines[1000..], ["Error: oops", "(1000 rows)"]);
+        // About 9 KB of rows went out in a few blocks.
+        assert!(inner.writes < 10, "{} writes", inner.writes);
+    }
+
+    #[test]
+    fn test_buffered_writes_out_when_dropped() {
+        let mut inner = Interleaved::default();
+        let mut buffered = Buffered::new(&mut inner);
+        buffered.message("held back").unwrap();
+        assert!(buffered.more().unwrap());
+        buffered.message("then dropped").unwrap();
+        drop(buffered);
+        assert_eq!(String::from_utf8(inner.out).unwrap(), "held back\nthen dropped\n");
+    }
+}
@@ -0,0 +1,654 @@
+//! Fixed-size pages of a file, cached in memory and written back on
+//! `Pager::flush`. The table is built on this and nothing else, and other
+//! layouts can be built on it too.
+//!
+//! The rules a client can count on:
+//!
+//! - Pages are `PAGE_SIZE` bytes, numbered from 0, and a file holds at most
+//!   `MAX_PAGES` of them. Page `n` is at byte `n * PAGE_SIZE` of the file;
+//!   the pager adds no header of its own.
+//! - Pages `0..num_pages()` exist. New ones come from `allocate_page`,
+//!   zeroed; reading or changing a page past the end is an error.
+//! - Only pages changed through `get_page_mut` since the last flush are
+//!   written by the next one. Nothing reaches the file before a flush.
+//! - A flush writes page 0 after every other page, so a header kept there
+//!   only takes in the rest of a flush once all of it is written: a crash
+//!   part way leaves the old header in place.
+//! - `free_page` zeroes a page and hands it out again from
+//!   `allocate_page`. The free list is kept in memory only: a client that
+//!   wants freed pages back after reopening the file must note them itself.
+//!
+//! ```
+//! use VoidDB::pager::Pager;
+//!
+//! let mut pager = Pager::memory();
+//! let first = pager.allocate_page().unwrap();
+//! pager.get_page_mut(first).unwrap()[..5].copy_from_slice(b"hello");
+//! assert_eq!(&pager.get_page(first).unwrap()[..5], b"hello");
+//!
+//! pager.free_page(first).unwrap();
+//! assert_eq!(pager.allocate_page().unwrap(), first);
+//! assert_eq!(&pager.get_page(first).unwrap()[..5], &[0; 5]);
+//! ```
+
+use std::fs::TryLockError;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::compiler::ExecuteError;
+use crate::error::DbError;
+use crate::interrupt::Interrupt;
+use crate::vfs::{self, OpenMode, Vfs, VfsFile};
+
+pub const PAGE_SIZE: usize = 4096;
+/// The most pages a file can hold.
+pub const MAX_PAGES: usize = 100;
+/// The most page buffers a pager keeps after letting go of their pages.
+const SPARE_PAGES: usize = 16;
+
+/// A page to read, from `Pager::get_page`.
+#[derive(Debug, Clone, Copy)]
+pub struct PageRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PageRef<'a> {
+    /// The page's bytes, for as long as the pager is borrowed.
+    pub fn bytes(self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl std::ops::Deref for PageRef<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+/// A page to change, from `Pager::get_page_mut`, which has already marked
+/// it to be written by the next flush.
+#[derive(Debug)]
+pub struct PageMut<'a> {
+    bytes: &'a mut [u8],
+}
+
+impl<'a> PageMut<'a> {
+    /// The page's bytes, for as long as the pager is borrowed.
+    pub fn into_bytes(self) -> &'a mut [u8] {
+        self.bytes
+    }
+}
+
+impl std::ops::Deref for PageMut<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl std::ops::DerefMut for PageMut<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+}
+
+/// The page cache of one database file.
+///
+/// Reading goes through `&self`: each cached page is filled at most once
+/// and never changes while it is shared, so many threads can read at once.
+/// Changing a page takes `&mut self`, which the borrow checker (or the
+/// `RwLock` of a `Connection`) keeps away from readers.
+///
+/// Pages are shared with the snapshots taken by `snapshot`, and copied the
+/// first time they change while a snapshot still holds them.
+///
+/// A pager holds an exclusive lock on its file until it is dropped, so only
+/// one handle, in this process or any other, has a database file open at a
+/// time. Read-only pagers share the lock with each other instead.
+pub struct Pager {
+    file: Option<Box<dyn VfsFile>>,
+    file_length: u64,
+    num_pages: usize,
+    pages_read: AtomicU64,
+    read_only: bool,
+    cache_pages: usize,
+    sync: SyncMode,
+    syncs: u64,
+    counters: Counters,
+    pages: [OnceLock<Arc<Vec<u8>>>; MAX_PAGES],
+    /// Pages changed since the last flush.
+    dirty: [bool; MAX_PAGES],
+    /// Pages freed and not handed out again, in the order they were freed.
+    free: Vec<usize>,
+    /// Buffers of pages the cache let go of, held by nothing else, to load
+    /// other pages into instead of allocating.
+    spare: Mutex<Vec<Arc<Vec<u8>>>>,
+}
+
+/// How a flush makes sure the pages it wrote reach the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Waits until the data and the file's metadata, such as its length,
+    /// are on the disk.
+    #[default]
+    Full,
+    /// Waits for the data only. The file's length is still synced when it
+    /// grows, so this only skips updates such as the modification time.
+    Normal,
+    /// Leaves the writes to the operating system. Quicker, but a power cut
+    /// or system crash can lose what the last flushes wrote.
+    Off,
+}
+
+/// How `Pager::open_with` opens a file; `database::Options` has the same
+/// settings for a whole database.
+#[derive(Debug, Clone)]
+pub struct PagerOptions {
+    /// Where the file is.
+    pub vfs: Arc<dyn Vfs>,
+    /// Open the file for reading only, under a lock shared with other
+    /// read-only handles. `flush` writes nothing.
+    pub read_only: bool,
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// How long to wait for another handle to let go of the file.
+    pub busy_timeout: Duration,
+    /// How many pages of a file stay cached after a flush; the rest are
+    /// dropped and read in again when next needed.
+    pub cache_pages: usize,
+    pub sync: SyncMode,
+}
+
+impl Default for PagerOptions {
+    fn default() -> Self {
+        PagerOptions { vfs: vfs::default(), read_only: false, create: true, busy_timeout: Duration::ZERO, cache_pages: MAX_PAGES, sync: SyncMode::Full }
+    }
+}
+
+/// What a pager has done since it was opened or its counters were last
+/// reset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PagerCounters {
+    /// Page fetches answered from the cache.
+    pub cache_hits: u64,
+    /// Page fetches that had to load the page or start a new one.
+    pub cache_misses: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Pages loaded from the file.
+    pub pages_read: u64,
+    /// Pages written back to the file.
+    pub pages_written: u64,
+    /// Pages added past the end of the database.
+    pub pages_allocated: u64,
+}
+
+impl std::ops::AddAssign for PagerCounters {
+    fn add_assign(&mut self, other: PagerCounters) {
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.bytes_read += other.bytes_read;
+        self.bytes_written += other.bytes_written;
+        self.pages_read += other.pages_read;
+        self.pages_written += other.pages_written;
+        self.pages_allocated += other.pages_allocated;
+    }
+}
+
+impl std::ops::Sub for PagerCounters {
+    type Output = PagerCounters;
+
+    /// The activity between two readings, the earlier one being `other`.
+    fn sub(self, other: PagerCounters) -> PagerCounters {
+        PagerCounters {
+            cache_hits: self.cache_hits.saturating_sub(other.cache_hits),
+            cache_misses: self.cache_misses.saturating_sub(other.cache_misses),
+            bytes_read: self.bytes_read.saturating_sub(other.bytes_read),
+            bytes_written: self.bytes_written.saturating_sub(other.bytes_written),
+            pages_read: self.pages_read.saturating_sub(other.pages_read),
+            pages_written: self.pages_written.saturating_sub(other.pages_written),
+            pages_allocated: self.pages_allocated.saturating_sub(other.pages_allocated),
+        }
+    }
+}
+
+/// `PagerCounters`, bumped by readers on any thread.
+#[derive(Debug, Default)]
+struct Counters {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    pages_read: AtomicU64,
+    pages_written: AtomicU64,
+    pages_allocated: AtomicU64,
+}
+
+impl Pager {
+    /// Opens the file at `path`, creating it if it does not exist. Fails
+    /// with `DbError::Locked` if another handle has it open.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        Self::open_with(path, &PagerOptions::default(), &Interrupt::default())
+    }
+
+    /// Opens the file at `path` as `options` say. While another handle has
+    /// the file open, tries again with growing pauses until the busy timeout
+    /// has passed; using `interrupt` stops the wait with
+    /// `DbError::Interrupted`.
+    ///
+    /// Fails with `DbError::CannotOpen` if the file does not exist and may
+    /// not be created, or if `options` ask for no cache.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &PagerOptions, interrupt: &Interrupt) -> Result<Self, DbError> {
+        let path = path.as_ref();
+        let cannot_open = |why: &str| DbError::CannotOpen(format!("'{}': {}", path.display(), why));
+        if options.cache_pages == 0 {
+            return Err(cannot_open("the cache must hold at least one page"));
+        }
+        let mode = match (options.read_only, options.create) {
+            (true, _) => OpenMode::ReadOnly,
+            (false, true) => OpenMode::Create,
+            (false, false) => OpenMode::ReadWrite,
+        };
+        let file = match options.vfs.open(path, mode) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && options.read_only && options.create => {
+                return Err(cannot_open("it does not exist, and a read-only database cannot create it"));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !options.create => {
+                return Err(cannot_open("it does not exist, and create_if_missing is off"));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        lock(&*file, options.read_only, options.busy_timeout, interrupt)?;
+        let file_length = file.file_size()?;
+
+        if file_length % PAGE_SIZE as u64 != 0 {
+            return Err(DbError::Corrupt(format!(
+                "file length {} is not a whole number of pages",
+                file_length
+            )));
+        }
+
+        let mut pager = Pager::memory();
+        pager.file = Some(file);
+        pager.file_length = file_length;
+        pager.num_pages = (file_length / PAGE_SIZE as u64) as usize;
+        pager.read_only = options.read_only;
+        pager.cache_pages = options.cache_pages;
+        pager.sync = options.sync;
+        Ok(pager)
+    }
+
+    /// A pager with no backing file; pages only ever live in the cache.
+    pub fn memory() -> Self {
+        Pager {
+            file: None,
+            file_length: 0,
+            num_pages: 0,
+            pages_read: AtomicU64::new(0),
+            read_only: false,
+            cache_pages: MAX_PAGES,
+            sync: SyncMode::Full,
+            syncs: 0,
+            counters: Counters::default(),
+            pages: std::array::from_fn(|_| OnceLock::new()),
+            dirty: [false; MAX_PAGES],
+            free: Vec::new(),
+            spare: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    /// Number of pages loaded from the file since it was opened.
+    pub fn pages_read(&self) -> u64 {
+        self.pages_read.load(Ordering::Relaxed)
+    }
+
+    /// Whether the file was opened for reading only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Number of times a flush has synced the file since it was opened.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Length of the file as of the last flush, or 0 in memory.
+    pub fn file_length(&self) -> u64 {
+        self.file_length
+    }
+
+    /// Number of pages held in the cache.
+    pub fn cached_pages(&self) -> usize {
+        self.pages.iter().filter(|page| page.get().is_some()).count()
+    }
+
+    pub fn counters(&self) -> PagerCounters {
+        PagerCounters {
+            cache_hits: self.counters.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.counters.cache_misses.load(Ordering::Relaxed),
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.counters.bytes_written.load(Ordering::Relaxed),
+            pages_read: self.counters.pages_read.load(Ordering::Relaxed),
+            pages_written: self.counters.pages_written.load(Ordering::Relaxed),
+            pages_allocated: self.counters.pages_allocated.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.counters = Counters::default();
+    }
+
+    /// The page `page_num`, read in from the file the first time.
+    pub fn get_page(&self, page_num: usize) -> Result<PageRef<'_>, DbError> {
+        self.check(page_num)?;
+        Ok(PageRef { bytes: self.load(page_num)? })
+    }
+
+    /// The page `page_num`, to change. Counts as one page fetch, like
+    /// `get_page`, and marks the page to be written by the next flush.
+    pub fn get_page_mut(&mut self, page_num: usize) -> Result<PageMut<'_>, DbError> {
+        self.check(page_num)?;
+        self.load(page_num)?;
+        self.dirty[page_num] = true;
+        let mut spare = (Arc::strong_count(self.pages[page_num].get().expect("page was just loaded")) > 1).then(|| self.buffer(false));
+        let page = self.pages[page_num].get_mut().expect("page was just loaded");
+        if let Some(mut copy) = spare.take() {
+            // A snapshot holds the page: it keeps this one, we change a copy.
+            Arc::get_mut(&mut copy).expect("spare buffers are not shared").copy_from_slice(page);
+            *page = copy;
+        }
+        Ok(PageMut { bytes: Arc::get_mut(page).expect("page is not shared").as_mut_slice() })
+    }
+
+    /// A zeroed page to use: the last one freed, or else a new one past the
+    /// end. Fails with `ExecuteError::TableFull` once the file holds
+    /// `MAX_PAGES` pages and none is free.
+    pub fn allocate_page(&mut self) -> Result<usize, DbError> {
+        if let Some(page_num) = self.free.pop() {
+            return Ok(page_num);
+        }
+        if self.num_pages == MAX_PAGES {
+            return Err(DbError::Execute(ExecuteError::TableFull));
+        }
+        let page_num = self.num_pages;
+        self.num_pages += 1;
+        self.counters.pages_allocated.fetch_add(1, Ordering::Relaxed);
+        // Cached, zeroed, and marked to be written.
+        self.get_page_mut(page_num)?;
+        Ok(page_num)
+    }
+
+    /// Zeroes the page `page_num` and keeps it for `allocate_page` to hand
+    /// out again. The file does not shrink.
+    pub fn free_page(&mut self, page_num: usize) -> Result<(), DbError> {
+        if self.free.contains(&page_num) {
+            return Err(DbError::Corrupt(format!("page {} was freed twice", page_num)));
+        }
+        self.get_page_mut(page_num)?.fill(0);
+        self.free.push(page_num);
+        Ok(())
+    }
+
+    /// Fails unless the page `page_num` exists.
+    fn check(&self, page_num: usize) -> Result<(), DbError> {
+        if page_num >= self.num_pages {
+            return Err(DbError::Corrupt(format!("page {} is past the end, at {} pages", page_num, self.num_pages)));
+        }
+        Ok(())
+    }
+
+    /// The page `page_num`, read in from the file the first time. Pages past
+    /// the end of the file start out zeroed.
+    fn load(&self, page_num: usize) -> Result<&[u8], DbError> {
+        let cached = &self.pages[page_num];
+        if let Some(page) = cached.get() {
+            self.counters.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(page.as_slice());
+        }
+        self.counters.cache_misses.fetch_add(1, Ordering::Relaxed);
+
+        let pages_on_disk = (self.file_length / PAGE_SIZE as u64) as usize;
+        let file = self.file.as_ref().filter(|_| page_num < pages_on_disk);
+        // A page read from the file overwrites every byte, so only a new
+        // page needs zeroing.
+        let mut page = self.buffer(file.is_none());
+        if let Some(file) = file {
+            let bytes = Arc::get_mut(&mut page).expect("spare buffers are not shared");
+            if let Err(err) = file.read_at(bytes, (page_num * PAGE_SIZE) as u64) {
+                self.recycle(page);
+                return Err(err.into());
+            }
+            self.pages_read.fetch_add(1, Ordering::Relaxed);
+            self.counters.pages_read.fetch_add(1, Ordering::Relaxed);
+            self.counters.bytes_read.fetch_add(PAGE_SIZE as u64, Ordering::Relaxed);
+        }
+        // Another reader may have loaded it meanwhile; theirs is as good.
+        let mut page = Some(page);
+        let loaded = cached.get_or_init(|| page.take().expect("initialized once"));
+        if let Some(page) = page {
+            self.recycle(page);
+        }
+        Ok(loaded.as_slice())
+    }
+
+    /// A page buffer held by nothing else: a spare one if there is any, else
+    /// a new one. Its bytes are left as they were unless `zeroed` is set.
+    fn buffer(&self, zeroed: bool) -> Arc<Vec<u8>> {
+        let spare = self.spare.lock().unwrap_or_else(PoisonError::into_inner).pop();
+        match spare {
+            Some(mut page) if zeroed => {
+                Arc::get_mut(&mut page).expect("spare buffers are not shared").fill(0);
+                page
+            }
+            Some(page) => page,
+            None => Arc::new(vec![0; PAGE_SIZE]),
+        }
+    }
+
+    /// Keeps `page` to be handed out again by `buffer`, unless something
+    /// else still holds it or there are spares enough.
+    fn recycle(&self, mut page: Arc<Vec<u8>>) {
+        let mut spare = self.spare.lock().unwrap_or_else(PoisonError::into_inner);
+        if Arc::get_mut(&mut page).is_some() && spare.len() < SPARE_PAGES {
+            spare.push(page);
+        }
+    }
+
+    /// A pager with no file holding the first `num_pages` pages as they
+    /// are now, loading any that are not cached yet. The pages are shared
+    /// until this pager changes them, and each is let go when the last
+    /// pager holding it is dropped.
+    pub fn snapshot(&self, num_pages: usize) -> Result<Pager, DbError> {
+        let mut snapshot = Pager::memory();
+        for page_num in 0..num_pages.min(self.num_pages) {
+            self.load(page_num)?;
+            let page = self.pages[page_num].get().expect("page was just loaded");
+            let _ = snapshot.pages[page_num].set(page.clone());
+        }
+        snapshot.num_pages = num_pages.min(self.num_pages);
+        Ok(snapshot)
+    }
+
+    fn flush_page(&mut self, page_num: usize) -> io::Result<()> {
+        let (Some(file), Some(page)) = (self.file.as_mut(), self.pages[page_num].get()) else {
+            return Ok(());
+        };
+        if !self.dirty[page_num] {
+            return Ok(());
+        }
+
+        file.write_at(page, (page_num * PAGE_SIZE) as u64)?;
+        self.dirty[page_num] = false;
+        self.counters.bytes_written.fetch_add(PAGE_SIZE as u64, Ordering::Relaxed);
+        self.counters.pages_written.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes the pages changed since the last flush back to the file and
+    /// syncs it as the sync mode says, then lets go of the cached pages past the cache size. A
+    /// read-only pager writes nothing.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.file.is_none() || self.read_only {
+            return Ok(());
+        }
+        for page_num in (1..self.num_pages).chain(0..self.num_pages.min(1)) {
+            self.flush_page(page_num)?;
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            let grew = (self.num_pages * PAGE_SIZE) as u64 != self.file_length;
+            match self.sync {
+                SyncMode::Full => file.sync(false)?,
+                SyncMode::Normal => file.sync(!grew)?,
+                SyncMode::Off => {}
+            }
+            if self.sync != SyncMode::Off {
+                self.syncs += 1;
+            }
+            self.file_length = (self.num_pages * PAGE_SIZE) as u64;
+        }
+        // Every page is in the file now, so any can be read back.
+        for page_num in self.cache_pages.min(MAX_PAGES)..MAX_PAGES {
+            if let Some(page) = self.pages[page_num].take() {
+                self.recycle(page);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Takes a lock on `file`, shared or exclusive, retrying until `timeout`
+/// while another handle holds one that conflicts.
+fn lock(file: &dyn VfsFile, shared: bool, timeout: Duration, interrupt: &Interrupt) -> Result<(), DbError> {
+    const LONGEST_PAUSE: Duration = Duration::from_millis(50);
+    let deadline = Instant::now() + timeout;
+    let mut pause = Duration::from_millis(1);
+    loop {
+        match file.try_lock(shared) {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(DbError::Locked);
+        }
+        if interrupt.take() {
+            return Err(DbError::Interrupted);
+        }
+        std::thread::sleep(pause.min(deadline - now));
+        pause = (pause * 2).min(LONGEST_PAUSE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn test_flush_writes_only_changed_pages() {
+        let path = TempPath::new("pager-dirty.db");
+        let mut pager = Pager::open(&path).unwrap();
+        for page_num in 0..3 {
+            assert_eq!(pager.allocate_page().unwrap(), page_num);
+            pager.get_page_mut(page_num).unwrap()[0] = page_num as u8 + 1;
+        }
+        pager.flush().unwrap();
+        assert_eq!(pager.counters().pages_written, 3);
+
+        pager.get_page(0).unwrap();
+        pager.get_page_mut(1).unwrap()[1] = 9;
+        pager.flush().unwrap();
+        assert_eq!(pager.counters().pages_written, 4);
+        drop(pager);
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.num_pages(), 3);
+        let firsts: Vec<[u8; 2]> = (0..3).map(|n| pager.get_page(n).unwrap()[..2].try_into().unwrap()).collect();
+        assert_eq!(firsts, [[1, 0], [2, 9], [3, 0]]);
+        drop(pager);
+    }
+
+    #[test]
+    fn test_freed_pages_are_zeroed_and_handed_out_again() {
+        let mut pager = Pager::memory();
+        for _ in 0..3 {
+            let page_num = pager.allocate_page().unwrap();
+            pager.get_page_mut(page_num).unwrap().fill(7);
+        }
+        pager.free_page(0).unwrap();
+        pager.free_page(2).unwrap();
+        assert_eq!(pager.free_page(2).unwrap_err().code(), "CORRUPT");
+        assert!(pager.get_page(2).unwrap().iter().all(|&byte| byte == 0));
+        assert_eq!(pager.get_page(1).unwrap()[0], 7);
+
+        assert_eq!(pager.allocate_page().unwrap(), 2);
+        assert_eq!(pager.allocate_page().unwrap(), 0);
+        assert_eq!(pager.allocate_page().unwrap(), 3);
+        assert_eq!(pager.num_pages(), 4);
+        assert_eq!(pager.counters().pages_allocated, 4);
+    }
+
+    #[test]
+    fn test_page_buffers_are_reused() {
+        let options = PagerOptions { vfs: Arc::new(crate::vfs::MemoryVfs::new()), cache_pages: 2, ..PagerOptions::default() };
+        let mut pager = Pager::open_with("reused.db", &options, &Interrupt::default()).unwrap();
+        for page_num in 0..8 {
+            assert_eq!(pager.allocate_page().unwrap(), page_num);
+            pager.get_page_mut(page_num).unwrap().fill(page_num as u8 + 1);
+        }
+        pager.flush().unwrap();
+
+        // Every round reads back in the pages the last flush let go of, so
+        // without reuse each would allocate.
+        let (_, allocations) = crate::alloc_counter::allocations_during(|| {
+            for round in 0..100 {
+                for page_num in 0..8 {
+                    assert!(pager.get_page(page_num).unwrap()[2..].iter().all(|&byte| byte == page_num as u8 + 1));
+                }
+                pager.get_page_mut(round % 8).unwrap()[0] = round as u8;
+                pager.flush().unwrap();
+            }
+        });
+        assert!(allocations <= options.cache_pages, "{} allocations", allocations);
+        assert_eq!(pager.counters().pages_read, 600);
+
+        // A page a snapshot holds is copied into a spare buffer to change.
+        let snapshot = pager.snapshot(8).unwrap();
+        pager.get_page_mut(3).unwrap()[1] = 0;
+        assert!(snapshot.get_page(3).unwrap()[1..].iter().all(|&byte| byte == 4));
+        assert_eq!(pager.get_page(3).unwrap()[..3], [99, 0, 4]);
+
+        // A page that was never written starts out zeroed even in a buffer
+        // that held another.
+        let fresh = pager.allocate_page().unwrap();
+        assert!(pager.get_page(fresh).unwrap().iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_pages_past_the_end_and_a_full_file() {
+        let mut pager = Pager::memory();
+        assert_eq!(pager.get_page(0).unwrap_err().to_string(), "Database is corrupt: page 0 is past the end, at 0 pages");
+        assert!(pager.get_page_mut(0).is_err());
+        assert!(pager.free_page(0).is_err());
+
+        for page_num in 0..MAX_PAGES {
+            assert_eq!(pager.allocate_page().unwrap(), page_num);
+        }
+        assert_eq!(pager.allocate_page().unwrap_err().code(), "TABLE_FULL");
+        pager.free_page(MAX_PAGES / 2).unwrap();
+        assert_eq!(pager.allocate_page().unwrap(), MAX_PAGES / 2);
+    }
+}
@@ -0,0 +1,169 @@
+//! Progress reports for long imports and exports, and a way for whoever
+//! receives them to cancel.
+
+use std::cell::Cell;
+#[cfg(any(feature = "cli", test))]
+use std::io::{self, Read, Write};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Rows between progress reports.
+pub const PROGRESS_ROWS: usize = 1000;
+/// The longest wait between progress reports while rows keep coming.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far an import or export has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Rows handled so far, including any that failed.
+    pub rows: usize,
+    pub errors: usize,
+    /// Bytes read from the input of an import, or written to the output of
+    /// an export. Zero when there is no file.
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn rows_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.rows as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Receives progress reports. Returning `ControlFlow::Break` cancels.
+pub type Callback<'a> = &'a mut dyn FnMut(Progress) -> ControlFlow<()>;
+
+/// Decides when to report progress: every `PROGRESS_ROWS` rows, or sooner
+/// if `PROGRESS_INTERVAL` has passed since the last report.
+pub(crate) struct Tracker<'a> {
+    callback: Option<Callback<'a>>,
+    bytes: Option<Bytes>,
+    start: Instant,
+    last_report: Instant,
+    last_rows: usize,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(callback: Option<Callback<'a>>) -> Self {
+        let now = Instant::now();
+        Tracker { callback, bytes: None, start: now, last_report: now, last_rows: 0 }
+    }
+
+    /// Reports the bytes counted by `bytes` along with the rows.
+    #[cfg(any(feature = "cli", test))]
+    pub(crate) fn with_bytes(mut self, bytes: Bytes) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Called after each row. Reports progress if it is due, and passes on
+    /// a request to cancel.
+    pub(crate) fn tick(&mut self, rows: usize, errors: usize) -> ControlFlow<()> {
+        let Some(callback) = self.callback.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        let now = Instant::now();
+        if rows - self.last_rows < PROGRESS_ROWS && now - self.last_report < PROGRESS_INTERVAL {
+            return ControlFlow::Continue(());
+        }
+        self.last_report = now;
+        self.last_rows = rows;
+        let bytes = self.bytes.as_ref().map_or(0, Bytes::get);
+        callback(Progress { rows, errors, bytes, elapsed: now - self.start })
+    }
+}
+
+/// A running count of the bytes that went through a `Counted`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bytes(Rc<Cell<u64>>);
+
+impl Bytes {
+    pub(crate) fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    #[cfg(any(feature = "cli", test))]
+    fn add(&self, n: usize) {
+        self.0.set(self.0.get() + n as u64);
+    }
+}
+
+/// A reader or writer that counts the bytes passing through it. Only the
+/// shell's imports and exports read and write files.
+#[cfg(any(feature = "cli", test))]
+pub(crate) struct Counted<T> {
+    inner: T,
+    bytes: Bytes,
+}
+
+#[cfg(any(feature = "cli", test))]
+impl<T> Counted<T> {
+    pub(crate) fn new(inner: T) -> (Self, Bytes) {
+        let bytes = Bytes::default();
+        (Counted { inner, bytes: bytes.clone() }, bytes)
+    }
+
+    #[cfg(feature = "cli")]
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+#[cfg(any(feature = "cli", test))]
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.add(n);
+        Ok(n)
+    }
+}
+
+#[cfg(any(feature = "cli", test))]
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes.add(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_reports_every_progress_rows() {
+        let mut reports = Vec::new();
+        let mut callback = |progress: Progress| {
+            reports.push((progress.rows, progress.errors, progress.bytes));
+            ControlFlow::Continue(())
+        };
+        let (mut reader, bytes) = Counted::new(&[0u8; 10][..]);
+        reader.read_exact(&mut [0; 4]).unwrap();
+
+        let mut tracker = Tracker::new(Some(&mut callback)).with_bytes(bytes);
+        for rows in 1..=2500 {
+            assert_eq!(tracker.tick(rows, rows / 100), ControlFlow::Continue(()));
+        }
+        drop(tracker);
+        // A slow machine may add reports on time, but never goes more than
+        // PROGRESS_ROWS rows without one.
+        assert!(reports.len() >= 2 && reports[0].0 <= PROGRESS_ROWS, "{:?}", reports);
+        assert!(reports.windows(2).all(|pair| pair[1].0 - pair[0].0 <= PROGRESS_ROWS));
+        assert!(reports.iter().all(|&(rows, errors, bytes)| errors == rows / 100 && bytes == 4));
+    }
+
+    #[test]
+    fn test_tracker_without_callback() {
+        let mut tracker = Tracker::new(None);
+        assert_eq!(tracker.tick(PROGRESS_ROWS, 0), ControlFlow::Continue(()));
+    }
+}
@@ -0,0 +1,277 @@
+//! The line protocol `VoidDB serve` speaks.
+//!
+//! The client sends one request per line: a statement (or several
+//! separated by `;`), or one of the commands `.mode csv`, `.mode json`,
+//! `.statement_timeout [DURATION|off]`, `.shutdown` and `.quit`. Every
+//! request but `.quit` gets one response: the lines of its results in the
+//! session's output mode, then an error line if it failed, then the end
+//! line, `.`.
+//!
+//! A statement that runs longer than the session's statement timeout, such
+//! as `5s` or `250ms`, is stopped and fails with `TIMEOUT`; the client
+//! carries on as after any other error. `.statement_timeout` on its own
+//! gives the timeout as a result line.
+//!
+//! A server that asks for a token takes `AUTH <token>` as the first
+//! request, which gets an empty response. Any other first request, or a
+//! wrong token, gets an `AUTH_FAILED` error and the connection is closed.
+//! Only a client that has sent the token may send `.shutdown`, which stops
+//! the server as SIGTERM does, and ends the client's connection after its
+//! response. A server that asks for no token cannot be shut down this way:
+//! `.shutdown` fails with `NOT_ALLOWED` for every client.
+//!
+//! An error line is `!CODE message`, with the code as listed on
+//! `DbError::code` or one of the server's own, `USAGE`,
+//! `UNRECOGNIZED_COMMAND`, `AUTH_FAILED`, `NOT_ALLOWED` and `TIMEOUT`. A result line that starts with `.` or `!` is
+//! sent with another `.` in front, so neither can be taken for the end of
+//! the response or an error; the reader takes it off again.
+//!
+//! A client that opens with `codec::MAGIC` instead speaks the binary
+//! protocol in `codec` for the rest of the connection.
+//!
+//! A request holding a line break is sent with `encode_request`, and
+//! `read_response` reads a whole response back.
+//!
+//! ```text
+//! > insert 1 alice alice@example.com
+//! < .
+//! > select
+//! < 1,alice,alice@example.com,0
+//! < .
+//! > insert 1 bob bob@example.com
+//! < !DUPLICATE_KEY Error: Duplicate key.
+//! < .
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+/// Where `VoidDB serve` listens unless told otherwise.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+/// The line that ends every response.
+pub const END: &str = ".";
+
+/// What one line of a response is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A line of results.
+    Data(String),
+    /// The request failed.
+    Error { code: String, message: String },
+    /// The response is over.
+    End,
+}
+
+impl Frame {
+    /// The frame a response line, without its line ending, holds.
+    pub fn parse(line: &str) -> Frame {
+        if line == END {
+            return Frame::End;
+        }
+        if let Some(error) = line.strip_prefix('!') {
+            let (code, message) = error.split_once(' ').unwrap_or((error, ""));
+            return Frame::Error { code: code.to_string(), message: message.to_string() };
+        }
+        match line.strip_prefix('.') {
+            Some(data) => Frame::Data(data.to_string()),
+            None => Frame::Data(line.to_string()),
+        }
+    }
+}
+
+/// A response as read back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Response {
+    /// The result lines, each ended by a line break.
+    pub results: String,
+    /// The code and message of the error line, if the request failed.
+    pub error: Option<(String, String)>,
+}
+
+/// Reads the response to a request, up to its end line. A connection
+/// closed before then is an `UnexpectedEof` error.
+pub fn read_response(reader: &mut dyn BufRead) -> io::Result<Response> {
+    let mut response = Response::default();
+    let mut buf = Vec::new();
+    loop {
+        let Some(line) = read_line(reader, &mut buf)? else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+        };
+        match Frame::parse(&line) {
+            Frame::Data(data) => {
+                response.results.push_str(&data);
+                response.results.push('\n');
+            }
+            Frame::Error { code, message } => response.error = Some((code, message)),
+            Frame::End => return Ok(response),
+        }
+    }
+}
+
+/// `sql` on one line, to send as a request. A line break inside a quoted
+/// value becomes the escape `quote` writes for it, and one anywhere else
+/// becomes a space, so the statement reads the same.
+pub fn encode_request(sql: &str) -> String {
+    let mut line = String::with_capacity(sql.len());
+    let mut quoted = false;
+    // Quotes only open a value at the start of a word, as in `prepare`.
+    let mut word_start = true;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' if quoted => line.push_str("\\n"),
+            '\r' if quoted => line.push_str("\\r"),
+            '\n' | '\r' => line.push(' '),
+            '\'' if quoted && chars.peek() == Some(&'\'') => {
+                chars.next();
+                line.push_str("''");
+            }
+            // An escape is kept as it is. A backslash that starts none is
+            // itself, which is written `\\` so that a line break after it
+            // cannot make it one.
+            '\\' if quoted => match chars.peek() {
+                Some(&escape @ ('\\' | 'n' | 'r' | 't' | '0')) => {
+                    chars.next();
+                    line.push('\\');
+                    line.push(escape);
+                }
+                _ => line.push_str("\\\\"),
+            },
+            '\'' => {
+                quoted = !quoted && word_start;
+                line.push(c);
+            }
+            c => line.push(c),
+        }
+        word_start = !quoted && (c.is_whitespace() || c == ';');
+    }
+    line
+}
+
+/// Writes `results` as the result lines of a response. A last line without
+/// a line ending gets one.
+pub fn write_results(out: &mut dyn Write, results: &str) -> io::Result<()> {
+    for line in results.lines() {
+        if line.starts_with(['.', '!']) {
+            out.write_all(b".")?;
+        }
+        out.write_all(line.as_bytes())?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes an error line. A message of several lines is put on one.
+pub fn write_error(out: &mut dyn Write, code: &str, message: &str) -> io::Result<()> {
+    let message: Vec<&str> = message.lines().collect();
+    writeln!(out, "!{} {}", code, message.join(" "))
+}
+
+/// Ends a response.
+pub fn write_end(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", END)
+}
+
+/// Reads one line, without its line ending, or `None` at the end of input.
+/// A last line without a line ending still counts.
+///
+/// What was read before an error, such as a read timing out part way
+/// through a line, stays in `buf`, and the next call with the same `buf`
+/// carries on from there.
+pub fn read_line(reader: &mut dyn BufRead, buf: &mut Vec<u8>) -> io::Result<Option<String>> {
+    if reader.read_until(b'\n', buf)? == 0 && buf.is_empty() {
+        return Ok(None);
+    }
+    let mut line = std::mem::take(buf);
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    }
+    String::from_utf8(line).map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "line is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_round_trip() {
+        let results = "1,alice,a@x,0\n.hidden\n!bang\n..\n\n";
+        let mut wire = Vec::new();
+        write_results(&mut wire, results).unwrap();
+        write_end(&mut wire).unwrap();
+        assert_eq!(String::from_utf8(wire.clone()).unwrap(), "1,alice,a@x,0\n..hidden\n.!bang\n...\n\n.\n");
+
+        let mut reader = &wire[..];
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        while let Some(line) = read_line(&mut reader, &mut buf).unwrap() {
+            frames.push(Frame::parse(&line));
+        }
+        let data = |text: &str| Frame::Data(text.to_string());
+        assert_eq!(frames, [data("1,alice,a@x,0"), data(".hidden"), data("!bang"), data(".."), data(""), Frame::End]);
+    }
+
+    #[test]
+    fn test_errors_fit_on_one_line() {
+        let mut wire = Vec::new();
+        write_error(&mut wire, "CORRUPT", "bad header:\nnot a VoidDB file").unwrap();
+        assert_eq!(wire, b"!CORRUPT bad header: not a VoidDB file\n");
+        let error = Frame::parse(std::str::from_utf8(&wire).unwrap().trim_end());
+        assert_eq!(error, Frame::Error { code: "CORRUPT".to_string(), message: "bad header: not a VoidDB file".to_string() });
+    }
+
+    #[test]
+    fn test_values_spanning_lines_round_trip() {
+        // CSV quotes a value with line breaks and keeps them, so a row can
+        // span lines, some looking like the end line or an error.
+        let mut results = Vec::new();
+        crate::csv::write_record(&mut results, ',', ["1", "two\n.\n!three", ".", "x".repeat(100_000).as_str()]).unwrap();
+        let results = String::from_utf8(results).unwrap();
+        let mut wire = Vec::new();
+        write_results(&mut wire, &results).unwrap();
+        write_end(&mut wire).unwrap();
+        write_error(&mut wire, "LOCKED", "Error: database is locked").unwrap();
+        write_end(&mut wire).unwrap();
+
+        let mut reader = &wire[..];
+        assert_eq!(read_response(&mut reader).unwrap(), Response { results, error: None });
+        let error = Some(("LOCKED".to_string(), "Error: database is locked".to_string()));
+        assert_eq!(read_response(&mut reader).unwrap(), Response { results: String::new(), error });
+        assert_eq!(read_response(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_response(&mut &b"1,a,a@x,0\n"[..]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_requests_fit_on_one_line() {
+        use crate::compiler::prepare;
+
+        for sql in [
+            "insert 1 'two\nlines' a@x",
+            "insert\r\n1\nalice\n'it''s\r\nhere' 3",
+            "insert 1 'back\\\nslash' 'tab\\t'",
+            "insert 1 a'b\nc a@x",
+            "insert 1 '' 'a;\nb'; select",
+        ] {
+            let line = encode_request(sql);
+            assert!(!line.contains(['\n', '\r']), "{:?} became {:?}", sql, line);
+            assert_eq!(prepare(&line), prepare(sql), "{:?} became {:?}", sql, line);
+        }
+        assert_eq!(encode_request("insert 1 'a\nb'\nc@x"), "insert 1 'a\\nb' c@x");
+    }
+
+    #[test]
+    fn test_read_line_endings() {
+        let mut reader: &[u8] = b"one\r\ntwo\nthree";
+        let mut buf = Vec::new();
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap().as_deref(), Some("one"));
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap().as_deref(), Some("two"));
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap().as_deref(), Some("three"));
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap(), None);
+
+        let mut reader: &[u8] = b"\xff\n";
+        assert_eq!(read_line(&mut reader, &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,2236 @@
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::client::Client;
+use crate::compiler::{create_table_sql, prepare, quote, is_complete, split_statements, MetaCommandResult, PrepareError, QueryResult, StatementType};
+use crate::database::Database;
+use crate::display::{plural, Printer};
+use crate::csv;
+use crate::error::DbError;
+use crate::format::FORMAT_VERSION;
+use crate::import::{self, OnConflict};
+use crate::input::InputBuffer;
+use crate::json;
+use crate::output::{Buffered, Captured, Output, Redirect};
+use crate::progress::{Counted, Progress, Tracker};
+use crate::row::Value;
+use crate::seed;
+use crate::style::{self, Style, Styled};
+
+pub use crate::display::{ErrorFormat, Mode, Settings};
+
+pub(crate) mod commands;
+
+pub use commands::RegisterError;
+use commands::Registry;
+
+/// The interactive shell on top of a `Database`: runs one line of input at
+/// a time and prints what happened to an `Output`.
+pub struct Repl {
+    db: Database,
+    settings: Settings,
+    /// Where `.output` sends results, if not to the caller's `Output`, and
+    /// the path it was given.
+    output_file: Option<(String, BufWriter<File>)>,
+    /// The scripts being run by `.read`, innermost last.
+    scripts: Vec<Location>,
+    errors: Cell<Errors>,
+    /// The status `.exit` asked for, once it has run.
+    exit: Option<i32>,
+    /// The time since some fixed point, for `.timer`.
+    clock: Box<dyn FnMut() -> Duration + Send>,
+    /// The meta commands this shell runs.
+    commands: Registry,
+    /// Whether `.shell` may run commands.
+    shell: bool,
+    /// The server statements run on, in place of `db`; see `connected`.
+    remote: Option<Client>,
+    /// The lines read so far of a statement whose quoted value runs on
+    /// past the end of a line, joined by newlines.
+    pending: String,
+}
+
+/// Errors reported so far, and the code of the last one.
+#[derive(Debug, Clone, Copy, Default)]
+struct Errors {
+    count: usize,
+    last: Option<&'static str>,
+}
+
+/// How deeply `.read` scripts may nest, counting numbered input such as a
+/// piped script, so a script that reads itself stops.
+pub const MAX_READ_DEPTH: usize = 16;
+
+/// How often `.seed` reports progress, in rows.
+pub const SEED_PROGRESS_ROWS: usize = 1000;
+
+impl Repl {
+    pub fn new(db: Database) -> Self {
+        Repl { db, settings: Settings::default(), output_file: None, scripts: Vec::new(), errors: Cell::default(), exit: None, clock: Box::new(uptime()), commands: Registry::new(), shell: true, remote: None, pending: String::new() }
+    }
+
+    /// A shell whose statements run on the server `client` is connected
+    /// to, and are printed here as the settings say. Meta commands that
+    /// work on a database of the shell's own, such as `.import` and
+    /// `.tables`, report `NOT_SUPPORTED`.
+    pub fn connected(client: Client) -> Self {
+        let mut repl = Repl::new(Database::open_in_memory());
+        repl.remote = Some(client);
+        repl
+    }
+
+    /// The server this shell is connected to, if it is.
+    pub fn remote(&self) -> Option<&Client> {
+        self.remote.as_ref()
+    }
+
+    /// Adds a meta command called `name` (the leading dot is optional) that
+    /// runs `handler` with the database, the words after the name and the
+    /// output. `args` is shown in its usage line and `summary` in `.help`.
+    ///
+    /// The handler returns `Exit` to end the shell like `.exit`, and
+    /// `UnrecognizedCommand` when its arguments are wrong, which reports
+    /// its usage line. An error it returns is reported as a
+    /// `COMMAND_ERROR`. Fails if the name is taken, by a built-in command
+    /// or another, or is not letters, digits, `-` and `_`.
+    ///
+    /// ```
+    /// use std::io;
+    /// use VoidDB::compiler::MetaCommandResult;
+    /// use VoidDB::output::Captured;
+    /// use VoidDB::repl::Repl;
+    /// use VoidDB::Database;
+    ///
+    /// let mut repl = Repl::new(Database::open_in_memory());
+    /// repl.register_meta_command("count", "", "Print the number of rows", |db, _, output| {
+    ///     let rows = db.query("select").map_err(io::Error::other)?.count();
+    ///     output.message(&rows.to_string())?;
+    ///     Ok(MetaCommandResult::Success)
+    /// })
+    /// .unwrap();
+    ///
+    /// let mut output = Captured::new();
+    /// repl.run_line(".count", &mut output).unwrap();
+    /// assert_eq!(output.results_str(), "0\n");
+    /// ```
+    pub fn register_meta_command(
+        &mut self,
+        name: &str,
+        args: &str,
+        summary: &str,
+        handler: impl FnMut(&mut Database, &[&str], &mut dyn Output) -> io::Result<MetaCommandResult> + Send + 'static,
+    ) -> Result<(), RegisterError> {
+        self.commands.register_custom(name, args, summary, Box::new(handler))
+    }
+
+    /// Makes `.shell` and `.system` refuse to run commands, for a shell
+    /// whose user should reach only the database. There is no way to turn
+    /// them back on.
+    pub fn disable_shell(&mut self) {
+        self.shell = false;
+    }
+
+    pub fn database(&mut self) -> &mut Database {
+        &mut self.db
+    }
+
+    pub fn into_database(self) -> Database {
+        self.db
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// How many errors have been reported, by statements, meta commands and
+    /// the scripts they read.
+    pub fn errors(&self) -> usize {
+        self.errors.get().count
+    }
+
+    /// The code of the last error reported, as listed on `DbError::code`
+    /// or one of the REPL's own such as `USAGE`.
+    pub fn last_error(&self) -> Option<&'static str> {
+        self.errors.get().last
+    }
+
+    /// The status to exit with once `.exit` has run. Nothing more runs
+    /// after that; the caller should `finish` and exit.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Runs `line`, which is line `number` of `source`, so that errors say
+    /// where it came from just as they do inside a `.read` script.
+    pub fn run_numbered_line(&mut self, source: &str, number: usize, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        self.scripts.push(Location { path: source.to_string(), line: number });
+        let result = self.run_line(line, output);
+        self.scripts.pop();
+        result
+    }
+
+    /// The prompt to show before reading a line: the continuation prompt
+    /// while a statement is still waiting for the line that completes it,
+    /// and the main one otherwise.
+    pub fn prompt(&self, output: &dyn Output) -> String {
+        match self.pending.is_empty() {
+            true => self.render(&self.settings.prompt, output),
+            false => self.render(&self.settings.continuation_prompt, output),
+        }
+    }
+
+    /// Drops the lines read so far of a statement still waiting for more,
+    /// as Ctrl-C at a prompt does, so the next line starts a new one.
+    pub fn cancel_statement(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Runs the statement still waiting for more lines when input ends,
+    /// which reports it as it stands; it fails to parse, as its quoted
+    /// value is never closed.
+    pub fn end_of_input(&mut self, output: &mut dyn Output) -> io::Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => {
+                let sql = std::mem::take(&mut self.pending);
+                self.run_statements(&sql, output)
+            }
+        }
+    }
+
+    fn render(&self, template: &str, output: &dyn Output) -> String {
+        let prompt = match &self.remote {
+            // A server writes out each statement before it answers.
+            Some(client) => render_prompt(template, Some(Path::new(client.address())), false),
+            None => render_prompt(template, self.db.path(), self.db.has_unsaved_changes()),
+        };
+        match self.settings.color.enabled(output) {
+            true => style::paint(&prompt, Style::Bold),
+            false => prompt,
+        }
+    }
+
+    /// Runs one line of input: a meta command, or statements separated by
+    /// `;` and followed by any `--` comment, split as `split_statements`
+    /// does. A quoted value left open at the end of the line continues on
+    /// the next, holding the newline, and the statements run once a line
+    /// closes it; see `prompt` and `end_of_input`.
+    pub fn run_line(&mut self, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        if self.exit.is_some() {
+            return Ok(());
+        }
+        if self.settings.echo {
+            match self.output_file.as_mut() {
+                Some((_, file)) => writeln!(file, "db> {}", line)?,
+                None => output.message(&format!("db> {}", line))?,
+            }
+        }
+
+        if !self.pending.is_empty() || !line.starts_with('.') {
+            if !self.pending.is_empty() {
+                self.pending.push('\n');
+            }
+            self.pending.push_str(line);
+            if !is_complete(&self.pending) {
+                return Ok(());
+            }
+            let sql = std::mem::take(&mut self.pending);
+            return self.run_statements(&sql, output);
+        }
+        match self.run_meta_command(line, output)? {
+            MetaCommandResult::Success | MetaCommandResult::Exit(_) => Ok(()),
+            MetaCommandResult::UnrecognizedCommand => {
+                let name = line.split_whitespace().next().unwrap_or(line);
+                self.report(output, "UNRECOGNIZED_COMMAND", &self.commands.unknown_command(name), line)
+            }
+        }
+    }
+
+    fn run_statements(&mut self, sql: &str, output: &mut dyn Output) -> io::Result<()> {
+        // Blank lines and comments, as found in scripts and dumps, run
+        // nothing.
+        for statement in split_statements(sql) {
+            self.run_statement(statement, output)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out everything still buffered: the `.output` file, if any, and
+    /// the database. For when the shell is about to exit.
+    pub fn finish(&mut self) -> Result<(), DbError> {
+        if let Some((_, file)) = self.output_file.as_mut() {
+            file.flush()?;
+        }
+        self.db.flush()
+    }
+
+    /// Runs lines read from `input` until it runs out or `.exit` runs, as
+    /// the shell does at a terminal: the prompt goes to the results before
+    /// each line unless the line editor shows it, and a Ctrl-C while waiting
+    /// for a line starts a fresh one, dropping any statement begun on the
+    /// lines before. With `.history on`, lines are added to
+    /// the line editor's history.
+    pub fn run_input(&mut self, input: &mut InputBuffer, output: &mut dyn Output) -> io::Result<()> {
+        let mut record_history = true;
+        while self.exit.is_none() {
+            let prompt = self.prompt(output);
+            if !input.shows_prompt() {
+                write!(output.results(), "{}", prompt)?;
+                output.results().flush()?;
+            }
+            let line = match input.read_line(&prompt) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    self.cancel_statement();
+                    if !input.shows_prompt() {
+                        output.message("")?;
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            self.run_line(line, output)?;
+            if record_history && self.settings.history {
+                if let Err(err) = input.record() {
+                    output.error(&format!("Warning: cannot save history: {}", err))?;
+                    record_history = false;
+                }
+            }
+        }
+        self.end_of_input(output)
+    }
+
+    /// Runs a line starting with `.`.
+    pub fn run_meta_command(&mut self, line: &str, output: &mut dyn Output) -> io::Result<MetaCommandResult> {
+        match self.settings.color.enabled(output) {
+            true => self.meta_command(line, &mut Styled::new(output)),
+            false => self.meta_command(line, output),
+        }
+    }
+
+    fn meta_command(&mut self, line: &str, output: &mut dyn Output) -> io::Result<MetaCommandResult> {
+        let mut args = line.split_whitespace();
+        let name = args.next().unwrap_or("");
+        let args: Vec<&str> = args.collect();
+
+        if commands::run(self, name, &args, line, output)? == MetaCommandResult::UnrecognizedCommand {
+            return Ok(MetaCommandResult::UnrecognizedCommand);
+        }
+        match self.exit {
+            Some(code) => Ok(MetaCommandResult::Exit(code)),
+            None => Ok(MetaCommandResult::Success),
+        }
+    }
+
+    /// Reports an error from a meta command or statement; see `report`.
+    fn report(&self, output: &mut dyn Output, code: &'static str, message: &str, statement: &str) -> io::Result<()> {
+        report(&self.settings, self.scripts.last(), &self.errors, output, code, message, statement)
+    }
+
+    /// Reports the usage line of the meta command that `line` runs.
+    fn usage(&self, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        let name = line.split_whitespace().next().unwrap_or("");
+        let message = self.commands.find(name).map_or_else(|| self.commands.unknown_command(name), |command| command.usage());
+        self.report(output, "USAGE", &message, line)
+    }
+
+    /// Prints the current settings, for `.show`.
+    fn show(&self, output: &mut dyn Output) -> io::Result<()> {
+        let on_off = |on| if on { "on" } else { "off" };
+        let target = self.output_file.as_ref().map_or("stdout", |(path, _)| path.as_str());
+
+        output.message(&format!("{:>9}: {}", "mode", self.settings.mode.name()))?;
+        output.message(&format!("{:>9}: {}", "echo", on_off(self.settings.echo)))?;
+        output.message(&format!("{:>9}: {}", "bail", on_off(self.settings.bail)))?;
+        output.message(&format!("{:>9}: {}", "errors", self.settings.errors.name()))?;
+        output.message(&format!("{:>9}: {}", "headers", on_off(self.settings.headers)))?;
+        output.message(&format!("{:>9}: {}", "quiet", on_off(self.settings.quiet)))?;
+        output.message(&format!("{:>9}: {}", "pager", on_off(self.settings.pager)))?;
+        output.message(&format!("{:>9}: {}", "color", self.settings.color.name()))?;
+        output.message(&format!("{:>9}: {}", "history", on_off(self.settings.history)))?;
+        output.message(&format!("{:>9}: {}", "timer", on_off(self.settings.timer)))?;
+        output.message(&format!("{:>9}: {:?} {:?}", "prompt", self.settings.prompt, self.settings.continuation_prompt))?;
+        output.message(&format!("{:>9}: \"{}\"", "nullvalue", self.settings.null_value))?;
+        output.message(&format!("{:>9}: {}", "output", target))?;
+        output.message(&format!("{:>9}: {:?}", "separator", self.settings.separator))?;
+        let widths: Vec<String> = self.settings.widths.iter().map(|width| width.to_string()).collect();
+        output.message(format!("{:>9}: {}", "width", widths.join(" ")).trim_end())
+    }
+
+    fn stats(&self, output: &mut dyn Output) -> io::Result<()> {
+        let stats = self.db.stats();
+        let path = stats.path.as_ref().map_or_else(|| ":memory:".to_string(), |path| path.display().to_string());
+
+        output.message(&format!("{:>10}: {}", "path", path))?;
+        output.message(&format!("{:>10}: {} bytes", "file size", stats.file_size))?;
+        output.message(&format!("{:>10}: {} ({} used, {} free)", "pages", stats.pages, stats.used_pages, stats.free_pages))?;
+        for (table, rows) in &stats.tables {
+            output.message(&format!("{:>10}: {} rows in {}", "table", rows, table))?;
+        }
+        output.message(&format!("{:>10}: {} pages, {} hits, {} misses", "cache", stats.cached_pages, stats.cache_hits, stats.cache_misses))?;
+        output.message(&format!("{:>10}: {} bytes", "read", stats.bytes_read))?;
+        output.message(&format!("{:>10}: {} bytes", "written", stats.bytes_written))?;
+        output.message(&format!("{:>10}: {}", "statements", stats.statements))
+    }
+
+    /// Runs a statement, printing its rows as they are read, and with
+    /// `.timer on` how long it took.
+    pub fn run_statement(&mut self, sql: &str, output: &mut dyn Output) -> io::Result<()> {
+        self.redirected(output, |repl, output| {
+            let start = repl.settings.timer.then(|| (repl.clock)());
+            let inserted = repl.execute(sql, output)?;
+            if let Some(start) = start {
+                output.message(&run_time((repl.clock)().saturating_sub(start), inserted))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Replaces the clock `.timer` reads, which gives the time since the
+    /// shell started, for instance with a fake one in tests.
+    pub fn set_clock(&mut self, clock: impl FnMut() -> Duration + Send + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Runs `f` with results going wherever `.output` says.
+    fn redirected(
+        &mut self,
+        output: &mut dyn Output,
+        f: impl FnOnce(&mut Self, &mut dyn Output) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match self.output_file.take() {
+            Some((path, mut file)) => {
+                let result = f(self, &mut Redirect::new(&mut file, output));
+                // Flushed after every statement so a crash loses at most the
+                // statement that was running.
+                let flushed = file.flush();
+                self.output_file = Some((path, file));
+                result.and(flushed)
+            }
+            None => f(self, output),
+        }
+    }
+
+    /// Runs the script at `path` as `.read` would, such as a startup script.
+    pub fn run_file(&mut self, path: &str, output: &mut dyn Output) -> io::Result<()> {
+        self.read(path, &format!(".read {}", path), output)
+    }
+
+    /// Runs each line of the file at `path` as if it had been typed in.
+    fn read(&mut self, path: &str, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        if self.scripts.len() >= MAX_READ_DEPTH {
+            let message = format!("Cannot read '{}': scripts nested more than {} deep", path, MAX_READ_DEPTH);
+            return report(&self.settings, self.scripts.last(), &self.errors, output, "RECURSION_LIMIT", &message, line);
+        }
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                let message = format!("Cannot open '{}': {}", path, err);
+                return report(&self.settings, self.scripts.last(), &self.errors, output, "IO_ERROR", &message, line);
+            }
+        };
+
+        self.scripts.push(Location { path: path.to_string(), line: 0 });
+        let result = self.run_script(InputBuffer::from_reader(BufReader::new(file)), output);
+        self.scripts.pop();
+        result
+    }
+
+    fn run_script(&mut self, mut script: InputBuffer, output: &mut dyn Output) -> io::Result<()> {
+        for number in 1.. {
+            let line = match script.read_input() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    self.pending.clear();
+                    let message = format!("Cannot read script: {}", err);
+                    return report(&self.settings, self.scripts.last(), &self.errors, output, "IO_ERROR", &message, "");
+                }
+            };
+            if let Some(location) = self.scripts.last_mut() {
+                location.line = number;
+            }
+            let errors = self.errors();
+            self.run_line(line, output)?;
+            if self.exit.is_some() || (self.settings.bail && self.errors() > errors) {
+                break;
+            }
+        }
+        // A statement the script leaves open ends with it.
+        self.end_of_input(output)
+    }
+
+    /// `.import [csv|jsonl] FILE [--skip-header] [--strict] [--on-conflict
+    /// MODE]`: loads CSV records of `id,username,email[,age]`, split on the
+    /// current separator, or JSON Lines objects with those fields.
+    fn import(&mut self, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+        let mut positional = Vec::new();
+        let (mut skip_header, mut strict, mut on_conflict) = (false, false, OnConflict::Fail);
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--skip-header" => skip_header = true,
+                "--strict" => strict = true,
+                "--on-conflict" => match args.next().and_then(|mode| OnConflict::parse(mode)) {
+                    Some(mode) => on_conflict = mode,
+                    None => return self.usage(line, output),
+                },
+                _ if !arg.starts_with("--") => positional.push(arg),
+                _ => return self.usage(line, output),
+            }
+        }
+        let (jsonl, path) = match positional[..] {
+            [path] => (false, path),
+            ["csv", path] => (false, path),
+            ["jsonl", path] => (true, path),
+            _ => return self.usage(line, output),
+        };
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => return report(&self.settings, self.scripts.last(), &self.errors, output, "IO_ERROR", &format!("Cannot open '{}': {}", path, err), line),
+        };
+        let quiet = self.settings.quiet;
+        let mut progress = |progress: Progress| {
+            if !quiet {
+                let _ = output.message(&format!(
+                    "... {} read, {} errors, {:.0} rows/s",
+                    plural(progress.rows),
+                    progress.errors,
+                    progress.rows_per_second()
+                ));
+            }
+            ControlFlow::Continue(())
+        };
+        let (file, bytes) = Counted::new(file);
+        let mut tracker = Tracker::new(Some(&mut progress)).with_bytes(bytes);
+        let result = if jsonl {
+            let source = import::jsonl_rows(BufReader::new(file));
+            import::import_rows(&mut self.db, source, strict, on_conflict, &mut tracker)
+        } else {
+            let reader = csv::Reader::new(BufReader::new(file), self.settings.separator);
+            import::import_rows(&mut self.db, import::csv_rows(reader, skip_header), strict, on_conflict, &mut tracker)
+        };
+        drop(tracker);
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(err) => return report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), line),
+        };
+
+        for err in &summary.errors {
+            report(&self.settings, self.scripts.last(), &self.errors, output, "IMPORT_ERROR", &format!("{}:{}: {}", path, err.line, err.message), line)?;
+        }
+        if summary.aborted {
+            let message = "Import aborted on a duplicate id, nothing was imported.";
+            return report(&self.settings, self.scripts.last(), &self.errors, output, "IMPORT_ABORTED", message, line);
+        }
+        let imported = format!(
+            "Imported {}: {} inserted, {} replaced, {} skipped.",
+            plural(summary.inserted + summary.replaced),
+            summary.inserted,
+            summary.replaced,
+            summary.skipped
+        );
+        if summary.cancelled {
+            let message = format!("{} {}", DbError::Interrupted, imported);
+            return report(&self.settings, self.scripts.last(), &self.errors, output, DbError::Interrupted.code(), &message, line);
+        }
+        if !self.settings.quiet {
+            output.message(&imported)?;
+        }
+        Ok(())
+    }
+
+    /// `.seed COUNT [--random] [--rng SEED]`: inserts generated rows. Without
+    /// `--rng` the seed comes from the clock, and is printed so the run can
+    /// be repeated.
+    fn seed(&mut self, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+        let mut count = None;
+        let mut random_ids = false;
+        let mut rng_seed = None;
+        let mut args = args.iter();
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--random" => random_ids = true,
+                "--rng" => match args.next().and_then(|seed| seed.parse().ok()) {
+                    Some(seed) => rng_seed = Some(seed),
+                    None => return self.usage(line, output),
+                },
+                _ => match arg.parse() {
+                    Ok(n) if count.is_none() => count = Some(n),
+                    _ => return self.usage(line, output),
+                },
+            }
+        }
+        let Some(count) = count else {
+            return self.usage(line, output);
+        };
+        let rng_seed = rng_seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            now.as_nanos() as u64
+        });
+
+        let quiet = self.settings.quiet;
+        let mut seeded = 0;
+        let mut progress = |n: usize| {
+            seeded = n;
+            if !quiet && n.is_multiple_of(SEED_PROGRESS_ROWS) && n < count {
+                // Progress is best effort; a failed write shows up on the
+                // summary line.
+                let _ = output.message(&format!("Seeded {} of {}...", n, plural(count)));
+            }
+        };
+        let result = seed::seed_rows(&mut self.db, count, random_ids, rng_seed, &mut progress);
+        match result {
+            Ok(_) if !quiet => output.message(&format!("Seeded {} (--rng {}).", plural(count), rng_seed)),
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let message = format!("{} Seeded {} of {}.", err, seeded, plural(count));
+                report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &message, line)
+            }
+        }
+    }
+
+    /// Writes the rows of `sql` (by default the whole table) to `path` as a
+    /// JSON array. The rows go to a temporary file next to `path` that is
+    /// renamed into place only once everything is written, so a failure
+    /// leaves no partial file behind.
+    fn export_json(&mut self, path: &str, sql: &str, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        let sql = if sql.is_empty() { "select" } else { sql };
+        match prepare(sql) {
+            Ok(statement) if statement.typ == StatementType::Select => {}
+            Ok(_) => {
+                let message = "Only a select can be exported.";
+                return report(&self.settings, self.scripts.last(), &self.errors, output, "USAGE", message, line);
+            }
+            Err(err) => {
+                let err = DbError::Prepare(err);
+                return report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), line);
+            }
+        }
+
+        let quiet = self.settings.quiet;
+        let mut progress = |progress: Progress| {
+            if !quiet {
+                let _ = output.message(&format!(
+                    "... {} written, {:.0} rows/s",
+                    plural(progress.rows),
+                    progress.rows_per_second()
+                ));
+            }
+            ControlFlow::Continue(())
+        };
+        let db = &mut self.db;
+        let result = create_temp_file(path).map_err(DbError::Io).and_then(|(temp_path, file)| {
+            let written = (|| {
+                let (file, bytes) = Counted::new(file);
+                let mut tracker = Tracker::new(Some(&mut progress)).with_bytes(bytes);
+                let mut file = BufWriter::new(file);
+                let count = export_rows(db, sql, &mut file, &mut tracker)?;
+                file.into_inner().map_err(|err| err.into_error())?.into_inner().sync_all()?;
+                std::fs::rename(&temp_path, path)?;
+                Ok(count)
+            })();
+            // The temporary file is this export's own, so no one else's
+            // file is taken with it.
+            if written.is_err() {
+                let _ = std::fs::remove_file(&temp_path);
+            }
+            written
+        });
+
+        match result {
+            Ok(count) => output.message(&format!("Exported {} to {}.", plural(count), path)),
+            Err(err) => {
+                let message = format!("Export to '{}' failed, nothing was written: {}", path, err);
+                report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &message, line)
+            }
+        }
+    }
+
+    /// Writes a script that recreates the database when fed back in: the
+    /// table's `create table`, then one insert per row in table order. Rows
+    /// are written as they are read.
+    fn dump(&mut self, line: &str, output: &mut dyn Output) -> io::Result<()> {
+        output.message(&format!("-- VoidDB dump, format version {}", FORMAT_VERSION))?;
+        output.message(&create_table_sql(None, &self.db.options()))?;
+
+        let rows = match self.db.query("select") {
+            Ok(rows) => rows,
+            Err(err) => return report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), line),
+        };
+        for row in rows {
+            match row {
+                Ok(row) => output.message(&format!(
+                    "insert {} {} {} {};",
+                    row.id,
+                    quote(row.username()),
+                    quote(row.email()),
+                    row.age
+                ))?,
+                Err(err) => return report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), line),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a statement and prints what it did. Returns how many rows it
+    /// inserted, if it is an insert.
+    ///
+    /// Results go out a block at a time, and the rest once the statement
+    /// is over, however it ends.
+    fn execute(&mut self, sql: &str, output: &mut dyn Output) -> io::Result<Option<usize>> {
+        let mut buffered = Buffered::new(output);
+        let inserted = self.print_statement(sql, &mut buffered)?;
+        buffered.finish()?;
+        Ok(inserted)
+    }
+
+    fn print_statement(&mut self, sql: &str, output: &mut dyn Output) -> io::Result<Option<usize>> {
+        let color = self.settings.color.enabled(output);
+        let mut styled;
+        let output: &mut dyn Output = match color {
+            true => {
+                styled = Styled::new(output);
+                &mut styled
+            }
+            false => output,
+        };
+
+        let mut printer = Printer::new(&self.settings, color);
+        if let Some(client) = self.remote.as_mut() {
+            match client.execute(sql) {
+                Ok(QueryResult::Rows(rows)) => {
+                    printer.begin(output)?;
+                    for row in &rows {
+                        printer.row(&row.values(), output)?;
+                    }
+                    printer.finish(output)?;
+                    printer.summary(output)?;
+                }
+                Ok(QueryResult::RowsAffected(count)) => {
+                    printer.affected(StatementType::Insert, count, output)?;
+                    return Ok(Some(count));
+                }
+                Ok(QueryResult::Done) => {}
+                Err(err) => report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), sql)?,
+            }
+            return Ok(None);
+        }
+        match self.db.query(sql) {
+            Ok(rows) => {
+                if let Some(count) = rows.rows_affected() {
+                    printer.affected(rows.statement_type(), count, output)?;
+                    return Ok(Some(count));
+                }
+
+                // One line of each page is left for the pager's prompt.
+                let page = output.page_height().filter(|_| self.settings.pager).map(|height| height.saturating_sub(1).max(1));
+
+                printer.begin(output)?;
+                for (shown, row) in rows.enumerate() {
+                    if let Some(page) = page {
+                        if shown > 0 && shown % page == 0 {
+                            printer.flush(output)?;
+                            // Stopping here drops the scan and its cursor.
+                            if !output.more()? {
+                                break;
+                            }
+                        }
+                    }
+                    match row {
+                        Ok(row) => printer.row(&row.values(), output)?,
+                        Err(err) => {
+                            printer.finish(output)?;
+                            report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), sql)?;
+                            return Ok(None);
+                        }
+                    }
+                }
+                printer.finish(output)?;
+                printer.summary(output)?;
+                Ok(None)
+            }
+            Err(err @ DbError::Prepare(PrepareError::UnrecognizedStatement)) => {
+                let message = format!("Unrecognized keyword at start of '{}'.", sql);
+                report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &message, sql)?;
+                Ok(None)
+            }
+            Err(err) => {
+                report(&self.settings, self.scripts.last(), &self.errors, output, err.code(), &err.to_string(), sql)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The time since this was called, each time the clock is read.
+fn uptime() -> impl FnMut() -> Duration + Send {
+    let start = Instant::now();
+    move || start.elapsed()
+}
+
+/// The line `.timer` prints after a statement that took `elapsed`, with the
+/// rate of an insert.
+fn run_time(elapsed: Duration, inserted: Option<usize>) -> String {
+    let seconds = elapsed.as_secs_f64();
+    match inserted {
+        Some(rows) if seconds > 0.0 => format!("Run Time: real {:.3}s ({:.0} rows/s)", seconds, rows as f64 / seconds),
+        _ => format!("Run Time: real {:.3}s", seconds),
+    }
+}
+
+/// Fills in the placeholders of a `.prompt` template: `%d` is the name of
+/// the database file, or `:memory:`, `%*` is `*` if there are changes not
+/// yet written out, and `%%` is `%`. Anything else is left as it is.
+pub fn render_prompt(template: &str, path: Option<&Path>, unsaved: bool) -> String {
+    let mut prompt = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('d') => match path.and_then(Path::file_name) {
+                Some(name) => prompt.push_str(&name.to_string_lossy()),
+                None => prompt.push_str(":memory:"),
+            },
+            Some('*') if unsaved => prompt.push('*'),
+            Some('*') => {}
+            Some('%') => prompt.push('%'),
+            Some(other) => {
+                prompt.push('%');
+                prompt.push(other);
+            }
+            None => prompt.push('%'),
+        }
+    }
+    prompt
+}
+
+/// Prints an error, as text or, with `.errors json` or in JSON mode, as
+/// `{"error":{"code":..,"message":..,"statement":..}}`. `code` is one of
+/// the strings listed on `DbError::code`, or one of the REPL's own:
+/// `USAGE`, `UNRECOGNIZED_COMMAND`, `IMPORT_ERROR`, `RECURSION_LIMIT`,
+/// `NOT_SUPPORTED` and, from a server, `SERVER_ERROR`.
+///
+/// Inside a `.read` script the error also says which file and line it came
+/// from. Every error is counted in `errors`.
+fn report(
+    settings: &Settings,
+    location: Option<&Location>,
+    errors: &Cell<Errors>,
+    output: &mut dyn Output,
+    code: &'static str,
+    message: &str,
+    statement: &str,
+) -> io::Result<()> {
+    errors.set(Errors { count: errors.get().count + 1, last: Some(code) });
+    if settings.errors != ErrorFormat::Json && settings.mode != Mode::Json {
+        return match location {
+            Some(location) => output.error(&format!("{}:{}: {}", location.path, location.line, message)),
+            None => output.error(message),
+        };
+    }
+
+    let mut fields = vec![("code", Value::Text(code)), ("message", Value::Text(message)), ("statement", Value::Text(statement))];
+    if let Some(location) = location {
+        fields.push(("file", Value::Text(&location.path)));
+        fields.push(("line", Value::Integer(location.line as u64)));
+    }
+    let mut line = Vec::new();
+    line.extend_from_slice(b"{\"error\":");
+    json::write_object(&mut line, fields)?;
+    line.extend_from_slice(b"}\n");
+    output.errors().write_all(&line)
+}
+
+/// Streams the rows of `sql` into `out` as a JSON array, returning how many
+/// were written.
+/// Creates a temporary file next to `path` that no one else has, named
+/// for this process and a count of the files it has made, and returns it
+/// with its path.
+fn create_temp_file(path: &str) -> io::Result<(String, File)> {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let temp_path = format!("{}.{}-{}.tmp", path, std::process::id(), CREATED.fetch_add(1, Ordering::Relaxed));
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            // Left behind by an earlier process with the same id.
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn export_rows(db: &mut Database, sql: &str, out: &mut dyn Write, tracker: &mut Tracker<'_>) -> Result<usize, DbError> {
+    let settings = Settings { mode: Mode::Json, ..Settings::default() };
+    let mut printer = Printer::new(&settings, false);
+    let mut sink = Captured::new();
+    let mut output = Redirect::new(out, &mut sink);
+
+    printer.begin(&mut output)?;
+    for row in db.query(sql)? {
+        printer.row(&row?.values(), &mut output)?;
+        if tracker.tick(printer.printed(), 0).is_break() {
+            break;
+        }
+    }
+    printer.finish(&mut output)?;
+    Ok(printer.printed())
+}
+
+/// A line of a script being run by `.read`.
+struct Location {
+    path: String,
+    line: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Options;
+    use crate::display::COLUMN_SAMPLE_ROWS;
+    use crate::row::{Row, RowLayout, COLUMNS};
+    use crate::testing::{Gen, TempPath};
+
+    fn run(repl: &mut Repl, lines: &[&str]) -> Captured {
+        let mut output = Captured::new();
+        for line in lines {
+            repl.run_line(line, &mut output).unwrap();
+        }
+        output
+    }
+
+    /// Just enough of a CSV reader to check what the writer produced.
+    fn parse_csv(text: &str) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut chars = text.chars().peekable();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') => quoted = true,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (false, c) => field.push(c),
+            }
+        }
+        records
+    }
+
+    #[test]
+    fn test_insert_and_select_output() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 1 alice alice@x.com 34", "insert 2 bob bob@x.com", "select"]);
+
+        assert_eq!(
+            output.results_str(),
+            "1 row inserted\n1 row inserted\n(1, alice, alice@x.com, 34)\n(2, bob, bob@x.com, 0)\n(2 rows)\n"
+        );
+        assert_eq!(output.errors_str(), "");
+    }
+
+    #[test]
+    fn test_errors_go_to_error_channel() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 1 alice", "update 1", "select"]);
+
+        assert_eq!(output.results_str(), "(0 rows)\n");
+        assert_eq!(
+            output.errors_str(),
+            "Syntax error. Could not parse statement.\nUnrecognized keyword at start of 'update 1'.\n"
+        );
+    }
+
+    #[test]
+    fn test_summaries() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[
+            "select",
+            "insert 1 alice a@x.com",
+            "insert 1 bob",
+            "select",
+            "insert 2 bob b@x.com",
+            "select",
+        ]);
+
+        assert_eq!(
+            output.results_str(),
+            "(0 rows)\n1 row inserted\n(1, alice, a@x.com, 0)\n(1 row)\n1 row inserted\n(1, alice, a@x.com, 0)\n(2, bob, b@x.com, 0)\n(2 rows)\n"
+        );
+        assert_eq!(output.errors_str(), "Syntax error. Could not parse statement.\n");
+    }
+
+    #[test]
+    fn test_quiet_leaves_out_summaries() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".quiet on", "insert 1 alice a@x.com", "select", ".quiet off", "select"]);
+
+        assert_eq!(output.results_str(), "(1, alice, a@x.com, 0)\n(1, alice, a@x.com, 0)\n(1 row)\n");
+    }
+
+    /// A terminal `height` lines tall that answers the pager with `answers`.
+    struct Terminal {
+        captured: Captured,
+        height: usize,
+        answers: Vec<bool>,
+    }
+
+    impl Output for Terminal {
+        fn results(&mut self) -> &mut dyn Write {
+            &mut self.captured.results
+        }
+
+        fn errors(&mut self) -> &mut dyn Write {
+            &mut self.captured.errors
+        }
+
+        fn is_terminal(&self) -> bool {
+            true
+        }
+
+        fn page_height(&self) -> Option<usize> {
+            Some(self.height)
+        }
+
+        fn more(&mut self) -> io::Result<bool> {
+            self.captured.results.extend_from_slice(b"-- More --\n");
+            Ok(self.answers.remove(0))
+        }
+    }
+
+    #[test]
+    fn test_pager() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        for id in 1..=5 {
+            repl.database().execute(&format!("insert {} u u@x", id)).unwrap();
+        }
+
+        let mut terminal = Terminal { captured: Captured::new(), height: 3, answers: vec![true, false] };
+        repl.run_statement("select", &mut terminal).unwrap();
+        assert_eq!(
+            terminal.captured.results_str(),
+            "(1, u, u@x, 0)\n(2, u, u@x, 0)\n-- More --\n(3, u, u@x, 0)\n(4, u, u@x, 0)\n-- More --\n(4 rows)\n"
+        );
+
+        // The scan that was cut short must not hold the table.
+        repl.database().execute("insert 6 u u@x").unwrap();
+
+        let mut terminal = Terminal { captured: Captured::new(), height: 3, answers: vec![] };
+        run(&mut repl, &[".pager off"]);
+        repl.run_statement("select", &mut terminal).unwrap();
+        assert!(!terminal.captured.results_str().contains("-- More --"));
+    }
+
+    #[test]
+    fn test_pager_never_engages_without_a_terminal() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        for id in 1..=50 {
+            repl.database().execute(&format!("insert {} u u@x", id)).unwrap();
+        }
+
+        let output = run(&mut repl, &["select"]);
+        assert!(output.results_str().ends_with("(50 rows)\n"));
+    }
+
+    #[test]
+    fn test_no_color_off_a_terminal() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        for choice in ["auto", "on", "off"] {
+            let output = run(&mut repl, &[
+                &format!(".color {}", choice),
+                ".mode column",
+                "insert 1 alice a@x.com",
+                "select",
+                "bogus",
+                ".bogus x",
+            ]);
+            assert!(!output.results_str().contains('\x1b'));
+            assert!(!output.errors_str().contains('\x1b'));
+            assert_eq!(repl.prompt(&output), "db > ");
+        }
+    }
+
+    #[cfg(feature = "color")]
+    #[test]
+    fn test_color_on_a_terminal() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        run(&mut repl, &[".color on", ".mode column", "insert 1 alice a@x.com"]);
+
+        let mut terminal = Terminal { captured: Captured::new(), height: 100, answers: vec![] };
+        repl.run_statement("select", &mut terminal).unwrap();
+        repl.run_statement("bogus", &mut terminal).unwrap();
+        assert!(terminal.captured.results_str().starts_with("\x1b[1mid  username  email    age\x1b[0m\n"));
+        assert_eq!(terminal.captured.errors_str(), "\x1b[31mUnrecognized keyword at start of 'bogus'.\x1b[0m\n");
+        assert_eq!(repl.prompt(&terminal), "\x1b[1mdb > \x1b[0m");
+
+        run(&mut repl, &[".color off"]);
+        assert_eq!(repl.prompt(&terminal), "db > ");
+    }
+
+    #[test]
+    fn test_echo() {
+        let script = ["insert 1 alice a@x.com", "insert 2 bob", ".bogus", "select"];
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &script);
+        assert_eq!(output.results_str(), "1 row inserted\n(1, alice, a@x.com, 0)\n(1 row)\n");
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut lines = vec![".echo on"];
+        lines.extend(script);
+        lines.push(".echo off");
+        lines.push("select");
+        let output = run(&mut repl, &lines);
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "db> insert 1 alice a@x.com\n",
+                "1 row inserted\n",
+                "db> insert 2 bob\n",
+                "db> .bogus\n",
+                "db> select\n",
+                "(1, alice, a@x.com, 0)\n",
+                "(1 row)\n",
+                "db> .echo off\n",
+                "(1, alice, a@x.com, 0)\n",
+                "(1 row)\n",
+            )
+        );
+        assert_eq!(output.errors_str(), "Syntax error. Could not parse statement.\nUnrecognized command '.bogus'. Use .help to list commands.\n");
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let long_name = "x".repeat(33);
+        let output = run(&mut repl, &[
+            ".errors json",
+            "insert 1 alice",
+            &format!("insert 1 {} a@x", long_name),
+            "update 1",
+            ".output /nonexistent-dir/out.txt",
+            ".bogus",
+            ".mode nope",
+            "insert 1 alice a@x",
+        ]);
+
+        let expected = [
+            r#"{"error":{"code":"SYNTAX_ERROR","message":"Syntax error. Could not parse statement.","statement":"insert 1 alice"}}"#.to_string(),
+            format!(
+                r#"{{"error":{{"code":"VALUE_TOO_LONG","message":"Error: username is 33 bytes, at most 32 allowed.","statement":"insert 1 {} a@x"}}}}"#,
+                long_name
+            ),
+            r#"{"error":{"code":"UNRECOGNIZED_STATEMENT","message":"Unrecognized keyword at start of 'update 1'.","statement":"update 1"}}"#.to_string(),
+        ];
+        let lines: Vec<&str> = output.errors_str().lines().collect();
+        assert_eq!(lines[..3], expected);
+        assert!(lines[3].starts_with(r#"{"error":{"code":"IO_ERROR","message":"Cannot open '/nonexistent-dir/out.txt': "#));
+        assert!(lines[4].starts_with(r#"{"error":{"code":"UNRECOGNIZED_COMMAND","#));
+        assert!(lines[5].starts_with(r#"{"error":{"code":"USAGE","#));
+        assert_eq!(lines.len(), 6);
+        assert_eq!(output.results_str(), "1 row inserted\n");
+
+        // JSON mode implies JSON errors.
+        let output = run(&mut repl, &[".errors text", ".mode json", "bogus"]);
+        assert!(output.errors_str().starts_with(r#"{"error":{"code":"UNRECOGNIZED_STATEMENT","#));
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut gen = Gen::new(134);
+        let layout = RowLayout::DEFAULT;
+        for separator in [",", "'\\t'", "|", ";"] {
+            let mut repl = Repl::new(Database::open_in_memory());
+            let mut rows = Vec::new();
+            for id in 0..200 {
+                let row = Row::new(id, &gen.text(layout.username_size()), &gen.text(layout.email_size()))
+                    .with_age(gen.below(u16::MAX as u64 + 1) as u16);
+                repl.database().insert_row(&row).unwrap();
+                rows.push(row);
+            }
+
+            let output = run(&mut repl, &[".mode csv", ".headers on", ".quiet on", &format!(".separator {}", separator), "select"]);
+            assert_eq!(output.errors_str(), "");
+
+            let mut copy = Database::open_in_memory();
+            let mut records = csv::Reader::new(output.results.as_slice(), repl.settings().separator);
+            assert_eq!(records.next().unwrap().unwrap().fields, COLUMNS);
+            for record in records {
+                let fields = record.unwrap().fields;
+                let [id, username, email, age] = &fields[..] else { panic!("bad record {:?}", fields) };
+                let row = Row::new(id.parse().unwrap(), username, email).with_age(age.parse().unwrap());
+                copy.insert_row(&row).unwrap();
+            }
+
+            let copied: Vec<Row> = copy.query("select").unwrap().map(Result::unwrap).collect();
+            assert_eq!(copied, rows, "separator {}", separator);
+        }
+    }
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/users-v7.db");
+
+    /// Everything a select prints for `repl`'s database.
+    fn select_all(repl: &mut Repl) -> String {
+        run(repl, &["select"]).results_str().to_string()
+    }
+
+    #[test]
+    fn test_dump() {
+        let path = TempPath::new("dump.db");
+        std::fs::copy(FIXTURE, &path).unwrap();
+        let mut repl = Repl::new(Database::open(&path).unwrap());
+
+        let dump = run(&mut repl, &[".dump"]);
+        assert_eq!(
+            dump.results_str(),
+            concat!(
+                "-- VoidDB dump, format version 7\n",
+                "create table users (id integer primary key, username varchar(32), email varchar(255), age integer);\n",
+                "insert 1 'alice' 'alice@example.com' 34;\n",
+                "insert 2 'bob' 'bob@example.com' 0;\n",
+                "insert 4294967296 'carol' 'carol@example.com' 65535;\n",
+            )
+        );
+
+        let mut copy = Repl::new(Database::open_in_memory());
+        let replay = run(&mut copy, &dump.results_str().lines().collect::<Vec<_>>());
+        assert_eq!(replay.errors_str(), "");
+        assert_eq!(select_all(&mut copy), select_all(&mut repl));
+
+        // The table shape comes first, so widths and checksums replay too.
+        let mut repl = Repl::new(Database::open_in_memory());
+        let long_name = "u".repeat(52);
+        let output = run(&mut repl, &[
+            "create table users (id integer primary key, username varchar(64), email varchar(40), age integer) with checksums;",
+            &format!("insert 1 {} a@x.com 7", long_name),
+        ]);
+        assert_eq!(output.errors_str(), "");
+
+        let dump = run(&mut repl, &[".dump"]);
+        assert_eq!(
+            dump.results_str(),
+            format!(
+                "-- VoidDB dump, format version {}\n{}\ninsert 1 '{}' 'a@x.com' 7;\n",
+                FORMAT_VERSION,
+                "create table users (id integer primary key, username varchar(64), email varchar(40), age integer) with checksums;",
+                long_name
+            )
+        );
+
+        let mut copy = Repl::new(Database::open_in_memory());
+        let replay = run(&mut copy, &dump.results_str().lines().collect::<Vec<_>>());
+        assert_eq!(replay.errors_str(), "");
+        let options = copy.database().options();
+        assert_eq!((options.username_size, options.email_size, options.row_checksums), (64, 40, true));
+        assert_eq!(select_all(&mut copy), select_all(&mut repl));
+    }
+
+    #[test]
+    fn test_dump_replays_awkward_values() {
+        let mut gen = Gen::new(135);
+        let mut repl = Repl::new(Database::open_in_memory());
+        for id in 0..300 {
+            repl.database().insert_row(&Row::new(id, &gen.text(32), &gen.text(255))).unwrap();
+        }
+
+        let dump = run(&mut repl, &[".dump"]);
+        let mut copy = Repl::new(Database::open_in_memory());
+        let replay = run(&mut copy, &dump.results_str().lines().collect::<Vec<_>>());
+        assert_eq!(replay.errors_str(), "");
+
+        let rows = |repl: &mut Repl| repl.database().query("select").unwrap().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(rows(&mut copy), rows(&mut repl));
+    }
+
+    #[test]
+    fn test_import() {
+        let path = TempPath::new("import.tsv");
+        std::fs::write(&path, "id\tusername\temail\n1\talice\ta@x.com\nbad\tbob\tb@x.com\n3\tcarol\tc@x.com\n").unwrap();
+        let path = path.display().to_string();
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".separator \\t", &format!(".import {} --skip-header", path), "select"]);
+        assert_eq!(
+            output.results_str(),
+            "Imported 2 rows: 2 inserted, 0 replaced, 0 skipped.\n(1, alice, a@x.com, 0)\n(3, carol, c@x.com, 0)\n(2 rows)\n"
+        );
+        assert_eq!(output.errors_str(), format!("{}:3: bad id 'bad'\n", path));
+
+        let output = run(&mut repl, &[&format!(".import {} --strict", path), ".import", ".import /nonexistent.csv"]);
+        assert_eq!(output.results_str(), "Imported 0 rows: 0 inserted, 0 replaced, 0 skipped.\n");
+        let errors: Vec<&str> = output.errors_str().lines().collect();
+        assert_eq!(errors[0], format!("{}:1: bad id 'id'", path));
+        assert_eq!(
+            errors[1],
+            "Usage: .import [csv|jsonl] FILE [--skip-header] [--strict] [--on-conflict fail|abort|skip|replace]"
+        );
+        assert!(errors[2].starts_with("Cannot open '/nonexistent.csv': "));
+    }
+
+    #[test]
+    fn test_import_jsonl() {
+        let path = TempPath::new("import.ndjson");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"id": 1, "username": "alice", "email": "a@x.com", "age": 34}"#,
+                "\n",
+                r#"{"id": 2, "username": "bob"}"#,
+                "\n",
+                r#"{"id": 3, "username": "carol", "email": "c@x.com"}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+        let path = path.display().to_string();
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 3 old old@x.com", &format!(".import jsonl {}", path)]);
+        assert_eq!(output.results_str(), "1 row inserted\nImported 1 row: 1 inserted, 0 replaced, 0 skipped.\n");
+        assert_eq!(
+            output.errors_str(),
+            format!("{}:2: missing field 'email'\n{}:3: duplicate id 3\n", path, path)
+        );
+
+        let output = run(&mut repl, &[&format!(".import jsonl {} --on-conflict abort", path), "select"]);
+        assert_eq!(output.results_str(), "(3, old, old@x.com, 0)\n(1, alice, a@x.com, 34)\n(2 rows)\n");
+        assert_eq!(
+            output.errors_str(),
+            format!(
+                "{}:1: duplicate id 1\nImport aborted on a duplicate id, nothing was imported.\n",
+                path
+            )
+        );
+
+        let output = run(&mut repl, &[&format!(".import jsonl {} --on-conflict skip", path), ".import x --on-conflict"]);
+        assert_eq!(output.results_str(), "Imported 0 rows: 0 inserted, 0 replaced, 2 skipped.\n");
+        assert!(output.errors_str().ends_with("Usage: .import [csv|jsonl] FILE [--skip-header] [--strict] [--on-conflict fail|abort|skip|replace]\n"));
+
+        let output = run(&mut repl, &[&format!(".import jsonl {} --on-conflict replace", path), "select"]);
+        assert_eq!(
+            output.results_str(),
+            "Imported 2 rows: 0 inserted, 2 replaced, 0 skipped.\n(3, carol, c@x.com, 0)\n(1, alice, a@x.com, 34)\n(2 rows)\n"
+        );
+    }
+
+    #[test]
+    fn test_clone() {
+        let path = TempPath::new("clone-repl.db");
+        let path = path.display().to_string();
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 1 alice a@x.com", "insert 2 bob b@x.com", &format!(".clone {}", path)]);
+        assert_eq!(output.results_str(), format!("1 row inserted\n1 row inserted\nCloned 2 rows to {} (2 pages).\n", path));
+
+        let output = run(&mut repl, &[&format!(".clone {}", path), ".clone"]);
+        let errors: Vec<&str> = output.errors_str().lines().collect();
+        assert!(errors[0].starts_with(&format!("Cannot clone to '{}': I/O error: ", path)));
+        assert_eq!(errors[1], "Usage: .clone FILE");
+
+        let mut copy = Repl::new(Database::open(&path).unwrap());
+        let output = run(&mut copy, &["select"]);
+        assert_eq!(output.results_str(), "(1, alice, a@x.com, 0)\n(2, bob, b@x.com, 0)\n(2 rows)\n");
+    }
+
+    #[test]
+    fn test_import_and_export_report_progress() {
+        let dir = TempPath::new("progress");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (csv_path, json_path) = (dir.join("in.csv"), dir.join("out.json"));
+        let input: String = (0..1200).map(|id| format!("{},user{},user{}@x\n", id, id, id)).collect();
+        std::fs::write(&csv_path, input).unwrap();
+        let (csv_path, json_path) = (csv_path.display().to_string(), json_path.display().to_string());
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[&format!(".import {}", csv_path), &format!(".export json {}", json_path)]);
+        let lines: Vec<&str> = output.results_str().lines().collect();
+        assert!(lines.iter().any(|line| line.starts_with("... 1000 rows read, 0 errors, ")), "{:?}", lines);
+        assert!(lines.iter().any(|line| line.starts_with("... 1000 rows written, ")), "{:?}", lines);
+        assert!(lines.contains(&"Imported 1200 rows: 1200 inserted, 0 replaced, 0 skipped."));
+        assert_eq!(lines.last(), Some(&format!("Exported 1200 rows to {}.", json_path).as_str()));
+
+        // Quiet drops the progress lines along with the summaries.
+        let output = run(&mut repl, &[".quiet on", &format!(".import {} --on-conflict skip", csv_path)]);
+        assert_eq!(output.results_str(), "");
+    }
+
+    #[test]
+    fn test_seed() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".seed 2 --rng 42", ".seed 1 --random --rng 42", "select"]);
+        assert_eq!(
+            output.results_str(),
+            "Seeded 2 rows (--rng 42).\nSeeded 1 row (--rng 42).\n\
+             (1, peggy2291, peggy2291@example.net, 64)\n\
+             (2, mallory9062, mallory9062@example.org, 8)\n\
+             (701532786141963250, peggy2291, peggy2291@example.net, 64)\n\
+             (3 rows)\n"
+        );
+
+        let output = run(&mut repl, &[".seed 1200 --rng 1"]);
+        assert_eq!(output.results_str(), "Seeded 1000 of 1200 rows...\nSeeded 1200 rows (--rng 1).\n");
+
+        let output = run(&mut repl, &[".seed", ".seed x", ".seed 1 --rng", ".seed 100000"]);
+        let errors: Vec<&str> = output.errors_str().lines().collect();
+        assert_eq!(errors[..3], ["Usage: .seed COUNT [--random] [--rng SEED]"; 3]);
+        assert!(errors[3].starts_with("Error: Table full. Seeded "), "{}", errors[3]);
+        assert!(errors[3].ends_with(" of 100000 rows."));
+        assert!(output.results_str().is_empty());
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = TempPath::new("read");
+        std::fs::create_dir_all(&dir).unwrap();
+        let setup = dir.join("setup.sql");
+        let nested = dir.join("nested.sql");
+        let looping = dir.join("loop.sql");
+        std::fs::write(&setup, "-- users\ninsert 1 alice a@x.com\n\ninsert 2 bob\ninsert 3 carol c@x.com\n").unwrap();
+        std::fs::write(&nested, format!("insert 4 dave d@x.com\n.read {}\n", setup.display())).unwrap();
+        std::fs::write(&looping, format!(".read {}\n", looping.display())).unwrap();
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".quiet on", &format!(".read {}", nested.display()), "select"]);
+        assert_eq!(
+            output.results_str(),
+            "(4, dave, d@x.com, 0)\n(1, alice, a@x.com, 0)\n(3, carol, c@x.com, 0)\n"
+        );
+        assert_eq!(output.errors_str(), format!("{}:4: Syntax error. Could not parse statement.\n", setup.display()));
+
+        let output = run(&mut repl, &[&format!(".read {}", looping.display())]);
+        assert_eq!(
+            output.errors_str(),
+            format!("{}:1: Cannot read '{}': scripts nested more than 16 deep\n", looping.display(), looping.display())
+        );
+
+        // Errors after the script finishes are no longer attributed to it.
+        let output = run(&mut repl, &["bogus"]);
+        assert_eq!(output.errors_str(), "Unrecognized keyword at start of 'bogus'.\n");
+
+        // With `.bail on` the first error ends every script being read.
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".quiet on", ".bail on", &format!(".read {}", nested.display()), "select"]);
+        assert_eq!(output.results_str(), "(4, dave, d@x.com, 0)\n(1, alice, a@x.com, 0)\n");
+        assert_eq!(output.errors_str(), format!("{}:4: Syntax error. Could not parse statement.\n", setup.display()));
+    }
+
+    #[test]
+    fn test_read_splits_statements_and_strips_comments() {
+        let script = TempPath::new("read-split.sql");
+        std::fs::write(
+            &script,
+            "insert 1 alice a@x.com; insert 2 'bob -- b' b@x.com -- two rows\nselect -- all rows\nselect where id = 2; bogus\n",
+        )
+        .unwrap();
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".quiet on", &format!(".read {}", script.display())]);
+        assert_eq!(
+            output.results_str(),
+            "(1, alice, a@x.com, 0)\n(2, bob -- b, b@x.com, 0)\n(2, bob -- b, b@x.com, 0)\n"
+        );
+        assert_eq!(output.errors_str(), format!("{}:3: Unrecognized keyword at start of 'bogus'.\n", script.display()));
+
+        // Typed or piped lines are split the same way.
+        let output = run(&mut repl, &["select where id = 1; select where id = 2 -- both"]);
+        assert_eq!(output.results_str(), "(1, alice, a@x.com, 0)\n(2, bob -- b, b@x.com, 0)\n");
+        assert_eq!(output.errors_str(), "");
+    }
+
+    #[test]
+    fn test_export_json() {
+        let path = TempPath::new("export.json");
+        let path = path.display().to_string();
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut gen = Gen::new(138);
+        for id in 0..50 {
+            repl.database().insert_row(&Row::new(id, &gen.text(32), &gen.text(255))).unwrap();
+        }
+
+        let output = run(&mut repl, &[&format!(".export json {}", path)]);
+        assert_eq!(output.results_str(), format!("Exported 50 rows to {}.\n", path));
+
+        let mut expected = b"[".to_vec();
+        for (i, row) in repl.database().query("select").unwrap().enumerate() {
+            if i > 0 {
+                expected.extend_from_slice(b",\n");
+            }
+            json::write_object(&mut expected, COLUMNS.into_iter().zip(row.unwrap().values())).unwrap();
+        }
+        expected.extend_from_slice(b"]\n");
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        assert!(temp_files(&path).is_empty());
+    }
+
+    #[test]
+    fn test_a_failed_export_removes_only_its_own_file() {
+        // A directory in the way makes the rename at the end fail.
+        let path = TempPath::new("export-dir.json");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("keep"), "").unwrap();
+        let theirs = TempPath::new("export-dir.json.tmp");
+        std::fs::write(&theirs, "someone else's").unwrap();
+
+        let path = path.display().to_string();
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[&format!(".export json {}", path)]);
+        assert!(output.errors_str().starts_with(&format!("Export to '{}' failed, nothing was written: ", path)));
+        assert_eq!(std::fs::read_to_string(&theirs).unwrap(), "someone else's");
+        assert!(temp_files(&path).is_empty());
+    }
+
+    /// The temporary files `.export` made for `path` that are still there.
+    fn temp_files(path: &str) -> Vec<std::path::PathBuf> {
+        let path = Path::new(path);
+        let prefix = format!("{}.{}-", path.file_name().unwrap().to_str().unwrap(), std::process::id());
+        std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|entry| entry.file_name().unwrap().to_str().unwrap().starts_with(&prefix))
+            .collect()
+    }
+
+    #[test]
+    fn test_export_errors() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[
+            ".export json /nonexistent-dir/out.json",
+            ".export json out.json insert 1 a a@x",
+            ".export json out.json bogus",
+            ".export csv out.csv",
+            "select",
+        ]);
+
+        let errors: Vec<&str> = output.errors_str().lines().collect();
+        assert!(errors[0].starts_with("Export to '/nonexistent-dir/out.json' failed, nothing was written: I/O error: "));
+        assert_eq!(errors[1..], ["Only a select can be exported.", "Unrecognized statement", "Usage: .export json FILE [select]"]);
+        assert_eq!(output.results_str(), "(0 rows)\n");
+        assert!(!std::path::Path::new("out.json").exists());
+    }
+
+    #[test]
+    fn test_csv_mode() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 1 alice a@x.com 34", ".mode csv", ".headers on", "select"]);
+
+        assert_eq!(output.results_str(), "1 row inserted\nid,username,email,age\n1,alice,a@x.com,34\n(1 row)\n");
+
+        let output = run(&mut repl, &[".headers off", ".mode list", "select"]);
+        assert_eq!(output.results_str(), "(1, alice, a@x.com, 34)\n(1 row)\n");
+    }
+
+    #[test]
+    fn test_json_mode() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".mode json", "select", "insert 1 alice a@x.com 34", "insert 2 bob b@x.com", "select"]);
+
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "[]\n",
+                "{\"rows_affected\":1}\n",
+                "{\"rows_affected\":1}\n",
+                "[{\"id\":1,\"username\":\"alice\",\"email\":\"a@x.com\",\"age\":34},\n",
+                "{\"id\":2,\"username\":\"bob\",\"email\":\"b@x.com\",\"age\":0}]\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_mode_escapes_values() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        repl.database().insert_row(&Row::new(1, "say \"hi\"", "back\\slash\nnew\u{1}line")).unwrap();
+
+        let output = run(&mut repl, &[".mode json", "select"]);
+        assert_eq!(
+            output.results_str(),
+            "[{\"id\":1,\"username\":\"say \\\"hi\\\"\",\"email\":\"back\\\\slash\\nnew\\u0001line\",\"age\":0}]\n"
+        );
+    }
+
+    #[test]
+    fn test_column_mode() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[
+            ".mode column",
+            "select",
+            "insert 1 alice alice@example.com 34",
+            "insert 22 bob bob@x.com",
+            "insert 333 日本語 jp@example.jp 7",
+            "select",
+        ]);
+
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "id  username  email  age\n",
+                "--  --------  -----  ---\n",
+                "(0 rows)\n",
+                "1 row inserted\n",
+                "1 row inserted\n",
+                "1 row inserted\n",
+                "id   username  email              age\n",
+                "---  --------  -----------------  ---\n",
+                "1    alice     alice@example.com  34\n",
+                "22   bob       bob@x.com          0\n",
+                "333  日本語    jp@example.jp      7\n",
+                "(3 rows)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_column_mode_truncates_rows_after_the_sample() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        for id in 0..COLUMN_SAMPLE_ROWS {
+            repl.database().execute(&format!("insert {} u u@x", id)).unwrap();
+        }
+        repl.database().execute("insert 100 a_much_longer_name u@x").unwrap();
+
+        let output = run(&mut repl, &[".mode column", "select"]);
+        let lines: Vec<&str> = output.results_str().lines().collect();
+        assert_eq!(lines[0], "id  username  email  age");
+        assert_eq!(lines[lines.len() - 2], "1…  a_much_…  u@x    0");
+    }
+
+    #[test]
+    fn test_output_redirects_results_to_a_file() {
+        let path = TempPath::new("output.csv");
+
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[
+            "insert 1 alice a@x.com 34",
+            ".mode csv",
+            &format!(".output {}", path.display()),
+            "select",
+            "bogus",
+            ".output stdout",
+            "select",
+        ]);
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1,alice,a@x.com,34\n(1 row)\n");
+        assert_eq!(output.results_str(), "1 row inserted\n1,alice,a@x.com,34\n(1 row)\n");
+        assert_eq!(output.errors_str(), "Unrecognized keyword at start of 'bogus'.\n");
+    }
+
+    #[test]
+    fn test_output_reports_unwritable_paths() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".output /nonexistent-dir/results.txt", "insert 1 a a@x"]);
+
+        assert!(output.errors_str().starts_with("Cannot open '/nonexistent-dir/results.txt': "));
+        assert_eq!(output.results_str(), "1 row inserted\n");
+    }
+
+    #[test]
+    fn test_column_widths() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[
+            "insert 1 alice alice@example.com 34",
+            "insert 22 ünïcödé_ñámé_日本語 jp@example.jp 7",
+            ".mode column",
+            ".width -4 12 0 -3",
+            "select",
+            ".width 1",
+            "select",
+        ]);
+
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "1 row inserted\n",
+                "1 row inserted\n",
+                "  id  username      email              age\n",
+                "----  ------------  -----------------  ---\n",
+                "   1  alice         alice@example.com   34\n",
+                "  22  ünïcödé_ñám…  jp@example.jp        7\n",
+                "(2 rows)\n",
+                "…  username             email              age\n",
+                "-  -------------------  -----------------  ---\n",
+                "1  alice                alice@example.com  34\n",
+                "…  ünïcödé_ñámé_日本語  jp@example.jp      7\n",
+                "(2 rows)\n",
+            )
+        );
+
+        let output = run(&mut repl, &[".width 6 x"]);
+        assert_eq!(output.errors_str(), "Usage: .width [NUM ...]\n");
+        assert_eq!(repl.settings().widths, vec![1]);
+    }
+
+    #[test]
+    fn test_show_settings() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".show", ".mode csv", ".headers on", ".nullvalue NULL", ".width 6 -20", ".show"]);
+
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "     mode: list\n",
+                "     echo: off\n",
+                "     bail: off\n",
+                "   errors: text\n",
+                "  headers: off\n",
+                "    quiet: off\n",
+                "    pager: on\n",
+                "    color: auto\n",
+                "  history: on\n",
+                "    timer: off\n",
+                "   prompt: \"db > \" \"...> \"\n",
+                "nullvalue: \"\"\n",
+                "   output: stdout\n",
+                "separator: ','\n",
+                "    width:\n",
+                "     mode: csv\n",
+                "     echo: off\n",
+                "     bail: off\n",
+                "   errors: text\n",
+                "  headers: on\n",
+                "    quiet: off\n",
+                "    pager: on\n",
+                "    color: auto\n",
+                "  history: on\n",
+                "    timer: off\n",
+                "   prompt: \"db > \" \"...> \"\n",
+                "nullvalue: \"NULL\"\n",
+                "   output: stdout\n",
+                "separator: ','\n",
+                "    width: 6 -20\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_exit_returns_to_the_caller() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut output = Captured::new();
+        assert_eq!(repl.run_meta_command(".exit two", &mut output).unwrap(), MetaCommandResult::Success);
+        assert_eq!(output.errors_str(), "Usage: .exit [CODE]\n");
+        assert_eq!(repl.exit_code(), None);
+        assert_eq!(repl.run_meta_command(".exit 2", &mut output).unwrap(), MetaCommandResult::Exit(2));
+        assert_eq!(repl.exit_code(), Some(2));
+
+        // Nothing runs after it, even later in the same script.
+        let script = TempPath::new("exit.sql");
+        std::fs::write(&script, "insert 1 a a@x\n.exit\ninsert 2 b b@x\n").unwrap();
+        let mut repl = Repl::new(Database::open_in_memory());
+        run(&mut repl, &[&format!(".read {}", script.display()), "insert 3 c c@x"]);
+        assert_eq!(repl.exit_code(), Some(0));
+        let mut output = Captured::new();
+        repl.run_statement("select", &mut output).unwrap();
+        assert_eq!(output.results_str(), "(1, a, a@x, 0)\n(1 row)\n");
+    }
+
+    #[test]
+    fn test_attach() {
+        let path = TempPath::new("attach-repl.db");
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(
+            &mut repl,
+            &[
+                &format!(".attach {} as archive", path.display()),
+                "insert into archive.users 1 alice a@x",
+                "insert into users select from archive.users",
+                ".databases",
+                ".detach archive",
+                "select from archive.users",
+                ".attach x.db archive",
+                "select",
+            ],
+        );
+        assert_eq!(
+            output.results_str(),
+            format!(
+                "1 row inserted\n1 row inserted\nmain: :memory:\narchive: {}\n(1, alice, a@x, 0)\n(1 row)\n",
+                path.canonicalize().unwrap().display()
+            )
+        );
+        assert_eq!(output.errors_str(), "Error: No such database 'archive'.\nUsage: .attach FILE as NAME\n");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let path = TempPath::new("timeout-repl.db");
+        let holder = Database::open(&path).unwrap();
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".timeout", ".timeout 20", ".timeout", &format!(".attach {} as busy", path.display()), ".timeout soon"]);
+        assert_eq!(output.results_str(), "0\n20\n");
+        assert_eq!(output.errors_str(), "Error: Database is locked.\nUsage: .timeout [MS]\n");
+
+        drop(holder);
+        let output = run(&mut repl, &[&format!(".attach {} as busy", path.display()), ".databases"]);
+        assert_eq!(output.errors_str(), "");
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut ticks = [0, 12, 100, 350, 400, 400].into_iter();
+        repl.set_clock(move || Duration::from_millis(ticks.next().unwrap()));
+        let output = run(&mut repl, &["insert 1 a a@x", ".timer on", "insert 2 b b@x", ".show", "select", "bogus", ".timer off", "select"]);
+        let results = output.results_str();
+        assert!(results.starts_with("1 row inserted\n1 row inserted\nRun Time: real 0.012s (83 rows/s)\n"), "{}", results);
+        assert!(results.contains("    timer: on\n"));
+        assert!(results.ends_with(concat!(
+            "(1, a, a@x, 0)\n(2, b, b@x, 0)\n(2 rows)\nRun Time: real 0.250s\n",
+            "Run Time: real 0.000s\n",
+            "(1, a, a@x, 0)\n(2, b, b@x, 0)\n(2 rows)\n",
+        )), "{}", results);
+        assert_eq!(output.errors_str(), "Unrecognized keyword at start of 'bogus'.\n");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &["insert 1 a a@x", "insert 2 b b@x", "select", ".stats", ".stats reset", ".stats", ".stats all"]);
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "1 row inserted\n1 row inserted\n(1, a, a@x, 0)\n(2, b, b@x, 0)\n(2 rows)\n",
+                "      path: :memory:\n",
+                " file size: 0 bytes\n",
+                "     pages: 2 (2 used, 0 free)\n",
+                "     table: 2 rows in users\n",
+                "     cache: 2 pages, 4 hits, 2 misses\n",
+                "      read: 0 bytes\n",
+                "   written: 0 bytes\n",
+                "statements: 3\n",
+                "      path: :memory:\n",
+                " file size: 0 bytes\n",
+                "     pages: 2 (2 used, 0 free)\n",
+                "     table: 2 rows in users\n",
+                "     cache: 2 pages, 0 hits, 0 misses\n",
+                "      read: 0 bytes\n",
+                "   written: 0 bytes\n",
+                "statements: 0\n",
+            )
+        );
+        assert_eq!(output.errors_str(), "Usage: .stats [reset]\n");
+    }
+
+    #[test]
+    fn test_register_meta_command() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        repl.register_meta_command(".copy-row", "ID NEW_ID", "Insert a copy of a row under another id", |db, args, output| {
+            let [id, new_id] = args else {
+                return Ok(MetaCommandResult::UnrecognizedCommand);
+            };
+            let Some(row) = db.query("select").map_err(io::Error::other)?.find_map(|row| row.ok().filter(|row| row.id.to_string() == *id)) else {
+                return Err(io::Error::other(format!("no row {}", id)));
+            };
+            let sql = format!("insert {} {} {} {}", new_id, row.username(), row.email(), row.age);
+            db.execute(&sql).map_err(io::Error::other)?;
+            output.message(&format!("Copied {} to {}.", id, new_id))?;
+            Ok(MetaCommandResult::Success)
+        })
+        .unwrap();
+        repl.register_meta_command("quit-with", "CODE", "Exit with CODE", |_, args, _| match args {
+            [code] => Ok(code.parse().map_or(MetaCommandResult::UnrecognizedCommand, MetaCommandResult::Exit)),
+            _ => Ok(MetaCommandResult::UnrecognizedCommand),
+        })
+        .unwrap();
+        assert_eq!(repl.register_meta_command("help", "", "", |_, _, _| Ok(MetaCommandResult::Success)), Err(RegisterError::NameTaken(".help".to_string())));
+
+        let output = run(&mut repl, &["insert 1 alice alice@x 30", ".copy-row 1 2", ".copy-row 1", ".copy-row 9 3", ".copy-rwo 1 2", "select"]);
+        assert_eq!(output.results_str(), "1 row inserted\nCopied 1 to 2.\n(1, alice, alice@x, 30)\n(2, alice, alice@x, 30)\n(2 rows)\n");
+        assert_eq!(
+            output.errors_str(),
+            concat!(
+                "Usage: .copy-row ID NEW_ID\n",
+                "Error: no row 9\n",
+                "Unrecognized command '.copy-rwo'. Did you mean .copy-row? Use .help to list commands.\n",
+            )
+        );
+        assert_eq!((repl.errors(), repl.last_error()), (3, Some("UNRECOGNIZED_COMMAND")));
+
+        let help = run(&mut repl, &[".help", ".help copy-row"]).results_str().to_string();
+        assert!(help.contains(".copy-row ID NEW_ID           Insert a copy of a row under another id\n.databases"), "{}", help);
+        assert!(help.ends_with("Usage: .copy-row ID NEW_ID\n\nInsert a copy of a row under another id.\n"), "{}", help);
+
+        let mut output = Captured::new();
+        assert_eq!(repl.run_meta_command(".quit-with 4", &mut output).unwrap(), MetaCommandResult::Exit(4));
+        assert_eq!(repl.exit_code(), Some(4));
+    }
+
+    #[test]
+    fn test_run_input() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut input = InputBuffer::from_reader(io::Cursor::new("insert 1 a a@x\nselect\n.prompt 'vdb> '\nbogus\n.exit 3\nselect\n"));
+        let mut output = Captured::new();
+        repl.run_input(&mut input, &mut output).unwrap();
+        assert_eq!(output.results_str(), "db > 1 row inserted\ndb > (1, a, a@x, 0)\n(1 row)\ndb > vdb> vdb> ");
+        assert_eq!(output.errors_str(), "Unrecognized keyword at start of 'bogus'.\n");
+        assert_eq!(repl.exit_code(), Some(3));
+        // `.exit` leaves the rest unread.
+        assert_eq!(input.read_input().unwrap(), Some("select"));
+
+        // Running out of input ends the loop too.
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut input = InputBuffer::from_reader(io::Cursor::new("insert 1 a a@x"));
+        let mut output = Captured::new();
+        repl.run_input(&mut input, &mut output).unwrap();
+        assert_eq!(output.results_str(), "db > 1 row inserted\ndb > ");
+        assert!(input.is_eof());
+        assert_eq!(repl.exit_code(), None);
+    }
+
+    /// Input that is interrupted once, as by Ctrl-C at the prompt, after
+    /// `before` and before `after`.
+    fn interrupted_input(before: &'static str, after: &'static str) -> InputBuffer {
+        struct Interrupt(bool);
+        impl io::Read for Interrupt {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+        }
+        impl io::BufRead for Interrupt {
+            fn fill_buf(&mut self) -> io::Result<&[u8]> {
+                match std::mem::replace(&mut self.0, true) {
+                    false => Err(io::ErrorKind::Interrupted.into()),
+                    true => Ok(&[]),
+                }
+            }
+            fn consume(&mut self, _: usize) {}
+        }
+        let interrupted = io::Read::chain(io::Cursor::new(before), Interrupt(false));
+        InputBuffer::from_reader(io::Read::chain(interrupted, io::Cursor::new(after)))
+    }
+
+    #[test]
+    fn test_ctrl_c_at_the_prompt_drops_the_pending_statement() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let mut input = interrupted_input("insert 1 'half\n", "insert 2 b b@x\nselect\n");
+        let mut output = Captured::new();
+        repl.run_input(&mut input, &mut output).unwrap();
+        assert_eq!(output.results_str(), "db > ...> \ndb > 1 row inserted\ndb > (2, b, b@x, 0)\n(1 row)\ndb > ");
+        assert_eq!(output.errors_str(), "");
+    }
+
+    #[test]
+    fn test_quoted_values_continue_onto_later_lines() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let script = "insert 1 'two\n.lines -- kept' a@x; select\ninsert 2 'never closed\n";
+        let mut input = InputBuffer::from_reader(io::Cursor::new(script));
+        let mut output = Captured::new();
+        repl.run_input(&mut input, &mut output).unwrap();
+        assert_eq!(output.results_str(), "db > ...> 1 row inserted\n(1, two\n.lines -- kept, a@x, 0)\n(1 row)\ndb > ...> ");
+        // The statement still open when input ends fails as it stands.
+        assert_eq!(output.errors_str(), "Syntax error. Could not parse statement.\n");
+        assert_eq!(repl.prompt(&output), "db > ");
+
+        // Scripts end their own statements, which go no further.
+        let script = TempPath::new("open-quote.sql");
+        std::fs::write(&script, "insert 3 'open\n").unwrap();
+        let output = run(&mut repl, &[&format!(".read {}", script.display()), "select where id = 1"]);
+        assert_eq!(output.errors_str(), format!("{}:1: Syntax error. Could not parse statement.\n", script.display()));
+        assert!(output.results_str().ends_with("(1 row)\n"));
+    }
+
+    #[test]
+    fn test_shell() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".shell echo hello", ".system echo oops 1>&2", ".shell exit 3", ".shell"]);
+        assert_eq!(output.results_str(), "hello\n");
+        assert_eq!(output.errors_str(), "oops\nCommand exited with status 3.\nUsage: .shell COMMAND ...\n");
+        assert_eq!((repl.errors(), repl.last_error()), (2, Some("USAGE")));
+
+        repl.disable_shell();
+        let output = run(&mut repl, &[".shell echo hello"]);
+        assert_eq!(output.results_str(), "");
+        assert_eq!(output.errors_str(), "Running commands is turned off by --no-shell.\n");
+        assert_eq!(repl.last_error(), Some("SHELL_DISABLED"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_passes_the_line_to_sh() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".shell   printf '%s|%s\\n' 'a  b' \"$((1 + 2))\" | tr a A"]);
+        assert_eq!(output.results_str(), "A  b|3\n");
+    }
+
+    #[test]
+    fn test_render_prompt() {
+        let path = Path::new("/data/users.db");
+        assert_eq!(render_prompt("db > ", None, false), "db > ");
+        assert_eq!(render_prompt("%d> ", None, false), ":memory:> ");
+        assert_eq!(render_prompt("%d%*> ", Some(path), false), "users.db> ");
+        assert_eq!(render_prompt("%d%*> ", Some(path), true), "users.db*> ");
+        assert_eq!(render_prompt("100%% %x %", None, true), "100% %x %");
+    }
+
+    #[test]
+    fn test_prompt() {
+        let path = TempPath::new("prompt.db");
+        let mut repl = Repl::new(Database::open(&path).unwrap());
+        let output = Captured::new();
+        assert_eq!(repl.prompt(&output), "db > ");
+        run(&mut repl, &["insert 1 'a"]);
+        assert_eq!(repl.prompt(&output), "...> ");
+        run(&mut repl, &["b' a@x"]);
+        assert_eq!(repl.prompt(&output), "db > ");
+
+        let errors = run(&mut repl, &[".prompt \"%d%*> \" '  %*> '", ".prompt", ".prompt 'open"]);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(repl.prompt(&output), format!("{}*> ", name));
+        run(&mut repl, &["insert 2 'c"]);
+        assert_eq!(repl.prompt(&output), "  *> ");
+        run(&mut repl, &["' c@x"]);
+        repl.finish().unwrap();
+        assert_eq!(repl.prompt(&output), format!("{}> ", name));
+        assert_eq!(errors.errors_str(), "Usage: .prompt MAIN [CONTINUE]\nUsage: .prompt MAIN [CONTINUE]\n");
+    }
+
+    #[test]
+    fn test_tables() {
+        let dir = TempPath::new("tables");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut repl = Repl::new(Database::open_in_memory());
+        // Even an empty database has its one table.
+        assert_eq!(run(&mut repl, &[".tables"]).results_str(), "users\n");
+
+        let attach = |name: &str| format!(".attach {} as {}", dir.join(format!("{}.db", name)).display(), name);
+        run(&mut repl, &[&attach("archive"), &attach("audit")]);
+        let output = run(&mut repl, &[".tables", ".tables a%", ".tables %.USERS", ".tables x%", ".tables a b"]);
+        assert_eq!(
+            output.results_str(),
+            "users\narchive.users\naudit.users\narchive.users\naudit.users\narchive.users\naudit.users\n"
+        );
+        assert_eq!(output.errors_str(), "Usage: .tables [PATTERN]\n");
+    }
+
+    #[test]
+    fn test_schema() {
+        let path = TempPath::new("schema.db");
+        let options = Options { username_size: 8, email_size: 40, row_checksums: true, ..Options::default() };
+        let mut repl = Repl::new(Database::open_in_memory_with(options).unwrap());
+        let output = run(&mut repl, &[&format!(".attach {} as archive", path.display()), ".schema", ".schema archive.users", ".schema orders"]);
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "create table users (id integer primary key, username varchar(8), email varchar(40), age integer) with checksums;\n",
+                "create table archive.users (id integer primary key, username varchar(8), email varchar(40), age integer) with checksums;\n",
+                "create table archive.users (id integer primary key, username varchar(8), email varchar(40), age integer) with checksums;\n",
+            )
+        );
+        assert_eq!(output.errors_str(), "Error: No such table 'orders'.\n");
+
+        // Creating the table again with the same columns does nothing.
+        let output = run(&mut repl, &["create table users (id integer primary key, username varchar(8), email varchar(40), age integer) with checksums"]);
+        assert_eq!((output.results_str(), output.errors_str()), ("", ""));
+    }
+
+    #[test]
+    fn test_help() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".help"]);
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                ".attach FILE as NAME          Open another database file alongside this one\n",
+                ".bail on|off                  Stop a script at its first error\n",
+                ".clone FILE                   Copy the database into a new file\n",
+                ".color on|off|auto            Color prompts, headers and errors\n",
+                ".databases                    List the main and attached databases\n",
+                ".detach NAME                  Close a database opened with .attach\n",
+                ".dump                         Print the database as a script that rebuilds it\n",
+                ".echo on|off                  Print each line of input before running it\n",
+                ".errors text|json             Print errors as text or as JSON objects\n",
+                ".exit [CODE]                  Save everything and leave the shell\n",
+                ".export json FILE [select]    Write query results to a file as JSON\n",
+                ".headers on|off               Print column names above results\n",
+                ".help [COMMAND]               List meta commands, or describe one\n",
+                ".history on|off               Record lines in the command history\n",
+                ".import [csv|jsonl] FILE [--skip-header] [--strict] [--on-conflict fail|abort|skip|replace]\n",
+                "                              Load rows from a CSV or JSON Lines file\n",
+                ".mode list|csv|json|column    Choose how results are printed\n",
+                ".nullvalue TEXT               Text to show for NULL\n",
+                ".output [FILE|stdout]         Send results to a file, or back to stdout\n",
+                ".pager on|off                 Stop after each screenful of results\n",
+                ".prompt MAIN [CONTINUE]       Set the prompts\n",
+                ".quiet on|off                 Leave out summary lines such as row counts\n",
+                ".read FILE                    Run the lines of a script\n",
+                ".schema [TABLE]               Print the statements that create the tables\n",
+                ".seed COUNT [--random] [--rng SEED]\n",
+                "                              Insert generated rows\n",
+                ".separator CHAR               Field separator for CSV output and .import\n",
+                ".shell COMMAND ...            Run a command of the operating system\n",
+                ".show                         Print the current settings\n",
+                ".stats [reset]                Print the database's size and session counters\n",
+                ".system COMMAND ...           Same as .shell\n",
+                ".tables [PATTERN]             List the tables\n",
+                ".timeout [MS]                 How long to wait for a file in use\n",
+                ".timer on|off                 Print how long each statement took\n",
+                ".width [NUM ...]              Column widths for column mode\n",
+                "Use .help COMMAND for more about one command.\n",
+            )
+        );
+
+        let output = run(&mut repl, &[".help mode", ".help .width"]);
+        assert_eq!(
+            output.results_str(),
+            concat!(
+                "Usage: .mode list|csv|json|column\n",
+                "\n",
+                "Choose how results are printed. list prints rows as tuples, csv as quoted\n",
+                "records, json as one array per statement and column as an aligned table with\n",
+                "a header.\n",
+                "Usage: .width [NUM ...]\n",
+                "\n",
+                "Column widths for column mode. One width per column, in order. 0 sizes a\n",
+                "column to fit and a negative width aligns it to the right. With no widths\n",
+                "every column is sized to fit.\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_unrecognized_commands_suggest_the_closest() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        let output = run(&mut repl, &[".hepl", ".mod csv", ".help mdoe", ".help a b", ".frobnicate"]);
+        assert_eq!(
+            output.errors_str(),
+            concat!(
+                "Unrecognized command '.hepl'. Did you mean .help? Use .help to list commands.\n",
+                "Unrecognized command '.mod'. Did you mean .mode? Use .help to list commands.\n",
+                "Unrecognized command '.mdoe'. Did you mean .mode? Use .help to list commands.\n",
+                "Usage: .help [COMMAND]\n",
+                "Unrecognized command '.frobnicate'. Use .help to list commands.\n",
+            )
+        );
+        assert_eq!(repl.last_error(), Some("UNRECOGNIZED_COMMAND"));
+    }
+
+    #[test]
+    fn test_errors_are_counted() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        run(&mut repl, &["insert 1 a a@x", "select", ".mode", "insert 1 a a@x", ".bogus"]);
+        assert_eq!(repl.errors(), 3);
+        assert_eq!(repl.last_error(), Some("UNRECOGNIZED_COMMAND"));
+
+        let mut output = Captured::new();
+        repl.run_numbered_line("<stdin>", 7, "insert 1 a a@x", &mut output).unwrap();
+        assert_eq!(output.errors_str(), "<stdin>:7: Error: Duplicate key.\n");
+        assert_eq!(repl.last_error(), Some("DUPLICATE_KEY"));
+    }
+
+    #[test]
+    fn test_history_toggle() {
+        let mut repl = Repl::new(Database::open_in_memory());
+        run(&mut repl, &[".history off"]);
+        assert!(!repl.settings().history);
+        run(&mut repl, &[".history on"]);
+        assert!(repl.settings().history);
+
+        let output = run(&mut repl, &[".history maybe"]);
+        assert_eq!(output.errors_str(), "Usage: .history on|off\n");
+        assert!(repl.settings().history);
+    }
+
+    #[test]
+    fn test_csv_mode_quotes_special_values() {
+        // Values with spaces cannot be typed into an insert, so put the
+        // awkward rows in through the library.
+        let mut repl = Repl::new(Database::open_in_memory());
+        let awkward = [
+            Row::new(1, "a,b", "plain"),
+            Row::new(2, "say \"hi\"", "x@y"),
+            Row::new(3, "two\nlines", "c,\"d\"\ne"),
+        ];
+        for row in &awkward {
+            repl.database().insert_row(row).unwrap();
+        }
+
+        let output = run(&mut repl, &[".mode csv", "select"]);
+        let text = output.results_str().strip_suffix("(3 rows)\n").unwrap();
+        let records = parse_csv(text);
+
+        assert_eq!(records.len(), awkward.len());
+        for (record, row) in records.iter().zip(&awkward) {
+            let expected: Vec<String> = row.values().iter().map(|value| value.to_string()).collect();
+            assert_eq!(record, &expected);
+        }
+    }
+
+    /// Throws results away, so that measuring an export does not count the
+    /// memory its output takes.
+    #[derive(Default)]
+    struct Discard {
+        results: io::Sink,
+        errors: Vec<u8>,
+    }
+
+    impl Output for Discard {
+        fn results(&mut self) -> &mut dyn Write {
+            &mut self.results
+        }
+
+        fn errors(&mut self) -> &mut dyn Write {
+            &mut self.errors
+        }
+    }
+
+    #[test]
+    fn test_exports_run_in_constant_memory() {
+        // The most memory each export path may use beyond the table itself,
+        // whatever the number of rows.
+        const LIMIT: usize = 16 * 1024;
+        let peak = |rows: u64, export: &dyn Fn(&mut Repl)| {
+            let mut repl = Repl::new(Database::open_in_memory());
+            let mut gen = Gen::new(rows);
+            for id in 0..rows {
+                let mut row = gen.row(RowLayout::DEFAULT);
+                row.id = id;
+                repl.db.insert_row(&row).unwrap();
+            }
+            crate::alloc_counter::peak_bytes_during(|| export(&mut repl)).1
+        };
+
+        let csv = |repl: &mut Repl| {
+            let mut output = Discard::default();
+            for line in [".mode csv", ".headers on", "select"] {
+                repl.run_line(line, &mut output).unwrap();
+            }
+            assert!(output.errors.is_empty());
+        };
+        let dump = |repl: &mut Repl| {
+            let mut output = Discard::default();
+            repl.run_line(".dump", &mut output).unwrap();
+            assert!(output.errors.is_empty());
+        };
+        let json = |repl: &mut Repl| {
+            let mut out = io::BufWriter::new(io::sink());
+            export_rows(&mut repl.db, "select", &mut out, &mut Tracker::new(None)).unwrap();
+        };
+
+        for (name, export) in [("csv", &csv as &dyn Fn(&mut Repl)), ("dump", &dump), ("json", &json)] {
+            let small = peak(10, export);
+            let large = peak(1000, export);
+            assert!(large <= LIMIT, "{} export of 1000 rows peaked at {} bytes", name, large);
+            assert!(large <= small + 1024, "{} export grew from {} to {} bytes", name, small, large);
+        }
+    }
+}
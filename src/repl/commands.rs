@@ -0,0 +1,919 @@
+//! The meta commands: the arguments each takes, what it does and the code
+//! that runs it. `.help` and tab completion read the same table, so they
+//! always match what the shell accepts. Each shell registers the table in
+//! its `Registry`, where embedders can add commands of their own.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read};
+use std::process::{self, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use super::{ErrorFormat, Mode, Repl};
+use crate::compiler::MetaCommandResult;
+use crate::csv;
+use crate::database::Database;
+use crate::display::plural;
+use crate::output::Output;
+use crate::style::ColorChoice;
+
+/// Runs a meta command, given its arguments and the whole line.
+type Handler = fn(&mut Repl, &[&str], &str, &mut dyn Output) -> io::Result<()>;
+
+/// Runs a meta command added with `Repl::register_meta_command`, given the
+/// database and the command's arguments.
+pub type CustomHandler = Box<dyn FnMut(&mut Database, &[&str], &mut dyn Output) -> io::Result<MetaCommandResult> + Send>;
+
+/// One meta command.
+pub struct Command {
+    pub name: &'static str,
+    /// The arguments it takes, as shown in its usage line.
+    pub args: &'static str,
+    /// One line for the `.help` list.
+    pub summary: &'static str,
+    /// The rest of what `.help NAME` prints.
+    pub help: &'static str,
+    run: Handler,
+}
+
+/// Every meta command, in alphabetical order.
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: ".attach",
+        args: "FILE as NAME",
+        summary: "Open another database file alongside this one",
+        help: "Its table is then NAME.users: select from NAME.users reads it, and insert into users select \
+               from NAME.users copies its rows into this database. A new FILE is created with this \
+               database's column sizes. NAME must not be main or in use, and FILE must not be open already.",
+        run: |repl, args, line, output| match args {
+            [path, "as", name] => match repl.db.attach(path, name) {
+                Ok(()) => Ok(()),
+                Err(err) => repl.report(output, err.code(), &err.to_string(), line),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".bail",
+        args: "on|off",
+        summary: "Stop a script at its first error",
+        help: "With bail on, an error inside a .read script stops it and every script that read it. \
+               Piped input to the shell runs with bail on unless --continue-on-error is given.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.bail),
+    },
+    Command {
+        name: ".clone",
+        args: "FILE",
+        summary: "Copy the database into a new file",
+        help: "Writes every row into a new database file with the same column sizes. FILE must not exist; \
+               a copy that fails part way is removed.",
+        run: clone,
+    },
+    Command {
+        name: ".color",
+        args: "on|off|auto",
+        summary: "Color prompts, headers and errors",
+        help: "auto colors output only when it goes to a terminal and NO_COLOR is not set.",
+        run: |repl, args, line, output| match args {
+            [choice] => match ColorChoice::parse(choice) {
+                Some(choice) => {
+                    repl.settings.color = choice;
+                    Ok(())
+                }
+                None => repl.usage(line, output),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".databases",
+        args: "",
+        summary: "List the main and attached databases",
+        help: "Prints each database's name and file, main first.",
+        run: |repl, args, line, output| {
+            if !args.is_empty() {
+                return repl.usage(line, output);
+            }
+            for (name, path) in repl.db.databases() {
+                let path = path.map_or_else(|| ":memory:".to_string(), |path| path.display().to_string());
+                output.message(&format!("{}: {}", name, path))?;
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: ".detach",
+        args: "NAME",
+        summary: "Close a database opened with .attach",
+        help: "Writes out everything it has buffered first.",
+        run: |repl, args, line, output| match args {
+            [name] => match repl.db.detach(name) {
+                Ok(()) => Ok(()),
+                Err(err) => repl.report(output, err.code(), &err.to_string(), line),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".dump",
+        args: "",
+        summary: "Print the database as a script that rebuilds it",
+        help: "Prints one insert per row, with values quoted so that .read gives the same rows back. \
+               Follows .output like query results do.",
+        run: |repl, args, line, output| match args {
+            [] => repl.redirected(output, |repl, output| repl.dump(line, output)),
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".echo",
+        args: "on|off",
+        summary: "Print each line of input before running it",
+        help: "Useful for seeing which statement of a script produced which output.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.echo),
+    },
+    Command {
+        name: ".errors",
+        args: "text|json",
+        summary: "Print errors as text or as JSON objects",
+        help: "In json, each error is one line: {\"error\":{\"code\":..,\"message\":..,\"statement\":..}}, \
+               with the file and line when it comes from a script. JSON mode always prints errors as JSON.",
+        run: |repl, args, line, output| match args {
+            [format] => match ErrorFormat::parse(format) {
+                Some(format) => {
+                    repl.settings.errors = format;
+                    Ok(())
+                }
+                None => repl.usage(line, output),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".exit",
+        args: "[CODE]",
+        summary: "Save everything and leave the shell",
+        help: "Flushes the .output file and the database, then exits with status CODE, or 0. \
+               Lines after it, here or in the scripts that read it, do not run. End of input exits the same way.",
+        run: |repl, args, line, output| match args {
+            [] => {
+                repl.exit = Some(0);
+                Ok(())
+            }
+            [code] => match code.parse() {
+                Ok(code) => {
+                    repl.exit = Some(code);
+                    Ok(())
+                }
+                Err(_) => repl.usage(line, output),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".export",
+        args: "json FILE [select]",
+        summary: "Write query results to a file as JSON",
+        help: "Writes the rows of the select, or of every row without one, as a JSON array. \
+               The file is replaced only once every row is written.",
+        run: |repl, args, line, output| match args {
+            ["json", path, sql @ ..] => repl.export_json(path, &sql.join(" "), line, output),
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".headers",
+        args: "on|off",
+        summary: "Print column names above results",
+        help: "Applies to csv and column modes; .mode column turns headers on.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.headers),
+    },
+    Command {
+        name: ".help",
+        args: "[COMMAND]",
+        summary: "List meta commands, or describe one",
+        help: "COMMAND may be given with or without its leading dot.",
+        run: help,
+    },
+    Command {
+        name: ".history",
+        args: "on|off",
+        summary: "Record lines in the command history",
+        help: "Lines typed at the prompt are kept for Up and Down, and saved to the history file \
+               (~/.voiddb_history, VOIDDB_HISTORY or --history-file).",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.history),
+    },
+    Command {
+        name: ".import",
+        args: "[csv|jsonl] FILE [--skip-header] [--strict] [--on-conflict fail|abort|skip|replace]",
+        summary: "Load rows from a CSV or JSON Lines file",
+        help: "CSV records are id,username,email[,age], split on the current separator. JSON Lines \
+               objects have the fields id, username, email and optionally age. Bad lines are reported \
+               and skipped, or stop the import with --strict. --on-conflict says what to do with an id \
+               that is already taken: fail reports the line, abort takes the whole import back, skip \
+               leaves the old row and replace overwrites it.",
+        run: |repl, args, line, output| repl.import(args, line, output),
+    },
+    Command {
+        name: ".mode",
+        args: "list|csv|json|column",
+        summary: "Choose how results are printed",
+        help: "list prints rows as tuples, csv as quoted records, json as one array per statement and \
+               column as an aligned table with a header.",
+        run: mode,
+    },
+    Command {
+        name: ".nullvalue",
+        args: "TEXT",
+        summary: "Text to show for NULL",
+        help: "Used by every mode but json, which writes null.",
+        run: |repl, args, line, output| match args {
+            [text] => {
+                repl.settings.null_value = text.to_string();
+                Ok(())
+            }
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".output",
+        args: "[FILE|stdout]",
+        summary: "Send results to a file, or back to stdout",
+        help: "Results of later statements go to FILE, which is overwritten. Errors still go to stderr.",
+        run: output,
+    },
+    Command {
+        name: ".pager",
+        args: "on|off",
+        summary: "Stop after each screenful of results",
+        help: "Only when results go to a terminal. The screen height comes from LINES, or is 24.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.pager),
+    },
+    Command {
+        name: ".prompt",
+        args: "MAIN [CONTINUE]",
+        summary: "Set the prompts",
+        help: "MAIN is shown before each line and CONTINUE before the later lines of a statement whose \
+               quoted value runs on past the end of a line; quote them with \" or ' to keep spaces. In either, %d is the database file name, %* is * while \
+               there are changes not yet written to disk, and %% is %.",
+        run: |repl, _, line, output| match quoted_args(line).as_deref() {
+            Some([_, prompt]) => {
+                repl.settings.prompt = prompt.clone();
+                Ok(())
+            }
+            Some([_, prompt, continuation]) => {
+                repl.settings.prompt = prompt.clone();
+                repl.settings.continuation_prompt = continuation.clone();
+                Ok(())
+            }
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".quiet",
+        args: "on|off",
+        summary: "Leave out summary lines such as row counts",
+        help: "Rows and errors are still printed.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.quiet),
+    },
+    Command {
+        name: ".read",
+        args: "FILE",
+        summary: "Run the lines of a script",
+        help: "Each line runs as if it had been typed. Errors give the file and line number. \
+               Scripts may read others, up to 16 deep.",
+        run: |repl, args, line, output| match args {
+            [path] => repl.read(path, line, output),
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".schema",
+        args: "[TABLE]",
+        summary: "Print the statements that create the tables",
+        help: "One create table statement per table, or only for TABLE, with its column sizes. \
+               Running them on a new database gives it the same tables.",
+        run: |repl, args, line, output| {
+            let table = match args {
+                [] => None,
+                [table] => Some(*table),
+                _ => return repl.usage(line, output),
+            };
+            match repl.db.schema(table) {
+                Ok(statements) => statements.iter().try_for_each(|sql| output.message(sql)),
+                Err(err) => repl.report(output, err.code(), &err.to_string(), line),
+            }
+        },
+    },
+    Command {
+        name: ".seed",
+        args: "COUNT [--random] [--rng SEED]",
+        summary: "Insert generated rows",
+        help: "Ids follow on from the largest in the table, or are random with --random. The same SEED \
+               always gives the same rows; without --rng the seed is printed so the run can be repeated.",
+        run: |repl, args, line, output| repl.seed(args, line, output),
+    },
+    Command {
+        name: ".separator",
+        args: "CHAR",
+        summary: "Field separator for CSV output and .import",
+        help: "One character, or \\t for a tab.",
+        run: |repl, args, line, output| match args {
+            [arg] => match csv::parse_separator(arg) {
+                Some(separator) => {
+                    repl.settings.separator = separator;
+                    Ok(())
+                }
+                None => repl.usage(line, output),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".shell",
+        args: "COMMAND ...",
+        summary: "Run a command of the operating system",
+        help: "The rest of the line goes to sh -c, or cmd /C on Windows, as typed, so quotes, pipes and \
+               wildcards work as they do in that shell. Its output and errors are printed as they arrive \
+               and a failing exit status is reported as an error. The command gets no input. Refused \
+               when the shell was started with --no-shell.",
+        run: shell,
+    },
+    Command {
+        name: ".show",
+        args: "",
+        summary: "Print the current settings",
+        help: "Shows every setting the other meta commands change.",
+        run: |repl, args, line, output| match args {
+            [] => repl.show(output),
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".stats",
+        args: "[reset]",
+        summary: "Print the database's size and session counters",
+        help: "Shows the file, its pages, the rows in each table, the page cache and the bytes read, \
+               bytes written and statements run this session. reset zeroes those counters.",
+        run: |repl, args, line, output| match args {
+            [] => repl.stats(output),
+            ["reset"] => {
+                repl.db.reset_stats();
+                Ok(())
+            }
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".system",
+        args: "COMMAND ...",
+        summary: "Same as .shell",
+        help: "Runs COMMAND with the system shell; see .help shell.",
+        run: shell,
+    },
+    Command {
+        name: ".tables",
+        args: "[PATTERN]",
+        summary: "List the tables",
+        help: "One per line, with the tables of attached databases as NAME.users. PATTERN keeps the \
+               tables it matches, where % matches any run of characters and _ any one character.",
+        run: |repl, args, line, output| {
+            let pattern = match args {
+                [] => "%",
+                [pattern] => pattern,
+                _ => return repl.usage(line, output),
+            };
+            for table in repl.db.tables().iter().filter(|table| like(pattern, table)) {
+                output.message(table)?;
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: ".timeout",
+        args: "[MS]",
+        summary: "How long to wait for a file in use",
+        help: "How long .attach waits for a database file that another handle or process has open, \
+               retrying until it is free, before failing with LOCKED. Ctrl-C stops the wait. The \
+               default, 0, fails at once. With no MS, prints the current timeout.",
+        run: |repl, args, line, output| match args {
+            [] => output.message(&repl.db.options().busy_timeout.as_millis().to_string()),
+            [ms] => match ms.parse() {
+                Ok(ms) => {
+                    repl.db.set_busy_timeout(Duration::from_millis(ms));
+                    Ok(())
+                }
+                Err(_) => repl.usage(line, output),
+            },
+            _ => repl.usage(line, output),
+        },
+    },
+    Command {
+        name: ".timer",
+        args: "on|off",
+        summary: "Print how long each statement took",
+        help: "After each statement, prints Run Time: real and the seconds it took to run and print, \
+               with the rows per second of an insert. Meta commands are not timed.",
+        run: |repl, args, line, output| set_on_off(repl, args, line, output, |settings| &mut settings.timer),
+    },
+    Command {
+        name: ".width",
+        args: "[NUM ...]",
+        summary: "Column widths for column mode",
+        help: "One width per column, in order. 0 sizes a column to fit and a negative width aligns it \
+               to the right. With no widths every column is sized to fit.",
+        run: |repl, args, line, output| match args.iter().map(|width| width.parse()).collect() {
+            Ok(widths) => {
+                repl.settings.widths = widths;
+                Ok(())
+            }
+            Err(_) => repl.usage(line, output),
+        },
+    },
+];
+
+/// A meta command as a shell knows it: one of `COMMANDS` or one added by
+/// an embedder.
+pub struct Registered {
+    pub name: String,
+    pub args: String,
+    pub summary: String,
+    pub help: String,
+    run: Run,
+}
+
+enum Run {
+    Builtin(Handler),
+    Custom(CustomHandler),
+}
+
+impl Registered {
+    /// `Usage: .name ARGS`.
+    pub fn usage(&self) -> String {
+        format!("Usage: {} {}", self.name, self.args).trim_end().to_string()
+    }
+}
+
+/// Why `Repl::register_meta_command` refused a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    /// A command with this name is already registered.
+    NameTaken(String),
+    /// Names are letters, digits, `-` and `_` after the leading dot.
+    InvalidName(String),
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::NameTaken(name) => write!(f, "There is already a {} command.", name),
+            RegisterError::InvalidName(name) => write!(f, "'{}' is not a valid command name.", name),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+/// The meta commands a shell runs, in name order.
+pub struct Registry {
+    commands: Vec<Registered>,
+}
+
+impl Registry {
+    /// A registry holding the built-in commands.
+    pub fn new() -> Self {
+        let mut registry = Registry { commands: Vec::with_capacity(COMMANDS.len()) };
+        for command in COMMANDS {
+            let registered = Registered {
+                name: command.name.to_string(),
+                args: command.args.to_string(),
+                summary: command.summary.to_string(),
+                help: command.help.to_string(),
+                run: Run::Builtin(command.run),
+            };
+            registry.register(registered).expect("built-in commands have distinct, valid names");
+        }
+        registry
+    }
+
+    /// Adds a command called `name`, with or without its leading dot.
+    pub(super) fn register_custom(&mut self, name: &str, args: &str, summary: &str, handler: CustomHandler) -> Result<(), RegisterError> {
+        let registered = Registered {
+            name: format!(".{}", name.trim_start_matches('.')),
+            args: args.to_string(),
+            summary: summary.trim_end_matches('.').to_string(),
+            help: String::new(),
+            run: Run::Custom(handler),
+        };
+        self.register(registered)
+    }
+
+    fn register(&mut self, command: Registered) -> Result<(), RegisterError> {
+        let rest = &command.name[1..];
+        if rest.is_empty() || !rest.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(RegisterError::InvalidName(command.name));
+        }
+        match self.commands.binary_search_by(|other| other.name.cmp(&command.name)) {
+            Ok(_) => Err(RegisterError::NameTaken(command.name)),
+            Err(index) => {
+                self.commands.insert(index, command);
+                Ok(())
+            }
+        }
+    }
+
+    /// Every command, in name order.
+    pub fn iter(&self) -> impl Iterator<Item = &Registered> {
+        self.commands.iter()
+    }
+
+    /// The command called `name`, which may leave out the leading dot.
+    pub fn find(&self, name: &str) -> Option<&Registered> {
+        self.commands.iter().find(|command| command.name.strip_prefix('.') == Some(name.trim_start_matches('.')))
+    }
+
+    /// The command whose name is nearest to `name`, if any is close enough
+    /// to be a likely typo.
+    pub fn closest(&self, name: &str) -> Option<&Registered> {
+        self.commands
+            .iter()
+            .map(|command| (edit_distance(name, &command.name), command))
+            .filter(|&(distance, _)| distance <= 2)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, command)| command)
+    }
+
+    /// Says that there is no `name` command, suggesting the likeliest one.
+    pub(super) fn unknown_command(&self, name: &str) -> String {
+        match self.closest(name) {
+            Some(command) => format!("Unrecognized command '{}'. Did you mean {}? Use .help to list commands.", name, command.name),
+            None => format!("Unrecognized command '{}'. Use .help to list commands.", name),
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The number of single character insertions, deletions and substitutions
+/// that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The built-in commands that read or change the shell's own database
+/// rather than its settings, which a shell connected to a server refuses,
+/// along with every custom command.
+const LOCAL_COMMANDS: &[&str] =
+    &[".attach", ".clone", ".databases", ".detach", ".dump", ".export", ".import", ".schema", ".seed", ".stats", ".tables", ".timeout"];
+
+/// Runs the command called exactly `name`, returning `UnrecognizedCommand`
+/// if there is none. A custom command's `UnrecognizedCommand` means its
+/// arguments were wrong, and an error it returns is reported like any
+/// other command's.
+pub(super) fn run(repl: &mut Repl, name: &str, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<MetaCommandResult> {
+    let Some(index) = repl.commands.commands.iter().position(|command| command.name == name) else {
+        return Ok(MetaCommandResult::UnrecognizedCommand);
+    };
+    let local = LOCAL_COMMANDS.contains(&name) || matches!(repl.commands.commands[index].run, Run::Custom(_));
+    if local && repl.remote.is_some() {
+        let message = format!("{} works on a database file of the shell's own, not over a connection to a server.", name);
+        repl.report(output, "NOT_SUPPORTED", &message, line)?;
+        return Ok(MetaCommandResult::Success);
+    }
+    let result = match &mut repl.commands.commands[index].run {
+        Run::Builtin(handler) => {
+            let handler = *handler;
+            handler(repl, args, line, output)?;
+            return Ok(MetaCommandResult::Success);
+        }
+        Run::Custom(handler) => handler(&mut repl.db, args, output),
+    };
+    match result {
+        Ok(MetaCommandResult::Success) => {}
+        Ok(MetaCommandResult::Exit(code)) => repl.exit = Some(code),
+        Ok(MetaCommandResult::UnrecognizedCommand) => repl.usage(line, output)?,
+        Err(err) => repl.report(output, "COMMAND_ERROR", &format!("Error: {}", err), line)?,
+    }
+    Ok(MetaCommandResult::Success)
+}
+
+/// Sets the on/off setting picked out by `setting` from the single
+/// argument.
+fn set_on_off(
+    repl: &mut Repl,
+    args: &[&str],
+    line: &str,
+    output: &mut dyn Output,
+    setting: fn(&mut super::Settings) -> &mut bool,
+) -> io::Result<()> {
+    match args {
+        ["on"] => *setting(&mut repl.settings) = true,
+        ["off"] => *setting(&mut repl.settings) = false,
+        _ => return repl.usage(line, output),
+    }
+    Ok(())
+}
+
+fn clone(repl: &mut Repl, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+    let [path] = args else {
+        return repl.usage(line, output);
+    };
+    match repl.db.clone_to(path) {
+        Ok(summary) if !repl.settings.quiet => {
+            let pages = if summary.pages == 1 { "1 page".to_string() } else { format!("{} pages", summary.pages) };
+            output.message(&format!("Cloned {} to {} ({}).", plural(summary.rows), path, pages))
+        }
+        Ok(_) => Ok(()),
+        Err(err) => repl.report(output, err.code(), &format!("Cannot clone to '{}': {}", path, err), line),
+    }
+}
+
+/// Runs the rest of the line with the system shell, for `.shell` and
+/// `.system`. Its output is read on two threads and printed here as each
+/// line arrives, so neither pipe can fill up and stall it.
+fn shell(repl: &mut Repl, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+    if args.is_empty() {
+        return repl.usage(line, output);
+    }
+    if !repl.shell {
+        return repl.report(output, "SHELL_DISABLED", "Running commands is turned off by --no-shell.", line);
+    }
+    let line = line.trim();
+    let command = line[line.find(char::is_whitespace).unwrap_or(line.len())..].trim_start();
+
+    let spawned = system_shell(command).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(err) => return repl.report(output, "IO_ERROR", &format!("Cannot run '{}': {}", command, err), line),
+    };
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, false, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, true, sender);
+    }
+    for (is_error, text) in receiver {
+        let written = if is_error { output.error(&text) } else { output.message(&text) };
+        if let Err(err) = written {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err);
+        }
+    }
+
+    let status = child.wait()?;
+    match status.code() {
+        _ if status.success() => Ok(()),
+        Some(code) => repl.report(output, "COMMAND_FAILED", &format!("Command exited with status {}.", code), line),
+        None => repl.report(output, "COMMAND_FAILED", "Command was stopped by a signal.", line),
+    }
+}
+
+/// The system shell, set to run `command`.
+fn system_shell(command: &str) -> process::Command {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut process = process::Command::new(shell);
+    process.arg(flag).arg(command);
+    process
+}
+
+/// Sends each line of `pipe` to `sender` from a new thread, tagged with
+/// whether it is an error, until the pipe closes.
+fn forward_lines(pipe: impl Read + Send + 'static, is_error: bool, sender: mpsc::Sender<(bool, String)>) {
+    thread::spawn(move || {
+        let mut pipe = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while matches!(pipe.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+            let text = String::from_utf8_lossy(&line);
+            if sender.send((is_error, text.trim_end_matches(['\n', '\r']).to_string())).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+}
+
+/// How wide the syntax column of the `.help` list is. Longer syntaxes put
+/// their summary on the next line.
+const SYNTAX_WIDTH: usize = 28;
+
+/// Where `.help COMMAND` wraps its description.
+const HELP_WIDTH: usize = 76;
+
+fn help(repl: &mut Repl, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+    match args {
+        [] => {
+            for command in repl.commands.iter() {
+                let syntax = format!("{} {}", command.name, command.args);
+                let syntax = syntax.trim_end();
+                if syntax.len() > SYNTAX_WIDTH {
+                    output.message(syntax)?;
+                    output.message(&format!("{:width$}  {}", "", command.summary, width = SYNTAX_WIDTH))?;
+                } else {
+                    output.message(&format!("{:width$}  {}", syntax, command.summary, width = SYNTAX_WIDTH))?;
+                }
+            }
+            output.message("Use .help COMMAND for more about one command.")
+        }
+        [name] => match repl.commands.find(name) {
+            Some(command) => {
+                output.message(&command.usage())?;
+                output.message("")?;
+                for line in wrap(&format!("{}. {}", command.summary, command.help), HELP_WIDTH) {
+                    output.message(&line)?;
+                }
+                Ok(())
+            }
+            None => {
+                let name = format!(".{}", name.trim_start_matches('.'));
+                repl.report(output, "USAGE", &repl.commands.unknown_command(&name), line)
+            }
+        },
+        _ => repl.usage(line, output),
+    }
+}
+
+/// Splits `text` into lines of at most `width` characters, breaking
+/// between words. A word longer than `width` gets a line to itself.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Whether `text` matches `pattern`, in which `%` matches any run of
+/// characters and `_` any one character. Case is ignored, as in SQL.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    // matched[j]: whether the pattern so far matches the first j characters.
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    for &p in &pattern {
+        let mut next = vec![false; text.len() + 1];
+        for j in 0..=text.len() {
+            next[j] = match p {
+                '%' => matched[j] || (j > 0 && next[j - 1]),
+                '_' => j > 0 && matched[j - 1],
+                p => j > 0 && matched[j - 1] && text[j - 1] == p,
+            };
+        }
+        matched = next;
+    }
+    matched[text.len()]
+}
+
+/// The words of `line`, where a word starting with `"` or `'` runs to the
+/// same quote and may hold spaces. `None` if a quote is not closed.
+fn quoted_args(line: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (arg, after) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = rest[1..].find(quote)? + 1;
+                (&rest[1..end], &rest[end + 1..])
+            }
+            _ => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        args.push(arg.to_string());
+        rest = after.trim_start();
+    }
+    Some(args)
+}
+
+fn mode(repl: &mut Repl, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+    let [name] = args else {
+        return repl.usage(line, output);
+    };
+    match Mode::parse(name) {
+        Some(mode) => {
+            // A column table is unreadable without its header.
+            if mode == Mode::Column {
+                repl.settings.headers = true;
+            }
+            repl.settings.mode = mode;
+            Ok(())
+        }
+        None => {
+            let message = format!("Unknown mode '{}'. Use list, csv, json or column.", name);
+            repl.report(output, "USAGE", &message, line)
+        }
+    }
+}
+
+fn output(repl: &mut Repl, args: &[&str], line: &str, output: &mut dyn Output) -> io::Result<()> {
+    match args {
+        [] | ["stdout"] => repl.output_file = None,
+        [path] => match File::create(path) {
+            Ok(file) => repl.output_file = Some((path.to_string(), BufWriter::new(file))),
+            Err(err) => return repl.report(output, "IO_ERROR", &format!("Cannot open '{}': {}", path, err), line),
+        },
+        _ => return repl.usage(line, output),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_sorted_and_documented() {
+        assert!(COMMANDS.windows(2).all(|pair| pair[0].name < pair[1].name));
+        for command in COMMANDS {
+            assert!(command.name.starts_with('.'));
+            assert!(!command.summary.ends_with('.'), "{}", command.name);
+            assert!(command.help.ends_with('.'), "{}", command.name);
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = Registry::new();
+        let handler = || -> CustomHandler { Box::new(|_, _, _| Ok(MetaCommandResult::Success)) };
+        registry.register_custom("sync-to-s3", "BUCKET", "Upload the database.", handler()).unwrap();
+        let command = registry.find(".sync-to-s3").unwrap();
+        assert_eq!((command.summary.as_str(), command.usage()), ("Upload the database", "Usage: .sync-to-s3 BUCKET".to_string()));
+        // Kept in name order with the built-in commands.
+        let names: Vec<&str> = registry.iter().map(|command| command.name.as_str()).collect();
+        assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert_eq!(registry.register_custom(".mode", "", "", handler()), Err(RegisterError::NameTaken(".mode".to_string())));
+        assert_eq!(registry.register_custom("sync-to-s3", "", "", handler()), Err(RegisterError::NameTaken(".sync-to-s3".to_string())));
+        assert_eq!(registry.register_custom(".", "", "", handler()), Err(RegisterError::InvalidName(".".to_string())));
+        assert_eq!(registry.register_custom("a b", "", "", handler()), Err(RegisterError::InvalidName(".a b".to_string())));
+    }
+
+    #[test]
+    fn test_find_and_closest() {
+        let registry = Registry::new();
+        assert_eq!(registry.find(".mode").unwrap().name, ".mode");
+        assert_eq!(registry.find("mode").unwrap().name, ".mode");
+        assert!(registry.find(".mod").is_none());
+        assert_eq!(registry.closest(".hepl").unwrap().name, ".help");
+        assert_eq!(registry.closest(".mod").unwrap().name, ".mode");
+        assert_eq!(registry.closest(".exti").unwrap().name, ".exit");
+        assert!(registry.closest(".bogus").is_none());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("%", ""));
+        assert!(like("us%", "users"));
+        assert!(like("US%", "users"));
+        assert!(like("%.users", "archive.users"));
+        assert!(!like("%.users", "users"));
+        assert!(like("u_ers", "users"));
+        assert!(!like("u_ers", "uers"));
+        assert!(like("%s%s", "users"));
+        assert!(!like("us", "users"));
+    }
+
+    #[test]
+    fn test_quoted_args() {
+        assert_eq!(quoted_args(".prompt \"main> \" '...> '").unwrap(), [".prompt", "main> ", "...> "]);
+        assert_eq!(quoted_args(".prompt  a  \"\"").unwrap(), [".prompt", "a", ""]);
+        assert_eq!(quoted_args(".prompt \"it's\"").unwrap(), [".prompt", "it's"]);
+        assert!(quoted_args(".prompt \"open").is_none());
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("one two three four", 9), ["one two", "three", "four"]);
+        assert_eq!(wrap("a longword b", 4), ["a", "longword", "b"]);
+        assert!(wrap("  ", 10).is_empty());
+    }
+}
@@ -0,0 +1,792 @@
+//! A warm standby: `VoidDB serve --replicate-to ADDRESS` sends what it
+//! writes to its file to `VoidDB follow`, which writes the same to its own
+//! copy and serves selects from it.
+//!
+//! VoidDB has no write-ahead log, so the leader keeps one of its own: its
+//! file goes through a `ReplicatingVfs`, which notes every write and
+//! truncation, and each sync turns those noted since the last into a
+//! numbered frame. The frames are what has been committed, as a flush
+//! writes changed pages whole and then syncs.
+//!
+//! The leader connects to the follower as a binary protocol client (see
+//! `codec`), presenting its own token if it has one, and sends
+//! `Replicate` with its epoch, the time it started. The follower answers
+//! with the position it has reached: the epoch of the leader it copied and
+//! the last frame it applied. A follower of this leader that the leader
+//! still has every later frame for picks up where it left off; any other
+//! is sent a `Snapshot` of the whole file first. After that each frame is
+//! sent as it is made, with a checksum, and the follower applies them in
+//! order, syncs, and notes its position next to its file, so that a
+//! follower that is restarted resumes rather than starting again.
+//!
+//! The follower acknowledges its position every `ACK_EVERY` frames and
+//! whenever the frames stop coming. The leader keeps frames until they are
+//! acknowledged, up to `MAX_HISTORY_SIZE` bytes of them; a follower that
+//! falls further behind than that is sent a snapshot again.
+//!
+//! Failing over is not handled: a follower stays read-only.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, SystemTime};
+
+use crate::auth::Token;
+use crate::codec::{self, Decoder, Message};
+use crate::connection::Connection;
+use crate::database::{Database, Options};
+use crate::error::DbError;
+use crate::interrupt::Interrupt;
+use crate::net::{Address, Stream};
+use crate::server::{is_retryable, retry, Log, POLL_INTERVAL};
+use crate::vfs::{self, OpenMode, Vfs, VfsFile};
+
+/// How many bytes of unacknowledged frames a leader keeps.
+pub const MAX_HISTORY_SIZE: usize = 64 << 20;
+
+/// How many frames a follower applies before it acknowledges them, if they
+/// keep coming.
+pub const ACK_EVERY: u64 = 64;
+
+/// How long a leader waits before it tries its follower again.
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// One thing done to the leader's file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// `data` was written at `offset`.
+    Write { offset: u64, data: Vec<u8> },
+    /// The file was cut down, or padded with zeroes, to this length.
+    Truncate(u64),
+}
+
+impl Change {
+    /// Roughly how much memory the change takes, to bound a `History`.
+    fn size(&self) -> usize {
+        match self {
+            Change::Write { data, .. } => 16 + data.len(),
+            Change::Truncate(_) => 16,
+        }
+    }
+}
+
+/// The frames a leader has made and not yet seen acknowledged.
+#[derive(Debug)]
+pub struct History {
+    /// When the leader started, which tells its frames from another's.
+    epoch: u64,
+    max_size: usize,
+    state: Mutex<HistoryState>,
+}
+
+#[derive(Debug, Default)]
+struct HistoryState {
+    /// Changes since the last sync.
+    pending: Vec<Change>,
+    /// Frames by number, oldest first.
+    frames: VecDeque<(u64, Vec<Change>)>,
+    size: usize,
+    /// The number of the last frame made; frames count from 1.
+    last_seq: u64,
+}
+
+impl History {
+    /// A history of a leader that started at `epoch`, keeping up to
+    /// `max_size` bytes of frames.
+    pub fn new(epoch: u64, max_size: usize) -> Self {
+        History { epoch, max_size, state: Mutex::default() }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.state().last_seq
+    }
+
+    fn state(&self) -> MutexGuard<'_, HistoryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, change: Change) {
+        self.state().pending.push(change);
+    }
+
+    /// Makes the changes noted since the last commit the next frame,
+    /// letting go of the oldest frames but that one if there are too many.
+    /// A sync with nothing written before it makes none.
+    fn commit(&self) {
+        let mut state = self.state();
+        if state.pending.is_empty() {
+            return;
+        }
+        let changes = std::mem::take(&mut state.pending);
+        state.size += changes.iter().map(Change::size).sum::<usize>();
+        state.last_seq += 1;
+        let seq = state.last_seq;
+        state.frames.push_back((seq, changes));
+        while state.size > self.max_size && state.frames.len() > 1 {
+            let (_, changes) = state.frames.pop_front().expect("there are frames");
+            state.size -= changes.iter().map(Change::size).sum::<usize>();
+        }
+    }
+
+    /// The frames after frame `seq`, or `None` if some of them are gone.
+    pub fn frames_after(&self, seq: u64) -> Option<Vec<(u64, Vec<Change>)>> {
+        let state = self.state();
+        let first = state.frames.front().map_or(state.last_seq + 1, |(first, _)| *first);
+        if seq > state.last_seq || seq + 1 < first {
+            return None;
+        }
+        Some(state.frames.iter().filter(|(frame, _)| *frame > seq).cloned().collect())
+    }
+
+    /// Lets go of the frames up to frame `seq`, which the follower has.
+    pub fn acknowledge(&self, seq: u64) {
+        let mut state = self.state();
+        while state.frames.front().is_some_and(|(frame, _)| *frame <= seq) {
+            let (_, changes) = state.frames.pop_front().expect("there is a front frame");
+            state.size -= changes.iter().map(Change::size).sum::<usize>();
+        }
+    }
+}
+
+/// A `Vfs` that notes in a `History` what is done to one file, the
+/// leader's, and passes everything through to another `Vfs`. A sync ends
+/// a frame, so the database must be opened with a sync mode other than
+/// `SyncMode::Off`.
+#[derive(Debug)]
+pub struct ReplicatingVfs {
+    inner: Arc<dyn Vfs>,
+    path: PathBuf,
+    history: Arc<History>,
+}
+
+impl ReplicatingVfs {
+    pub fn new(inner: Arc<dyn Vfs>, path: impl Into<PathBuf>, history: Arc<History>) -> Self {
+        ReplicatingVfs { inner, path: path.into(), history }
+    }
+}
+
+impl Vfs for ReplicatingVfs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let file = self.inner.open(path, mode)?;
+        if path != self.path || mode == OpenMode::ReadOnly {
+            return Ok(file);
+        }
+        Ok(Box::new(ReplicatingFile { inner: file, history: self.history.clone() }))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+}
+
+/// The leader's file, opened through a `ReplicatingVfs`.
+#[derive(Debug)]
+struct ReplicatingFile {
+    inner: Box<dyn VfsFile>,
+    history: Arc<History>,
+}
+
+impl VfsFile for ReplicatingFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.inner.read_at(buf, offset)
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.inner.write_at(buf, offset)?;
+        self.history.record(Change::Write { offset, data: buf.to_vec() });
+        Ok(())
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        self.inner.sync(data_only)?;
+        self.history.commit();
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.inner.truncate(len)?;
+        self.history.record(Change::Truncate(len));
+        Ok(())
+    }
+
+    fn try_lock(&self, shared: bool) -> Result<(), fs::TryLockError> {
+        self.inner.try_lock(shared)
+    }
+
+    fn file_size(&self) -> io::Result<u64> {
+        self.inner.file_size()
+    }
+}
+
+/// The serving end: a database whose changes are sent to a follower.
+pub struct Leader {
+    connection: Connection,
+    path: PathBuf,
+    vfs: Arc<dyn Vfs>,
+    history: Arc<History>,
+    follower: Address,
+    token: Option<Token>,
+    log: Log,
+}
+
+impl Leader {
+    /// Opens the database file at `path`, as `Database::open` does, to be
+    /// replicated to the follower at `follower` once `run` is called.
+    pub fn open<P: AsRef<Path>>(path: P, follower: Address) -> Result<Leader, DbError> {
+        let path = path.as_ref().to_path_buf();
+        let epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(1, |since| since.as_nanos() as u64);
+        let history = Arc::new(History::new(epoch, MAX_HISTORY_SIZE));
+        let vfs = vfs::default();
+        let db = Options::new().vfs(Arc::new(ReplicatingVfs::new(vfs.clone(), &path, history.clone()))).open(&path)?;
+        Ok(Leader { connection: Connection::new(db), path, vfs, history, follower, token: None, log: Log::default() })
+    }
+
+    /// Presents `token` to the follower, which must have been given the
+    /// same one.
+    pub fn auth_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Notes when the follower is reached and lost on `out`, a line each.
+    pub fn log(mut self, out: impl Write + Send + 'static) -> Self {
+        self.log = Log::new(out);
+        self
+    }
+
+    /// The database, to serve.
+    pub fn connection(&self) -> Connection {
+        self.connection.clone()
+    }
+
+    /// Keeps the follower up to date until `stop` is interrupted,
+    /// connecting again whenever it cannot be reached.
+    pub fn run(&self, stop: &Interrupt) {
+        let mut last_error = None;
+        while !stop.is_interrupted() {
+            if let Err(err) = self.replicate(stop, &mut last_error) {
+                let err = err.to_string();
+                if last_error.as_ref() != Some(&err) {
+                    self.log.entry(&format!("Not replicating to {}: {}", self.follower, err));
+                    last_error = Some(err);
+                }
+            }
+            let mut waited = Duration::ZERO;
+            while waited < RECONNECT_INTERVAL && !stop.is_interrupted() {
+                std::thread::sleep(POLL_INTERVAL);
+                waited += POLL_INTERVAL;
+            }
+        }
+    }
+
+    /// Connects to the follower once and sends it frames until `stop` is
+    /// interrupted or the connection fails. `last_error` is cleared once
+    /// the follower has answered.
+    fn replicate(&self, stop: &Interrupt, last_error: &mut Option<String>) -> io::Result<()> {
+        let stream = Stream::connect(&self.follower)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        writer.write_all(&codec::MAGIC)?;
+        writer.flush()?;
+        let mut greeting = Vec::new();
+        if retry(stop, || codec::read_magic(&mut reader, &mut greeting))?.is_none() {
+            return Ok(());
+        }
+
+        let mut decoder = Decoder::default();
+        let mut request = |message: Message, writer: &mut BufWriter<Stream>| -> io::Result<Option<Message>> {
+            codec::write_message(writer, &message)?;
+            writer.flush()?;
+            match retry(stop, || codec::read_message(&mut reader, &mut decoder))? {
+                Some(Some(Message::Error { message, .. })) => Err(io::Error::other(message)),
+                Some(Some(message)) => Ok(Some(message)),
+                Some(None) => Err(hung_up()),
+                None => Ok(None),
+            }
+        };
+        if let Some(token) = &self.token {
+            match request(Message::Auth(token.as_str().to_string()), &mut writer)? {
+                Some(Message::Done) => {}
+                Some(message) => return Err(unexpected(&message)),
+                None => return Ok(()),
+            }
+        }
+        let (epoch, applied) = match request(Message::Replicate { epoch: self.history.epoch() }, &mut writer)? {
+            Some(Message::Position { epoch, applied }) => (epoch, applied),
+            Some(message) => return Err(unexpected(&message)),
+            None => return Ok(()),
+        };
+
+        let resumable = epoch == self.history.epoch() && self.history.frames_after(applied).is_some();
+        let mut sent = if resumable {
+            self.log.entry(&format!("Replicating to {}, resuming after frame {}.", self.follower, applied));
+            applied
+        } else {
+            let (seq, data) = self.snapshot()?;
+            codec::write_message(&mut writer, &Message::Snapshot { seq, data })?;
+            self.log.entry(&format!("Replicating to {}, starting from a copy of the database as of frame {}.", self.follower, seq));
+            seq
+        };
+        *last_error = None;
+
+        while !stop.is_interrupted() {
+            let Some(frames) = self.history.frames_after(sent) else {
+                return Err(io::Error::other("the follower fell too far behind"));
+            };
+            for (seq, changes) in frames {
+                codec::write_message(&mut writer, &Message::Frame { seq, changes })?;
+                sent = seq;
+            }
+            writer.flush()?;
+            match codec::read_message(&mut reader, &mut decoder) {
+                Ok(Some(Message::Position { epoch, applied })) if epoch == self.history.epoch() => self.history.acknowledge(applied),
+                Ok(Some(Message::Error { message, .. })) => return Err(io::Error::other(message)),
+                Ok(Some(message)) => return Err(unexpected(&message)),
+                Ok(None) => return Err(hung_up()),
+                Err(err) if is_retryable(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let _ = codec::write_message(&mut writer, &Message::Quit).and_then(|()| writer.flush());
+        Ok(())
+    }
+
+    /// The whole file and the number of the last frame it holds, read
+    /// with nothing left unwritten and no statement running.
+    fn snapshot(&self) -> io::Result<(u64, Vec<u8>)> {
+        let mut db = self.connection.write();
+        db.flush().map_err(|err| io::Error::other(err.to_string()))?;
+        let file = self.vfs.open(&self.path, OpenMode::ReadOnly)?;
+        let mut data = vec![0; file.file_size()? as usize];
+        file.read_at(&mut data, 0)?;
+        Ok((self.history.last_seq(), data))
+    }
+}
+
+fn hung_up() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the connection was closed")
+}
+
+fn unexpected(message: &Message) -> io::Error {
+    let name = format!("{:?}", message);
+    let name = name.split([' ', '(']).next().unwrap_or_default();
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected {} message", name))
+}
+
+/// Where a follower has got to: the last frame it applied of the leader
+/// that started at `epoch`. A follower that has copied nothing is at 0, 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Position {
+    epoch: u64,
+    applied: u64,
+}
+
+/// The receiving end: a read-only copy of a leader's database, kept up to
+/// date by the leader through `Session`s of a `Server` given it with
+/// `Server::follower`.
+pub struct Follower {
+    path: PathBuf,
+    /// What the copy is opened with, read-only; its VFS holds the copy and
+    /// the position noted next to it.
+    options: Options,
+    connection: Connection,
+    /// Held while a leader is replicating, so only one does at a time.
+    position: Mutex<Position>,
+    log: Log,
+}
+
+impl Follower {
+    /// Opens the copy at `path` for reading, creating an empty database
+    /// there first if there is none, along with the position noted next to
+    /// it. Short for `Follower::open_with(path, Options::new())`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Follower, DbError> {
+        Self::open_with(path, Options::new())
+    }
+
+    /// Opens the copy at `path` as `open` does, with `options`, which
+    /// choose among other things the VFS that the copy and its position
+    /// are read from and written through. The copy is always read-only.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: Options) -> Result<Follower, DbError> {
+        let path = path.as_ref().to_path_buf();
+        if options.vfs.open(&path, OpenMode::ReadOnly).is_err() {
+            options.clone().read_only(false).create_if_missing(true).open(&path)?.close()?;
+        }
+        let options = options.read_only(true);
+        let connection = Connection::new(options.open(&path)?);
+        let position = read_position(&*options.vfs, &position_path(&path));
+        Ok(Follower { path, options, connection, position: Mutex::new(position), log: Log::default() })
+    }
+
+    /// Notes on `out` where each leader that connects starts from.
+    pub fn log(mut self, out: impl Write + Send + 'static) -> Self {
+        self.log = Log::new(out);
+        self
+    }
+
+    /// The copy, to serve. It is opened anew after each frame, so a
+    /// select sees each frame whole or not at all.
+    pub fn connection(&self) -> Connection {
+        self.connection.clone()
+    }
+
+    /// Takes frames from a leader, which started at `epoch` and has sent
+    /// `Replicate`, until it goes away, a frame cannot be applied, or
+    /// `stop` is interrupted. `decoder` holds what has been read from
+    /// `reader` of the frames after `Replicate`.
+    pub(crate) fn follow(&self, epoch: u64, reader: &mut dyn BufRead, writer: &mut dyn Write, stop: &Interrupt, decoder: &mut Decoder) -> io::Result<()> {
+        let mut position = match self.position.try_lock() {
+            Ok(position) => position,
+            Err(TryLockError::Poisoned(position)) => position.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                let message = Message::Error { code: "LOCKED".to_string(), message: "Another leader is replicating to this server.".to_string() };
+                codec::write_message(writer, &message)?;
+                codec::write_message(writer, &Message::Done)?;
+                return writer.flush();
+            }
+        };
+        if position.epoch == epoch {
+            self.log.entry(&format!("Resuming after frame {}.", position.applied));
+        }
+        let ack = |position: &Position, writer: &mut dyn Write| {
+            codec::write_message(writer, &Message::Position { epoch: position.epoch, applied: position.applied })?;
+            writer.flush()
+        };
+        ack(&position, writer)?;
+
+        let mut unacknowledged = 0;
+        loop {
+            let message = match codec::read_message(reader, decoder) {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(err) if is_retryable(&err) => {
+                    // Nothing more has come, so the leader hears where
+                    // this one got to.
+                    if unacknowledged > 0 {
+                        ack(&position, writer)?;
+                        unacknowledged = 0;
+                    }
+                    if stop.is_interrupted() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    self.refuse(writer, "BAD_FRAME", &format!("Bad frame: {}.", err));
+                    return Err(err);
+                }
+            };
+            let result = match message {
+                Message::Snapshot { seq, data } => self.install(&data).map(|()| {
+                    self.log.entry(&format!("Copied the leader's database as of frame {}.", seq));
+                    Position { epoch, applied: seq }
+                }),
+                Message::Frame { seq, changes } if position.epoch == epoch && seq == position.applied + 1 => {
+                    self.apply(&changes).map(|()| Position { epoch, applied: seq })
+                }
+                Message::Frame { seq, .. } => Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame {} does not follow frame {}", seq, position.applied))),
+                Message::Quit => return ack(&position, writer),
+                message => Err(unexpected(&message)),
+            };
+            match result.and_then(|applied| save_position(&*self.options.vfs, &position_path(&self.path), applied).map(|()| applied)) {
+                Ok(applied) => *position = applied,
+                Err(err) => {
+                    self.refuse(writer, "REPLICATION_FAILED", &format!("Unable to apply the frame: {}.", err));
+                    return Err(err);
+                }
+            }
+            unacknowledged += 1;
+            if unacknowledged >= ACK_EVERY {
+                ack(&position, writer)?;
+                unacknowledged = 0;
+            }
+        }
+    }
+
+    /// Tells the leader why it is being hung up on; it may be gone already.
+    fn refuse(&self, writer: &mut dyn Write, code: &str, message: &str) {
+        let message = Message::Error { code: code.to_string(), message: message.to_string() };
+        let _ = codec::write_message(writer, &message).and_then(|()| codec::write_message(writer, &Message::Done)).and_then(|()| writer.flush());
+    }
+
+    /// Applies one frame's changes to the file and opens it anew, with
+    /// selects waiting meanwhile.
+    fn apply(&self, changes: &[Change]) -> io::Result<()> {
+        let mut db = self.connection.write();
+        let mut file = self.options.vfs.open(&self.path, OpenMode::ReadWrite)?;
+        for change in changes {
+            match change {
+                Change::Write { offset, data } => file.write_at(data, *offset)?,
+                Change::Truncate(len) => file.truncate(*len)?,
+            }
+        }
+        file.sync(false)?;
+        drop(file);
+        self.reopen(&mut db)
+    }
+
+    /// Puts a copy of the leader's whole file in place of this one.
+    fn install(&self, data: &[u8]) -> io::Result<()> {
+        let mut db = self.connection.write();
+        replace_file(&*self.options.vfs, &self.path, "-incoming", data)?;
+        self.reopen(&mut db)
+    }
+
+    /// Opens the copy anew in place of `db`, keeping its interrupt handle
+    /// so that the server can still stop selects on it.
+    fn reopen(&self, db: &mut Database) -> io::Result<()> {
+        let interrupt = db.interrupt_handle();
+        *db = self.options.open(&self.path).map_err(|err| io::Error::other(err.to_string()))?;
+        db.use_interrupt(interrupt);
+        Ok(())
+    }
+}
+
+/// Writes `data` to a file beside `path`, named with `suffix`, syncs it
+/// and moves it over `path`, so that `path` holds either all of the old
+/// bytes or all of the new.
+fn replace_file(vfs: &dyn Vfs, path: &Path, suffix: &str, data: &[u8]) -> io::Result<()> {
+    let new = sibling(path, suffix);
+    let mut file = vfs.open(&new, OpenMode::Create)?;
+    file.truncate(0)?;
+    file.write_at(data, 0)?;
+    file.sync(false)?;
+    drop(file);
+    vfs.rename(&new, path)
+}
+
+/// `path` with `suffix` on the end of its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Where a follower notes its position: next to its file.
+fn position_path(path: &Path) -> PathBuf {
+    sibling(path, "-position")
+}
+
+/// The position noted at `path`; one that is missing or cannot be read is
+/// the start, from which a leader sends a snapshot.
+fn read_position(vfs: &dyn Vfs, path: &Path) -> Position {
+    let text = vfs.open(path, OpenMode::ReadOnly).and_then(|file| {
+        let mut data = vec![0; file.file_size()? as usize];
+        file.read_at(&mut data, 0)?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    });
+    let text = text.unwrap_or_default();
+    match text.split_whitespace().map(str::parse).collect::<Result<Vec<u64>, _>>().as_deref() {
+        Ok([epoch, applied]) => Position { epoch: *epoch, applied: *applied },
+        _ => Position::default(),
+    }
+}
+
+fn save_position(vfs: &dyn Vfs, path: &Path, position: Position) -> io::Result<()> {
+    replace_file(vfs, path, "-new", format!("{} {}\n", position.epoch, position.applied).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use crate::vfs::MemoryVfs;
+
+    fn write(offset: u64, data: &[u8]) -> Change {
+        Change::Write { offset, data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = History::new(7, 100);
+        assert_eq!(history.frames_after(0), Some(Vec::new()));
+        history.commit();
+        assert_eq!(history.last_seq(), 0);
+
+        history.record(write(0, b"a"));
+        history.record(Change::Truncate(10));
+        history.commit();
+        history.record(write(5, b"b"));
+        history.commit();
+        assert_eq!(history.frames_after(0), Some(vec![(1, vec![write(0, b"a"), Change::Truncate(10)]), (2, vec![write(5, b"b")])]));
+        assert_eq!(history.frames_after(1), Some(vec![(2, vec![write(5, b"b")])]));
+        assert_eq!(history.frames_after(2), Some(Vec::new()));
+        assert_eq!(history.frames_after(3), None);
+
+        history.acknowledge(1);
+        assert_eq!(history.frames_after(0), None);
+        assert_eq!(history.frames_after(1), Some(vec![(2, vec![write(5, b"b")])]));
+
+        // Too much unacknowledged, and the oldest frames go.
+        history.record(write(0, &[0; 90]));
+        history.commit();
+        assert_eq!(history.frames_after(1), None);
+        assert_eq!(history.frames_after(2).map(|frames| frames.len()), Some(1));
+    }
+
+    #[test]
+    fn test_frames_rebuild_the_file() {
+        let memory = Arc::new(MemoryVfs::new());
+        let history = Arc::new(History::new(1, MAX_HISTORY_SIZE));
+        let vfs = Arc::new(ReplicatingVfs::new(memory.clone(), "leader.db", history.clone()));
+        let mut db = Options::new().vfs(vfs.clone()).open("leader.db").unwrap();
+        let mut other = Options::new().vfs(vfs).open("other.db").unwrap();
+        for id in 1..=40 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+            other.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+            if id % 10 == 0 {
+                db.flush().unwrap();
+                other.flush().unwrap();
+            }
+        }
+        db.close().unwrap();
+        assert!(history.last_seq() >= 4);
+
+        let mut copy = Vec::new();
+        for (_, changes) in history.frames_after(0).unwrap() {
+            for change in changes {
+                match change {
+                    Change::Write { offset, data } => {
+                        let end = offset as usize + data.len();
+                        copy.resize(copy.len().max(end), 0);
+                        copy[offset as usize..end].copy_from_slice(&data);
+                    }
+                    Change::Truncate(len) => copy.resize(len as usize, 0),
+                }
+            }
+        }
+        assert_eq!(Some(copy), memory.contents(Path::new("leader.db")));
+    }
+
+    #[test]
+    fn test_following_through_a_vfs() {
+        let leader = Arc::new(MemoryVfs::new());
+        let history = Arc::new(History::new(5, MAX_HISTORY_SIZE));
+        let mut db = Options::new().vfs(Arc::new(ReplicatingVfs::new(leader.clone(), "leader.db", history.clone()))).open("leader.db").unwrap();
+        db.execute("insert 1 a a@x").unwrap();
+        db.flush().unwrap();
+        let snapshot = Message::Snapshot { seq: history.last_seq(), data: leader.contents(Path::new("leader.db")).unwrap() };
+        db.execute("insert 2 a a@x").unwrap();
+        db.flush().unwrap();
+        let applied = history.last_seq();
+        let frame = Message::Frame { seq: applied, changes: history.frames_after(snapshot_seq(&snapshot)).unwrap().remove(0).1 };
+
+        // Nothing of the copy reaches the disk.
+        let path = TempPath::new("follower-vfs.db");
+        let memory = Arc::new(MemoryVfs::new());
+        let options = Options::new().vfs(memory.clone());
+        let follower = Follower::open_with(&path, options.clone()).unwrap();
+        let (result, _) = follow(&follower, 5, &[snapshot, frame]);
+        result.unwrap();
+        assert_eq!(rows(&follower.connection()), [1, 2]);
+        assert_eq!(memory.contents(&path), leader.contents(Path::new("leader.db")));
+        assert_eq!(memory.contents(&position_path(&path)).unwrap(), format!("5 {}\n", applied).into_bytes());
+        assert!(!path.exists() && !position_path(&path).exists());
+
+        // Opened again on the same files, it resumes where it got to.
+        drop(follower);
+        let follower = Follower::open_with(&path, options).unwrap();
+        let (result, replies) = follow(&follower, 5, &[Message::Quit]);
+        result.unwrap();
+        assert_eq!(replies, [Message::Position { epoch: 5, applied }, Message::Position { epoch: 5, applied }]);
+        db.close().unwrap();
+    }
+
+    fn snapshot_seq(message: &Message) -> u64 {
+        match message {
+            Message::Snapshot { seq, .. } => *seq,
+            _ => unreachable!(),
+        }
+    }
+
+    /// A follower of a new database in `dir`, a directory of its own.
+    fn follower(dir: &Path) -> (Follower, PathBuf) {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join("follower.db");
+        (Follower::open(&path).unwrap(), path)
+    }
+
+    /// Sends `messages` to `follower` as the leader at `epoch`, and returns
+    /// how that ended and what the follower sent back.
+    fn follow(follower: &Follower, epoch: u64, messages: &[Message]) -> (io::Result<()>, Vec<Message>) {
+        let input: Vec<u8> = messages.iter().flat_map(Message::encode).collect();
+        let mut writer = Vec::new();
+        let result = follower.follow(epoch, &mut &input[..], &mut writer, &Interrupt::default(), &mut Decoder::default());
+        let (mut output, mut decoder, mut replies) = (&writer[..], Decoder::default(), Vec::new());
+        while let Some(message) = codec::read_message(&mut output, &mut decoder).unwrap() {
+            replies.push(message);
+        }
+        (result, replies)
+    }
+
+    fn rows(connection: &Connection) -> Vec<u64> {
+        connection.select("select").unwrap().iter().map(|row| row.id).collect()
+    }
+
+    #[test]
+    fn test_following() {
+        // A leader's frames, from a database on the side.
+        let memory = Arc::new(MemoryVfs::new());
+        let history = Arc::new(History::new(5, MAX_HISTORY_SIZE));
+        let mut db = Options::new().vfs(Arc::new(ReplicatingVfs::new(memory.clone(), "leader.db", history.clone()))).open("leader.db").unwrap();
+        db.execute("insert 1 a a@x").unwrap();
+        db.flush().unwrap();
+        let snapshot = Message::Snapshot { seq: history.last_seq(), data: memory.contents(Path::new("leader.db")).unwrap() };
+        let first = history.last_seq();
+        for id in 2..=3 {
+            db.execute(&format!("insert {} a a@x", id)).unwrap();
+            db.flush().unwrap();
+        }
+        let frames: Vec<Message> = history.frames_after(first).unwrap().into_iter().map(|(seq, changes)| Message::Frame { seq, changes }).collect();
+        assert_eq!(frames.len(), 2);
+
+        let dir = TempPath::new("follower");
+        let (follower, path) = follower(&dir);
+        assert!(rows(&follower.connection()).is_empty());
+        let (result, replies) = follow(&follower, 5, &[snapshot, frames[0].clone()]);
+        result.unwrap();
+        assert_eq!(replies, [Message::Position { epoch: 0, applied: 0 }]);
+        assert_eq!(rows(&follower.connection()), [1, 2]);
+        assert!(follower.connection().execute("insert 9 a a@x").is_err());
+
+        // Opened again, it knows where it got to; the next frame carries on
+        // and a quit gets the position acknowledged.
+        drop(follower);
+        let follower = Follower::open(&path).unwrap();
+        let (result, replies) = follow(&follower, 5, &[frames[1].clone(), Message::Quit]);
+        result.unwrap();
+        assert_eq!(replies, [Message::Position { epoch: 5, applied: first + 1 }, Message::Position { epoch: 5, applied: first + 2 }]);
+        assert_eq!(rows(&follower.connection()), [1, 2, 3]);
+        db.close().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), memory.contents(Path::new("leader.db")).unwrap());
+
+        // A frame out of order, or one from another leader, is refused.
+        for (epoch, frame) in [(5, frames[0].clone()), (6, frames[1].clone())] {
+            let (result, replies) = follow(&follower, epoch, &[frame]);
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert!(matches!(&replies[1], Message::Error { code, .. } if code == "REPLICATION_FAILED"), "{:?}", replies);
+        }
+
+        // As is a damaged one, whose checksum does not match.
+        let mut damaged = Message::Frame { seq: first + 3, changes: vec![write(0, &[1; 8])] }.encode();
+        damaged[20] ^= 0xff;
+        let mut writer = Vec::new();
+        let err = follower.follow(5, &mut &damaged[..], &mut writer, &Interrupt::default(), &mut Decoder::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(rows(&follower.connection()), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,489 @@
+use std::fmt;
+
+use crate::error::ErrorCode;
+use crate::format::{
+    read_u16, read_u64, AGE_SIZE, EMAIL_LEN_SIZE, ID_OFFSET, USERNAME_LEN_OFFSET, USERNAME_OFFSET,
+};
+use crate::pager::PAGE_SIZE;
+use crate::typed::FromValue;
+
+pub const DEFAULT_USERNAME_SIZE: usize = 32;
+pub const DEFAULT_EMAIL_SIZE: usize = 255;
+
+
+/// Size of a row with the default column widths.
+pub const ROW_SIZE: usize = RowLayout::DEFAULT.row_size();
+
+/// Column widths of a table, fixed when the database is created, and the
+/// byte offsets of each field that follow from them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowLayout {
+    username_size: usize,
+    email_size: usize,
+}
+
+impl Default for RowLayout {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RowLayout {
+    pub const DEFAULT: RowLayout = RowLayout {
+        username_size: DEFAULT_USERNAME_SIZE,
+        email_size: DEFAULT_EMAIL_SIZE,
+    };
+
+    pub fn new(username_size: usize, email_size: usize) -> Result<Self, RowError> {
+        let layout = RowLayout { username_size, email_size };
+        let fits_length_fields = username_size <= u8::MAX as usize && email_size <= u16::MAX as usize;
+        if username_size == 0 || email_size == 0 || !fits_length_fields || layout.row_size() > PAGE_SIZE {
+            return Err(RowError::BadLayout { username_size, email_size });
+        }
+        Ok(layout)
+    }
+
+    pub fn username_size(&self) -> usize {
+        self.username_size
+    }
+
+    pub fn email_size(&self) -> usize {
+        self.email_size
+    }
+
+    const fn email_len_offset(&self) -> usize {
+        USERNAME_OFFSET + self.username_size
+    }
+
+    const fn email_offset(&self) -> usize {
+        self.email_len_offset() + EMAIL_LEN_SIZE
+    }
+
+    const fn age_offset(&self) -> usize {
+        self.email_offset() + self.email_size
+    }
+
+    pub const fn row_size(&self) -> usize {
+        self.age_offset() + AGE_SIZE
+    }
+
+    /// Checks that every value of `row` fits its column.
+    pub fn check(&self, row: &Row) -> Result<(), RowError> {
+        if row.username.len() > self.username_size {
+            return Err(RowError::ValueTooLong { column: "username", max: self.username_size, got: row.username.len() });
+        }
+        if row.email.len() > self.email_size {
+            return Err(RowError::ValueTooLong { column: "email", max: self.email_size, got: row.email.len() });
+        }
+        Ok(())
+    }
+
+    /// Writes `row` into `dst`, which must be exactly `row_size()` bytes
+    /// long. The row must already have passed `check`.
+    pub fn serialize_into(&self, row: &Row, dst: &mut [u8]) {
+        assert_eq!(dst.len(), self.row_size(), "row slot has the wrong size");
+        assert!(self.check(row).is_ok(), "row does not fit the table layout");
+
+        let email_offset = self.email_offset();
+        dst.fill(0);
+        dst[ID_OFFSET..USERNAME_LEN_OFFSET].copy_from_slice(&row.id.to_le_bytes());
+        dst[USERNAME_LEN_OFFSET] = row.username.len() as u8;
+        dst[USERNAME_OFFSET..USERNAME_OFFSET + row.username.len()].copy_from_slice(row.username.as_bytes());
+        dst[self.email_len_offset()..email_offset].copy_from_slice(&(row.email.len() as u16).to_le_bytes());
+        dst[email_offset..email_offset + row.email.len()].copy_from_slice(row.email.as_bytes());
+        dst[self.age_offset()..].copy_from_slice(&row.age.to_le_bytes());
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RowError {
+    TruncatedData { expected: usize, got: usize },
+    ValueTooLong { column: &'static str, max: usize, got: usize },
+    BadLength { column: &'static str, max: usize, got: usize },
+    InvalidUtf8 { column: &'static str },
+    BadLayout { username_size: usize, email_size: usize },
+    ChecksumMismatch { id: u64 },
+    /// `RowRef::get` asked for a column the table does not have.
+    NoSuchColumn { column: String },
+    /// `RowRef::get` asked for a column as a type its value does not fit.
+    TypeMismatch { column: String, expected: &'static str, found: String },
+}
+
+impl RowError {
+    /// A short, stable name for the kind of error: `VALUE_TOO_LONG`,
+    /// `BAD_LAYOUT`, `CHECKSUM_MISMATCH`, or `CORRUPT` for stored data that
+    /// cannot be decoded.
+    pub fn code(&self) -> &'static str {
+        self.error_code().as_str()
+    }
+
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            RowError::ValueTooLong { .. } => ErrorCode::ValueTooLong,
+            RowError::BadLayout { .. } => ErrorCode::BadLayout,
+            RowError::ChecksumMismatch { .. } => ErrorCode::ChecksumMismatch,
+            RowError::NoSuchColumn { .. } => ErrorCode::NoSuchColumn,
+            RowError::TypeMismatch { .. } => ErrorCode::TypeMismatch,
+            RowError::TruncatedData { .. } | RowError::BadLength { .. } | RowError::InvalidUtf8 { .. } => ErrorCode::Corrupt,
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::TruncatedData { expected, got } => {
+                write!(f, "row data is {} bytes, expected {}", got, expected)
+            }
+            RowError::ValueTooLong { column, max, got } => {
+                write!(f, "{} is {} bytes, at most {} allowed", column, got, max)
+            }
+            RowError::BadLength { column, max, got } => {
+                write!(f, "stored {} length {} exceeds column width {}", column, got, max)
+            }
+            RowError::InvalidUtf8 { column } => write!(f, "stored {} is not valid UTF-8", column),
+            RowError::ChecksumMismatch { id } => write!(f, "checksum mismatch for row with id {}", id),
+            RowError::NoSuchColumn { column } => write!(f, "no column named '{}'", column),
+            RowError::TypeMismatch { column, expected, found } => write!(f, "cannot read {} ({}) as {}", column, found, expected),
+            RowError::BadLayout { username_size, email_size } => write!(
+                f,
+                "column widths username={} email={} are not supported (username at most 255 bytes, row at most one page)",
+                username_size, email_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Column names of the table, in storage and display order.
+pub const COLUMNS: [&str; 4] = ["id", "username", "email", "age"];
+
+/// A single column value, borrowed from a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// No value. Nothing in the built-in schema is nullable yet, but every
+    /// output mode already knows how to show one.
+    Null,
+    Integer(u64),
+    Text(&'a str),
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("NULL"),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub id: u64,
+    username: String,
+    email: String,
+    pub age: u16,
+}
+
+impl Row {
+    pub(crate) fn new(id: u64, username: &str, email: &str) -> Self {
+        Row {
+            id,
+            username: username.to_string(),
+            email: email.to_string(),
+            age: 0,
+        }
+    }
+
+    pub fn with_age(mut self, age: u16) -> Self {
+        self.age = age;
+        self
+    }
+
+    /// Builds a row, rejecting values that do not fit the default column
+    /// widths.
+    pub fn try_new(id: u64, username: &str, email: &str) -> Result<Self, RowError> {
+        let row = Row::new(id, username, email);
+        RowLayout::DEFAULT.check(&row)?;
+        Ok(row)
+    }
+
+    /// Serializes with the default column widths.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![0; ROW_SIZE];
+        self.serialize_into(&mut result);
+        result
+    }
+
+    /// Writes the row straight into `dst` using the default column widths.
+    /// `dst` must be exactly `ROW_SIZE` bytes long.
+    pub fn serialize_into(&self, dst: &mut [u8]) {
+        RowLayout::DEFAULT.serialize_into(self, dst);
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, RowError> {
+        Self::deserialize_from(data)
+    }
+
+    pub fn deserialize_from(src: &[u8]) -> Result<Self, RowError> {
+        RowRef::new(src)?.to_row()
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    /// The row's values in `COLUMNS` order.
+    pub fn values(&self) -> [Value<'_>; 4] {
+        [
+            Value::Integer(self.id),
+            Value::Text(&self.username),
+            Value::Text(&self.email),
+            Value::Integer(self.age as u64),
+        ]
+    }
+
+    pub fn print(&self) {
+        println!("{}", self);
+    }
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {}, {}, {})", self.id, self.username(), self.email(), self.age)
+    }
+}
+
+/// A read-only view of a serialized row that borrows the slot bytes rather
+/// than copying them. Columns are decoded only when asked for.
+#[derive(Clone, Copy)]
+pub struct RowRef<'a> {
+    data: &'a [u8],
+    layout: RowLayout,
+}
+
+impl<'a> RowRef<'a> {
+    /// A view of a row serialized with the default column widths.
+    pub fn new(data: &'a [u8]) -> Result<Self, RowError> {
+        Self::with_layout(data, RowLayout::DEFAULT)
+    }
+
+    pub fn with_layout(data: &'a [u8], layout: RowLayout) -> Result<Self, RowError> {
+        if data.len() != layout.row_size() {
+            return Err(RowError::TruncatedData { expected: layout.row_size(), got: data.len() });
+        }
+
+        let row = RowRef { data, layout };
+        let username_len = row.username_len();
+        if username_len > layout.username_size {
+            return Err(RowError::BadLength { column: "username", max: layout.username_size, got: username_len });
+        }
+        let email_len = row.email_len();
+        if email_len > layout.email_size {
+            return Err(RowError::BadLength { column: "email", max: layout.email_size, got: email_len });
+        }
+        Ok(row)
+    }
+
+    fn username_len(&self) -> usize {
+        self.data[USERNAME_LEN_OFFSET] as usize
+    }
+
+    fn email_len(&self) -> usize {
+        read_u16(self.data, self.layout.email_len_offset()) as usize
+    }
+
+    pub fn id(&self) -> u64 {
+        read_u64(self.data, ID_OFFSET)
+    }
+
+    pub fn age(&self) -> u16 {
+        read_u16(self.data, self.layout.age_offset())
+    }
+
+    /// The stored username bytes, exactly as long as the value.
+    pub fn username(&self) -> &'a [u8] {
+        &self.data[USERNAME_OFFSET..USERNAME_OFFSET + self.username_len()]
+    }
+
+    /// The stored email bytes, exactly as long as the value.
+    pub fn email(&self) -> &'a [u8] {
+        let offset = self.layout.email_offset();
+        &self.data[offset..offset + self.email_len()]
+    }
+
+    /// The value of the column named `column`, one of `COLUMNS`.
+    pub fn value(&self, column: &str) -> Result<Value<'a>, RowError> {
+        let text = |bytes, column| std::str::from_utf8(bytes).map(Value::Text).map_err(|_| RowError::InvalidUtf8 { column });
+        match column {
+            "id" => Ok(Value::Integer(self.id())),
+            "username" => text(self.username(), "username"),
+            "email" => text(self.email(), "email"),
+            "age" => Ok(Value::Integer(self.age().into())),
+            _ => Err(RowError::NoSuchColumn { column: column.to_string() }),
+        }
+    }
+
+    /// The value of the column named `column` as a `T`, such as a `u32`
+    /// for `id` or a `&str` borrowed from the page for `username`.
+    pub fn get<T: FromValue<'a>>(&self, column: &str) -> Result<T, RowError> {
+        let value = self.value(column)?;
+        T::from_value(value).ok_or_else(|| RowError::TypeMismatch {
+            column: column.to_string(),
+            expected: T::EXPECTED,
+            found: match value {
+                Value::Null => "NULL".to_string(),
+                Value::Integer(n) => format!("integer {}", n),
+                Value::Text(_) => "text".to_string(),
+            },
+        })
+    }
+
+    pub fn to_row(&self) -> Result<Row, RowError> {
+        let username = std::str::from_utf8(self.username())
+            .map_err(|_| RowError::InvalidUtf8 { column: "username" })?;
+        let email = std::str::from_utf8(self.email())
+            .map_err(|_| RowError::InvalidUtf8 { column: "email" })?;
+
+        Ok(Row {
+            id: self.id(),
+            username: username.to_string(),
+            email: email.to_string(),
+            age: self.age(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_into_matches_serialize() {
+        let row = Row::new(7, "username", "email@email.com");
+
+        let mut slot = [0xffu8; ROW_SIZE];
+        row.serialize_into(&mut slot);
+
+        assert_eq!(row.serialize(), slot.to_vec());
+    }
+
+    #[test]
+    fn test_deserialize_from_round_trip() {
+        let row = Row::new(7, "username", "email@email.com").with_age(41);
+        let decoded = Row::deserialize_from(&row.serialize()).unwrap();
+
+        assert_eq!(decoded, row);
+    }
+
+    #[test]
+    fn test_row_ref_reads_columns_in_place() {
+        let row = Row::new(42, "alice", "alice@example.com");
+        let data = row.serialize();
+        let row_ref = RowRef::new(&data).unwrap();
+
+        assert_eq!(row_ref.id(), 42);
+        assert_eq!(row_ref.username(), b"alice");
+        assert_eq!(row_ref.email().as_ptr(), data[RowLayout::DEFAULT.email_offset()..].as_ptr());
+        assert_eq!(row_ref.to_row().unwrap(), row);
+    }
+
+    #[test]
+    fn test_string_accessors() {
+        let row = Row::new(1, "alice", "alice@example.com");
+
+        assert_eq!(row.username(), "alice");
+        assert_eq!(row.email(), "alice@example.com");
+        assert_eq!(row.to_string(), "(1, alice, alice@example.com, 0)");
+        assert!(!row.to_string().contains('\0'));
+    }
+
+    #[test]
+    fn test_custom_layout_round_trip() {
+        let layout = RowLayout::new(64, 16).unwrap();
+        let row = Row::new(3, &"u".repeat(64), &"e".repeat(16)).with_age(9);
+
+        let mut data = vec![0u8; layout.row_size()];
+        layout.serialize_into(&row, &mut data);
+
+        assert_eq!(layout.row_size(), 8 + 1 + 64 + 2 + 16 + 2);
+        assert_eq!(RowRef::with_layout(&data, layout).unwrap().to_row().unwrap(), row);
+        assert_eq!(
+            layout.check(&Row::new(3, "u", &"e".repeat(17))),
+            Err(RowError::ValueTooLong { column: "email", max: 16, got: 17 })
+        );
+    }
+
+    #[test]
+    fn test_layout_limits() {
+        assert!(RowLayout::new(255, 255).is_ok());
+        assert!(RowLayout::new(256, 255).is_err());
+        assert!(RowLayout::new(0, 255).is_err());
+        assert!(RowLayout::new(32, PAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_embedded_nul_round_trips() {
+        let row = Row::new(1, "al\0ice", "a@b.c\0");
+        let decoded = Row::deserialize(&row.serialize()).unwrap();
+
+        assert_eq!(decoded.username(), "al\0ice");
+        assert_eq!(decoded.email(), "a@b.c\0");
+    }
+
+    #[test]
+    fn test_serialized_layout() {
+        let data = Row::new(0x0102030405060708, "bob", "b@x").with_age(0x0201).serialize();
+
+        let mut expected = vec![0u8; ROW_SIZE];
+        expected[0..8].copy_from_slice(&[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        expected[8] = 3;
+        expected[9..12].copy_from_slice(b"bob");
+        expected[41..43].copy_from_slice(&[3, 0]);
+        expected[43..46].copy_from_slice(b"b@x");
+        expected[298..300].copy_from_slice(&[0x01, 0x02]);
+
+        assert_eq!(ROW_SIZE, 300);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_deserialize_rejects_bad_lengths() {
+        let mut data = Row::new(1, "bob", "b@x").serialize();
+        data[USERNAME_LEN_OFFSET] = 33;
+        assert_eq!(
+            Row::deserialize(&data),
+            Err(RowError::BadLength { column: "username", max: 32, got: 33 })
+        );
+    }
+
+    #[test]
+    fn test_try_new_validates_lengths() {
+        assert_eq!(Row::try_new(1, "alice", "a@b.c").unwrap(), Row::new(1, "alice", "a@b.c"));
+        assert_eq!(
+            Row::try_new(1, &"a".repeat(33), "a@b.c"),
+            Err(RowError::ValueTooLong { column: "username", max: 32, got: 33 })
+        );
+        assert_eq!(
+            Row::try_new(1, "alice", &"a".repeat(256)),
+            Err(RowError::ValueTooLong { column: "email", max: 255, got: 256 })
+        );
+    }
+
+    #[test]
+    fn test_deserialize_wrong_length() {
+        for len in [0, 4, 290, 292] {
+            let data = vec![0u8; len];
+            assert_eq!(
+                Row::deserialize(&data).err(),
+                Some(RowError::TruncatedData { expected: ROW_SIZE, got: len })
+            );
+        }
+    }
+}
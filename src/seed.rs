@@ -0,0 +1,121 @@
+//! Synthetic rows for benchmarks and demos. Rows come from a seeded
+//! generator, so the same seed on the same table always inserts the same
+//! rows.
+
+use std::ops::ControlFlow;
+
+use crate::compiler::ExecuteError;
+use crate::database::Database;
+use crate::error::DbError;
+use crate::rng::SplitMix64;
+use crate::row::Row;
+
+const NAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy", "mallory", "niaj", "olivia",
+    "peggy", "rupert", "sybil", "trent", "victor", "walter", "yolanda",
+];
+const DOMAINS: &[&str] = &["example.com", "example.org", "example.net", "mail.test"];
+
+/// Inserts `count` generated rows into `db`, calling `progress` with the
+/// number inserted so far after each one. Ids follow on from the largest
+/// id in the table, or with `random_ids` are drawn from `rng_seed`'s
+/// generator, skipping any already taken.
+pub(crate) fn seed_rows(
+    db: &mut Database,
+    count: usize,
+    random_ids: bool,
+    rng_seed: u64,
+    progress: &mut dyn FnMut(usize),
+) -> Result<usize, DbError> {
+    let mut rng = SplitMix64::new(rng_seed);
+    let options = db.options();
+    let mut max_id = None;
+    db.execute_with::<()>("select", |row| {
+        max_id = max_id.max(Some(row.id()));
+        ControlFlow::Continue(())
+    })?;
+    let mut next_id = max_id.map_or(1, |id: u64| id.wrapping_add(1));
+
+    for seeded in 1..=count {
+        let name = NAMES[rng.below(NAMES.len() as u64) as usize];
+        let mut username = format!("{}{}", name, rng.below(10_000));
+        let mut email = format!("{}@{}", username, DOMAINS[rng.below(DOMAINS.len() as u64) as usize]);
+        // Generated text is ASCII, so any byte length is a char boundary.
+        username.truncate(options.username_size);
+        email.truncate(options.email_size);
+        let age = rng.below(100) as u16;
+
+        loop {
+            let id = if random_ids { rng.next_u64() } else { next_id };
+            next_id = next_id.wrapping_add(1);
+            match db.insert_row(&Row::new(id, &username, &email).with_age(age)) {
+                Err(DbError::Execute(ExecuteError::DuplicateKey)) if random_ids => continue,
+                Err(err) => return Err(err),
+                Ok(_) => break,
+            }
+        }
+        progress(seeded);
+        if db.interrupted() {
+            return Err(DbError::Interrupted);
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Options;
+
+    fn seed(db: &mut Database, count: usize, random_ids: bool, rng_seed: u64) -> Vec<Row> {
+        seed_rows(db, count, random_ids, rng_seed, &mut |_| {}).unwrap();
+        db.query("select").unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_seed_rows() {
+        let mut db = Database::open_in_memory();
+        assert_eq!(
+            seed(&mut db, 3, false, 42),
+            vec![
+                Row::new(1, "peggy2291", "peggy2291@example.net").with_age(64),
+                Row::new(2, "mallory9062", "mallory9062@example.org").with_age(8),
+                Row::new(3, "frank8974", "frank8974@mail.test").with_age(46),
+            ]
+        );
+
+        // Sequential ids carry on from the largest one in the table.
+        db.execute("insert 100 zed zed@x").unwrap();
+        let rows = seed(&mut db, 2, false, 42);
+        assert_eq!(rows.iter().map(|row| row.id).collect::<Vec<_>>(), [1, 2, 3, 100, 101, 102]);
+    }
+
+    #[test]
+    fn test_same_seed_same_rows() {
+        for random_ids in [false, true] {
+            let first = seed(&mut Database::open_in_memory(), 200, random_ids, 7);
+            let second = seed(&mut Database::open_in_memory(), 200, random_ids, 7);
+            assert_eq!(first, second);
+            assert_ne!(first, seed(&mut Database::open_in_memory(), 200, random_ids, 8));
+        }
+    }
+
+    #[test]
+    fn test_seed_rows_fit_narrow_columns() {
+        let options = Options { username_size: 4, email_size: 6, ..Options::default() };
+        let mut db = Database::open_in_memory_with(options).unwrap();
+        let rows = seed(&mut db, 50, true, 1);
+        assert_eq!(rows.len(), 50);
+        assert!(rows.iter().all(|row| row.username().len() <= 4 && row.email().len() <= 6));
+    }
+
+    #[test]
+    fn test_seed_rows_reports_progress_and_stops_when_full() {
+        let mut db = Database::open_in_memory();
+        let mut last = 0;
+        let err = seed_rows(&mut db, 100_000, false, 0, &mut |seeded| last = seeded).unwrap_err();
+        assert!(matches!(err, DbError::Execute(ExecuteError::TableFull)));
+        assert!(last > 0);
+        assert_eq!(db.query("select").unwrap().count(), last);
+    }
+}
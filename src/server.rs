@@ -0,0 +1,920 @@
+//! `VoidDB serve`: runs statements sent over TCP, or a Unix socket,
+//! against one database, speaking the line protocol in `protocol` or, to a
+//! client that opens with its magic bytes, the binary one in `codec`.
+//!
+//! Each client is served on a thread of its own, up to a limit on how many
+//! are served at once. Each gets a `Session` of its own on a handle to the
+//! shared `Connection`, so a session keeps nothing but its output mode
+//! between requests. Selects from different clients run at once, and other
+//! statements one at a time, as `Connection` allows.
+//!
+//! A server given a token with `auth_token` serves only clients that send
+//! `AUTH <token>` as their first request, and turns the rest away; see
+//! `protocol`.
+//!
+//! A server given a `Follower` with `follower` serves its read-only copy,
+//! and takes the frames a leader sends to keep it up to date; see
+//! `replication`.
+//!
+//! A statement holds the connection's lock only while it runs, so a client
+//! that goes away, even in the middle of a request, leaves nothing locked
+//! behind.
+//!
+//! A server that is stopped accepts no more clients, and gives the
+//! statements its clients are running up to its grace period to finish
+//! before it interrupts them. Each client is hung up on once its response
+//! is sent.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::auth::Token;
+use crate::codec::{self, Decoder, Message};
+use crate::compiler::{prepare, split_statements, PrepareError, QueryResult, StatementType};
+use crate::connection::Connection;
+use crate::display::{Mode, Printer, Settings};
+use crate::error::DbError;
+use crate::interrupt::Interrupt;
+use crate::net::{Address, Listener, Stream};
+use crate::output::Captured;
+use crate::protocol;
+use crate::replication::Follower;
+
+/// How long the server waits on a client or for one to connect before it
+/// looks again at whether it has been told to stop.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many clients a server serves at once unless told otherwise.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// How long a server that has been stopped lets running statements finish
+/// unless told otherwise.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// A listening server.
+pub struct Server {
+    listener: Listener,
+    connection: Connection,
+    stop: Interrupt,
+    max_connections: usize,
+    grace_period: Duration,
+    statement_timeout: Option<Duration>,
+    token: Option<Token>,
+    log: Log,
+    follower: Option<Arc<Follower>>,
+}
+
+/// Where a server notes the clients it turns away.
+#[derive(Default)]
+pub(crate) struct Log(Option<Mutex<Box<dyn Write + Send>>>);
+
+impl Log {
+    pub(crate) fn new(out: impl Write + Send + 'static) -> Log {
+        Log(Some(Mutex::new(Box::new(out))))
+    }
+
+    /// Writes `line`; a log that cannot be written to is not worth failing
+    /// a client over.
+    pub(crate) fn entry(&self, line: &str) {
+        if let Some(out) = &self.0 {
+            let mut out = out.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = writeln!(out, "{}", line).and_then(|()| out.flush());
+        }
+    }
+}
+
+impl Server {
+    /// Listens on `address`, as `Listener::bind` does.
+    pub fn bind(address: &Address, connection: Connection) -> io::Result<Server> {
+        let listener = Listener::bind(address)?;
+        // Accepting without blocking lets `run` notice `stop` between
+        // clients.
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener,
+            connection,
+            stop: Interrupt::default(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            statement_timeout: None,
+            token: None,
+            log: Log::default(),
+            follower: None,
+        })
+    }
+
+    /// Serves only clients that send `token` first.
+    pub fn auth_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Notes each client turned away for want of the token on `out`, a
+    /// line each.
+    pub fn log(mut self, out: impl Write + Send + 'static) -> Self {
+        self.log = Log::new(out);
+        self
+    }
+
+    /// Takes frames for `follower` from a leader that connects, as well as
+    /// serving it. The server's connection should be the follower's.
+    pub fn follower(mut self, follower: Arc<Follower>) -> Self {
+        self.follower = Some(follower);
+        self
+    }
+
+    /// Serves at most `max` clients at once, at least one. Clients beyond
+    /// that wait to be accepted until another leaves.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max.max(1);
+        self
+    }
+
+    /// Once stopped, lets the statements being run go on for up to
+    /// `grace_period` before interrupting them.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Stops a statement that runs longer than `timeout`, which then fails
+    /// with `TIMEOUT` for its client alone. A client can change its own
+    /// with `.statement_timeout`.
+    pub fn statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<Address> {
+        self.listener.local_addr()
+    }
+
+    /// A handle that makes `run` stop accepting clients once it is
+    /// interrupted, and return after the requests being run, if any, have
+    /// their responses.
+    pub fn stop_handle(&self) -> Interrupt {
+        self.stop.clone()
+    }
+
+    /// Serves clients until stopped. A client that goes away, or whose
+    /// connection fails, ends only its own session.
+    pub fn run(&self) -> io::Result<()> {
+        let draining = Draining { statements: self.connection.read().interrupt_handle(), grace_period: self.grace_period };
+        accept_until_stopped(&self.listener, &self.stop, self.max_connections, &draining, |stream| self.serve(stream))
+    }
+
+    fn serve(&self, stream: Stream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let peer = stream.peer();
+        let mut writer = BufWriter::new(stream);
+        let mut session = Session::new(self.connection.clone());
+        if let Some(timeout) = self.statement_timeout {
+            session = session.statement_timeout(timeout);
+        }
+        if let Some(token) = &self.token {
+            session = session.require_token(token.clone());
+        }
+        if let Some(follower) = &self.follower {
+            session = session.following(follower.clone());
+        }
+        let result = match wants_binary(&mut reader, &self.stop) {
+            Ok(true) => session.run_binary(&mut reader, &mut writer, &self.stop),
+            Ok(false) => session.run(&mut reader, &mut writer, &self.stop),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &result {
+            if err.kind() == io::ErrorKind::PermissionDenied {
+                self.log.entry(&format!("Turned away {}: {}", peer, err));
+            }
+        }
+        result
+    }
+}
+
+/// Whether the client opened with the binary protocol's magic bytes,
+/// waiting until it has sent something, or hung up, or `stop` is
+/// interrupted.
+fn wants_binary(reader: &mut dyn BufRead, stop: &Interrupt) -> io::Result<bool> {
+    while !stop.is_interrupted() {
+        match reader.fill_buf() {
+            Ok(buf) => return Ok(buf.first() == Some(&codec::MAGIC[0])),
+            Err(err) if is_retryable(&err) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(false)
+}
+
+/// Whether a read failed only for want of anything to read yet.
+pub(crate) fn is_retryable(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted)
+}
+
+/// How a stopped server sees off the clients it is still serving.
+pub(crate) struct Draining {
+    /// Stops the statements running on the server's database.
+    pub(crate) statements: Interrupt,
+    /// How long they have to finish before `statements` is interrupted.
+    pub(crate) grace_period: Duration,
+}
+
+/// Hands each client that connects to `listener`, which must not block, to
+/// `serve` on a thread of its own, with at most `max_connections` served at
+/// once, until `stop` is interrupted. Returns once every client has been
+/// seen off, interrupting the statements still running once `draining`'s
+/// grace period is up. What becomes of one client does not stop the others
+/// being served.
+///
+/// If accepting fails, `stop` is interrupted so that the clients already
+/// being served are let go.
+pub(crate) fn accept_until_stopped(
+    listener: &Listener,
+    stop: &Interrupt,
+    max_connections: usize,
+    draining: &Draining,
+    serve: impl Fn(Stream) -> io::Result<()> + Sync,
+) -> io::Result<()> {
+    let active = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let mut result = Ok(());
+        while !stop.is_interrupted() {
+            if active.load(Ordering::SeqCst) >= max_connections {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            match listener.accept() {
+                Ok(stream) => {
+                    active.fetch_add(1, Ordering::SeqCst);
+                    let (active, serve) = (&active, &serve);
+                    scope.spawn(move || {
+                        let _ = serve(stream);
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(POLL_INTERVAL),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    stop.interrupt();
+                    result = Err(err);
+                }
+            }
+        }
+        // Sessions end after the request they are running, if any.
+        let _deadline = (active.load(Ordering::SeqCst) > 0).then(|| draining.statements.after(draining.grace_period));
+        while active.load(Ordering::SeqCst) > 0 {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        result
+    })
+}
+
+/// One client's requests and the settings they have made.
+pub struct Session {
+    connection: Connection,
+    settings: Settings,
+    /// The token the client has yet to send, if it must.
+    token: Option<Token>,
+    /// Whether the client has sent the token, in either protocol, which
+    /// lets it shut the server down. A client of a server that asks for
+    /// no token never has, so no client can shut that server down.
+    authenticated: bool,
+    /// How long a statement may run, if there is a limit.
+    timeout: Option<Duration>,
+    /// The copy a leader may send frames for.
+    follower: Option<Arc<Follower>>,
+}
+
+impl Session {
+    /// A session starting in CSV mode.
+    pub fn new(connection: Connection) -> Self {
+        // No summary lines: a response ends with its end line instead.
+        let settings = Settings { mode: Mode::Csv, quiet: true, ..Settings::default() };
+        Session { connection, settings, token: None, authenticated: false, timeout: None, follower: None }
+    }
+
+    /// Makes the client send `AUTH <token>` before anything else.
+    pub fn require_token(mut self, token: Token) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Stops a statement that runs longer than `timeout`, as
+    /// `Server::statement_timeout` does.
+    pub fn statement_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Lets a leader that connects send frames for `follower`.
+    pub fn following(mut self, follower: Arc<Follower>) -> Self {
+        self.follower = Some(follower);
+        self
+    }
+
+    /// Answers requests read from `reader` until the client sends `.quit`
+    /// or hangs up, or `stop` is interrupted. A read that times out is
+    /// tried again.
+    ///
+    /// A client that does not authenticate itself when it must is told so
+    /// and the session fails with `PermissionDenied`.
+    pub fn run(&mut self, reader: &mut dyn BufRead, writer: &mut dyn Write, stop: &Interrupt) -> io::Result<()> {
+        let mut buf = Vec::new();
+        while !stop.is_interrupted() {
+            let line = match protocol::read_line(reader, &mut buf) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) if is_retryable(&err) => continue,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    protocol::write_error(writer, "USAGE", "Requests must be UTF-8.")?;
+                    protocol::write_end(writer)?;
+                    writer.flush()?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if line.trim() == ".quit" {
+                break;
+            }
+            if let Some(token) = &self.token {
+                let message = match line.trim().strip_prefix("AUTH ") {
+                    Some(given) if token.matches(given.trim()) => None,
+                    Some(_) => Some("Authentication failed."),
+                    None => Some("Authentication required."),
+                };
+                if let Some(message) = message {
+                    protocol::write_error(writer, "AUTH_FAILED", message)?;
+                    protocol::write_end(writer)?;
+                    writer.flush()?;
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+                }
+                self.token = None;
+                self.authenticated = true;
+                protocol::write_end(writer)?;
+                writer.flush()?;
+                continue;
+            }
+            self.request(line.trim(), writer, stop)?;
+            protocol::write_end(writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Runs one request and writes its results and error, if any.
+    fn request(&mut self, line: &str, out: &mut dyn Write, stop: &Interrupt) -> io::Result<()> {
+        if line.starts_with('.') {
+            return self.command(line, out, stop);
+        }
+        for sql in split_statements(line) {
+            match self.statement(sql) {
+                Ok(result) => self.write_result(result, out)?,
+                Err(failure) => return protocol::write_error(out, failure.code, &failure.message),
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str, out: &mut dyn Write, stop: &Interrupt) -> io::Result<()> {
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [".mode", "csv"] => self.settings.mode = Mode::Csv,
+            [".mode", "json"] => self.settings.mode = Mode::Json,
+            [".mode", ..] => return protocol::write_error(out, "USAGE", "Usage: .mode csv|json"),
+            [".statement_timeout"] => {
+                let timeout = self.timeout.map_or_else(|| "off".to_string(), format_duration);
+                return protocol::write_results(out, &format!("{}\n", timeout));
+            }
+            [".statement_timeout", "off"] => self.timeout = None,
+            [".statement_timeout", timeout] => match parse_duration(timeout) {
+                Some(timeout) => self.timeout = Some(timeout),
+                None => return protocol::write_error(out, "USAGE", "Usage: .statement_timeout [DURATION|off]"),
+            },
+            [".shutdown"] if self.authenticated => stop.interrupt(),
+            // Past the first request, a session with no token to check
+            // never had one to ask for.
+            [".shutdown"] => return protocol::write_error(out, "NOT_ALLOWED", "A server started without a token cannot be shut down by a client."),
+            _ => return protocol::write_error(out, "UNRECOGNIZED_COMMAND", &format!("Unrecognized command '{}'.", line)),
+        }
+        Ok(())
+    }
+
+    /// Runs one statement within the session's timeout, if it has one,
+    /// writing out anything it changed before it answers, so what a
+    /// client has been told is done survives the server.
+    fn statement(&mut self, sql: &str) -> Result<QueryResult, Failure> {
+        let Some(timeout) = self.timeout else {
+            let result = self.connection.execute(sql).and_then(|result| match result {
+                QueryResult::Rows(rows) => Ok(QueryResult::Rows(rows)),
+                result => self.connection.flush().map(|()| result),
+            });
+            return result.map_err(|err| Failure::new(sql, err));
+        };
+        let statement = prepare(sql.trim()).map_err(|err| Failure::new(sql, DbError::Prepare(err)))?;
+        if statement.typ == StatementType::Select {
+            // Selects from other clients run alongside, and share the
+            // database's interrupt, so this one watches the clock itself.
+            let started = Instant::now();
+            let db = self.connection.read();
+            let mut rows = Vec::new();
+            for row in db.select(sql).map_err(|err| Failure::new(sql, err))? {
+                if started.elapsed() > timeout {
+                    return Err(Failure::timed_out(timeout));
+                }
+                rows.push(row.map_err(|err| Failure::new(sql, err))?);
+            }
+            return Ok(QueryResult::Rows(rows));
+        }
+        // Anything else runs alone, so the interrupt stops it and nothing
+        // else. It is withdrawn before the lock is let go.
+        let mut db = self.connection.write();
+        let deadline = db.interrupt_handle().after(timeout);
+        let result = db.execute(sql).and_then(|result| db.flush().map(|()| result));
+        let timed_out = deadline.expired();
+        drop(deadline);
+        result.map_err(|err| match err {
+            DbError::Interrupted if timed_out => Failure::timed_out(timeout),
+            err => Failure::new(sql, err),
+        })
+    }
+
+    /// Writes what a statement did as result lines in the session's mode.
+    fn write_result(&self, result: QueryResult, out: &mut dyn Write) -> io::Result<()> {
+        let mut printer = Printer::new(&self.settings, false);
+        let mut results = Captured::new();
+        match result {
+            QueryResult::Rows(rows) => {
+                printer.begin(&mut results)?;
+                for row in &rows {
+                    printer.row(&row.values(), &mut results)?;
+                }
+                printer.finish(&mut results)?;
+            }
+            QueryResult::RowsAffected(count) => printer.affected(StatementType::Insert, count, &mut results)?,
+            QueryResult::Done => {}
+        }
+        protocol::write_results(out, &String::from_utf8_lossy(&results.results))
+    }
+
+    /// As `run`, for a client that opened with `codec::MAGIC` (which is
+    /// still to be read from `reader`) and speaks the binary protocol. A
+    /// client that sends a frame that cannot be read is told so and hung
+    /// up on, as there is no telling where its next frame starts.
+    pub fn run_binary(&mut self, reader: &mut dyn BufRead, writer: &mut dyn Write, stop: &Interrupt) -> io::Result<()> {
+        let mut greeting = Vec::new();
+        if retry(stop, || codec::read_magic(reader, &mut greeting))?.is_none() {
+            return Ok(());
+        }
+        writer.write_all(&codec::MAGIC)?;
+        writer.flush()?;
+
+        let mut decoder = Decoder::default();
+        loop {
+            let message = match retry(stop, || codec::read_message(reader, &mut decoder)) {
+                Ok(Some(Some(message))) => message,
+                Ok(Some(None) | None) => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    codec::write_message(writer, &Message::Error { code: "BAD_FRAME".to_string(), message: format!("Bad frame: {}.", err) })?;
+                    codec::write_message(writer, &Message::Done)?;
+                    writer.flush()?;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            };
+            let error = |code: &str, message: &str| Message::Error { code: code.to_string(), message: message.to_string() };
+            match (message, &self.token) {
+                (Message::Quit, _) => return Ok(()),
+                (Message::Auth(given), Some(token)) if token.matches(given.trim()) => {
+                    self.token = None;
+                    self.authenticated = true;
+                }
+                (message, Some(_)) => {
+                    let message = if matches!(message, Message::Auth(_)) { "Authentication failed." } else { "Authentication required." };
+                    codec::write_message(writer, &error("AUTH_FAILED", message))?;
+                    codec::write_message(writer, &Message::Done)?;
+                    writer.flush()?;
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+                }
+                // A server that asks for no token lets in one that has one.
+                (Message::Auth(_), None) => {}
+                (Message::Query(sql), None) if sql.trim_start().starts_with('.') => {
+                    codec::write_message(writer, &error("UNRECOGNIZED_COMMAND", "Commands are for the line protocol only."))?;
+                }
+                (Message::Query(sql), None) => self.binary_request(&sql, writer)?,
+                (Message::Replicate { epoch }, None) => match self.follower.clone() {
+                    Some(follower) => return follower.follow(epoch, reader, writer, stop, &mut decoder),
+                    None => codec::write_message(writer, &error("USAGE", "This server is not a follower."))?,
+                },
+                (_, None) => codec::write_message(writer, &error("USAGE", "Clients send Query, Auth, Replicate or Quit."))?,
+            }
+            codec::write_message(writer, &Message::Done)?;
+            writer.flush()?;
+        }
+    }
+
+    /// Runs one binary request and writes what each statement did, and
+    /// the error, if any.
+    fn binary_request(&mut self, sql: &str, out: &mut dyn Write) -> io::Result<()> {
+        for sql in split_statements(sql) {
+            match self.statement(sql) {
+                // A select that read nothing still says it was one.
+                Ok(QueryResult::Rows(rows)) if rows.is_empty() => codec::write_message(out, &Message::Rows(rows))?,
+                Ok(QueryResult::Rows(rows)) => {
+                    for rows in rows.chunks(codec::ROWS_PER_FRAME) {
+                        codec::write_message(out, &Message::Rows(rows.to_vec()))?;
+                    }
+                }
+                Ok(QueryResult::RowsAffected(count)) => codec::write_message(out, &Message::RowsAffected(count as u64))?,
+                Ok(QueryResult::Done) => {}
+                Err(failure) => return codec::write_message(out, &Message::Error { code: failure.code.to_string(), message: failure.message }),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a client is told about a statement that failed.
+struct Failure {
+    code: &'static str,
+    message: String,
+}
+
+impl Failure {
+    /// `sql` failed with `err`.
+    fn new(sql: &str, err: DbError) -> Failure {
+        let message = match err {
+            DbError::Prepare(PrepareError::UnrecognizedStatement) => format!("Unrecognized keyword at start of '{}'.", sql),
+            ref err => err.to_string(),
+        };
+        Failure { code: err.code(), message }
+    }
+
+    /// The statement ran longer than `timeout` and was stopped.
+    fn timed_out(timeout: Duration) -> Failure {
+        let message = format!("The statement ran longer than the {} statement timeout.", format_duration(timeout));
+        Failure { code: "TIMEOUT", message }
+    }
+}
+
+/// Reads a duration such as `5s` or `250ms`; a bare number is seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    match text.strip_suffix("ms") {
+        Some(ms) => ms.parse().ok().map(Duration::from_millis),
+        None => text.strip_suffix('s').unwrap_or(text).parse().ok().map(Duration::from_secs),
+    }
+}
+
+/// Writes `duration` as `parse_duration` reads it, in whole seconds when
+/// it is some.
+pub fn format_duration(duration: Duration) -> String {
+    match duration.as_millis() {
+        ms if ms % 1000 == 0 && ms > 0 => format!("{}s", ms / 1000),
+        ms => format!("{}ms", ms),
+    }
+}
+
+/// Calls `read` until it does not fail for want of anything to read yet,
+/// or `stop` is interrupted, which gives `None`.
+pub(crate) fn retry<T>(stop: &Interrupt, mut read: impl FnMut() -> io::Result<T>) -> io::Result<Option<T>> {
+    while !stop.is_interrupted() {
+        match read() {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) if is_retryable(&err) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::client::Client;
+    use crate::database::Options;
+    use crate::import::OnConflict;
+    use crate::row::Row;
+    use crate::testing::FaultyVfs;
+    use crate::vfs::MemoryVfs;
+
+    /// Runs `requests` through a new session on `connection` and returns
+    /// what it wrote back.
+    fn session(connection: &Connection, requests: &str) -> String {
+        run(Session::new(connection.clone()), requests)
+    }
+
+    /// Runs `requests` through `session` and returns what it wrote back.
+    fn run(mut session: Session, requests: &str) -> String {
+        let mut reader = requests.as_bytes();
+        let mut writer = Vec::new();
+        session.run(&mut reader, &mut writer, &Interrupt::default()).unwrap();
+        String::from_utf8(writer).unwrap()
+    }
+
+    /// A connection to 500 rows, each page of which takes `delay` to load,
+    /// so that a select of them all takes 36 times that.
+    fn slow_connection(delay: Duration) -> Connection {
+        let files = Arc::new(MemoryVfs::new());
+        let mut db = Options::new().vfs(files.clone()).open("slow.db").unwrap();
+        db.insert_rows((1..=500).map(|id| Row::new(id, "user", "user@example.com")), OnConflict::Fail).unwrap();
+        db.flush().unwrap();
+        drop(db);
+
+        let slow = Arc::new(FaultyVfs::new(files));
+        let connection = Connection::new(Options::new().vfs(slow.clone()).open("slow.db").unwrap());
+        slow.slow_reads(delay);
+        connection
+    }
+
+    #[test]
+    fn test_statements_and_modes() {
+        let connection = Connection::open_in_memory();
+        let requests = "insert 1 alice alice@example.com\nselect\n.mode json\nselect\ninsert 2 bob b@x; insert 3 carol c@x\n";
+        assert_eq!(
+            session(&connection, requests),
+            concat!(
+                ".\n",
+                "1,alice,alice@example.com,0\n.\n",
+                ".\n",
+                "[{\"id\":1,\"username\":\"alice\",\"email\":\"alice@example.com\",\"age\":0}]\n.\n",
+                "{\"rows_affected\":1}\n{\"rows_affected\":1}\n.\n",
+            )
+        );
+        // The mode belongs to the session; the rows to the database.
+        assert_eq!(session(&connection, "select where id = 3\n"), "3,carol,c@x,0\n.\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let connection = Connection::open_in_memory();
+        let requests = "insert 1 a a@x\ninsert 1 a a@x; insert 2 b b@x\nupdate\n.mode xml\n.tables\n\nselect\n.quit\nselect\n";
+        assert_eq!(
+            session(&connection, requests),
+            concat!(
+                ".\n",
+                "!DUPLICATE_KEY Error: Duplicate key.\n.\n",
+                "!UNRECOGNIZED_STATEMENT Unrecognized keyword at start of 'update'.\n.\n",
+                "!USAGE Usage: .mode csv|json\n.\n",
+                "!UNRECOGNIZED_COMMAND Unrecognized command '.tables'.\n.\n",
+                ".\n",
+                "1,a,a@x,0\n.\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_authentication() {
+        let connection = Connection::open_in_memory();
+        let token = Token::new("s3cret").unwrap();
+        let run = |requests: &str| {
+            let mut writer = Vec::new();
+            let mut session = Session::new(connection.clone()).require_token(token.clone());
+            let result = session.run(&mut requests.as_bytes(), &mut writer, &Interrupt::default());
+            (result.map_err(|err| err.kind()), String::from_utf8(writer).unwrap())
+        };
+        let turned_away = |message: &str| (Err(io::ErrorKind::PermissionDenied), format!("!AUTH_FAILED {}\n.\n", message));
+        assert_eq!(run("insert 1 a a@x\nselect\n"), turned_away("Authentication required."));
+        assert_eq!(run("AUTH s3cre\nselect\n"), turned_away("Authentication failed."));
+        assert_eq!(run("AUTH\n"), turned_away("Authentication required."));
+        assert_eq!(run("AUTH s3cret\ninsert 1 a a@x\nselect\n"), (Ok(()), ".\n.\n1,a,a@x,0\n.\n".to_string()));
+        assert_eq!(connection.select("select").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_shutdown_command() {
+        let connection = Connection::open_in_memory();
+        let stop = Interrupt::default();
+        let mut writer = Vec::new();
+        Session::new(connection.clone()).run(&mut ".shutdown\nselect\n".as_bytes(), &mut writer, &stop).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "!NOT_ALLOWED A server started without a token cannot be shut down by a client.\n.\n.\n");
+        assert!(!stop.is_interrupted());
+
+        // The session ends with the server.
+        let mut writer = Vec::new();
+        let mut session = Session::new(connection).require_token(Token::new("s3cret").unwrap());
+        session.run(&mut "AUTH s3cret\n.shutdown\nselect\n".as_bytes(), &mut writer, &stop).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), ".\n.\n");
+        assert!(stop.is_interrupted());
+    }
+
+    #[test]
+    fn test_stopping_lets_statements_finish_within_the_grace_period() {
+        // Stops the server part way through a select that takes the best
+        // part of a second.
+        let select_while_stopping = |grace_period: Duration| {
+            let connection = slow_connection(Duration::from_millis(20));
+            let server = Server::bind(&Address::parse("127.0.0.1:0"), connection).unwrap().grace_period(grace_period);
+            let address = server.local_addr().unwrap().to_string();
+            std::thread::scope(|scope| {
+                let running = scope.spawn(|| server.run());
+                let mut client = Client::connect(&address, None).unwrap();
+                let selecting = scope.spawn(move || client.execute("select"));
+                std::thread::sleep(Duration::from_millis(200));
+                server.stop_handle().interrupt();
+                let start = Instant::now();
+                running.join().unwrap().unwrap();
+                (selecting.join().unwrap(), start.elapsed())
+            })
+        };
+
+        let (result, waited) = select_while_stopping(DEFAULT_GRACE_PERIOD);
+        assert!(matches!(&result, Ok(QueryResult::Rows(rows)) if rows.len() == 500), "{:?}", result.map(|_| ()));
+        assert!(waited >= Duration::from_millis(100), "{:?}", waited);
+
+        // Past the grace period the select is interrupted, but the client
+        // still gets a whole response.
+        let (result, waited) = select_while_stopping(Duration::ZERO);
+        assert_eq!(result.unwrap_err().code(), "INTERRUPTED");
+        assert!(waited < Duration::from_millis(300), "{:?}", waited);
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        let connection = slow_connection(Duration::from_millis(20));
+        let timeout = |ms| Session::new(connection.clone()).statement_timeout(Duration::from_millis(ms));
+        std::thread::scope(|scope| {
+            let slow = scope.spawn(|| run(timeout(100), "select\n.statement_timeout\n.statement_timeout off\nselect where id = 2\n"));
+            // Meanwhile another client's statements, selects and not, are
+            // not cut short with it.
+            std::thread::sleep(Duration::from_millis(20));
+            let fast = run(timeout(5000), "select where id = 1\ninsert 501 late late@x\n");
+            assert_eq!(fast, "1,user,user@example.com,0\n.\n.\n");
+            assert_eq!(
+                slow.join().unwrap(),
+                concat!("!TIMEOUT The statement ran longer than the 100ms statement timeout.\n.\n", "100ms\n.\n", ".\n", "2,user,user@example.com,0\n.\n")
+            );
+        });
+
+        // Statements that write are stopped too, and leave nothing behind
+        // for the next one.
+        let connection = slow_connection(Duration::from_millis(20));
+        let session = Session::new(connection.clone()).statement_timeout(Duration::from_millis(100));
+        let output = run(session, "insert into users select\n.statement_timeout 10s\n.statement_timeout\n.statement_timeout soon\nselect where id = 3\n");
+        assert_eq!(
+            output,
+            concat!(
+                "!TIMEOUT The statement ran longer than the 100ms statement timeout.\n.\n",
+                ".\n",
+                "10s\n.\n",
+                "!USAGE Usage: .statement_timeout [DURATION|off]\n.\n",
+                "3,user,user@example.com,0\n.\n",
+            )
+        );
+        assert!(!connection.read().interrupt_handle().is_interrupted());
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        for duration in [Duration::from_secs(5), Duration::from_millis(250), Duration::from_millis(1500), Duration::ZERO] {
+            assert_eq!(parse_duration(&format_duration(duration)), Some(duration));
+        }
+    }
+
+    /// Runs the binary `requests` through a new session on `connection`,
+    /// after the magic bytes, and returns the result and what it wrote
+    /// back, after the magic bytes.
+    fn binary_session(session: Session, requests: &[Message]) -> (io::Result<()>, Vec<Message>) {
+        let mut input = codec::MAGIC.to_vec();
+        input.extend(requests.iter().flat_map(Message::encode));
+        binary_session_bytes(session, &input)
+    }
+
+    fn binary_session_bytes(mut session: Session, input: &[u8]) -> (io::Result<()>, Vec<Message>) {
+        let mut writer = Vec::new();
+        let result = session.run_binary(&mut &input[..], &mut writer, &Interrupt::default());
+        assert_eq!(writer[..codec::MAGIC.len()], codec::MAGIC);
+        let mut output = &writer[codec::MAGIC.len()..];
+        let mut decoder = Decoder::default();
+        let mut messages = Vec::new();
+        while let Some(message) = codec::read_message(&mut output, &mut decoder).unwrap() {
+            messages.push(message);
+        }
+        (result, messages)
+    }
+
+    fn query(sql: &str) -> Message {
+        Message::Query(sql.to_string())
+    }
+
+    fn error(code: &str, message: &str) -> Message {
+        Message::Error { code: code.to_string(), message: message.to_string() }
+    }
+
+    #[test]
+    fn test_binary_sessions() {
+        let connection = Connection::open_in_memory();
+        let requests = [
+            query("insert 1 alice alice@example.com 30"),
+            query("select where id = 2"),
+            query("insert 2 bob b@x; insert 1 a a@x; insert 3 carol c@x"),
+            query("select"),
+            query("create table users (id integer, username varchar(32), email varchar(255), age integer)"),
+            query(".mode json"),
+            Message::Done,
+            Message::Quit,
+            query("select"),
+        ];
+        let (result, messages) = binary_session(Session::new(connection.clone()), &requests);
+        result.unwrap();
+        assert_eq!(
+            messages,
+            [
+                Message::RowsAffected(1),
+                Message::Done,
+                Message::Rows(Vec::new()),
+                Message::Done,
+                Message::RowsAffected(1),
+                error("DUPLICATE_KEY", "Error: Duplicate key."),
+                Message::Done,
+                Message::Rows(vec![Row::new(1, "alice", "alice@example.com").with_age(30), Row::new(2, "bob", "b@x")]),
+                Message::Done,
+                Message::Done,
+                error("UNRECOGNIZED_COMMAND", "Commands are for the line protocol only."),
+                Message::Done,
+                error("USAGE", "Clients send Query, Auth, Replicate or Quit."),
+                Message::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_rows_come_in_frames() {
+        let connection = Connection::open_in_memory();
+        for id in 0..codec::ROWS_PER_FRAME as u64 + 1 {
+            connection.execute(&format!("insert {} u{} u@x", id, id)).unwrap();
+        }
+        let (result, messages) = binary_session(Session::new(connection), &[query("select")]);
+        result.unwrap();
+        let sizes: Vec<_> = messages.iter().map(|message| match message {
+            Message::Rows(rows) => rows.len(),
+            _ => 0,
+        }).collect();
+        assert_eq!(sizes, [codec::ROWS_PER_FRAME, 1, 0]);
+        assert_eq!(messages.last(), Some(&Message::Done));
+    }
+
+    #[test]
+    fn test_binary_authentication_and_bad_frames() {
+        let connection = Connection::open_in_memory();
+        let token = Token::new("s3cret").unwrap();
+        let session = || Session::new(connection.clone()).require_token(token.clone());
+        for (first, message) in [(query("select"), "Authentication required."), (Message::Auth("s3cre".to_string()), "Authentication failed.")] {
+            let (result, messages) = binary_session(session(), &[first, query("select")]);
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+            assert_eq!(messages, [error("AUTH_FAILED", message), Message::Done]);
+        }
+        let (result, messages) = binary_session(session(), &[Message::Auth("s3cret".to_string()), query("select")]);
+        result.unwrap();
+        assert_eq!(messages, [Message::Done, Message::Rows(Vec::new()), Message::Done]);
+
+        // The token counts for as much as in the line protocol.
+        let mut session = session();
+        let mut input = codec::MAGIC.to_vec();
+        input.extend(Message::Auth("s3cret".to_string()).encode());
+        session.run_binary(&mut &input[..], &mut Vec::new(), &Interrupt::default()).unwrap();
+        assert!(session.authenticated);
+        let mut session = Session::new(connection.clone());
+        session.run_binary(&mut &input[..], &mut Vec::new(), &Interrupt::default()).unwrap();
+        assert!(!session.authenticated);
+
+        // A frame too long to read ends the session.
+        let mut input = codec::MAGIC.to_vec();
+        input.extend_from_slice(&query("select").encode());
+        input.extend_from_slice(&u32::MAX.to_le_bytes());
+        input.extend_from_slice(&query("select").encode());
+        let (result, messages) = binary_session_bytes(Session::new(connection.clone()), &input);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let message = format!("Bad frame: a frame of {} bytes is over the limit of {}.", u32::MAX, codec::MAX_FRAME_SIZE);
+        assert_eq!(messages, [Message::Rows(Vec::new()), Message::Done, error("BAD_FRAME", &message), Message::Done]);
+
+        let mut writer = Vec::new();
+        let err = Session::new(connection).run_binary(&mut &b"\0VDB\x09"[..], &mut writer, &Interrupt::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_the_first_byte_picks_the_protocol() {
+        let stop = Interrupt::default();
+        assert!(wants_binary(&mut &codec::MAGIC[..], &stop).unwrap());
+        assert!(!wants_binary(&mut &b"select\n"[..], &stop).unwrap());
+        assert!(!wants_binary(&mut &b""[..], &stop).unwrap());
+    }
+
+    #[test]
+    fn test_stop_ends_the_session() {
+        let stop = Interrupt::default();
+        stop.interrupt();
+        let mut writer = Vec::new();
+        Session::new(Connection::open_in_memory()).run(&mut "select\n".as_bytes(), &mut writer, &stop).unwrap();
+        assert!(writer.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+//! Read-only views of a database frozen at one moment; see
+//! `Database::snapshot`.
+
+use crate::cursor::Rows;
+use crate::database::{Database, Options};
+use crate::error::DbError;
+
+/// The tables of a database, attached ones included, as they were when
+/// the snapshot was taken. Later writes to the database do not show, and
+/// the snapshot does not hold the database borrowed or locked, so it can
+/// be read from while writes go on.
+///
+/// The snapshot shares pages with the database instead of copying them;
+/// a page is copied when the database first changes it. Dropping the
+/// snapshot lets go of the pages only it still holds.
+pub struct Snapshot {
+    db: Database,
+}
+
+impl Snapshot {
+    pub(crate) fn new(db: Database) -> Self {
+        Snapshot { db }
+    }
+
+    /// The rows of a select, read as `Database::select` reads them. Other
+    /// statements fail with `DbError::ReadOnly`.
+    pub fn select(&self, sql: &str) -> Result<Rows<'_>, DbError> {
+        self.db.select(sql)
+    }
+
+    /// The tables a select can name, as `Database::tables` names them.
+    pub fn tables(&self) -> Vec<String> {
+        self.db.tables()
+    }
+
+    /// The settings of the main database.
+    pub fn options(&self) -> Options {
+        self.db.options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::Connection;
+    use crate::database::Database;
+    use crate::row::Row;
+    use crate::testing::TempPath;
+
+    fn ids(rows: impl Iterator<Item = Result<Row, crate::DbError>>) -> Vec<u64> {
+        rows.map(|row| row.unwrap().id).collect()
+    }
+
+    #[test]
+    fn test_snapshot_does_not_see_later_writes() {
+        let path = TempPath::new("snapshot.db");
+        let mut db = Database::open(&path).unwrap();
+        for id in 1..=3 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        let before: Vec<Row> = db.select("select").unwrap().map(Result::unwrap).collect();
+
+        let first = db.snapshot().unwrap();
+        // Enough rows to change the page the snapshot holds and start more.
+        for id in 4..=100 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        db.flush().unwrap();
+        let second = db.snapshot().unwrap();
+        db.execute("insert 101 last last@example.com").unwrap();
+
+        let seen: Vec<Row> = first.select("select").unwrap().map(Result::unwrap).collect();
+        assert_eq!(seen, before);
+        assert_eq!(ids(first.select("select where id = 50").unwrap()), Vec::<u64>::new());
+        assert_eq!(ids(second.select("select").unwrap()), (1..=100).collect::<Vec<_>>());
+        assert_eq!(ids(db.select("select").unwrap()), (1..=101).collect::<Vec<_>>());
+        assert_eq!(first.select("insert 200 a a@x").err().unwrap().code(), "READ_ONLY");
+
+        drop(first);
+        drop(second);
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_of_a_connection_is_read_while_writes_go_on() {
+        let connection = Connection::open_in_memory();
+        connection.execute("insert 1 alice alice@example.com").unwrap();
+        connection.execute("insert 2 bob bob@example.com").unwrap();
+
+        let snapshot = connection.snapshot().unwrap();
+        let mut rows = snapshot.select("select").unwrap();
+        assert_eq!(rows.next().unwrap().unwrap().id, 1);
+        // The write lock is free although the scan is under way.
+        connection.execute("insert 3 carol carol@example.com").unwrap();
+        assert_eq!(ids(rows), [2]);
+        assert_eq!(connection.select("select").unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,127 @@
+//! Terminal colors for the REPL. Styling is applied on top of an `Output`
+//! only when the REPL decides the output is an interactive terminal, so the
+//! plain writers stay byte-for-byte the same for tests and pipes.
+
+use std::io::{self, Write};
+
+use crate::output::Output;
+
+/// When to color output, set by `.color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// On a terminal, unless `NO_COLOR` is set.
+    Auto,
+    /// On a terminal, even if `NO_COLOR` is set.
+    On,
+    Off,
+}
+
+impl ColorChoice {
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::On => "on",
+            ColorChoice::Off => "off",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<ColorChoice> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "on" => Some(ColorChoice::On),
+            "off" => Some(ColorChoice::Off),
+            _ => None,
+        }
+    }
+
+    /// Whether to color `output`. Always false unless built with the
+    /// `color` feature, and never for anything but a terminal.
+    pub(crate) fn enabled(self, output: &dyn Output) -> bool {
+        cfg!(feature = "color")
+            && output.is_terminal()
+            && match self {
+                ColorChoice::Auto => std::env::var_os("NO_COLOR").is_none(),
+                ColorChoice::On => true,
+                ColorChoice::Off => false,
+            }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Red,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "\x1b[1m",
+            Style::Red => "\x1b[31m",
+        }
+    }
+}
+
+pub const RESET: &str = "\x1b[0m";
+
+/// `text` wrapped in the escape codes for `style`.
+pub fn paint(text: &str, style: Style) -> String {
+    format!("{}{}{}", style.code(), text, RESET)
+}
+
+/// An `Output` that prints errors in red.
+pub struct Styled<'a> {
+    inner: &'a mut dyn Output,
+}
+
+impl<'a> Styled<'a> {
+    pub fn new(inner: &'a mut dyn Output) -> Self {
+        Styled { inner }
+    }
+}
+
+impl Output for Styled<'_> {
+    fn results(&mut self) -> &mut dyn Write {
+        self.inner.results()
+    }
+
+    fn errors(&mut self) -> &mut dyn Write {
+        self.inner.errors()
+    }
+
+    fn error(&mut self, text: &str) -> io::Result<()> {
+        self.inner.error(&paint(text, Style::Red))
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.inner.is_terminal()
+    }
+
+    fn page_height(&self) -> Option<usize> {
+        self.inner.page_height()
+    }
+
+    fn more(&mut self) -> io::Result<bool> {
+        self.inner.more()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Captured;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(paint("db > ", Style::Bold), "\x1b[1mdb > \x1b[0m");
+        assert_eq!(paint("oops", Style::Red), "\x1b[31moops\x1b[0m");
+    }
+
+    #[test]
+    fn test_never_enabled_off_a_terminal() {
+        let output = Captured::new();
+        for choice in [ColorChoice::Auto, ColorChoice::On, ColorChoice::Off] {
+            assert!(!choice.enabled(&output));
+        }
+    }
+}
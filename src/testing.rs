@@ -0,0 +1,528 @@
+//! Random inputs for tests: rows, insert statements (well-formed and not)
+//! and statement sequences, all driven by a seeded generator so failures
+//! can be replayed; `FaultyVfs`, a file system that fails on cue; and
+//! `TempPath`, a scratch file or directory that cleans up after itself.
+//! Available to downstream crates with the `test-utils` feature.
+
+use std::fs::TryLockError;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::rng::SplitMix64;
+use crate::row::{Row, RowLayout};
+use crate::vfs::{OpenMode, Vfs, VfsFile};
+
+/// A seeded source of test inputs.
+#[derive(Debug, Clone)]
+pub struct Gen {
+    rng: SplitMix64,
+}
+
+// A spread of 1- to 4-byte characters, including a combining mark and
+// right-to-left text, so length limits are hit on and off char boundaries.
+const CHARS: &[char] = &['a', 'z', 'Q', '7', '_', '.', '@', 'é', 'ß', 'ж', 'א', '\u{301}', '中', '€', '😀', '𝄞'];
+
+impl Gen {
+    pub fn new(seed: u64) -> Self {
+        Gen { rng: SplitMix64::new(seed) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.rng.below(n)
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    /// A non-empty string of at most `max_bytes` bytes with no whitespace.
+    pub fn word(&mut self, max_bytes: usize) -> String {
+        let mut word = String::new();
+        let target = 1 + self.below(max_bytes as u64) as usize;
+        while word.len() < target {
+            let c = CHARS[self.below(CHARS.len() as u64) as usize];
+            if word.len() + c.len_utf8() > max_bytes {
+                break;
+            }
+            word.push(c);
+        }
+        if word.is_empty() {
+            word.push('a');
+        }
+        word
+    }
+
+    /// A possibly empty string of at most `max_bytes` bytes, heavy on the
+    /// characters that need quoting or escaping in CSV, JSON and SQL.
+    pub fn text(&mut self, max_bytes: usize) -> String {
+        const NASTY: &[char] = &[',', '"', '\'', '\\', '\n', '\r', '\t', ' ', ';', '|', '\0'];
+        let mut text = String::new();
+        let target = self.below(max_bytes as u64 + 1) as usize;
+        while text.len() < target {
+            let c = match self.chance(50) {
+                true => NASTY[self.below(NASTY.len() as u64) as usize],
+                false => CHARS[self.below(CHARS.len() as u64) as usize],
+            };
+            if text.len() + c.len_utf8() > max_bytes {
+                break;
+            }
+            text.push(c);
+        }
+        text
+    }
+
+    /// A row whose values fit `layout`.
+    pub fn row(&mut self, layout: RowLayout) -> Row {
+        let username = self.word(layout.username_size());
+        let email = self.word(layout.email_size());
+        Row::new(self.next_u64(), &username, &email).with_age(self.below(1 << 16) as u16)
+    }
+
+    /// An insert statement that parses and fits the default column widths.
+    pub fn insert_statement(&mut self) -> String {
+        let row = self.row(RowLayout::DEFAULT);
+        if self.chance(50) {
+            format!("insert {} {} {} {}", row.id, row.username(), row.email(), row.age)
+        } else {
+            format!("insert {} {} {}", row.id, row.username(), row.email())
+        }
+    }
+
+    /// Text that is not a valid statement, or is a statement whose values do
+    /// not fit: wrong arity, bad numbers, oversized strings, unknown keywords.
+    pub fn malformed_statement(&mut self) -> String {
+        match self.below(7) {
+            0 => format!("insert {}", self.next_u64()),
+            1 => format!("insert {} {}", self.word(8), self.word(8)),
+            2 => format!("insert -{} a a@x", self.below(1000) + 1),
+            3 => format!("insert 1 a a@x {}", 65536 + self.below(1000)),
+            4 => format!("insert 1 {} a@x", "u".repeat(33 + self.below(100) as usize)),
+            5 => format!("insert 1 a a@x 2 {}", self.word(4)),
+            _ => self.word(20),
+        }
+    }
+
+    /// A mix of selects, valid inserts and malformed statements.
+    pub fn statements(&mut self, len: usize) -> Vec<String> {
+        (0..len)
+            .map(|_| match self.below(10) {
+                0 => "select".to_string(),
+                1 | 2 => self.malformed_statement(),
+                _ => self.insert_statement(),
+            })
+            .collect()
+    }
+}
+
+/// A path in the system's temp directory for a test to put a file or a
+/// directory at, named `voiddb-test-<pid>-<name>` so tests running at the
+/// same time, in this process or another, do not collide. Anything already
+/// there is removed when it is made, and whatever the test left there when
+/// it is dropped, including when the test panics.
+#[derive(Debug)]
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// `name` keeps any extension it has, for tests where that matters.
+    pub fn new(name: &str) -> Self {
+        let path = TempPath(std::env::temp_dir().join(format!("voiddb-test-{}-{}", std::process::id(), name)));
+        path.remove();
+        path
+    }
+
+    fn remove(&self) {
+        let _ = match self.0.is_dir() {
+            true => std::fs::remove_dir_all(&self.0),
+            false => std::fs::remove_file(&self.0),
+        };
+    }
+}
+
+impl std::ops::Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Writes a database with row checksums to `path`, holding rows 1 to 3, and
+/// breaks the checksum stored with row 2: reading that row fails with
+/// `CHECKSUM_MISMATCH`, wherever it is read from. Panics if the file cannot
+/// be written.
+pub fn write_bad_checksum(path: &Path) {
+    let mut db = crate::database::Options::new().row_checksums(true).open(path).unwrap();
+    for id in 1..=3 {
+        db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+    }
+    db.close().unwrap();
+
+    let cell_size = crate::row::ROW_SIZE + crate::format::CHECKSUM_SIZE;
+    let mut bytes = std::fs::read(path).unwrap();
+    bytes[crate::pager::PAGE_SIZE + cell_size + crate::row::ROW_SIZE] ^= 0xff;
+    std::fs::write(path, bytes).unwrap();
+}
+
+/// A `Vfs` that passes everything on to another, but can be told to fail
+/// or tear a chosen write, drop syncs, crash, or read slowly. Writes are numbered from 1
+/// across every file opened through it, in the order they are made.
+///
+/// A crash freezes the files of the inner VFS as they are: the write it
+/// happens at, and every open, write, sync, truncate, remove and rename
+/// after it, fails without reaching them. Reopening the files through the
+/// inner VFS then shows what a process killed at that write would have
+/// left behind.
+///
+/// ```
+/// use std::sync::Arc;
+/// use VoidDB::testing::FaultyVfs;
+/// use VoidDB::vfs::MemoryVfs;
+/// use VoidDB::Options;
+///
+/// let files = Arc::new(MemoryVfs::new());
+/// let faulty = Arc::new(FaultyVfs::new(files.clone()));
+/// let mut db = Options::new().vfs(faulty.clone()).open("app.db").unwrap();
+/// db.execute("insert 1 alice alice@example.com").unwrap();
+/// faulty.crash_at_write(faulty.writes() + 1);
+/// assert!(db.flush().is_err());
+/// drop(db);
+///
+/// let db = Options::new().vfs(files).open("app.db").unwrap();
+/// assert_eq!(db.select("select").unwrap().count(), 0);
+/// ```
+#[derive(Debug)]
+pub struct FaultyVfs {
+    inner: Arc<dyn Vfs>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    writes: u64,
+    dropped_syncs: u64,
+    fail_write: Option<u64>,
+    /// A write to tear, and how many of its bytes to let through.
+    tear_write: Option<(u64, usize)>,
+    crash_at: Option<u64>,
+    crashed: bool,
+    drop_syncs: bool,
+    read_delay: Duration,
+}
+
+impl Faults {
+    fn check_crashed(&self) -> io::Result<()> {
+        match self.crashed {
+            true => Err(fault("crashed")),
+            false => Ok(()),
+        }
+    }
+}
+
+fn fault(what: &str) -> io::Error {
+    io::Error::other(format!("injected fault: {}", what))
+}
+
+impl FaultyVfs {
+    pub fn new(inner: Arc<dyn Vfs>) -> Self {
+        FaultyVfs { inner, faults: Arc::default() }
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Makes write `n` fail without writing anything.
+    pub fn fail_write(&self, n: u64) {
+        self.faults().fail_write = Some(n);
+    }
+
+    /// Makes write `n` write only its first `keep` bytes, then fail.
+    pub fn tear_write(&self, n: u64, keep: usize) {
+        self.faults().tear_write = Some((n, keep));
+    }
+
+    /// Crashes at write `n`, which is not made.
+    pub fn crash_at_write(&self, n: u64) {
+        self.faults().crash_at = Some(n);
+    }
+
+    /// Whether syncs are reported done without being passed on.
+    pub fn drop_syncs(&self, on: bool) {
+        self.faults().drop_syncs = on;
+    }
+
+    /// Makes every read take `delay` longer, as from a slow disk.
+    pub fn slow_reads(&self, delay: Duration) {
+        self.faults().read_delay = delay;
+    }
+
+    /// The number of writes made or attempted so far.
+    pub fn writes(&self) -> u64 {
+        self.faults().writes
+    }
+
+    /// The number of syncs dropped so far.
+    pub fn dropped_syncs(&self) -> u64 {
+        self.faults().dropped_syncs
+    }
+
+    pub fn crashed(&self) -> bool {
+        self.faults().crashed
+    }
+}
+
+impl Vfs for FaultyVfs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        self.faults().check_crashed()?;
+        let file = self.inner.open(path, mode)?;
+        Ok(Box::new(FaultyFile { inner: file, faults: self.faults.clone() }))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.faults().check_crashed()?;
+        self.inner.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.faults().check_crashed()?;
+        self.inner.rename(from, to)
+    }
+}
+
+/// A file opened through a `FaultyVfs`.
+#[derive(Debug)]
+struct FaultyFile {
+    inner: Box<dyn VfsFile>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyFile {
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl VfsFile for FaultyFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let delay = self.faults.lock().unwrap_or_else(PoisonError::into_inner).read_delay;
+        std::thread::sleep(delay);
+        self.inner.read_at(buf, offset)
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut faults = self.faults.lock().unwrap_or_else(PoisonError::into_inner);
+        faults.check_crashed()?;
+        faults.writes += 1;
+        let n = faults.writes;
+        if faults.crash_at == Some(n) {
+            faults.crashed = true;
+            return Err(fault("crashed"));
+        }
+        if faults.fail_write == Some(n) {
+            return Err(fault("write failed"));
+        }
+        if let Some((_, keep)) = faults.tear_write.filter(|&(at, _)| at == n) {
+            self.inner.write_at(&buf[..keep.min(buf.len())], offset)?;
+            return Err(fault("write torn"));
+        }
+        self.inner.write_at(buf, offset)
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        let mut faults = self.faults.lock().unwrap_or_else(PoisonError::into_inner);
+        faults.check_crashed()?;
+        if faults.drop_syncs {
+            faults.dropped_syncs += 1;
+            return Ok(());
+        }
+        self.inner.sync(data_only)
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.faults().check_crashed()?;
+        self.inner.truncate(len)
+    }
+
+    fn try_lock(&self, shared: bool) -> Result<(), TryLockError> {
+        self.inner.try_lock(shared)
+    }
+
+    fn file_size(&self) -> io::Result<u64> {
+        self.inner.file_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, Options};
+    use crate::row::RowRef;
+    use crate::vfs::MemoryVfs;
+
+    #[test]
+    fn test_temp_paths_are_removed_when_dropped() {
+        let path = TempPath::new("temp.db");
+        std::fs::write(&path, "left over").unwrap();
+        let path = TempPath::new("temp.db");
+        assert!(!path.exists());
+        std::fs::write(&path, "written").unwrap();
+        drop(path);
+        assert!(!TempPath::new("temp.db").exists());
+
+        let dir = TempPath::new("temp-dir");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested").join("file"), "written").unwrap();
+        let kept = dir.to_path_buf();
+        drop(dir);
+        assert!(!kept.exists());
+    }
+
+    #[test]
+    fn test_row_round_trip() {
+        for seed in 0..500 {
+            let mut gen = Gen::new(seed);
+            let layout = RowLayout::new(1 + gen.below(255) as usize, 1 + gen.below(1000) as usize).unwrap();
+            let row = gen.row(layout);
+
+            let mut data = vec![0u8; layout.row_size()];
+            layout.serialize_into(&row, &mut data);
+            assert_eq!(RowRef::with_layout(&data, layout).unwrap().to_row().unwrap(), row, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_generated_inserts_parse() {
+        let mut gen = Gen::new(7);
+        let mut db = Database::open_in_memory();
+        for _ in 0..200 {
+            let sql = gen.insert_statement();
+            db.execute(&sql).unwrap_or_else(|err| panic!("{}: {}", sql, err));
+        }
+    }
+
+    #[test]
+    fn test_malformed_statements_fail() {
+        let mut gen = Gen::new(11);
+        let mut db = Database::open_in_memory();
+        for _ in 0..200 {
+            let sql = gen.malformed_statement();
+            assert!(db.execute(&sql).is_err(), "{} was accepted", sql);
+        }
+    }
+
+    #[test]
+    fn test_statement_sequences_never_panic() {
+        for seed in 0..50 {
+            let mut db = Database::open_in_memory();
+            for sql in Gen::new(seed).statements(100) {
+                let _ = db.execute(&sql);
+            }
+        }
+    }
+
+    const BATCH: u64 = 7;
+
+    /// Inserts rows 1, 2, ... in batches, flushing after each, until five
+    /// batches are in or something fails. Returns the last id of the last
+    /// batch whose flush succeeded.
+    fn crash_workload(vfs: Arc<dyn Vfs>) -> u64 {
+        let Ok(mut db) = Options::new().vfs(vfs).row_checksums(true).open("crash.db") else {
+            return 0;
+        };
+        let mut acknowledged = 0;
+        for batch in 0..5 {
+            for id in batch * BATCH + 1..=(batch + 1) * BATCH {
+                if db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).is_err() {
+                    return acknowledged;
+                }
+            }
+            if db.flush().is_err() {
+                return acknowledged;
+            }
+            acknowledged = (batch + 1) * BATCH;
+        }
+        acknowledged
+    }
+
+    /// The ids in `crash.db`, after checking that every row reads back
+    /// whole.
+    fn surviving_ids(files: Arc<MemoryVfs>) -> Vec<u64> {
+        let db = Options::new().vfs(files).open("crash.db").unwrap();
+        db.select("select").unwrap().map(|row| row.unwrap().id).collect()
+    }
+
+    #[test]
+    fn test_a_crash_at_any_write_keeps_what_was_flushed() {
+        let counting = FaultyVfs::new(Arc::new(MemoryVfs::new()));
+        let counting = Arc::new(counting);
+        assert_eq!(crash_workload(counting.clone()), 5 * BATCH);
+        let writes = counting.writes();
+        assert!(writes >= 10, "only {} writes", writes);
+
+        for crash_at in 1..=writes {
+            let files = Arc::new(MemoryVfs::new());
+            let faulty = Arc::new(FaultyVfs::new(files.clone()));
+            faulty.crash_at_write(crash_at);
+            let acknowledged = crash_workload(faulty.clone());
+            assert!(faulty.crashed());
+
+            // Rows flushed before the crash are all there, and the ones
+            // whose flush it cut short are all there or all gone.
+            let ids = surviving_ids(files);
+            assert_eq!(ids, (1..=ids.len() as u64).collect::<Vec<_>>(), "crash at write {}", crash_at);
+            assert!(ids.len() as u64 == acknowledged || ids.len() as u64 == acknowledged + BATCH, "crash at write {}: {} rows, {} acknowledged", crash_at, ids.len(), acknowledged);
+        }
+    }
+
+    #[test]
+    fn test_failed_torn_and_unsynced_writes() {
+        let files = Arc::new(MemoryVfs::new());
+        let faulty = Arc::new(FaultyVfs::new(files.clone()));
+        let mut db = Options::new().vfs(faulty.clone()).open("crash.db").unwrap();
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        faulty.fail_write(faulty.writes() + 1);
+        assert_eq!(db.flush().unwrap_err().code(), "IO_ERROR");
+        db.flush().unwrap();
+        faulty.drop_syncs(true);
+        db.execute("insert 2 bob bob@example.com").unwrap();
+        db.flush().unwrap();
+        assert_eq!(faulty.dropped_syncs(), 1);
+        drop(db);
+        assert_eq!(surviving_ids(files.clone()), [1, 2]);
+
+        // Tearing the write of page 2, past the end of the file, leaves it a
+        // part page long.
+        let mut db = Options::new().vfs(faulty.clone()).open("crash.db").unwrap();
+        for id in 3..=20 {
+            db.execute(&format!("insert {} user{} u@x", id, id)).unwrap();
+        }
+        faulty.tear_write(faulty.writes() + 2, 100);
+        assert!(db.flush().is_err());
+        drop(db);
+        let err = Options::new().vfs(files).open("crash.db").err().unwrap();
+        assert_eq!(err.code(), "CORRUPT");
+    }
+}
@@ -0,0 +1,195 @@
+//! Reports on each statement a database runs, for logging and debugging;
+//! see `Database::on_statement`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::compiler::{create_table_sql, table_name, Statement, StatementType};
+use crate::error::DbError;
+use crate::pager::PagerCounters;
+
+/// Called after each statement with what it did.
+pub type StatementHook = Arc<dyn Fn(&StatementTrace<'_>) + Send + Sync>;
+
+/// What one statement did, from `Database::on_statement`.
+#[derive(Debug)]
+pub struct StatementTrace<'a> {
+    /// The statement as given, values and all; `redacted_sql` leaves them
+    /// out.
+    pub sql: &'a str,
+    /// `None` if the statement did not parse.
+    pub kind: Option<StatementType>,
+    pub prepare_time: Duration,
+    pub execute_time: Duration,
+    /// Rows a select returned or an insert wrote. `None` for `query` and
+    /// `select`, whose rows are read after the call returns, and when the
+    /// statement failed.
+    pub rows: Option<usize>,
+    /// Cache and file activity while the statement ran, over the database
+    /// and every attached one.
+    pub io: PagerCounters,
+    pub error: Option<&'a DbError>,
+    pub(crate) statement: Option<&'a Statement>,
+}
+
+impl StatementTrace<'_> {
+    /// The statement with the values it inserts or looks up replaced by
+    /// `?`, safe to log where the data is not: `insert into users ?`,
+    /// `select from users where id = ?`. A statement that did not parse
+    /// comes out as `?`.
+    pub fn redacted_sql(&self) -> String {
+        let Some(statement) = self.statement else {
+            return "?".to_string();
+        };
+        let table = |schema: &Option<String>| table_name(schema.as_deref());
+        let filter = if statement.where_id.is_some() { " where id = ?" } else { "" };
+        match statement.typ {
+            StatementType::Insert => format!("insert into {} ?", table(&statement.into)),
+            StatementType::Select => format!("select from {}{}", table(&statement.from), filter),
+            StatementType::InsertSelect => format!("insert into {} select from {}{}", table(&statement.into), table(&statement.from), filter),
+            StatementType::CreateTable => match &statement.options {
+                Some(options) => create_table_sql(statement.into.as_deref(), options),
+                None => "create table ?".to_string(),
+            },
+        }
+    }
+}
+
+/// A statement being timed for a hook.
+pub(crate) struct Tracer {
+    hook: StatementHook,
+    /// When the statement started to run, after it was prepared.
+    start: Instant,
+    prepare_time: Duration,
+    /// The counters before the statement ran.
+    io: PagerCounters,
+}
+
+impl Tracer {
+    /// Starts timing a statement whose preparing began at `prepare_start`.
+    pub(crate) fn new(hook: StatementHook, prepare_start: Instant, io: PagerCounters) -> Self {
+        let start = Instant::now();
+        Tracer { hook, start, prepare_time: start - prepare_start, io }
+    }
+
+    /// Calls the hook with what the statement did; `io` is the counters
+    /// after it ran.
+    pub(crate) fn finish(self, sql: &str, statement: Option<&Statement>, outcome: Result<Option<usize>, &DbError>, io: PagerCounters) {
+        let trace = StatementTrace {
+            sql,
+            kind: statement.map(|statement| statement.typ),
+            prepare_time: self.prepare_time,
+            execute_time: self.start.elapsed(),
+            rows: outcome.ok().flatten(),
+            io: io - self.io,
+            error: outcome.err(),
+            statement,
+        };
+        (self.hook)(&trace);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::testing::TempPath;
+    use std::ops::ControlFlow;
+    use std::sync::Mutex;
+
+    /// What the tests keep of a trace.
+    #[derive(Debug, PartialEq)]
+    struct Seen {
+        sql: String,
+        redacted: String,
+        kind: Option<StatementType>,
+        rows: Option<usize>,
+        error: Option<&'static str>,
+    }
+
+    fn collect(db: &mut Database) -> Arc<Mutex<Vec<Seen>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        db.on_statement(move |trace| {
+            sink.lock().unwrap().push(Seen {
+                sql: trace.sql.to_string(),
+                redacted: trace.redacted_sql(),
+                kind: trace.kind,
+                rows: trace.rows,
+                error: trace.error.map(DbError::code),
+            })
+        });
+        seen
+    }
+
+    fn seen(sql: &str, redacted: &str, kind: Option<StatementType>, rows: Option<usize>, error: Option<&'static str>) -> Seen {
+        Seen { sql: sql.to_string(), redacted: redacted.to_string(), kind, rows, error }
+    }
+
+    #[test]
+    fn test_one_trace_per_statement() {
+        let mut db = Database::open_in_memory();
+        db.execute("insert 9 before before@example.com").unwrap();
+        let traces = collect(&mut db);
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        db.execute("insert 1 alice alice@example.com").unwrap_err();
+        db.execute("select").unwrap();
+        db.execute("bogus").unwrap_err();
+        db.query("select where id = 1").unwrap().count();
+        db.execute_with::<()>("select", |_| ControlFlow::Continue(())).unwrap();
+        db.select("select from other.users").err().unwrap();
+        db.execute("create table users (id integer, username varchar(32), email varchar(255), age integer)").unwrap();
+        db.clear_statement_hook();
+        db.execute("select").unwrap();
+
+        use StatementType::*;
+        assert_eq!(
+            *traces.lock().unwrap(),
+            [
+                seen("insert 1 alice alice@example.com", "insert into users ?", Some(Insert), Some(1), None),
+                seen("insert 1 alice alice@example.com", "insert into users ?", Some(Insert), None, Some("DUPLICATE_KEY")),
+                seen("select", "select from users", Some(Select), Some(2), None),
+                seen("bogus", "?", None, None, Some("UNRECOGNIZED_STATEMENT")),
+                seen("select where id = 1", "select from users where id = ?", Some(Select), None, None),
+                seen("select", "select from users", Some(Select), Some(2), None),
+                seen("select from other.users", "select from other.users", Some(Select), None, Some("NO_SUCH_DATABASE")),
+                seen(
+                    "create table users (id integer, username varchar(32), email varchar(255), age integer)",
+                    "create table users (id integer primary key, username varchar(32), email varchar(255), age integer);",
+                    Some(CreateTable),
+                    Some(0),
+                    None
+                ),
+            ]
+        );
+        // A statement that does not parse is traced but not counted.
+        assert_eq!(db.stats().statements, 9);
+    }
+
+    #[test]
+    fn test_traces_count_pager_io() {
+        let path = TempPath::new("trace.db");
+        let mut db = Database::open(&path).unwrap();
+        for id in 1..=100 {
+            db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+        }
+        db.close().unwrap();
+
+        let mut db = Database::open(&path).unwrap();
+        let traces = Arc::new(Mutex::new(Vec::new()));
+        let sink = traces.clone();
+        db.on_statement(move |trace| sink.lock().unwrap().push((trace.io, trace.prepare_time, trace.execute_time)));
+        db.execute("select").unwrap();
+        db.execute("select").unwrap();
+
+        let traces = traces.lock().unwrap();
+        let (cold, warm) = (traces[0].0, traces[1].0);
+        let pages = db.stats().pages as u64;
+        // The first select loads every page from the file, the second finds
+        // them all in the cache.
+        assert!(cold.cache_misses > 0 && cold.bytes_read > 0, "{:?}", cold);
+        assert_eq!((warm.cache_misses, warm.bytes_read), (0, 0));
+        assert!(warm.cache_hits >= pages - 1, "{:?}", warm);
+        assert!(traces.iter().all(|&(_, prepare, execute)| prepare < Duration::from_secs(1) && execute < Duration::from_secs(5)));
+    }
+}
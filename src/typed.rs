@@ -0,0 +1,199 @@
+//! Reading selected rows into the caller's own types, column by name; see
+//! `Database::query_as`.
+//!
+//! ```
+//! use VoidDB::Database;
+//! use VoidDB::row::{RowError, RowRef};
+//! use VoidDB::typed::FromRow;
+//!
+//! struct User {
+//!     id: u32,
+//!     name: String,
+//! }
+//!
+//! impl FromRow<'_> for User {
+//!     fn from_row(row: RowRef<'_>) -> Result<Self, RowError> {
+//!         Ok(User { id: row.get("id")?, name: row.get("username")? })
+//!     }
+//! }
+//!
+//! let mut db = Database::open_in_memory();
+//! db.execute("insert 1 alice alice@example.com").unwrap();
+//! let users: Vec<User> = db.query_as("select").unwrap();
+//! assert_eq!((users[0].id, users[0].name.as_str()), (1, "alice"));
+//! ```
+
+use crate::row::{Row, RowError, RowRef, Value};
+
+/// A type a selected row can be read into. Implementations pick the
+/// columns they want by name with `RowRef::get`; the name of a field need
+/// not match that of its column.
+///
+/// The lifetime is that of the page the row is read from, so a type
+/// borrowing `&'a str` fields can be read inside `Database::execute_with`,
+/// while `query_as` needs types that own their values.
+pub trait FromRow<'a>: Sized {
+    fn from_row(row: RowRef<'a>) -> Result<Self, RowError>;
+}
+
+impl FromRow<'_> for Row {
+    fn from_row(row: RowRef<'_>) -> Result<Self, RowError> {
+        row.to_row()
+    }
+}
+
+/// A type one column value can be read as.
+pub trait FromValue<'a>: Sized {
+    /// The type's name, for errors.
+    const EXPECTED: &'static str;
+
+    /// The value as this type, or `None` if it is of another kind or out
+    /// of range.
+    fn from_value(value: Value<'a>) -> Option<Self>;
+}
+
+macro_rules! integer_from_value {
+    ($($t:ty),*) => {$(
+        impl FromValue<'_> for $t {
+            const EXPECTED: &'static str = stringify!($t);
+
+            fn from_value(value: Value<'_>) -> Option<Self> {
+                match value {
+                    Value::Integer(n) => n.try_into().ok(),
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+integer_from_value!(u8, u16, u32, u64, usize, i32, i64);
+
+impl<'a> FromValue<'a> for &'a str {
+    const EXPECTED: &'static str = "&str";
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue<'_> for String {
+    const EXPECTED: &'static str = "String";
+
+    fn from_value(value: Value<'_>) -> Option<Self> {
+        <&str>::from_value(value).map(str::to_string)
+    }
+}
+
+/// `None` for a null value.
+impl<'a, T: FromValue<'a>> FromValue<'a> for Option<T> {
+    const EXPECTED: &'static str = T::EXPECTED;
+
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use std::ops::ControlFlow;
+
+    /// Two of the four columns, one under another name.
+    #[derive(Debug, PartialEq)]
+    struct Named {
+        id: u64,
+        name: String,
+    }
+
+    impl FromRow<'_> for Named {
+        fn from_row(row: RowRef<'_>) -> Result<Self, RowError> {
+            Ok(Named { id: row.get("id")?, name: row.get("username")? })
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Contact<'a> {
+        email: &'a str,
+        age: Option<u8>,
+    }
+
+    impl<'a> FromRow<'a> for Contact<'a> {
+        fn from_row(row: RowRef<'a>) -> Result<Self, RowError> {
+            Ok(Contact { email: row.get("email")?, age: row.get("age")? })
+        }
+    }
+
+    /// A struct with one field, read from `column` as a `T`.
+    #[derive(Debug)]
+    struct One<T, const C: char>(T);
+
+    impl<T: for<'a> FromValue<'a>, const C: char> FromRow<'_> for One<T, C> {
+        fn from_row(row: RowRef<'_>) -> Result<Self, RowError> {
+            let column = match C {
+                'i' => "id",
+                'u' => "username",
+                _ => "name",
+            };
+            row.get(column).map(One)
+        }
+    }
+
+    fn database() -> Database {
+        let mut db = Database::open_in_memory();
+        db.execute("insert 1 alice alice@example.com").unwrap();
+        db.execute("insert 5000000000 bob bob@example.com").unwrap();
+        db
+    }
+
+    #[test]
+    fn test_query_as_reads_columns_by_name() {
+        let mut db = database();
+        assert_eq!(
+            db.query_as::<Named>("select").unwrap(),
+            [Named { id: 1, name: "alice".to_string() }, Named { id: 5000000000, name: "bob".to_string() }]
+        );
+        assert_eq!(db.query_as::<Named>("select where id = 2").unwrap(), []);
+        assert_eq!(db.query_as::<Row>("select where id = 1").unwrap(), [Row::new(1, "alice", "alice@example.com")]);
+    }
+
+    #[test]
+    fn test_borrowed_fields_inside_execute_with() {
+        let mut db = database();
+        let mut emails = Vec::new();
+        db.execute_with::<()>("select", |row| {
+            let contact = Contact::from_row(row).unwrap();
+            assert_eq!(contact.age, Some(0));
+            emails.push(contact.email.to_string());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(emails, ["alice@example.com", "bob@example.com"]);
+    }
+
+    #[test]
+    fn test_mismatched_and_missing_columns() {
+        let mut db = database();
+        let err = db.query_as::<One<String, 'i'>>("select").unwrap_err();
+        assert_eq!(err.code(), "TYPE_MISMATCH");
+        assert_eq!(err.to_string(), "Error: cannot read id (integer 1) as String.");
+
+        let err = db.query_as::<One<u32, 'i'>>("select").unwrap_err();
+        assert_eq!(err.to_string(), "Error: cannot read id (integer 5000000000) as u32.");
+        let ids: Vec<u64> = db.query_as::<One<u64, 'i'>>("select").unwrap().into_iter().map(|One(id)| id).collect();
+        assert_eq!(ids, [1, 5000000000]);
+        let err = db.query_as::<One<u64, 'u'>>("select").unwrap_err();
+        assert_eq!(err.to_string(), "Error: cannot read username (text) as u64.");
+
+        let err = db.query_as::<One<String, 'n'>>("select").unwrap_err();
+        assert_eq!(err.code(), "NO_SUCH_COLUMN");
+        assert_eq!(err.to_string(), "Error: no column named 'name'.");
+    }
+}
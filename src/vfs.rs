@@ -0,0 +1,392 @@
+//! Where database files live. All file access goes through a `Vfs`, which
+//! is the real file system unless `Options::vfs` picks another, such as
+//! `MemoryVfs` for tests that should leave nothing on disk.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use VoidDB::vfs::MemoryVfs;
+//! use VoidDB::Options;
+//!
+//! let vfs = Arc::new(MemoryVfs::new());
+//! let mut db = Options::new().vfs(vfs.clone()).open("app.db").unwrap();
+//! db.execute("insert 1 alice alice@example.com").unwrap();
+//! db.close().unwrap();
+//!
+//! assert!(!std::path::Path::new("app.db").exists());
+//! let db = Options::new().vfs(vfs).open("app.db").unwrap();
+//! assert_eq!(db.select("select").unwrap().count(), 1);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// How `Vfs::open` opens a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// An existing file, for reading only.
+    ReadOnly,
+    /// An existing file, for reading and writing.
+    ReadWrite,
+    /// As `ReadWrite`, creating the file empty if it does not exist.
+    Create,
+    /// A new, empty file; fails with `AlreadyExists` if there is one.
+    CreateNew,
+}
+
+/// A place files can be opened in. A missing file fails with an
+/// `io::ErrorKind::NotFound` error, as with `std::fs`.
+pub trait Vfs: fmt::Debug + Send + Sync {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn VfsFile>>;
+
+    /// The one name of the file at `path`, whichever of its names `path` is,
+    /// to tell whether two paths are the same file.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Moves the file at `from` to `to`, replacing any file there, in one
+    /// step that leaves one or the other in place should it fail.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+}
+
+/// A file opened by a `Vfs`. Reads and locks go through a shared
+/// reference, so readers on several threads can use one file at once.
+pub trait VfsFile: fmt::Debug + Send + Sync {
+    /// Fills `buf` from the bytes at `offset`, failing with
+    /// `UnexpectedEof` if the file ends first.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Writes all of `buf` at `offset`, growing the file if it ends before.
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Waits until what was written is on the disk; with `data_only`, only
+    /// the data and not metadata such as the modification time.
+    fn sync(&mut self, data_only: bool) -> io::Result<()>;
+
+    /// Cuts the file down, or pads it with zeroes, to `len` bytes.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+
+    /// Takes a lock on the file without waiting: a shared one, which other
+    /// shared locks may hold too, or an exclusive one. Fails with
+    /// `WouldBlock` while another handle holds a lock that conflicts. The
+    /// lock is let go when the file is dropped.
+    fn try_lock(&self, shared: bool) -> Result<(), TryLockError>;
+
+    fn file_size(&self) -> io::Result<u64>;
+}
+
+/// The VFS used when none is chosen.
+pub fn default() -> Arc<dyn Vfs> {
+    static STD: OnceLock<Arc<dyn Vfs>> = OnceLock::new();
+    STD.get_or_init(|| Arc::new(StdVfs)).clone()
+}
+
+/// The real file system, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdVfs;
+
+impl Vfs for StdVfs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(mode != OpenMode::ReadOnly);
+        match mode {
+            OpenMode::Create => options.create(true).truncate(false),
+            OpenMode::CreateNew => options.create_new(true),
+            OpenMode::ReadOnly | OpenMode::ReadWrite => &mut options,
+        };
+        Ok(Box::new(StdFile(Mutex::new(options.open(path)?))))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+/// A file of `StdVfs`, locked while a read seeks and reads.
+#[derive(Debug)]
+struct StdFile(Mutex<File>);
+
+impl StdFile {
+    fn file(&self) -> std::sync::MutexGuard<'_, File> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl VfsFile for StdFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut file = self.file();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let file = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(buf)
+    }
+
+    fn sync(&mut self, data_only: bool) -> io::Result<()> {
+        let file = self.0.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if data_only {
+            file.sync_data()
+        } else {
+            file.sync_all()
+        }
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.0.get_mut().unwrap_or_else(PoisonError::into_inner).set_len(len)
+    }
+
+    fn try_lock(&self, shared: bool) -> Result<(), TryLockError> {
+        let file = self.file();
+        if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        }
+    }
+
+    fn file_size(&self) -> io::Result<u64> {
+        Ok(self.file().metadata()?.len())
+    }
+}
+
+/// Files kept in memory, by path, for as long as the VFS lives. Each has
+/// its own locks, which act as file locks do, so two handles in one
+/// `MemoryVfs` shut each other out as they would on disk.
+#[derive(Debug, Default)]
+pub struct MemoryVfs {
+    files: Mutex<HashMap<PathBuf, Arc<MemoryFile>>>,
+}
+
+/// The bytes and locks of one file in a `MemoryVfs`.
+#[derive(Debug, Default)]
+struct MemoryFile {
+    bytes: Mutex<Vec<u8>>,
+    locks: Mutex<Locks>,
+}
+
+#[derive(Debug, Default)]
+struct Locks {
+    shared: usize,
+    exclusive: bool,
+}
+
+impl MemoryVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of the bytes of the file at `path`, if there is one.
+    pub fn contents(&self, path: &Path) -> Option<Vec<u8>> {
+        let files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        files.get(path).map(|file| file.bytes.lock().unwrap_or_else(PoisonError::into_inner).clone())
+    }
+
+    /// Puts a file with `bytes` at `path`, replacing any there.
+    pub fn insert(&self, path: &Path, bytes: Vec<u8>) {
+        let file = MemoryFile { bytes: Mutex::new(bytes), locks: Mutex::default() };
+        self.files.lock().unwrap_or_else(PoisonError::into_inner).insert(path.to_path_buf(), Arc::new(file));
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Path, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        let file = match (files.get(path), mode) {
+            (Some(_), OpenMode::CreateNew) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists")),
+            (Some(file), _) => file.clone(),
+            (None, OpenMode::Create | OpenMode::CreateNew) => files.entry(path.to_path_buf()).or_default().clone(),
+            (None, _) => return Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        };
+        Ok(Box::new(MemoryHandle { file, writable: mode != OpenMode::ReadOnly, lock: Mutex::new(None) }))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.files.lock().unwrap_or_else(PoisonError::into_inner).contains_key(path) {
+            true => Ok(path.to_path_buf()),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        }
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match self.files.lock().unwrap_or_else(PoisonError::into_inner).remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap_or_else(PoisonError::into_inner);
+        match files.remove(from) {
+            Some(file) => {
+                files.insert(to.to_path_buf(), file);
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+        }
+    }
+}
+
+/// A file of a `MemoryVfs` as one handle has it open.
+#[derive(Debug)]
+struct MemoryHandle {
+    file: Arc<MemoryFile>,
+    writable: bool,
+    /// The lock this handle holds: `Some(true)` for a shared one.
+    lock: Mutex<Option<bool>>,
+}
+
+impl MemoryHandle {
+    fn bytes(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.file.bytes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        match self.writable {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::PermissionDenied, "file is open for reading only")),
+        }
+    }
+}
+
+impl VfsFile for MemoryHandle {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let bytes = self.bytes();
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        let Some(found) = bytes.get(start..).and_then(|rest| rest.get(..buf.len())) else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past the end of the file"));
+        };
+        buf.copy_from_slice(found);
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.check_writable()?;
+        let mut bytes = self.bytes();
+        let start = offset as usize;
+        if bytes.len() < start + buf.len() {
+            bytes.resize(start + buf.len(), 0);
+        }
+        bytes[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync(&mut self, _data_only: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.check_writable()?;
+        self.bytes().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn try_lock(&self, shared: bool) -> Result<(), TryLockError> {
+        let mut held = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut locks = self.file.locks.lock().unwrap_or_else(PoisonError::into_inner);
+        // The lock this handle holds is swapped for the new one, and kept if
+        // another handle is in the way.
+        let others_shared = locks.shared - usize::from(*held == Some(true));
+        let others_exclusive = locks.exclusive && *held != Some(false);
+        if others_exclusive || (!shared && others_shared > 0) {
+            return Err(TryLockError::WouldBlock);
+        }
+        match held.take() {
+            Some(true) => locks.shared -= 1,
+            Some(false) => locks.exclusive = false,
+            None => {}
+        }
+        if shared {
+            locks.shared += 1;
+        } else {
+            locks.exclusive = true;
+        }
+        *held = Some(shared);
+        Ok(())
+    }
+
+    fn file_size(&self) -> io::Result<u64> {
+        Ok(self.bytes().len() as u64)
+    }
+}
+
+impl Drop for MemoryHandle {
+    fn drop(&mut self) {
+        let mut locks = self.file.locks.lock().unwrap_or_else(PoisonError::into_inner);
+        match self.lock.get_mut().unwrap_or_else(PoisonError::into_inner).take() {
+            Some(true) => locks.shared -= 1,
+            Some(false) => locks.exclusive = false,
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_files_act_like_real_ones() {
+        let vfs = MemoryVfs::new();
+        let path = Path::new("a.db");
+        assert_eq!(vfs.open(path, OpenMode::ReadWrite).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(vfs.canonicalize(path).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let mut file = vfs.open(path, OpenMode::Create).unwrap();
+        file.write_at(b"world", 6).unwrap();
+        file.write_at(b"hello", 0).unwrap();
+        assert_eq!(file.file_size().unwrap(), 11);
+        let mut buf = [0; 5];
+        file.read_at(&mut buf, 6).unwrap();
+        assert_eq!(&buf, b"world");
+        assert_eq!(file.read_at(&mut buf, 8).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        file.truncate(5).unwrap();
+        assert_eq!(vfs.contents(path).unwrap(), b"hello");
+
+        let mut reader = vfs.open(path, OpenMode::ReadOnly).unwrap();
+        assert_eq!(vfs.open(path, OpenMode::CreateNew).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(vfs.canonicalize(path).unwrap(), path);
+        assert_eq!(reader.write_at(b"x", 0).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+        // A rename replaces what is at the new path.
+        vfs.insert(Path::new("b.db"), b"old".to_vec());
+        vfs.rename(path, Path::new("b.db")).unwrap();
+        assert!(vfs.contents(path).is_none());
+        assert_eq!(vfs.contents(Path::new("b.db")).unwrap(), b"hello");
+        assert_eq!(vfs.rename(path, Path::new("b.db")).unwrap_err().kind(), io::ErrorKind::NotFound);
+        vfs.remove(Path::new("b.db")).unwrap();
+        assert!(vfs.contents(Path::new("b.db")).is_none());
+    }
+
+    #[test]
+    fn test_memory_locks_shut_out_other_handles() {
+        let vfs = MemoryVfs::new();
+        let path = Path::new("a.db");
+        let first = vfs.open(path, OpenMode::Create).unwrap();
+        let second = vfs.open(path, OpenMode::ReadWrite).unwrap();
+        first.try_lock(true).unwrap();
+        second.try_lock(true).unwrap();
+        assert!(matches!(first.try_lock(false), Err(TryLockError::WouldBlock)));
+        assert!(matches!(vfs.open(path, OpenMode::ReadWrite).unwrap().try_lock(false), Err(TryLockError::WouldBlock)));
+        drop(second);
+        first.try_lock(false).unwrap();
+        let third = vfs.open(path, OpenMode::ReadWrite).unwrap();
+        assert!(matches!(third.try_lock(true), Err(TryLockError::WouldBlock)));
+        drop(first);
+        third.try_lock(false).unwrap();
+    }
+}
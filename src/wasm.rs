@@ -0,0 +1,181 @@
+//! Running VoidDB in a browser, on an in-memory database.
+//!
+//! Build with
+//! `cargo rustc --release --lib --target wasm32-unknown-unknown --features wasm --crate-type cdylib`.
+//! The module needs no JavaScript glue: it exports plain functions taking
+//! and returning pointers into its memory, and `exec` answers in JSON.
+//!
+//! ```js
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("VoidDB.wasm"));
+//! const wasm = instance.exports;
+//! const db = wasm.voiddb_wasm_new();
+//! function exec(sql) {
+//!     const bytes = new TextEncoder().encode(sql);
+//!     const ptr = wasm.voiddb_wasm_alloc(bytes.length);
+//!     new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+//!     const out = wasm.voiddb_wasm_exec(db, ptr, bytes.length);
+//!     wasm.voiddb_wasm_dealloc(ptr, bytes.length);
+//!     const len = wasm.voiddb_wasm_result_len(db);
+//!     return JSON.parse(new TextDecoder().decode(new Uint8Array(wasm.memory.buffer, out, len)));
+//! }
+//! exec("insert 1 alice alice@example.com");
+//! exec("select").rows[0].username; // "alice"
+//! ```
+
+use crate::compiler::QueryResult;
+use crate::database::Database;
+use crate::json;
+use crate::row::COLUMNS;
+
+/// An in-memory database that takes statements as text and answers in
+/// JSON, for callers that only speak strings.
+pub struct WasmDb {
+    db: Database,
+    /// The answer to the last `exec`, kept for `voiddb_wasm_exec` to point
+    /// into.
+    result: Vec<u8>,
+}
+
+impl WasmDb {
+    pub fn new() -> Self {
+        WasmDb { db: Database::open_in_memory(), result: Vec::new() }
+    }
+
+    /// Runs one statement. Answers `{"rows":[{"id":1,"username":...},...]}`
+    /// for a select, `{"rowsAffected":N}` for an insert, `{"done":true}`
+    /// for other statements, or `{"error":{"code":...,"message":...}}`.
+    pub fn exec(&mut self, sql: &str) -> String {
+        self.run(sql);
+        String::from_utf8(self.result.clone()).expect("JSON is UTF-8")
+    }
+
+    fn run(&mut self, sql: &str) {
+        let out = &mut self.result;
+        out.clear();
+        // Writing to a Vec cannot fail, hence the ignored results.
+        match self.db.execute(sql) {
+            Ok(QueryResult::Rows(rows)) => {
+                out.extend_from_slice(b"{\"rows\":[");
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        out.push(b',');
+                    }
+                    let _ = json::write_object(out, COLUMNS.into_iter().zip(row.values()));
+                }
+                out.extend_from_slice(b"]}");
+            }
+            Ok(QueryResult::RowsAffected(n)) => out.extend_from_slice(format!("{{\"rowsAffected\":{}}}", n).as_bytes()),
+            Ok(QueryResult::Done) => out.extend_from_slice(b"{\"done\":true}"),
+            Err(err) => {
+                out.extend_from_slice(b"{\"error\":{\"code\":");
+                let _ = json::write_string(out, err.code());
+                out.extend_from_slice(b",\"message\":");
+                let _ = json::write_string(out, &err.to_string());
+                out.extend_from_slice(b"}}");
+            }
+        }
+    }
+}
+
+impl Default for WasmDb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A new database, to be freed with `voiddb_wasm_free`.
+#[no_mangle]
+pub extern "C" fn voiddb_wasm_new() -> *mut WasmDb {
+    Box::into_raw(Box::new(WasmDb::new()))
+}
+
+/// # Safety
+///
+/// `db` must come from `voiddb_wasm_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_wasm_free(db: *mut WasmDb) {
+    drop(Box::from_raw(db));
+}
+
+/// `len` bytes for the caller to write a statement into.
+#[no_mangle]
+pub extern "C" fn voiddb_wasm_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()).cast()
+}
+
+/// # Safety
+///
+/// `ptr` and `len` must come from one call to `voiddb_wasm_alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_wasm_dealloc(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Runs the statement in the `len` bytes at `sql` and returns where its
+/// JSON answer starts; `voiddb_wasm_result_len` gives its length. The
+/// answer stays put until the next call on `db`. Text that is not UTF-8
+/// is answered with an error.
+///
+/// # Safety
+///
+/// `db` must come from `voiddb_wasm_new`, and `sql` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_wasm_exec(db: *mut WasmDb, sql: *const u8, len: usize) -> *const u8 {
+    let db = &mut *db;
+    match std::str::from_utf8(std::slice::from_raw_parts(sql, len)) {
+        Ok(sql) => db.run(sql),
+        Err(_) => {
+            db.result.clear();
+            db.result.extend_from_slice(b"{\"error\":{\"code\":\"INVALID_UTF8\",\"message\":\"Statement is not valid UTF-8.\"}}");
+        }
+    }
+    db.result.as_ptr()
+}
+
+/// # Safety
+///
+/// `db` must come from `voiddb_wasm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn voiddb_wasm_result_len(db: *const WasmDb) -> usize {
+    (*db).result.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec() {
+        let mut db = WasmDb::new();
+        assert_eq!(db.exec("select"), r#"{"rows":[]}"#);
+        assert_eq!(db.exec("insert 1 alice alice@example.com"), r#"{"rowsAffected":1}"#);
+        assert_eq!(db.exec("insert 2 \"bob\" bob@example.com"), r#"{"rowsAffected":1}"#);
+        assert_eq!(
+            db.exec("select"),
+            r#"{"rows":[{"id":1,"username":"alice","email":"alice@example.com","age":0},{"id":2,"username":"\"bob\"","email":"bob@example.com","age":0}]}"#
+        );
+        assert_eq!(db.exec("create table users (id integer, username varchar(32), email varchar(255), age integer)"), r#"{"done":true}"#);
+        assert_eq!(db.exec("insert 1 a a@x"), r#"{"error":{"code":"DUPLICATE_KEY","message":"Error: Duplicate key."}}"#);
+        assert_eq!(json::parse(&db.exec("bogus")).unwrap(), json::parse(r#"{"error":{"code":"UNRECOGNIZED_STATEMENT","message":"Unrecognized statement"}}"#).unwrap());
+    }
+
+    #[test]
+    fn test_exports() {
+        fn exec(db: *mut WasmDb, sql: &[u8]) -> String {
+            unsafe {
+                let ptr = voiddb_wasm_alloc(sql.len());
+                std::slice::from_raw_parts_mut(ptr, sql.len()).copy_from_slice(sql);
+                let out = voiddb_wasm_exec(db, ptr, sql.len());
+                voiddb_wasm_dealloc(ptr, sql.len());
+                String::from_utf8(std::slice::from_raw_parts(out, voiddb_wasm_result_len(db)).to_vec()).unwrap()
+            }
+        }
+
+        let db = voiddb_wasm_new();
+        assert_eq!(exec(db, b"insert 1 alice alice@example.com"), r#"{"rowsAffected":1}"#);
+        assert_eq!(exec(db, b"select where id = 1"), r#"{"rows":[{"id":1,"username":"alice","email":"alice@example.com","age":0}]}"#);
+        assert_eq!(exec(db, b"select \xff"), r#"{"error":{"code":"INVALID_UTF8","message":"Statement is not valid UTF-8."}}"#);
+        assert_eq!(exec(db, b""), r#"{"error":{"code":"UNRECOGNIZED_STATEMENT","message":"Unrecognized statement"}}"#);
+        unsafe { voiddb_wasm_free(db) };
+    }
+}
@@ -0,0 +1,99 @@
+//! An append-only log of byte records, built on the public pager alone.
+//!
+//! Page 0 holds the number of records. Each page after it starts with the
+//! number of bytes it uses, followed by records of a two-byte length and
+//! the bytes themselves. A record never straddles two pages.
+
+use std::path::Path;
+
+use VoidDB::pager::{Pager, PAGE_SIZE};
+use VoidDB::DbError;
+
+const USED: usize = 2;
+
+struct Log {
+    pager: Pager,
+}
+
+impl Log {
+    fn open(path: &Path) -> Result<Log, DbError> {
+        let mut pager = Pager::open(path)?;
+        if pager.num_pages() == 0 {
+            pager.allocate_page()?;
+        }
+        Ok(Log { pager })
+    }
+
+    fn len(&self) -> u64 {
+        u64::from_le_bytes(self.pager.get_page(0).unwrap()[..8].try_into().unwrap())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<(), DbError> {
+        assert!(USED + 2 + record.len() <= PAGE_SIZE, "record too long for a page");
+        let last = self.pager.num_pages() - 1;
+        let used = if last == 0 { PAGE_SIZE } else { used(&self.pager.get_page(last)?) };
+        let (page_num, start) = if used + 2 + record.len() <= PAGE_SIZE {
+            (last, used)
+        } else {
+            (self.pager.allocate_page()?, USED)
+        };
+
+        let mut page = self.pager.get_page_mut(page_num)?;
+        page[start..start + 2].copy_from_slice(&(record.len() as u16).to_le_bytes());
+        page[start + 2..start + 2 + record.len()].copy_from_slice(record);
+        page[..USED].copy_from_slice(&((start + 2 + record.len()) as u16).to_le_bytes());
+
+        let len = self.len() + 1;
+        self.pager.get_page_mut(0)?[..8].copy_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<Vec<u8>>, DbError> {
+        let mut records = Vec::new();
+        for page_num in 1..self.pager.num_pages() {
+            let page = self.pager.get_page(page_num)?;
+            let mut at = USED;
+            while at < used(&page) {
+                let len = u16::from_le_bytes([page[at], page[at + 1]]) as usize;
+                records.push(page[at + 2..at + 2 + len].to_vec());
+                at += 2 + len;
+            }
+        }
+        Ok(records)
+    }
+}
+
+fn used(page: &[u8]) -> usize {
+    u16::from_le_bytes([page[0], page[1]]) as usize
+}
+
+#[test]
+fn test_append_only_log_survives_reopen() {
+    let path = std::env::temp_dir().join(format!("voiddb-test-append-log-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let expected: Vec<Vec<u8>> = (0..500).map(|n: usize| format!("event {} {}", n, "x".repeat(n % 40)).into_bytes()).collect();
+
+    let mut log = Log::open(&path).unwrap();
+    for record in &expected[..300] {
+        log.append(record).unwrap();
+    }
+    log.pager.flush().unwrap();
+    drop(log);
+
+    let mut log = Log::open(&path).unwrap();
+    assert_eq!(log.len(), 300);
+    for record in &expected[300..] {
+        log.append(record).unwrap();
+    }
+    // Only the pages the second batch touched are written.
+    let pages = log.pager.num_pages();
+    log.pager.flush().unwrap();
+    assert!(log.pager.counters().pages_written < pages as u64);
+    drop(log);
+
+    let log = Log::open(&path).unwrap();
+    assert_eq!(log.len(), 500);
+    assert_eq!(log.records().unwrap(), expected);
+    drop(log);
+    std::fs::remove_file(&path).unwrap();
+}
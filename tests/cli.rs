@@ -0,0 +1,358 @@
+//! Runs the shell binary on piped input.
+
+#![cfg(feature = "cli")]
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// Runs the shell with `args`, feeds it `script` and closes its stdin.
+/// Fails the test if it has not exited within a few seconds. The shell
+/// gets a home directory without a `.voiddbrc`.
+fn run(args: &[&str], script: &str) -> Output {
+    run_at_home(&std::env::temp_dir().join("voiddb-test-no-home"), args, script)
+}
+
+/// Like `run`, with `HOME` set to `home`.
+fn run_at_home(home: &Path, args: &[&str], script: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(args)
+        .env("HOME", home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("shell did not exit at end of input");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_exits_at_end_of_input() {
+    let output = run(&[], "insert 1 alice alice@example.com\nselect\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 row inserted\n(1, alice, alice@example.com, 0)\n(1 row)\n"
+    );
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    // A last line without a newline still runs.
+    let output = run(&[], "select");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(0 rows)\n");
+}
+
+#[test]
+fn test_prompts_only_when_interactive() {
+    let script = "insert 1 alice alice@example.com\nselect\n";
+    let results = "1 row inserted\n(1, alice, alice@example.com, 0)\n(1 row)\n";
+
+    // Piped input is not a terminal, so no prompts by default.
+    assert_eq!(String::from_utf8(run(&[], script).stdout).unwrap(), results);
+    assert_eq!(String::from_utf8(run(&["--batch"], script).stdout).unwrap(), results);
+    assert_eq!(
+        String::from_utf8(run(&["--interactive"], script).stdout).unwrap(),
+        "db > 1 row inserted\ndb > (1, alice, alice@example.com, 0)\n(1 row)\ndb > "
+    );
+
+    let output = run(&["--bogus"], "");
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        concat!(
+            "Usage: VoidDB [--interactive | --batch] [--continue-on-error] [--init FILE] [--no-rc] ",
+            "[--history-file PATH] [--prompt TEXT] [--no-shell] [--connect ADDRESS [--binary] [--token-file PATH]] [-c SQL]... [FILE [SQL]]\n",
+            "       VoidDB serve FILE [--listen ADDRESS [--max-connections N] | --http ADDRESS] [--auth-token-file PATH] [--statement-timeout DURATION] [--grace-period SECONDS] [--replicate-to ADDRESS]\n",
+            "       VoidDB follow FILE [--listen ADDRESS [--max-connections N]] [--auth-token-file PATH] [--statement-timeout DURATION] [--grace-period SECONDS]\n"
+        )
+    );
+}
+
+#[test]
+fn test_end_of_input_saves_the_database() {
+    let path = std::env::temp_dir().join(format!("voiddb-test-cli-eof-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    assert!(run(&[path], "insert 1 alice alice@example.com\n").status.success());
+    let output = run(&[path], "select\n");
+    assert!(String::from_utf8(output.stdout).unwrap().contains("(1, alice, alice@example.com, 0)\n"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ctrl_c_in_batch_mode_saves_and_exits() {
+    use std::io::{BufRead, BufReader};
+
+    let path = std::env::temp_dir().join(format!("voiddb-test-cli-sigint-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    // The shell waits for more input with stdin still open when the signal
+    // arrives.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(["--no-rc", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"insert 1 alice alice@example.com\n").unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "1 row inserted\n");
+
+    let killed = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("shell did not exit on SIGINT");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    drop(stdin);
+
+    let output = run(&[path], "select\n");
+    assert!(String::from_utf8(output.stdout).unwrap().contains("(1, alice, alice@example.com, 0)\n"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_one_shot_statements() {
+    let path = std::env::temp_dir().join(format!("voiddb-test-cli-one-shot-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    // Stdin is never read: the script given here would fail if it were.
+    let output = run(&[path, "-c", "insert 1 alice alice@example.com", "-c", "insert 2 'bob;b' b@x; select"], "bogus\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 row inserted\n1 row inserted\n(1, alice, alice@example.com, 0)\n(2, bob;b, b@x, 0)\n(2 rows)\n"
+    );
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    // The rows were saved, and a positional statement works the same way.
+    let output = run(&[path, ".mode csv\nselect"], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,alice,alice@example.com,0\n2,bob;b,b@x,0\n(2 rows)\n");
+
+    // A failing statement fails the run, but the others still ran.
+    let output = run(&[path, "insert 3 carol c@x; selec; insert 4 dave d@x"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n1 row inserted\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Unrecognized keyword at start of 'selec'.\n");
+
+    let output = run(&[path, "insert 1 again a@x"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Error: Duplicate key.\n");
+
+    assert!(!run(&[path, "select", "select"], "").status.success());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_init_script_runs_first() {
+    let dir = std::env::temp_dir().join(format!("voiddb-test-cli-init-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let init = dir.join("setup.sql");
+    std::fs::write(&init, ".mode csv\ninsert 1 alice alice@example.com\ninsert 2 bob bob@example.com\n").unwrap();
+    let init = init.to_str().unwrap();
+
+    let output = run(&["--init", init, "-c", "select"], "");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 row inserted\n1 row inserted\n1,alice,alice@example.com,0\n2,bob,bob@example.com,0\n(2 rows)\n"
+    );
+
+    // Piped input runs after it too.
+    let output = run(&["--init", init], "select\n");
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("2,bob,bob@example.com,0\n(2 rows)\n"));
+
+    // A failing init script stops a batch run before it starts.
+    std::fs::write(dir.join("bad.sql"), "insert 1 a a@x\nselec\n").unwrap();
+    let bad = dir.join("bad.sql");
+    let output = run(&["--init", bad.to_str().unwrap(), "-c", "select"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+    let message = format!("{}:2: Unrecognized keyword at start of 'selec'.\n", bad.display());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), message);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rc_file_unless_no_rc() {
+    let home = std::env::temp_dir().join(format!("voiddb-test-cli-rc-{}", std::process::id()));
+    std::fs::create_dir_all(&home).unwrap();
+    std::fs::write(home.join(".voiddbrc"), ".quiet on\n.mode csv\n").unwrap();
+
+    let output = run_at_home(&home, &["-c", "insert 1 a a@x; select"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1,a,a@x,0\n");
+    let output = run_at_home(&home, &["--no-rc", "-c", "insert 1 a a@x; select"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n(1, a, a@x, 0)\n(1 row)\n");
+
+    std::fs::remove_dir_all(&home).unwrap();
+}
+
+#[test]
+fn test_batch_stops_at_first_error() {
+    let path = std::env::temp_dir().join(format!("voiddb-test-cli-batch-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    let script = "insert 1 alice a@x\ninsert 1 again b@x\ninsert 2 bob b@x\n";
+    let output = run(&[path], script);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "<stdin>:2: Error: Duplicate key.\n");
+    // Nothing after the failure ran; what ran before it was kept.
+    let output = run(&[path, "select"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(1, alice, a@x, 0)\n(1 row)\n");
+
+    // I/O errors have a status of their own.
+    let output = run(&["--batch", path], "insert 3 carol c@x\n.read /nonexistent/voiddb.sql\ninsert 4 dave d@x\n");
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+
+    // Carrying on is still possible, and so is the prompt's forgiveness.
+    let output = run(&["--continue-on-error", path], "insert 1 x x@x\ninsert 5 eve e@x\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+    let output = run(&["--interactive", path], "selec\ninsert 6 frank f@x\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Unrecognized keyword at start of 'selec'.\n");
+
+    let output = run(&[path, "select"], "");
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("(6, frank, f@x, 0)\n(4 rows)\n"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_exit_statuses() {
+    assert_eq!(run(&["--bogus"], "").status.code(), Some(2));
+    assert_eq!(run(&["-c"], "").status.code(), Some(2));
+    let dir = std::env::temp_dir();
+    assert_eq!(run(&[dir.to_str().unwrap()], "").status.code(), Some(3));
+    assert_eq!(run(&["-c", "selec"], "").status.code(), Some(1));
+    assert_eq!(run(&["-c", ".read /nonexistent/voiddb.sql"], "").status.code(), Some(3));
+}
+
+#[test]
+fn test_batch_stops_inside_scripts() {
+    let script = std::env::temp_dir().join(format!("voiddb-test-cli-bail-{}.sql", std::process::id()));
+    std::fs::write(&script, "insert 1 a a@x\ninsert 1 b b@x\ninsert 2 c c@x\n").unwrap();
+    let read = format!(".read {}\nselect\n", script.display());
+
+    let output = run(&[], &read);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("{}:2: Error: Duplicate key.\n", script.display()));
+
+    let output = run(&["--continue-on-error"], &read);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("(2 rows)\n"));
+    std::fs::remove_file(&script).unwrap();
+}
+
+#[test]
+fn test_piped_lines_split_like_one_shot_statements() {
+    let script = "insert 1 a a@x; insert 2 b b@x -- two rows\nselect -- all rows\n";
+    let piped = run(&[], script);
+    assert!(piped.status.success(), "{}", String::from_utf8_lossy(&piped.stderr));
+    assert_eq!(String::from_utf8(piped.stdout).unwrap(), "1 row inserted\n1 row inserted\n(1, a, a@x, 0)\n(2, b, b@x, 0)\n(2 rows)\n");
+    assert_eq!(run(&["-c", script], "").stdout, run(&[], script).stdout);
+}
+
+#[test]
+fn test_exit_saves_and_stops_reading() {
+    let path = std::env::temp_dir().join(format!("voiddb-test-cli-exit-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    let output = run(&[path], "insert 1 alice alice@example.com\n.exit\ninsert 2 bob bob@example.com\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n");
+
+    let output = run(&[path], "insert 2 bob bob@example.com\n.exit 2\n");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(run(&[path, "-c", ".exit 4"], "").status.code(), Some(4));
+
+    let output = run(&[path, "select"], "");
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("(2, bob, bob@example.com, 0)\n(2 rows)\n"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_prompt_flag() {
+    let output = run(&["--interactive", "--prompt", "%d> "], "select\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ":memory:> (0 rows)\n:memory:> ");
+
+    // A quoted value left open shows the continuation prompt until closed.
+    let output = run(&["--interactive", "--prompt", "db> "], "insert 1 'a\nb' a@x\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "db> ...> 1 row inserted\ndb> ");
+
+    // It wins over the prompt set by a startup script.
+    let init = std::env::temp_dir().join(format!("voiddb-test-cli-prompt-{}.sql", std::process::id()));
+    std::fs::write(&init, ".prompt 'init> '\n").unwrap();
+    let init = init.to_str().unwrap();
+    let output = run(&["--interactive", "--init", init], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "init> ");
+    let output = run(&["--interactive", "--init", init, "--prompt", "flag> "], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "flag> ");
+    std::fs::remove_file(init).unwrap();
+}
+
+#[test]
+fn test_no_shell_refuses_commands() {
+    let output = run(&["-c", ".shell echo hello"], "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello\n");
+
+    let output = run(&["--no-shell", "-c", ".shell echo hello"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Running commands is turned off by --no-shell.\n");
+}
+
+#[test]
+fn test_schema_recreates_the_tables() {
+    let dir = std::env::temp_dir().join(format!("voiddb-test-cli-schema-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (old, new) = (dir.join("old.db"), dir.join("new.db"));
+    let (old, new) = (old.to_str().unwrap(), new.to_str().unwrap());
+
+    let create = "create table users (id integer primary key, username varchar(12), email varchar(60), age integer);\n";
+    assert!(run(&[old], &format!("{}insert 1 alice a@x\n", create)).status.success());
+    let schema = run(&[old, "-c", ".schema"], "");
+    assert_eq!(String::from_utf8(schema.stdout.clone()).unwrap(), create);
+
+    let output = run(&[new], &String::from_utf8(schema.stdout).unwrap());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(run(&[new, "-c", ".schema"], "").stdout).unwrap(), create);
+    // The new table is sized as the old one was.
+    let output = run(&[new, "insert 1 thirteen-long a@x"], "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Error: username is 13 bytes, at most 12 allowed.\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,38 @@
+/* Drives the C interface: prints each row, then the codes and messages of
+ * a few failing calls. Run by tests/ffi.rs. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "voiddb.h"
+
+static int print_row(void *user_data, int columns, const char *const *values, const char *const *names) {
+    int *rows = user_data;
+    for (int i = 0; i < columns; i++) {
+        printf("%s%s=%s", i ? " " : "", names[i], values[i] ? values[i] : "NULL");
+    }
+    printf("\n");
+    return ++*rows == 2;
+}
+
+int main(int argc, char **argv) {
+    voiddb *db;
+    int rows = 0;
+    if (argc != 2 || voiddb_open(argv[1], &db) != VOIDDB_OK) {
+        return 1;
+    }
+    voiddb_exec(db, "insert 1 alice alice@example.com", NULL, NULL);
+    voiddb_exec(db, "insert 2 bob bob@example.com", NULL, NULL);
+    voiddb_exec(db, "insert 3 carol carol@example.com", NULL, NULL);
+    printf("select: %d\n", voiddb_exec(db, "select", print_row, &rows));
+    voiddb_code code = voiddb_exec(db, "insert 1 alice alice@example.com", NULL, NULL);
+    printf("insert: %d %s\n", code, voiddb_errmsg(db));
+    code = voiddb_exec(db, "bogus", NULL, NULL);
+    printf("bogus: %d %s\n", code, voiddb_errmsg(db));
+    printf("close: %d\n", voiddb_close(db));
+
+    if (voiddb_open("/nonexistent/dir/test.db", &db) != VOIDDB_IO_ERROR || strncmp(voiddb_errmsg(db), "I/O error", 9) != 0) {
+        return 1;
+    }
+    return voiddb_close(db);
+}
@@ -0,0 +1,55 @@
+//! Builds the shared library, compiles tests/ffi.c against it and runs it.
+
+#![cfg(all(feature = "ffi", unix))]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // A target directory of its own, so other builds of the library with
+    // other features do not replace the one linked here.
+    let target = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let built = Command::new(env!("CARGO"))
+        .args(["rustc", "--offline", "--lib", "--features", "ffi", "--crate-type", "cdylib", "--target-dir"])
+        .arg(&target)
+        .current_dir(&root)
+        .status()
+        .unwrap();
+    assert!(built.success());
+
+    let lib_dir = target.join("debug");
+    let exe = target.join("voiddb-ffi-test");
+    let compiled = match Command::new("cc")
+        .arg(root.join("tests/ffi.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .args(["-lVoidDB", "-o"])
+        .arg(&exe)
+        .status()
+    {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("skipping: cannot run cc: {}", err);
+            return;
+        }
+    };
+    assert!(compiled.success());
+
+    // cargo test puts its own directories on the library path, and those
+    // may hold a build of the library without the ffi feature.
+    let output = Command::new(&exe).arg(":memory:").env("LD_LIBRARY_PATH", &lib_dir).env("DYLD_LIBRARY_PATH", &lib_dir).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "id=1 username=alice email=alice@example.com age=0\n\
+         id=2 username=bob email=bob@example.com age=0\n\
+         select: 18\n\
+         insert: 5 Error: Duplicate key.\n\
+         bogus: 2 Unrecognized statement\n\
+         close: 0\n"
+    );
+}
@@ -0,0 +1,184 @@
+//! What a database keeps across closing and reopening its file, run once
+//! against the real file system and once against a `MemoryVfs`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use VoidDB::pager::PAGE_SIZE;
+use VoidDB::vfs::{self, MemoryVfs, OpenMode, Vfs};
+use VoidDB::{Database, DbError, Options};
+
+/// Where one test keeps its files.
+struct Env {
+    vfs: Arc<dyn Vfs>,
+    dir: PathBuf,
+    /// Whether `dir` is on disk, to be removed when the test ends.
+    on_disk: bool,
+}
+
+impl Env {
+    fn disk(test: &str) -> Env {
+        let dir = std::env::temp_dir().join(format!("voiddb-test-persistence-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Env { vfs: vfs::default(), dir, on_disk: true }
+    }
+
+    fn memory() -> Env {
+        Env { vfs: Arc::new(MemoryVfs::new()), dir: PathBuf::from("/memory"), on_disk: false }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    fn options(&self) -> Options {
+        Options::new().vfs(self.vfs.clone())
+    }
+
+    fn open(&self, name: &str) -> Database {
+        self.options().open(self.path(name)).unwrap()
+    }
+
+    fn file_size(&self, name: &str) -> u64 {
+        self.vfs.open(&self.path(name), OpenMode::ReadOnly).unwrap().file_size().unwrap()
+    }
+}
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        if self.on_disk {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+fn insert(db: &mut Database, ids: std::ops::RangeInclusive<u64>) {
+    for id in ids {
+        db.execute(&format!("insert {} user{} user{}@example.com", id, id, id)).unwrap();
+    }
+}
+
+fn ids(db: &Database) -> Vec<u64> {
+    db.select("select").unwrap().map(|row| row.unwrap().id).collect()
+}
+
+fn reopen_keeps_rows(env: Env) {
+    let mut db = env.open("a.db");
+    insert(&mut db, 1..=200);
+    db.close().unwrap();
+    assert_eq!(env.file_size("a.db") % PAGE_SIZE as u64, 0);
+
+    let mut db = env.open("a.db");
+    assert_eq!(ids(&db), (1..=200).collect::<Vec<_>>());
+    insert(&mut db, 201..=210);
+    db.close().unwrap();
+    assert_eq!(ids(&env.open("a.db")).len(), 210);
+}
+
+fn dropping_without_close_keeps_the_last_flush(env: Env) {
+    let mut db = env.open("a.db");
+    insert(&mut db, 1..=3);
+    db.flush().unwrap();
+    insert(&mut db, 4..=6);
+    drop(db);
+    assert_eq!(ids(&env.open("a.db")), [1, 2, 3]);
+}
+
+fn the_file_keeps_its_layout(env: Env) {
+    let narrow = env.options().username_size(8).row_checksums(true);
+    let mut db = narrow.open(env.path("a.db")).unwrap();
+    insert(&mut db, 1..=2);
+    db.close().unwrap();
+
+    let mut db = env.options().username_size(64).open(env.path("a.db")).unwrap();
+    assert_eq!((db.options().username_size, db.options().row_checksums), (8, true));
+    assert_eq!(db.execute("insert 3 bartholomew b@x").unwrap_err().code(), "VALUE_TOO_LONG");
+}
+
+fn one_writer_or_many_readers(env: Env) {
+    env.open("a.db").close().unwrap();
+    let writer = env.open("a.db");
+    assert!(matches!(env.options().open(env.path("a.db")), Err(DbError::Locked)));
+    assert!(matches!(env.options().read_only(true).open(env.path("a.db")), Err(DbError::Locked)));
+    drop(writer);
+
+    let readers = env.options().read_only(true);
+    let first = readers.open(env.path("a.db")).unwrap();
+    let second = readers.open(env.path("a.db")).unwrap();
+    assert!(matches!(env.options().open(env.path("a.db")), Err(DbError::Locked)));
+    drop((first, second));
+    env.open("a.db");
+}
+
+fn missing_files(env: Env) {
+    let err = env.options().create_if_missing(false).open(env.path("missing.db")).err().unwrap();
+    assert_eq!(err.code(), "CANNOT_OPEN");
+    let err = env.options().read_only(true).open(env.path("missing.db")).err().unwrap();
+    assert_eq!(err.code(), "CANNOT_OPEN");
+    assert!(env.vfs.canonicalize(&env.path("missing.db")).is_err());
+}
+
+fn attach_copies_between_files(env: Env) {
+    let mut db = env.open("main.db");
+    insert(&mut db, 1..=5);
+    db.attach(env.path("archive.db"), "archive").unwrap();
+    db.execute("insert into archive.users select from users").unwrap();
+    assert_eq!(db.attach(env.path("main.db"), "again").unwrap_err().code(), "ATTACH_ERROR");
+    db.close().unwrap();
+
+    assert_eq!(ids(&env.open("archive.db")), [1, 2, 3, 4, 5]);
+}
+
+fn clone_to_writes_a_new_file(env: Env) {
+    let mut db = env.open("a.db");
+    insert(&mut db, 1..=50);
+    let summary = db.clone_to(env.path("copy.db")).unwrap();
+    assert_eq!(summary.rows, 50);
+    assert_eq!(env.file_size("copy.db"), (summary.pages * PAGE_SIZE) as u64);
+    assert!(db.clone_to(env.path("copy.db")).is_err());
+    assert_eq!(ids(&env.open("copy.db")).len(), 50);
+}
+
+fn a_small_cache_reads_pages_back(env: Env) {
+    let mut db = env.options().cache_pages(2).open(env.path("a.db")).unwrap();
+    insert(&mut db, 1..=300);
+    db.flush().unwrap();
+    assert_eq!(db.stats().cached_pages, 2);
+    let before = db.metrics().pages_read;
+    assert_eq!(ids(&db).len(), 300);
+    assert!(db.metrics().pages_read > before);
+}
+
+fn a_torn_file_is_corrupt(env: Env) {
+    let mut db = env.open("a.db");
+    insert(&mut db, 1..=3);
+    db.close().unwrap();
+    let mut file = env.vfs.open(&env.path("a.db"), OpenMode::ReadWrite).unwrap();
+    file.truncate(PAGE_SIZE as u64 + 100).unwrap();
+    drop(file);
+    assert_eq!(env.options().open(env.path("a.db")).err().unwrap().code(), "CORRUPT");
+}
+
+macro_rules! on_both {
+    ($($test:ident),* $(,)?) => {
+        mod disk {
+            $(#[test] fn $test() { super::$test(super::Env::disk(stringify!($test))) })*
+        }
+        mod memory {
+            $(#[test] fn $test() { super::$test(super::Env::memory()) })*
+        }
+    };
+}
+
+on_both!(
+    reopen_keeps_rows,
+    dropping_without_close_keeps_the_last_flush,
+    the_file_keeps_its_layout,
+    one_writer_or_many_readers,
+    missing_files,
+    attach_copies_between_files,
+    clone_to_writes_a_new_file,
+    a_small_cache_reads_pages_back,
+    a_torn_file_is_corrupt,
+);
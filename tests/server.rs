@@ -0,0 +1,638 @@
+//! Runs `VoidDB serve` and talks to it over TCP, by hand and with
+//! `VoidDB --connect`, on Unix over a socket file, and over HTTP; and
+//! replicates one to `VoidDB follow`.
+
+#![cfg(feature = "cli")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A server running on a file of its own, on a free port or the socket
+/// it was started on.
+struct Server {
+    child: Child,
+    address: String,
+    path: std::path::PathBuf,
+}
+
+impl Server {
+    fn start(test: &str) -> Server {
+        Server::start_with(test, &["--listen", "127.0.0.1:0"])
+    }
+
+    fn start_with(test: &str, args: &[&str]) -> Server {
+        let path = std::env::temp_dir().join(format!("voiddb-test-server-{}-{}.db", std::process::id(), test));
+        let _ = std::fs::remove_file(&path);
+        Server::launch("serve", path, args)
+    }
+
+    /// Runs `VoidDB command` on the file at `path`, as it is.
+    fn launch(command: &str, path: std::path::PathBuf, args: &[&str]) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+            .args([command, path.to_str().unwrap()])
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let address = line.trim().strip_prefix("Listening on ").unwrap_or_else(|| panic!("{:?}", line)).to_string();
+        Server { child, address, path }
+    }
+
+    fn connect(&self) -> Client {
+        let stream = TcpStream::connect(&self.address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream }
+    }
+
+    /// Stops the server with Ctrl-C, as its user would, and waits for it.
+    fn stop(self) -> std::path::PathBuf {
+        self.signal("-INT");
+        self.wait()
+    }
+
+    /// Sends the server `signal`, as `kill` takes it.
+    fn signal(&self, signal: &str) {
+        let killed = Command::new("kill").args([signal, &self.child.id().to_string()]).status().unwrap();
+        assert!(killed.success());
+    }
+
+    /// Waits for the server to exit, which it must do cleanly, and returns
+    /// its file.
+    fn wait(mut self) -> std::path::PathBuf {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while self.child.try_wait().unwrap().is_none() {
+            if Instant::now() > deadline {
+                self.child.kill().unwrap();
+                panic!("server did not exit");
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(self.child.wait().unwrap().success());
+        std::mem::take(&mut self.path)
+    }
+
+    /// Kills the server outright, leaving its file as it was.
+    fn kill(mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+        self.path = std::path::PathBuf::new();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if !self.path.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    /// Sends one request and returns the lines of its response, up to the
+    /// end line.
+    fn request(&mut self, line: &str) -> Vec<String> {
+        writeln!(self.writer, "{}", line).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            assert!(self.reader.read_line(&mut line).unwrap() > 0, "server hung up");
+            let line = line.trim_end_matches('\n');
+            if line == "." {
+                return lines;
+            }
+            lines.push(line.to_string());
+        }
+    }
+}
+
+#[test]
+fn test_clients_one_after_another() {
+    let server = Server::start("clients");
+    let mut first = server.connect();
+    assert!(first.request("insert 1 alice alice@example.com").is_empty());
+    assert_eq!(first.request("select"), ["1,alice,alice@example.com,0"]);
+    assert_eq!(first.request("insert 1 alice alice@example.com"), ["!DUPLICATE_KEY Error: Duplicate key."]);
+    writeln!(first.writer, ".quit").unwrap();
+    let mut rest = String::new();
+    assert_eq!(first.reader.read_line(&mut rest).unwrap(), 0);
+
+    // Hanging up without `.quit` ends the session just as well.
+    let mut second = server.connect();
+    assert!(second.request(".mode json").is_empty());
+    assert_eq!(second.request("insert 2 bob bob@example.com"), ["{\"rows_affected\":1}"]);
+    drop(second);
+
+    let mut third = server.connect();
+    assert_eq!(third.request("select"), ["1,alice,alice@example.com,0", "2,bob,bob@example.com,0"]);
+}
+
+#[test]
+fn test_rows_outlive_the_server() {
+    let server = Server::start("persist");
+    let mut client = server.connect();
+    client.request("insert 1 alice alice@example.com; insert 2 bob bob@example.com");
+    let path = server.stop();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["--no-rc", path.to_str().unwrap(), "select"]).output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(1, alice, alice@example.com, 0)\n(2, bob, bob@example.com, 0)\n(2 rows)\n"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_a_slow_client_holds_up_no_one() {
+    let server = Server::start("slow");
+    // Half a request, and then nothing for a while.
+    let mut slow = server.connect();
+    write!(slow.writer, "insert 1 alice ").unwrap();
+
+    let mut fast = server.connect();
+    assert!(fast.request("insert 2 bob bob@example.com").is_empty());
+    assert_eq!(fast.request("select"), ["2,bob,bob@example.com,0"]);
+
+    assert!(slow.request("alice@example.com").is_empty());
+    assert_eq!(fast.request("select where id = 1"), ["1,alice,alice@example.com,0"]);
+}
+
+#[test]
+fn test_max_connections() {
+    let server = Server::start_with("max", &["--listen", "127.0.0.1:0", "--max-connections", "1"]);
+    let mut first = server.connect();
+    assert!(first.request("insert 1 alice alice@example.com").is_empty());
+
+    // The second client gets in, but is not answered until the first one
+    // leaves.
+    let mut second = server.connect();
+    second.writer.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    writeln!(second.writer, "select").unwrap();
+    let mut line = String::new();
+    let err = second.reader.read_line(&mut line).unwrap_err();
+    assert!(matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut), "{:?}", err);
+    drop(first);
+    second.writer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    second.reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "1,alice,alice@example.com,0\n");
+}
+
+#[test]
+fn test_concurrent_clients() {
+    const CLIENTS: u64 = 24;
+    const ROWS: u64 = 12;
+
+    let server = Server::start("concurrent");
+    std::thread::scope(|scope| {
+        for client in 0..CLIENTS {
+            let server = &server;
+            scope.spawn(move || {
+                let mut connection = server.connect();
+                for row in 0..ROWS {
+                    let id = client * 100 + row;
+                    let response = connection.request(&format!("insert {} user{} user{}@example.com {}", id, id, id, client));
+                    assert!(response.is_empty(), "{:?}", response);
+                    assert_eq!(connection.request(&format!("select where id = {}", id)), [format!("{},user{},user{}@example.com,{}", id, id, id, client)]);
+                    // Every client sees its own rows, and whole rows of others.
+                    let rows = connection.request("select");
+                    assert!(rows.len() as u64 > row, "{} rows", rows.len());
+                    for line in &rows {
+                        let fields: Vec<&str> = line.split(',').collect();
+                        assert_eq!(fields.len(), 4, "{}", line);
+                        assert_eq!(fields[1], format!("user{}", fields[0]), "{}", line);
+                    }
+                }
+                // Every third client goes away in the middle of a request.
+                if client % 3 == 0 {
+                    writeln!(connection.writer, "select").unwrap();
+                }
+            });
+        }
+    });
+
+    let mut client = server.connect();
+    assert_eq!(client.request("select").len() as u64, CLIENTS * ROWS);
+    drop(client);
+
+    let path = server.stop();
+    let db = VoidDB::Database::open(&path).unwrap();
+    let rows: Vec<VoidDB::row::Row> = db.select("select").unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.len() as u64, CLIENTS * ROWS);
+    for row in &rows {
+        assert_eq!(row.username(), format!("user{}", row.id));
+        assert_eq!(u64::from(row.age), row.id / 100);
+    }
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Runs `VoidDB --connect` to `address` with `args`, feeding it `script`.
+fn connect(address: &str, args: &[&str], script: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(["--no-rc", "--connect", address])
+        .args(args)
+        .env("HOME", std::env::temp_dir().join("voiddb-test-no-home"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_client_shell() {
+    let server = Server::start("shell");
+    let output = connect(&server.address, &[], "insert 1 alice alice@example.com\nselect\n.mode json\nselect where id = 1\n");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            "1 row inserted\n(1, alice, alice@example.com, 0)\n(1 row)\n",
+            "[{\"id\":1,\"username\":\"alice\",\"email\":\"alice@example.com\",\"age\":0}]\n",
+        )
+    );
+    assert!(output.status.success());
+
+    // One-shot statements, and the first error stops a script.
+    let output = connect(&server.address, &["-c", "insert 2 bob 'b\\nob@x'; select where id = 2"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n(2, bob, b\nob@x, 0)\n(1 row)\n");
+    assert!(output.status.success());
+    let output = connect(&server.address, &[], "insert 1 alice a@x\ninsert 3 carol c@x\n");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "<stdin>:1: Error: Duplicate key.\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = connect(&server.address, &[], ".tables\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "<stdin>:1: .tables works on a database file of the shell's own, not over a connection to a server.\n"
+    );
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_binary_client() {
+    let server = Server::start("binary");
+    let output = connect(&server.address, &["--binary"], "insert 1 alice alice@example.com 30\nselect\n.mode json\nselect where id = 1\n");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            "1 row inserted\n(1, alice, alice@example.com, 30)\n(1 row)\n",
+            "[{\"id\":1,\"username\":\"alice\",\"email\":\"alice@example.com\",\"age\":30}]\n",
+        )
+    );
+    let output = connect(&server.address, &["--binary", "-c", "insert 1 a a@x"], "");
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "Error: Duplicate key.\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Line clients are served alongside.
+    let mut client = server.connect();
+    assert_eq!(client.request("select"), ["1,alice,alice@example.com,30"]);
+    drop(client);
+    std::fs::remove_file(server.stop()).unwrap();
+}
+
+#[test]
+fn test_client_connection_errors() {
+    // Nothing listens on a port just let go of.
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let output = connect(&address, &["-c", "select"], "");
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with(&format!("Unable to connect to {}: ", address)));
+    assert_eq!(output.status.code(), Some(3));
+
+    // The server goes away between two statements.
+    let server = Server::start("disconnect");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(["--no-rc", "--connect", &server.address])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "insert 1 alice alice@example.com").unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    assert_eq!(line, "1 row inserted\n");
+    let path = server.stop();
+    writeln!(stdin, "select").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("<stdin>:2: Lost the connection to the server: "), "{}", stderr);
+    assert_eq!(output.status.code(), Some(3));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_authentication() {
+    let token_file = std::env::temp_dir().join(format!("voiddb-test-server-{}.token", std::process::id()));
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+    let mut server = Server::start_with("auth", &["--listen", "127.0.0.1:0", "--auth-token-file", token_file.to_str().unwrap()]);
+    let mut stderr = server.child.stderr.take().unwrap();
+
+    // No token, and the wrong one: told so, and hung up on.
+    for (first, message) in [("select", "Authentication required."), ("AUTH s3cre", "Authentication failed.")] {
+        let mut client = server.connect();
+        assert_eq!(client.request(first), [format!("!AUTH_FAILED {}", message)]);
+        let mut rest = String::new();
+        assert_eq!(client.reader.read_line(&mut rest).unwrap(), 0);
+    }
+
+    let mut client = server.connect();
+    assert!(client.request("AUTH s3cret").is_empty());
+    assert!(client.request("insert 1 alice alice@example.com").is_empty());
+    drop(client);
+
+    let output = connect(&server.address, &["--token-file", token_file.to_str().unwrap(), "-c", "select"], "");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(1, alice, alice@example.com, 0)\n(1 row)\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(["--no-rc", "--connect", &server.address, "-c", "select where id = 1"])
+        .env("VOIDDB_TOKEN", "s3cret")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(1, alice, alice@example.com, 0)\n(1 row)\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB"))
+        .args(["--no-rc", "--connect", &server.address, "-c", "select"])
+        .env_remove("VOIDDB_TOKEN")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("Unable to connect to {}: Authentication required.\n", server.address));
+    assert_eq!(output.status.code(), Some(3));
+
+    let path = server.stop();
+    let mut log = String::new();
+    std::io::Read::read_to_string(&mut stderr, &mut log).unwrap();
+    let log: Vec<&str> = log.lines().collect();
+    assert_eq!(log.len(), 3, "{:?}", log);
+    assert!(log[0].starts_with("Turned away 127.0.0.1:") && log[0].ends_with(": Authentication required."), "{:?}", log);
+    assert!(log[1].ends_with(": Authentication failed."), "{:?}", log);
+    assert!(log[2].ends_with(": Authentication required."), "{:?}", log);
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(token_file).unwrap();
+}
+
+#[test]
+fn test_graceful_shutdown() {
+    let token_file = std::env::temp_dir().join(format!("voiddb-test-server-{}-shutdown.token", std::process::id()));
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+    let args = ["--listen", "127.0.0.1:0", "--auth-token-file", token_file.to_str().unwrap(), "--grace-period", "5"];
+    let server = Server::start_with("shutdown", &args);
+    let mut client = server.connect();
+    assert!(client.request("AUTH s3cret").is_empty());
+    let inserts: Vec<String> = (1..=200).map(|id| format!("insert {} user{} user{}@example.com", id, id, id)).collect();
+    assert!(client.request(&inserts.join("; ")).is_empty());
+
+    // A request under way when SIGTERM comes is answered in full before
+    // the connection is closed.
+    writeln!(client.writer, "{}", ["select"; 20].join("; ")).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    server.signal("-TERM");
+    let mut response = Vec::new();
+    loop {
+        let mut line = String::new();
+        assert!(client.reader.read_line(&mut line).unwrap() > 0, "server hung up after {} lines", response.len());
+        if line == ".\n" {
+            break;
+        }
+        response.push(line);
+    }
+    assert_eq!(response.len(), 20 * 200);
+    let mut rest = String::new();
+    assert_eq!(client.reader.read_line(&mut rest).unwrap(), 0);
+    let path = server.wait();
+
+    // `.shutdown` from a client with the token does the same.
+    let server = Server::launch("serve", path, &args);
+    let mut client = server.connect();
+    assert!(client.request("AUTH s3cret").is_empty());
+    assert!(client.request("insert 201 last last@example.com").is_empty());
+    assert!(client.request(".shutdown").is_empty());
+    assert_eq!(client.reader.read_line(&mut rest).unwrap(), 0);
+    let path = server.wait();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["--no-rc", path.to_str().unwrap(), "select where id = 201"]).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(201, last, last@example.com, 0)\n(1 row)\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["--no-rc", path.to_str().unwrap(), "select"]).output().unwrap();
+    assert!(String::from_utf8(output.stdout).unwrap().ends_with("(201 rows)\n"));
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(token_file).unwrap();
+}
+
+#[test]
+fn test_statement_timeout() {
+    let server = Server::start_with("timeout", &["--listen", "127.0.0.1:0", "--statement-timeout", "5s"]);
+    let mut client = server.connect();
+    assert_eq!(client.request(".statement_timeout"), ["5s"]);
+    assert!(client.request(".statement_timeout 250ms").is_empty());
+    assert!(client.request("insert 1 alice alice@example.com").is_empty());
+    assert_eq!(client.request(".statement_timeout"), ["250ms"]);
+    assert_eq!(server.connect().request(".statement_timeout"), ["5s"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["serve", "unused.db", "--statement-timeout", "soon"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_open_servers_are_warned_about() {
+    let mut server = Server::start_with("open", &["--listen", "0.0.0.0:0"]);
+    let mut stderr = server.child.stderr.take().unwrap();
+    let path = server.stop();
+    let mut warning = String::new();
+    std::io::Read::read_to_string(&mut stderr, &mut warning).unwrap();
+    assert!(warning.starts_with("WARNING: 0.0.0.0:"), "{}", warning);
+    assert!(warning.contains("--auth-token-file"), "{}", warning);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket() {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let socket = std::env::temp_dir().join(format!("voiddb-test-server-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    // The socket of a server that is gone, which the next one replaces.
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+    let address = format!("unix:{}", socket.display());
+
+    let server = Server::start_with("unix", &["--listen", &address]);
+    assert_eq!(server.address, address);
+    let metadata = std::fs::metadata(&socket).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+    // A second server cannot take the socket of one that is running.
+    let other = socket.with_extension("db");
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["serve", other.to_str().unwrap(), "--listen", &address]).output().unwrap();
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with(&format!("Unable to listen on {}: ", address)));
+    assert_eq!(output.status.code(), Some(3));
+    let _ = std::fs::remove_file(other);
+
+    let output = connect(&address, &[], "insert 1 alice alice@example.com
+insert 2 bob bob@example.com
+select where id = 2
+");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 row inserted\n1 row inserted\n(2, bob, bob@example.com, 0)\n(1 row)\n");
+    assert!(output.status.success());
+    let output = connect(&address, &["-c", "select"], "");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(1, alice, alice@example.com, 0)\n(2, bob, bob@example.com, 0)\n(2 rows)\n"
+    );
+
+    // SIGTERM shuts it down as cleanly as Ctrl-C, socket and all.
+    server.signal("-TERM");
+    let path = server.wait();
+    assert!(!socket.exists());
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Sends one HTTP request to `address` and returns the status and body of
+/// the response.
+#[cfg(feature = "http")]
+fn http(address: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+    http_with(address, method, path, "", body)
+}
+
+/// `http` with `headers`, each ended by `\r\n`, besides the usual ones.
+#[cfg(feature = "http")]
+fn http_with(address: &str, method: &str, path: &str, headers: &str, body: &str) -> (u16, String) {
+    use std::io::Read;
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n{}", method, path, address, headers).unwrap();
+    write!(stream, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("\r\nContent-Type: application/json\r\n"), "{}", head);
+    let status = head.strip_prefix("HTTP/1.1 ").unwrap()[..3].parse().unwrap();
+    (status, body.trim_end().to_string())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http() {
+    let server = Server::start_with("http", &["--http", "127.0.0.1:0"]);
+    let address = &server.address;
+    assert_eq!(http(address, "GET", "/health", ""), (200, r#"{"status":"ok"}"#.to_string()));
+
+    let insert = r#"{"sql": "insert 1 alice alice@example.com; insert 2 'b\"ob' bob@example.com"}"#;
+    assert_eq!(http(address, "POST", "/query", insert), (200, r#"{"rows_affected":1}"#.to_string()));
+    assert_eq!(
+        http(address, "POST", "/query", r#"{"sql": "select where id = 2"}"#),
+        (200, r#"{"rows":[{"id":2,"username":"b\"ob","email":"bob@example.com","age":0}]}"#.to_string())
+    );
+
+    // An SQL error, and requests the server cannot take.
+    assert_eq!(
+        http(address, "POST", "/query", r#"{"sql": "insert 1 carol carol@example.com"}"#),
+        (409, r#"{"error":{"code":"DUPLICATE_KEY","message":"Error: Duplicate key."}}"#.to_string())
+    );
+    let (status, body) = http(address, "POST", "/query", r#"{"sql": "select""#);
+    assert_eq!(status, 400);
+    assert!(body.starts_with(r#"{"error":{"code":"BAD_REQUEST","message":"Malformed JSON: "#), "{}", body);
+    let (status, body) = http(address, "POST", "/query", &format!(r#"{{"sql": "{}"}}"#, "x".repeat(2 * 1024 * 1024)));
+    assert_eq!(status, 413);
+    assert!(body.contains("PAYLOAD_TOO_LARGE"), "{}", body);
+    assert_eq!(http(address, "DELETE", "/query", "").0, 405);
+    assert_eq!(http(address, "GET", "/", "").0, 404);
+
+    // What was acknowledged is on disk once the server is stopped.
+    let path = server.stop();
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["--no-rc", path.to_str().unwrap(), "select"]).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(1, alice, alice@example.com, 0)\n(2, b\"ob, bob@example.com, 0)\n(2 rows)\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_bearer_token() {
+    let token_file = std::env::temp_dir().join(format!("voiddb-test-server-{}-http.token", std::process::id()));
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+    let server = Server::start_with("http-auth", &["--http", "127.0.0.1:0", "--auth-token-file", token_file.to_str().unwrap()]);
+    let query = r#"{"sql": "select"}"#;
+    assert_eq!(http(&server.address, "POST", "/query", query).0, 401);
+    assert_eq!(http_with(&server.address, "POST", "/query", "Authorization: Bearer nope\r\n", query).0, 401);
+    assert_eq!(http_with(&server.address, "POST", "/query", "Authorization: Bearer s3cret\r\n", query), (200, r#"{"rows":[]}"#.to_string()));
+    assert_eq!(http(&server.address, "GET", "/health", "").0, 200);
+    std::fs::remove_file(server.stop()).unwrap();
+    std::fs::remove_file(token_file).unwrap();
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn test_http_needs_the_feature() {
+    let output = Command::new(env!("CARGO_BIN_EXE_VoidDB")).args(["serve", "unused.db", "--http", "127.0.0.1:0"]).output().unwrap();
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "--http needs VoidDB built with the http feature.\n");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_replication() {
+    // The follower comes back on the same address after it is killed.
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let replica = std::env::temp_dir().join(format!("voiddb-test-server-{}-replica.db", std::process::id()));
+    let position = std::path::PathBuf::from(format!("{}-position", replica.display()));
+    let _ = std::fs::remove_file(&replica);
+    let _ = std::fs::remove_file(&position);
+    let mut follower = Server::launch("follow", replica.clone(), &["--listen", &address]);
+    let leader = Server::start_with("leader", &["--listen", "127.0.0.1:0", "--replicate-to", &address]);
+
+    let mut client = leader.connect();
+    let writer = std::thread::spawn(move || {
+        for id in 1..=120 {
+            assert!(client.request(&format!("insert {} user{} user{}@example.com {}", id, id, id, id % 90)).is_empty());
+        }
+    });
+    let rows_on = |server: &Server| server.connect().request("select");
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while rows_on(&follower).len() < 30 {
+        assert!(Instant::now() < deadline, "the follower never caught up");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(follower.connect().request("insert 1000 a a@x"), ["!READ_ONLY Error: Only a select can run without write access."]);
+
+    // Killed partway through the stream and started again, it picks up
+    // where it got to instead of copying the whole database again.
+    let mut stderr = follower.child.stderr.take().unwrap();
+    follower.kill();
+    let mut log = String::new();
+    std::io::Read::read_to_string(&mut stderr, &mut log).unwrap();
+    assert_eq!(log.matches("Copied the leader's database as of frame").count(), 1, "{}", log);
+    follower = Server::launch("follow", replica.clone(), &["--listen", &address]);
+    let mut stderr = follower.child.stderr.take().unwrap();
+
+    writer.join().unwrap();
+    let expected = rows_on(&leader);
+    assert_eq!(expected.len(), 120);
+    while rows_on(&follower) != expected {
+        assert!(Instant::now() < deadline, "the follower never caught up");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Byte for byte the same file.
+    let leader_path = leader.stop();
+    follower.stop();
+    let mut log = String::new();
+    std::io::Read::read_to_string(&mut stderr, &mut log).unwrap();
+    assert!(log.starts_with("Resuming after frame "), "{}", log);
+    assert!(!log.contains("Copied"), "{}", log);
+    assert_eq!(std::fs::read(&leader_path).unwrap(), std::fs::read(&replica).unwrap());
+    for path in [leader_path, replica, position] {
+        std::fs::remove_file(path).unwrap();
+    }
+}